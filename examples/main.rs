@@ -1,11 +1,10 @@
 use std::env;
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::Path;
 use std::process::Command;
-use std::str::FromStr;
-use smali::{ find_smali_files };
+use smali::patch::PatchSet;
+use smali::project::SmaliProject;
 use smali::types::*;
-use smali::types::SmaliInstruction::Instruction;
 
 // This demo unpacks an APK file with apktool (you need this on your path), searches for rootBeer and disables it.
 // Try it on the RootBeer Sample app https://play.google.com/store/apps/details?id=com.scottyab.rootbeer.sample
@@ -22,56 +21,23 @@ fn main()
     }
 }
 
-// Matches the known field / method signatures for the RootBeer.class (remember this could be renamed)
-fn is_rootbeer_class(c: &SmaliClass) -> bool
-{
-    if c.fields.len() == 2 && c.methods.len() == 25
-    {
-        // Probably unreliable if the ordering changes
-        if c.fields[0].signature == TypeSignature::Bool && c.methods[1].signature.return_type == TypeSignature::Bool
-            && c.methods[4].signature.args.len() == 0 && c.methods[4].signature.return_type == TypeSignature::Bool
-        {
-            println!("Detected RootBeer class: {}", c.name.as_java_type());
-            return true;
-        }
-    }
-    false
-}
-
 /* This is where all the processing takes place, to make error handling easier */
 fn process_apk(apk_file: &str) -> Result<(), Box<dyn Error>>
 {
    // Call apktool to unpack the APK
    execute_command("apktool", &["decode", "-f", apk_file, "-o", "out"])?;
 
-   // Load all the smali classes
-   let mut p = PathBuf::from_str("out")?;
-   p.push("smali");
-   let mut classes = find_smali_files(&p)?;
-   println!("{:} smali classes loaded.", classes.len());
+   // Load all the smali classes, from every smali_classesN folder
+   let mut project = SmaliProject::open(Path::new("out"))?;
+   println!("{:} smali classes loaded.", project.len());
+
+   // Patch RootBeer's methods returning a boolean to return false, see rootbeer.patch
+   let patches = PatchSet::parse(include_str!("rootbeer.patch"))?;
+   let report = patches.apply(project.classes_mut());
+   print!("{}", report);
 
-   // Search for the RootBeer class
-   for c in classes.iter_mut()
-   {
-       if is_rootbeer_class(&c)
-       {
-           // Patch all the methods returning a boolean
-           for m in c.methods.iter_mut()
-           {
-               if m.signature.return_type == TypeSignature::Bool && m.signature.args.len() == 0
-               {
-                   let mut new_instructions = vec![];
-                   new_instructions.push(Instruction("const/4 v0, 0x0".to_string())); // Set v0 to false
-                   new_instructions.push(Instruction("return v0".to_string()));       // return v0
-                   m.instructions = new_instructions;
-                   m.locals = 1;
-                   println!("{} method {} successfully patched.", c.name.as_java_type(), &m.name);
-               }
-           }
-       }
-       // Save all classes, even unmodified so we can thoroughly test parser and writer
-       c.save()?;
-   }
+   // Only the patched classes are written back
+   println!("{:} smali classes saved.", project.save()?);
 
    // Repack the APK
    execute_command("apktool", &["build", "out", "-o", "out.apk"])?;
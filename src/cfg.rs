@@ -0,0 +1,491 @@
+//! Control flow graphs of methods
+//!
+//! A [`Cfg`] splits a method's instructions into basic blocks joined by fallthrough, branch, switch, exception and
+//! exit edges, and can work out the dominator tree and loops of the method.
+//!
+//! # Examples
+//!
+//! ```
+//!  use smali::cfg::Cfg;
+//!  use smali::types::SmaliClass;
+//!
+//!  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n\
+//!               .method public static sum(I)I\n    .locals 1\n    const/4 v0, 0x0\n    :goto_0\n    if-lez p0, :cond_0\n\
+//!               add-int/2addr v0, p0\n    add-int/lit8 p0, p0, -0x1\n    goto :goto_0\n    :cond_0\n    return v0\n.end method\n";
+//!  let c = SmaliClass::from_smali(smali)?;
+//!  let cfg = Cfg::new(&c.methods[0])?;
+//!  assert_eq!(cfg.blocks().len(), 5); // entry, loop header, loop body, return and the exit block
+//!  assert_eq!(cfg.loops()[0].header, 1);
+//!  println!("{}", cfg.to_dot());
+//! # Ok::<(), smali::types::SmaliError>(())
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+use crate::instructions::{DexInstruction, Format, Operand, Payload};
+use crate::types::{ObjectIdentifier, SmaliError, SmaliInstruction, SmaliMethod};
+
+/// How control gets from one block to another
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    /// Falling through to the next block, including when an `if-*` or switch isn't taken
+    Fallthrough,
+    /// A `goto`
+    Goto,
+    /// A taken `if-*`
+    Branch,
+    /// A switch case with its key
+    Case(i32),
+    /// An exception caught by a handler, None for `.catchall`
+    Catch(Option<ObjectIdentifier>),
+    /// A `return` to the exit block
+    Return,
+    /// A `throw` to the exit block, the exception may also be caught
+    Throw
+}
+
+/// An edge to another block
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Edge {
+    /// The index of the target block
+    pub target: usize,
+    pub kind: EdgeKind
+}
+
+/// A run of instructions only entered at the top and only left at the bottom
+///
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    /// Index of the first of the method's instructions in the block
+    pub start: usize,
+    /// Index after the last of the method's instructions in the block
+    pub end: usize,
+    /// The labels in the block, without the leading `:`
+    pub labels: Vec<String>,
+    pub successors: Vec<Edge>,
+    /// The blocks with an edge to this one, in block order
+    pub predecessors: Vec<usize>
+}
+
+/// The immediate dominators of the blocks of a [`Cfg`]
+///
+#[derive(Debug, Clone)]
+pub struct DominatorTree {
+    idom: Vec<Option<usize>>
+}
+
+impl DominatorTree {
+    /// The immediate dominator of a block, None for the entry block and unreachable blocks
+    pub fn idom(&self, block: usize) -> Option<usize>
+    {
+        self.idom[block]
+    }
+
+    /// Does every path from the entry to `b` go through `a`
+    pub fn dominates(&self, a: usize, b: usize) -> bool
+    {
+        let mut b = Some(b);
+        while let Some(x) = b
+        {
+            if x == a { return true; }
+            b = self.idom[x];
+        }
+        false
+    }
+
+    /// The blocks immediately dominated by a block
+    pub fn children(&self, block: usize) -> Vec<usize>
+    {
+        (0..self.idom.len()).filter(|b| self.idom[*b] == Some(block)).collect()
+    }
+}
+
+/// A natural loop, found from the back edges to its header
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    /// The block dominating every block of the loop
+    pub header: usize,
+    /// The blocks in the loop including the header, sorted
+    pub blocks: Vec<usize>,
+    /// The blocks jumping back to the header
+    pub latches: Vec<usize>
+}
+
+/* Debug info, labels and directives which don't affect control flow */
+fn is_trivia(i: &SmaliInstruction) -> bool
+{
+    !matches!(i, SmaliInstruction::Instruction(_) | SmaliInstruction::Payload(_))
+}
+
+/* Does control never continue to the next instruction */
+fn ends_block(i: &DexInstruction) -> bool
+{
+    matches!(i.opcode().format(), Format::F10t | Format::F20t | Format::F30t | Format::F21t | Format::F22t)
+        || matches!(i, DexInstruction::PackedSwitch { .. } | DexInstruction::SparseSwitch { .. } | DexInstruction::Throw { .. }
+                       | DexInstruction::ReturnVoid | DexInstruction::Return { .. } | DexInstruction::ReturnWide { .. } | DexInstruction::ReturnObject { .. })
+}
+
+fn label_operand(i: &DexInstruction) -> Option<String>
+{
+    i.operands().into_iter().find_map(|o| if let Operand::Label(l) = o { Some(l) } else { None })
+}
+
+/// The control flow graph of a method
+///
+/// Blocks are in instruction order with the entry block first, the last block is an empty exit block which every
+/// `return` and `throw` has an edge to. Switch and array payloads aren't part of any block. Every block in a try
+/// range has an edge to each of its handlers.
+pub struct Cfg<'a> {
+    method: &'a SmaliMethod,
+    blocks: Vec<BasicBlock>
+}
+
+impl<'a> Cfg<'a> {
+    /// Builds the graph of a method, failing if a label or payload it uses doesn't exist
+    pub fn new(method: &'a SmaliMethod) -> Result<Cfg<'a>, SmaliError>
+    {
+        let insns = &method.instructions;
+        let n = insns.len();
+        let unknown = |l: &str| SmaliError::new(&format!("Unknown label :{} in method {}", l, method.name));
+        let find = |l: &str| method.label_index(l).ok_or_else(|| unknown(l));
+        let payload = |l: &str| method.payload(l).ok_or_else(|| unknown(l));
+        let tries = method.try_blocks()?;
+
+        // A block starts at each target, taking in any labels and debug info just before it
+        let mut leaders = BTreeSet::from([0]);
+        let mut leader = |mut i: usize| {
+            while i > 0 && is_trivia(&insns[i - 1]) { i -= 1; }
+            leaders.insert(i);
+        };
+        for t in &tries
+        {
+            leader(t.start);
+            leader(t.end);
+            leader(t.handler);
+        }
+        for (i, x) in insns.iter().enumerate()
+        {
+            match x {
+                SmaliInstruction::Instruction(d) if ends_block(d) => {
+                    match (d, label_operand(d)) {
+                        (DexInstruction::PackedSwitch { .. } | DexInstruction::SparseSwitch { .. }, Some(l)) => {
+                            let targets: Vec<String> = match payload(&l)? {
+                                Payload::PackedSwitch(p) => p.targets.clone(),
+                                Payload::SparseSwitch(s) => s.entries.iter().map(|e| e.1.clone()).collect(),
+                                Payload::ArrayData(_) => vec![]
+                            };
+                            for t in targets { leader(find(&t)?); }
+                        }
+                        (_, Some(l)) => leader(find(&l)?),
+                        _ => {}
+                    }
+                    leader(i + 1);
+                }
+                SmaliInstruction::Payload(_) => { leader(i); leader(i + 1); }
+                _ => {}
+            }
+        }
+
+        // Payloads are data, not code
+        let starts: Vec<usize> = leaders.into_iter().filter(|l| *l < n).collect();
+        let mut blocks = vec![];
+        let mut block_of = vec![None; n + 1];
+        for (k, s) in starts.iter().enumerate()
+        {
+            let end = starts.get(k + 1).copied().unwrap_or(n);
+            if insns[*s..end].iter().any(|i| matches!(i, SmaliInstruction::Payload(_))) { continue; }
+            for b in block_of.iter_mut().take(end).skip(*s) { *b = Some(blocks.len()); }
+            let labels = insns[*s..end].iter().filter_map(|i| if let SmaliInstruction::Label(l) = i { Some(l.clone()) } else { None }).collect();
+            blocks.push(BasicBlock { start: *s, end, labels, successors: vec![], predecessors: vec![] });
+        }
+        let exit = blocks.len();
+        block_of[n] = Some(exit);
+        blocks.push(BasicBlock { start: n, end: n, labels: vec![], successors: vec![], predecessors: vec![] });
+
+        let target = |l: &str| -> Result<usize, SmaliError> { block_of[find(l)?].ok_or_else(|| unknown(l)) };
+        for block in blocks.iter_mut().take(exit)
+        {
+            let (start, end) = (block.start, block.end);
+            let last = insns[start..end].iter().rev().find_map(|i| if let SmaliInstruction::Instruction(d) = i { Some(d) } else { None });
+            let mut edges = vec![];
+            let fallthrough = if end < n { block_of[end] } else { None };
+
+            match last {
+                Some(d @ (DexInstruction::PackedSwitch { .. } | DexInstruction::SparseSwitch { .. })) => {
+                    let l = label_operand(d).unwrap();
+                    match payload(&l)? {
+                        Payload::PackedSwitch(p) => for (k, t) in p.targets.iter().enumerate()
+                        {
+                            edges.push(Edge { target: target(t)?, kind: EdgeKind::Case(p.first_key.wrapping_add(k as i32)) });
+                        },
+                        Payload::SparseSwitch(s) => for (k, t) in &s.entries
+                        {
+                            edges.push(Edge { target: target(t)?, kind: EdgeKind::Case(*k) });
+                        },
+                        Payload::ArrayData(_) => {}
+                    }
+                    if let Some(t) = fallthrough { edges.push(Edge { target: t, kind: EdgeKind::Fallthrough }); }
+                }
+                Some(DexInstruction::Throw { .. }) => edges.push(Edge { target: exit, kind: EdgeKind::Throw }),
+                Some(DexInstruction::ReturnVoid | DexInstruction::Return { .. } | DexInstruction::ReturnWide { .. } | DexInstruction::ReturnObject { .. }) => {
+                    edges.push(Edge { target: exit, kind: EdgeKind::Return });
+                }
+                Some(d) if matches!(d.opcode().format(), Format::F10t | Format::F20t | Format::F30t) => {
+                    edges.push(Edge { target: target(&label_operand(d).unwrap())?, kind: EdgeKind::Goto });
+                }
+                Some(d) if matches!(d.opcode().format(), Format::F21t | Format::F22t) => {
+                    edges.push(Edge { target: target(&label_operand(d).unwrap())?, kind: EdgeKind::Branch });
+                    if let Some(t) = fallthrough { edges.push(Edge { target: t, kind: EdgeKind::Fallthrough }); }
+                }
+                _ => if let Some(t) = fallthrough { edges.push(Edge { target: t, kind: EdgeKind::Fallthrough }); }
+            }
+
+            // Blocks without instructions can't throw
+            let first = insns[start..end].iter().position(|i| !is_trivia(i)).map(|i| start + i);
+            if let Some(first) = first
+            {
+                for t in tries.iter().filter(|t| t.start <= first && first < t.end)
+                {
+                    if let Some(h) = block_of[t.handler] { edges.push(Edge { target: h, kind: EdgeKind::Catch(t.exception.clone()) }); }
+                }
+            }
+
+            let mut seen = BTreeSet::new();
+            edges.retain(|e| seen.insert((e.target, format!("{:?}", e.kind))));
+            block.successors = edges;
+        }
+
+        for b in 0..blocks.len()
+        {
+            let targets: BTreeSet<usize> = blocks[b].successors.iter().map(|e| e.target).collect();
+            for t in targets { blocks[t].predecessors.push(b); }
+        }
+        Ok(Cfg { method, blocks })
+    }
+
+    /// The method the graph was built from
+    pub fn method(&self) -> &'a SmaliMethod
+    {
+        self.method
+    }
+
+    /// The blocks of the method, the entry block is first and the exit block last
+    pub fn blocks(&self) -> &[BasicBlock]
+    {
+        &self.blocks
+    }
+
+    /// The index of the exit block
+    pub fn exit(&self) -> usize
+    {
+        self.blocks.len() - 1
+    }
+
+    /// The instructions of a block
+    pub fn instructions(&self, block: usize) -> &'a [SmaliInstruction]
+    {
+        let b = &self.blocks[block];
+        &self.method.instructions[b.start..b.end]
+    }
+
+    /// The block containing a label
+    pub fn block_of_label(&self, label: &str) -> Option<usize>
+    {
+        self.blocks.iter().position(|b| b.labels.iter().any(|l| l == label))
+    }
+
+    /// The blocks reachable from the entry block in reverse postorder
+    pub fn reverse_postorder(&self) -> Vec<usize>
+    {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = vec![];
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((b, next)) = stack.pop()
+        {
+            match self.blocks[b].successors.get(next) {
+                Some(e) => {
+                    stack.push((b, next + 1));
+                    if !visited[e.target] { visited[e.target] = true; stack.push((e.target, 0)); }
+                }
+                None => order.push(b)
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// Works out the dominator tree, see "A Simple, Fast Dominance Algorithm" by Cooper, Harvey and Kennedy
+    pub fn dominators(&self) -> DominatorTree
+    {
+        let rpo = self.reverse_postorder();
+        let mut rank = vec![usize::MAX; self.blocks.len()];
+        for (i, b) in rpo.iter().enumerate() { rank[*b] = i; }
+
+        let mut idom: Vec<Option<usize>> = vec![None; self.blocks.len()];
+        idom[0] = Some(0);
+        let mut changed = true;
+        while changed
+        {
+            changed = false;
+            for b in rpo.iter().skip(1)
+            {
+                let mut new_idom: Option<usize> = None;
+                for p in self.blocks[*b].predecessors.iter().filter(|p| idom[**p].is_some())
+                {
+                    new_idom = Some(match new_idom {
+                        None => *p,
+                        Some(mut a) => {
+                            let mut c = *p;
+                            while a != c
+                            {
+                                while rank[a] > rank[c] { a = idom[a].unwrap(); }
+                                while rank[c] > rank[a] { c = idom[c].unwrap(); }
+                            }
+                            a
+                        }
+                    });
+                }
+                if new_idom.is_some() && idom[*b] != new_idom { idom[*b] = new_idom; changed = true; }
+            }
+        }
+        idom[0] = None;
+        DominatorTree { idom }
+    }
+
+    /// The natural loops of the method, one per header sorted by header
+    pub fn loops(&self) -> Vec<Loop>
+    {
+        let dom = self.dominators();
+        let mut loops: HashMap<usize, Loop> = HashMap::new();
+        for (b, block) in self.blocks.iter().enumerate()
+        {
+            for h in block.successors.iter().map(|e| e.target).filter(|h| dom.dominates(*h, b))
+            {
+                let l = loops.entry(h).or_insert_with(|| Loop { header: h, blocks: vec![h], latches: vec![] });
+                if !l.latches.contains(&b) { l.latches.push(b); }
+
+                // Everything reaching the latch without going through the header
+                let mut stack = vec![b];
+                while let Some(x) = stack.pop()
+                {
+                    if l.blocks.contains(&x) { continue; }
+                    l.blocks.push(x);
+                    stack.extend(self.blocks[x].predecessors.iter().copied());
+                }
+            }
+        }
+        let mut loops: Vec<Loop> = loops.into_values().collect();
+        for l in loops.iter_mut() { l.blocks.sort(); l.latches.sort(); }
+        loops.sort_by_key(|l| l.header);
+        loops
+    }
+
+    /// Renders the graph in Graphviz DOT format
+    pub fn to_dot(&self) -> String
+    {
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut dot = format!("digraph \"{}{}\" {{\n    node [shape=box, fontname=\"monospace\"];\n",
+                              escape(&self.method.name), self.method.signature.to_jni());
+        for b in 0..self.blocks.len()
+        {
+            if b == self.exit() { let _ = writeln!(dot, "    b{} [label=\"exit\", shape=ellipse];", b); continue; }
+
+            let mut label = String::new();
+            for i in self.instructions(b)
+            {
+                match i {
+                    SmaliInstruction::Label(l) => { let _ = write!(label, ":{}\\l", escape(l)); }
+                    SmaliInstruction::Instruction(d) => { let _ = write!(label, "{}\\l", escape(&d.to_string())); }
+                    _ => {}
+                }
+            }
+            let _ = writeln!(dot, "    b{} [label=\"{}\"];", b, label);
+        }
+        for (b, block) in self.blocks.iter().enumerate()
+        {
+            for e in &block.successors
+            {
+                let attrs = match &e.kind {
+                    EdgeKind::Fallthrough | EdgeKind::Return => String::new(),
+                    EdgeKind::Goto => " [label=\"goto\"]".to_string(),
+                    EdgeKind::Branch => " [label=\"true\"]".to_string(),
+                    EdgeKind::Case(k) => format!(" [label=\"case {}\"]", k),
+                    EdgeKind::Catch(Some(t)) => format!(" [label=\"{}\", style=dashed]", escape(&t.as_jni_type())),
+                    EdgeKind::Catch(None) => " [label=\"catchall\", style=dashed]".to_string(),
+                    EdgeKind::Throw => " [label=\"throw\", style=dashed]".to_string()
+                };
+                let _ = writeln!(dot, "    b{} -> b{}{};", b, e.target, attrs);
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::cfg::{Cfg, EdgeKind};
+    use crate::find_smali_files;
+    use crate::types::SmaliClass;
+
+    #[test]
+    fn control_flow_graph() {
+        let smali = ".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\n\n\
+                     .method public static f(I)I\n    .locals 1\n\n\
+                     .line 1\n    const/4 v0, 0x0\n\n\
+                     :goto_0\n    if-lez p0, :cond_0\n\n\
+                     :try_start_0\n    div-int/2addr v0, p0\n    :try_end_0\n\
+                     .catch Ljava/lang/ArithmeticException; {:try_start_0 .. :try_end_0} :catch_0\n\n\
+                     packed-switch p0, :pswitch_data_0\n\n\
+                     add-int/lit8 p0, p0, -0x1\n    goto :goto_0\n\n\
+                     :pswitch_0\n    return v0\n\n\
+                     :catch_0\n    move-exception v0\n    throw v0\n\n\
+                     :cond_0\n    return v0\n\n\
+                     :pswitch_data_0\n    .packed-switch 0x5\n        :pswitch_0\n    .end packed-switch\n.end method\n";
+        let c = SmaliClass::from_smali(smali).unwrap();
+        let cfg = Cfg::new(&c.methods[0]).unwrap();
+        let blocks = cfg.blocks();
+        assert_eq!(blocks.len(), 9);
+        let goto = cfg.block_of_label("goto_0").unwrap();
+        let body = cfg.block_of_label("try_start_0").unwrap();
+        let catch = cfg.block_of_label("catch_0").unwrap();
+        let cond = cfg.block_of_label("cond_0").unwrap();
+        let case = cfg.block_of_label("pswitch_0").unwrap();
+        assert_eq!(cfg.block_of_label("pswitch_data_0"), None);
+
+        assert_eq!(blocks[0].successors[0].kind, EdgeKind::Fallthrough);
+        let kinds: Vec<_> = blocks[goto].successors.iter().map(|e| (e.target, e.kind.clone())).collect();
+        assert_eq!(kinds, vec![(cond, EdgeKind::Branch), (body, EdgeKind::Fallthrough)]);
+        assert!(blocks[body].successors.iter().any(|e| e.target == catch && matches!(e.kind, EdgeKind::Catch(Some(_)))));
+        let switch = blocks[body].successors.iter().find(|e| e.kind == EdgeKind::Fallthrough).unwrap().target;
+        assert!(blocks[switch].successors.iter().any(|e| e.target == case && e.kind == EdgeKind::Case(5)));
+        assert_eq!(blocks[catch].successors[0].target, cfg.exit());
+        assert_eq!(blocks[catch].successors[0].kind, EdgeKind::Throw);
+
+        let dom = cfg.dominators();
+        assert_eq!(dom.idom(goto), Some(0));
+        assert_eq!(dom.idom(catch), Some(body));
+        assert!(dom.dominates(goto, cfg.exit()));
+        assert!(!dom.dominates(body, cond));
+
+        let loops = cfg.loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].header, goto);
+        assert_eq!(loops[0].blocks.len(), 4);
+
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph \"f(I)I\" {\n"));
+        assert!(dot.contains("[label=\"case 5\"]"));
+
+        // Every method in the test classes has a graph
+        for c in find_smali_files(Path::new("tests")).unwrap()
+        {
+            for m in &c.methods { Cfg::new(m).unwrap().dominators(); }
+        }
+    }
+}
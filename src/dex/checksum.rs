@@ -0,0 +1,65 @@
+/* The checksums in a DEX header: an Adler-32 checksum and a SHA-1 signature */
+
+pub(crate) fn adler32(data: &[u8]) -> u32
+{
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+
+    // Sums can be deferred for 5552 bytes before they could overflow
+    for chunk in data.chunks(5552)
+    {
+        for byte in chunk
+        {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+pub(crate) fn sha1(data: &[u8]) -> [u8; 20]
+{
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 { message.push(0); }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64)
+    {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate()
+        {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80
+        {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, wi) in w.iter().enumerate()
+        {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6)
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (hi, v) in h.iter_mut().zip([a, b, c, d, e]) { *hi = hi.wrapping_add(v); }
+    }
+
+    let mut out = [0u8; 20];
+    for (i, v) in h.iter().enumerate() { out[i * 4..i * 4 + 4].copy_from_slice(&v.to_be_bytes()); }
+    out
+}
@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use crate::dex::DexFile;
+use crate::instructions::{ArrayData, DexInstruction, Format, Opcode, Operand, OperandKind, PackedSwitch, Payload, Register, RegisterRange, SparseSwitch};
+use crate::types::{ObjectIdentifier, RegisterCount, SmaliError, SmaliInstruction, SmaliMethod};
+
+/* An operand as encoded in the instruction, before indexes and branch targets are resolved */
+enum Raw {
+    Reg(u16),
+    Regs(Vec<u16>),
+    Range(u16, u16),
+    Lit(i64),
+    Target(u32),
+    Index(u32)
+}
+
+/* A payload with its targets still relative to the switch instruction that uses it */
+enum RawPayload {
+    Packed(i32, Vec<i32>),
+    Sparse(Vec<(i32, i32)>),
+    Array(ArrayData)
+}
+
+enum Item {
+    Instruction(Opcode, Vec<Raw>),
+    Payload(RawPayload)
+}
+
+struct Try {
+    start: u32,
+    end: u32,
+    handlers: Vec<(Option<ObjectIdentifier>, u32)>
+}
+
+/* Reads a code_item into the method's registers and instructions, returning the parameter names from the debug info */
+pub(crate) fn read_code(dex: &DexFile, offset: u32, method: &mut SmaliMethod) -> Result<Vec<Option<String>>, SmaliError>
+{
+    let mut r = dex.reader(offset);
+    let registers_size = r.u16()?;
+    let ins_size = r.u16()?;
+    let _outs_size = r.u16()?;
+    let tries_size = r.u16()?;
+    let debug_info_off = r.u32()?;
+    let insns_size = r.u32()?;
+    let mut insns = vec![];
+    for _ in 0..insns_size { insns.push(r.u16()?); }
+
+    // Tries and their handlers
+    let mut tries = vec![];
+    if tries_size > 0
+    {
+        if insns_size % 2 == 1 { r.u16()?; }
+        let mut raw = vec![];
+        for _ in 0..tries_size { raw.push((r.u32()?, r.u16()? as u32, r.u16()? as usize)); }
+        let handlers_start = r.pos;
+        for (start, count, handler_off) in raw
+        {
+            r.pos = handlers_start + handler_off;
+            let size = r.sleb128()?;
+            let mut handlers = vec![];
+            for _ in 0..size.unsigned_abs()
+            {
+                let t = dex.class_type(r.uleb128()?)?;
+                handlers.push((Some(t), r.uleb128()?));
+            }
+            if size <= 0 { handlers.push((None, r.uleb128()?)); }
+            let end = start.checked_add(count).ok_or_else(|| r.error("try block out of range"))?;
+            tries.push(Try { start, end, handlers });
+        }
+    }
+
+    let locals = registers_size.saturating_sub(ins_size);
+    method.registers = if ins_size as u32 == method.parameter_registers() { RegisterCount::Locals(locals as u32) }
+                       else { RegisterCount::Registers(registers_size as u32) };
+
+    // First pass, decode everything and collect the branch targets
+    let mut items: Vec<(u32, Item)> = vec![];
+    let mut addr = 0;
+    while addr < insns_size
+    {
+        let (item, size) = decode(&insns, addr).map_err(|m| SmaliError::InvalidDex { offset: offset as usize + 16 + addr as usize * 2, message: m })?;
+        items.push((addr, item));
+        addr += size;
+    }
+
+    let mut targets: Vec<(&str, u32)> = vec![];
+    let mut switches: HashMap<u32, u32> = HashMap::new();
+    for (addr, item) in &items
+    {
+        if let Item::Instruction(opcode, raw) = item
+        {
+            for r in raw
+            {
+                if let Raw::Target(t) = r
+                {
+                    targets.push((target_prefix(*opcode), *t));
+                    if matches!(opcode, Opcode::PackedSwitch | Opcode::SparseSwitch) { switches.insert(*t, *addr); }
+                }
+            }
+        }
+    }
+    for (addr, item) in &items
+    {
+        if let Item::Payload(p) = item
+        {
+            let base = *switches.get(addr).unwrap_or(addr) as i64;
+            match p {
+                RawPayload::Packed(_, t) => targets.extend(t.iter().map(|t| ("pswitch", (base + *t as i64) as u32))),
+                RawPayload::Sparse(e) => targets.extend(e.iter().map(|(_, t)| ("sswitch", (base + *t as i64) as u32))),
+                RawPayload::Array(_) => {}
+            }
+        }
+    }
+    for t in &tries
+    {
+        targets.push(("try_start", t.start));
+        targets.push(("try_end", t.end));
+        for (e, h) in &t.handlers { targets.push((if e.is_some() { "catch" } else { "catchall" }, *h)); }
+    }
+
+    // Labels are numbered in hex in address order for each prefix, the same as baksmali
+    let mut labels: HashMap<(&str, u32), String> = HashMap::new();
+    let mut by_prefix: HashMap<&str, Vec<u32>> = HashMap::new();
+    for (p, a) in &targets { by_prefix.entry(p).or_default().push(*a); }
+    for (p, mut addrs) in by_prefix
+    {
+        addrs.sort_unstable();
+        addrs.dedup();
+        for (i, a) in addrs.into_iter().enumerate() { labels.insert((p, a), format!("{}_{:x}", p, i)); }
+    }
+    let mut labels_at: HashMap<u32, Vec<String>> = HashMap::new();
+    for ((p, a), l) in &labels
+    {
+        if *p != "try_start" && *p != "try_end" { labels_at.entry(*a).or_default().push(l.clone()); }
+    }
+    for l in labels_at.values_mut() { l.sort(); }
+
+    let reg = |n: u16| if n >= locals { Register::P(n - locals) } else { Register::V(n) };
+    let (names, mut debug) = match debug_info_off {
+        0 => (vec![], HashMap::new()),
+        off => read_debug_info(dex, off, &reg)?
+    };
+
+    // Second pass, build the instructions with everything at each address in baksmali order
+    let label = |p: &str, a: u32| labels[&(p, a)].clone();
+    let mut out = vec![];
+    let emit_at = |addr: u32, out: &mut Vec<SmaliInstruction>, debug: &mut HashMap<u32, Vec<SmaliInstruction>>| {
+        if let Some(t) = tries.iter().find(|t| t.end == addr)
+        {
+            out.push(SmaliInstruction::Label(label("try_end", t.end)));
+        }
+        for t in tries.iter().filter(|t| t.end == addr)
+        {
+            for (e, h) in &t.handlers
+            {
+                out.push(SmaliInstruction::TryCatch {
+                    exception: e.clone(),
+                    start: label("try_start", t.start),
+                    end: label("try_end", t.end),
+                    handler: label(if e.is_some() { "catch" } else { "catchall" }, *h)
+                });
+            }
+        }
+        out.extend(debug.remove(&addr).into_iter().flatten());
+        for l in labels_at.get(&addr).into_iter().flatten() { out.push(SmaliInstruction::Label(l.clone())); }
+        if tries.iter().any(|t| t.start == addr)
+        {
+            out.push(SmaliInstruction::Label(label("try_start", addr)));
+        }
+    };
+
+    // Alignment nops before payloads are kept, as baksmali does
+    for (addr, item) in &items
+    {
+        emit_at(*addr, &mut out, &mut debug);
+        match item {
+            Item::Instruction(opcode, raw) => {
+                let i = build(dex, *opcode, raw, &reg, &|p, a| label(p, a))
+                    .map_err(|m| SmaliError::InvalidDex { offset: offset as usize + 16 + *addr as usize * 2, message: m })?;
+                out.push(SmaliInstruction::Instruction(i));
+            }
+            Item::Payload(p) => {
+                let base = *switches.get(addr).unwrap_or(addr) as i64;
+                let target = |p: &str, t: i32| label(p, (base + t as i64) as u32);
+                out.push(SmaliInstruction::Payload(match p {
+                    RawPayload::Packed(k, t) => Payload::PackedSwitch(PackedSwitch { first_key: *k, targets: t.iter().map(|t| target("pswitch", *t)).collect() }),
+                    RawPayload::Sparse(e) => Payload::SparseSwitch(SparseSwitch { entries: e.iter().map(|(k, t)| (*k, target("sswitch", *t))).collect() }),
+                    RawPayload::Array(a) => Payload::ArrayData(a.clone())
+                }));
+            }
+        }
+    }
+    emit_at(insns_size, &mut out, &mut debug);
+    let mut rest: Vec<u32> = debug.keys().copied().collect();
+    rest.sort_unstable();
+    for a in rest { out.extend(debug.remove(&a).into_iter().flatten()); }
+
+    method.instructions = out;
+    Ok(names)
+}
+
+fn target_prefix(opcode: Opcode) -> &'static str
+{
+    match opcode {
+        Opcode::Goto | Opcode::Goto16 | Opcode::Goto32 => "goto",
+        Opcode::PackedSwitch => "pswitch_data",
+        Opcode::SparseSwitch => "sswitch_data",
+        Opcode::FillArrayData => "array",
+        _ => "cond"
+    }
+}
+
+/* Decodes the instruction or payload at addr, returning it with its size in code units */
+fn decode(insns: &[u16], addr: u32) -> Result<(Item, u32), String>
+{
+    let a = addr as usize;
+    let unit = |i: usize| insns.get(a + i).copied().ok_or_else(|| "instruction runs past the end of the code".to_string());
+    let u0 = unit(0)?;
+    let op = (u0 & 0xff) as u8;
+
+    // Payloads are marked by a nop with a non-zero high byte
+    if op == 0 && u0 != 0
+    {
+        let int = |i: usize| -> Result<i32, String> { Ok((unit(i)? as u32 | (unit(i + 1)? as u32) << 16) as i32) };
+        return match u0 {
+            0x0100 => {
+                let size = unit(1)? as usize;
+                let targets = (0..size).map(|i| int(4 + i * 2)).collect::<Result<Vec<i32>, String>>()?;
+                Ok((Item::Payload(RawPayload::Packed(int(2)?, targets)), 4 + size as u32 * 2))
+            }
+            0x0200 => {
+                let size = unit(1)? as usize;
+                let entries = (0..size).map(|i| Ok((int(2 + i * 2)?, int(2 + size * 2 + i * 2)?))).collect::<Result<Vec<(i32, i32)>, String>>()?;
+                Ok((Item::Payload(RawPayload::Sparse(entries)), 2 + size as u32 * 4))
+            }
+            0x0300 => {
+                let width = unit(1)? as usize;
+                let size = int(2)? as u32 as usize;
+                if ![1, 2, 4, 8].contains(&width) { return Err(format!("invalid array-data element width {}", width)); }
+                let units = (size * width).div_ceil(2);
+                if 4 + units > insns.len() - a { return Err("array-data runs past the end of the code".to_string()); }
+                let mut bytes = Vec::with_capacity(units * 2);
+                for i in 0..units { bytes.extend_from_slice(&unit(4 + i)?.to_le_bytes()); }
+                let values = bytes.chunks(width).take(size).map(|c| {
+                    let v = c.iter().rev().fold(0u64, |v, b| (v << 8) | *b as u64);
+                    let shift = 64 - width * 8;
+                    ((v << shift) as i64) >> shift
+                }).collect();
+                Ok((Item::Payload(RawPayload::Array(ArrayData { element_width: width as u32, values })), 4 + units as u32))
+            }
+            _ => Err(format!("unknown payload 0x{:04x}", u0))
+        };
+    }
+
+    let opcode = Opcode::from_code(op).ok_or_else(|| format!("unknown opcode 0x{:02x}", op))?;
+    let aa = u0 >> 8;
+    let (a4, b4) = ((u0 >> 8) & 0xf, u0 >> 12);
+    let target = |off: i64| -> Result<Raw, String> {
+        let t = addr as i64 + off;
+        if t < 0 || t > insns.len() as i64 { return Err(format!("branch target {} out of range", t)); }
+        Ok(Raw::Target(t as u32))
+    };
+    let wide = |i: usize| -> Result<u32, String> { Ok(unit(i)? as u32 | (unit(i + 1)? as u32) << 16) };
+    let list = |count: u16, u2: u16| -> Result<Vec<u16>, String> {
+        if count > 5 { return Err(format!("invalid register count {}", count)); }
+        Ok([u2 & 0xf, (u2 >> 4) & 0xf, (u2 >> 8) & 0xf, u2 >> 12, (u0 >> 8) & 0xf][..count as usize].to_vec())
+    };
+
+    let raw = match opcode.format() {
+        Format::F10x => vec![],
+        Format::F12x => vec![Raw::Reg(a4), Raw::Reg(b4)],
+        Format::F11n => vec![Raw::Reg(a4), Raw::Lit((((b4 as i8) << 4) >> 4) as i64)],
+        Format::F11x => vec![Raw::Reg(aa)],
+        Format::F10t => vec![target(aa as u8 as i8 as i64)?],
+        Format::F20t => vec![target(unit(1)? as i16 as i64)?],
+        Format::F22x => vec![Raw::Reg(aa), Raw::Reg(unit(1)?)],
+        Format::F21t => vec![Raw::Reg(aa), target(unit(1)? as i16 as i64)?],
+        Format::F21s => vec![Raw::Reg(aa), Raw::Lit(unit(1)? as i16 as i64)],
+        Format::F21h => {
+            let v = unit(1)? as i16 as i64;
+            vec![Raw::Reg(aa), Raw::Lit(if opcode == Opcode::ConstHigh16 { v << 16 } else { v << 48 })]
+        }
+        Format::F21c => vec![Raw::Reg(aa), Raw::Index(unit(1)? as u32)],
+        Format::F23x => vec![Raw::Reg(aa), Raw::Reg(unit(1)? & 0xff), Raw::Reg(unit(1)? >> 8)],
+        Format::F22b => vec![Raw::Reg(aa), Raw::Reg(unit(1)? & 0xff), Raw::Lit((unit(1)? >> 8) as u8 as i8 as i64)],
+        Format::F22t => vec![Raw::Reg(a4), Raw::Reg(b4), target(unit(1)? as i16 as i64)?],
+        Format::F22s => vec![Raw::Reg(a4), Raw::Reg(b4), Raw::Lit(unit(1)? as i16 as i64)],
+        Format::F22c => vec![Raw::Reg(a4), Raw::Reg(b4), Raw::Index(unit(1)? as u32)],
+        Format::F30t => vec![target(wide(1)? as i32 as i64)?],
+        Format::F32x => vec![Raw::Reg(unit(1)?), Raw::Reg(unit(2)?)],
+        Format::F31i => vec![Raw::Reg(aa), Raw::Lit(wide(1)? as i32 as i64)],
+        Format::F31t => vec![Raw::Reg(aa), target(wide(1)? as i32 as i64)?],
+        Format::F31c => vec![Raw::Reg(aa), Raw::Index(wide(1)?)],
+        Format::F35c => vec![Raw::Regs(list(b4, unit(2)?)?), Raw::Index(unit(1)? as u32)],
+        Format::F3rc => vec![Raw::Range(unit(2)?, aa), Raw::Index(unit(1)? as u32)],
+        Format::F45cc => vec![Raw::Regs(list(b4, unit(2)?)?), Raw::Index(unit(1)? as u32), Raw::Index(unit(3)? as u32)],
+        Format::F4rcc => vec![Raw::Range(unit(2)?, aa), Raw::Index(unit(1)? as u32), Raw::Index(unit(3)? as u32)],
+        Format::F51l => vec![Raw::Reg(aa), Raw::Lit((wide(1)? as u64 | (wide(3)? as u64) << 32) as i64)]
+    };
+    Ok((Item::Instruction(opcode, raw), opcode.format().size() as u32))
+}
+
+/* Resolves the raw operands against the DEX pools and builds the typed instruction */
+fn build(dex: &DexFile, opcode: Opcode, raw: &[Raw], reg: &dyn Fn(u16) -> Register, label: &dyn Fn(&str, u32) -> String) -> Result<DexInstruction, String>
+{
+    let mut operands = vec![];
+    for (kind, r) in opcode.operand_kinds().into_iter().zip(raw)
+    {
+        let o = match (r, kind) {
+            (Raw::Reg(n), _) => Operand::Register(reg(*n)),
+            (Raw::Regs(l), _) => Operand::RegisterList(l.iter().map(|n| reg(*n)).collect()),
+            (Raw::Range(first, count), _) => {
+                if *count == 0 { return Err("empty register range".to_string()); }
+                let start = reg(*first);
+                let last = first.checked_add(count - 1).ok_or("register range out of range")?;
+                let end = match start { Register::P(_) => reg(last), Register::V(_) => Register::V(last) };
+                Operand::RegisterRange(RegisterRange { start, end })
+            }
+            (Raw::Lit(l), _) => Operand::Literal(*l),
+            (Raw::Target(t), _) => Operand::Label(label(target_prefix(opcode), *t)),
+            (Raw::Index(i), kind) => {
+                let resolved = match kind {
+                    OperandKind::String => dex.string(*i).map(|s| Operand::String(s.to_string())),
+                    OperandKind::Type => dex.type_signature(*i).map(Operand::Type),
+                    OperandKind::Field => dex.field(*i).map(Operand::Field),
+                    OperandKind::Method => dex.method(*i).map(Operand::Method),
+                    OperandKind::Proto => dex.proto(*i).map(Operand::Proto),
+                    OperandKind::CallSite => dex.call_site(*i).map(Operand::CallSite),
+                    OperandKind::MethodHandle => dex.method_handle(*i).map(Operand::MethodHandle),
+                    _ => return Err(format!("unexpected index operand for {}", opcode.mnemonic()))
+                };
+                resolved.map_err(|e| e.to_string())?
+            }
+        };
+        operands.push(o);
+    }
+    DexInstruction::from_operands(opcode, operands).ok_or_else(|| format!("invalid operands for {}", opcode.mnemonic()))
+}
+
+/* Runs the debug info state machine, returning the parameter names and the debug directives at each address */
+#[allow(clippy::type_complexity)]
+fn read_debug_info(dex: &DexFile, offset: u32, reg: &dyn Fn(u16) -> Register) -> Result<(Vec<Option<String>>, HashMap<u32, Vec<SmaliInstruction>>), SmaliError>
+{
+    let mut r = dex.reader(offset);
+    let mut line = r.uleb128()? as i64;
+    let mut names = vec![];
+    for _ in 0..r.uleb128()?
+    {
+        names.push(match r.uleb128p1()? { Some(i) => Some(dex.string(i)?.to_string()), None => None });
+    }
+
+    let string = |i: Option<u32>| -> Result<Option<String>, SmaliError> { i.map(|i| dex.string(i).map(|s| s.to_string())).transpose() };
+    let mut events: HashMap<u32, Vec<SmaliInstruction>> = HashMap::new();
+    let mut addr: u32 = 0;
+    loop
+    {
+        let op = r.u8()?;
+        let event = match op {
+            0x00 => break,
+            0x01 => {
+                let delta = r.uleb128()?;
+                addr = addr.checked_add(delta).ok_or_else(|| r.error("debug info address out of range"))?;
+                None
+            }
+            0x02 => { line += r.sleb128()? as i64; None }
+            0x03 | 0x04 => {
+                let register = reg(r.uleb128()? as u16);
+                let name = string(r.uleb128p1()?)?;
+                let signature = match r.uleb128p1()? { Some(t) => Some(dex.type_signature(t)?), None => None };
+                let generic = if op == 0x04 { string(r.uleb128p1()?)? } else { None };
+                Some(SmaliInstruction::Local { register, name, signature, generic })
+            }
+            0x05 => Some(SmaliInstruction::EndLocal(reg(r.uleb128()? as u16))),
+            0x06 => Some(SmaliInstruction::RestartLocal(reg(r.uleb128()? as u16))),
+            0x07 => Some(SmaliInstruction::Prologue),
+            0x08 => Some(SmaliInstruction::Epilogue),
+            0x09 => string(r.uleb128p1()?)?.map(|f| SmaliInstruction::Directive(format!(".source \"{}\"", crate::smali_write::escape_string(&f)))),
+            _ => {
+                let adjusted = (op - 0x0a) as i64;
+                line += -4 + adjusted % 15;
+                addr = addr.checked_add((adjusted / 15) as u32).ok_or_else(|| r.error("debug info address out of range"))?;
+                Some(SmaliInstruction::Line(line as u32))
+            }
+        };
+        if let Some(e) = event { events.entry(addr).or_default().push(e); }
+    }
+
+    // baksmali writes .prologue and .epilogue first, then .source, .line and the locals
+    let rank = |e: &SmaliInstruction| match e {
+        SmaliInstruction::Prologue | SmaliInstruction::Epilogue => 0,
+        SmaliInstruction::Directive(_) => 1,
+        SmaliInstruction::Line(_) => 2,
+        _ => 3
+    };
+    for e in events.values_mut() { e.sort_by_key(rank); }
+    Ok((names, events))
+}
@@ -0,0 +1,774 @@
+//! Reading and writing DEX files
+//!
+//! [`DexFile`] parses a `classes.dex` directly into [`SmaliClass`] values, the same structures
+//! produced by parsing smali, without needing baksmali or apktool. Method bodies are disassembled
+//! into typed instructions with baksmali style labels, and debug information, annotations and
+//! static field values are all kept.
+//!
+//! Going the other way, [`write_dex`] assembles a set of classes back into a DEX file, replacing smali.jar, and
+//! [`MultidexPlanner`] splits classes that don't fit in one file over `classes.dex`, `classes2.dex`, ...
+//!
+//! # Examples
+//!
+//! ```no_run
+//!  use std::path::Path;
+//!  use smali::dex::DexFile;
+//!
+//!  let dex = DexFile::read_from_file(Path::new("classes.dex"))?;
+//!  for c in dex.classes()?
+//!  {
+//!      println!("{}", c.name.as_java_type());
+//!  }
+//! # Ok::<(), smali::types::SmaliError>(())
+//! ```
+
+mod checksum;
+mod code;
+mod multidex;
+mod reader;
+mod values;
+mod writer;
+
+pub use multidex::{ClassPredicate, MultidexPlanner};
+
+use std::fs;
+use std::path::Path;
+use crate::instructions::Register;
+use crate::types::{FieldRef, MethodHandle, MethodRef, MethodSignature, Modifier, ModifierTarget, ObjectIdentifier,
+                   RegisterCount, SmaliAnnotation, SmaliClass, SmaliError, SmaliField, SmaliMethod, SmaliParameter,
+                   TypeSignature};
+use reader::{decode_mutf8, ByteReader};
+use values::{read_annotation_set, read_array};
+
+const NO_INDEX: u32 = 0xffff_ffff;
+const ENDIAN_CONSTANT: u32 = 0x1234_5678;
+const TYPE_CALL_SITE_ID_ITEM: u16 = 0x0007;
+const TYPE_METHOD_HANDLE_ITEM: u16 = 0x0008;
+
+/// A parsed DEX file with its constant pools decoded
+///
+/// Classes are only disassembled when asked for with [`DexFile::classes`] or [`DexFile::class`].
+///
+pub struct DexFile {
+    data: Vec<u8>,
+    version: u32,
+    strings: Vec<String>,
+    types: Vec<String>,
+    protos: Vec<MethodSignature>,
+    fields: Vec<FieldRef>,
+    methods: Vec<MethodRef>,
+    method_handles: Vec<MethodHandle>,
+    call_site_offsets: Vec<u32>,
+    class_defs_off: u32,
+    class_defs_size: u32
+}
+
+impl DexFile {
+    /// Parses a DEX file from its bytes
+    pub fn from_bytes(data: Vec<u8>) -> Result<DexFile, SmaliError>
+    {
+        let mut r = ByteReader::new(&data, 0);
+        let magic = r.bytes(8)?;
+        if &magic[0..4] != b"dex\n" || magic[7] != 0
+        {
+            return Err(SmaliError::InvalidDex { offset: 0, message: "bad magic".to_string() });
+        }
+        let version = std::str::from_utf8(&magic[4..7]).ok().and_then(|v| v.parse::<u32>().ok())
+            .ok_or_else(|| SmaliError::InvalidDex { offset: 4, message: "bad version".to_string() })?;
+
+        r.pos = 0x28;
+        if r.u32()? != ENDIAN_CONSTANT
+        {
+            return Err(SmaliError::Unsupported("big endian DEX files".to_string()));
+        }
+        r.pos = 0x34;
+        let map_off = r.u32()?;
+        let mut section = || -> Result<(u32, u32), SmaliError> { Ok((r.u32()?, r.u32()?)) };
+        let string_ids = section()?;
+        let type_ids = section()?;
+        let proto_ids = section()?;
+        let field_ids = section()?;
+        let method_ids = section()?;
+        let class_defs = section()?;
+
+        let mut dex = DexFile {
+            version,
+            strings: vec![],
+            types: vec![],
+            protos: vec![],
+            fields: vec![],
+            methods: vec![],
+            method_handles: vec![],
+            call_site_offsets: vec![],
+            class_defs_off: class_defs.1,
+            class_defs_size: class_defs.0,
+            data: vec![]
+        };
+
+        // The pools refer to each other, so they're decoded in order
+        let mut r = ByteReader::new(&data, string_ids.1 as usize);
+        for _ in 0..string_ids.0
+        {
+            let mut s = ByteReader::new(&data, r.u32()? as usize);
+            s.uleb128()?;
+            let start = s.pos;
+            let end = data[start..].iter().position(|b| *b == 0).map(|p| start + p).ok_or_else(|| s.error("unterminated string"))?;
+            dex.strings.push(decode_mutf8(&data[start..end]).map_err(|_| s.error("invalid MUTF-8 string"))?);
+        }
+
+        let mut r = ByteReader::new(&data, type_ids.1 as usize);
+        for _ in 0..type_ids.0
+        {
+            let s = dex.string(r.u32()?)?.to_string();
+            if !valid_descriptor(&s) { return Err(r.error(&format!("invalid type descriptor {}", s))); }
+            dex.types.push(s);
+        }
+
+        let mut r = ByteReader::new(&data, proto_ids.1 as usize);
+        for _ in 0..proto_ids.0
+        {
+            let _shorty = r.u32()?;
+            let return_type = dex.type_signature(r.u32()?)?;
+            let args = dex.type_list(&data, r.u32()?)?.iter().map(|t| TypeSignature::from_jni(t)).collect();
+            dex.protos.push(MethodSignature { args, return_type });
+        }
+
+        let mut r = ByteReader::new(&data, field_ids.1 as usize);
+        for _ in 0..field_ids.0
+        {
+            let class = dex.class_type(r.u16()? as u32)?;
+            let signature = dex.type_signature(r.u16()? as u32)?;
+            let name = dex.string(r.u32()?)?.to_string();
+            dex.fields.push(FieldRef { class, name, signature });
+        }
+
+        let mut r = ByteReader::new(&data, method_ids.1 as usize);
+        for _ in 0..method_ids.0
+        {
+            let class = dex.type_signature(r.u16()? as u32)?;
+            let signature = dex.proto(r.u16()? as u32)?;
+            let name = dex.string(r.u32()?)?.to_string();
+            dex.methods.push(MethodRef { class, name, signature });
+        }
+
+        // Method handles and call sites are only listed in the map
+        if map_off != 0
+        {
+            let mut r = ByteReader::new(&data, map_off as usize);
+            for _ in 0..r.u32()?
+            {
+                let item_type = r.u16()?;
+                r.u16()?;
+                let size = r.u32()?;
+                let offset = r.u32()?;
+                match item_type {
+                    TYPE_CALL_SITE_ID_ITEM => {
+                        let mut c = ByteReader::new(&data, offset as usize);
+                        for _ in 0..size { dex.call_site_offsets.push(c.u32()?); }
+                    }
+                    TYPE_METHOD_HANDLE_ITEM => {
+                        let mut m = ByteReader::new(&data, offset as usize);
+                        for _ in 0..size
+                        {
+                            let kind = m.u16()?;
+                            m.u16()?;
+                            let id = m.u16()? as u32;
+                            m.u16()?;
+                            let handle = match kind {
+                                0x00 => MethodHandle::StaticPut(dex.field(id)?),
+                                0x01 => MethodHandle::StaticGet(dex.field(id)?),
+                                0x02 => MethodHandle::InstancePut(dex.field(id)?),
+                                0x03 => MethodHandle::InstanceGet(dex.field(id)?),
+                                0x04 => MethodHandle::InvokeStatic(dex.method(id)?),
+                                0x05 => MethodHandle::InvokeInstance(dex.method(id)?),
+                                0x06 => MethodHandle::InvokeConstructor(dex.method(id)?),
+                                0x07 => MethodHandle::InvokeDirect(dex.method(id)?),
+                                0x08 => MethodHandle::InvokeInterface(dex.method(id)?),
+                                _ => return Err(m.error("unknown method handle type"))
+                            };
+                            dex.method_handles.push(handle);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        dex.data = data;
+        Ok(dex)
+    }
+
+    /// Reads and parses a DEX file
+    pub fn read_from_file(path: &Path) -> Result<DexFile, SmaliError>
+    {
+        let data = fs::read(path).map_err(|e| SmaliError::Io { path: Some(path.to_path_buf()), error: e })?;
+        DexFile::from_bytes(data)
+    }
+
+    /// The DEX format version from the header e.g. 35
+    pub fn version(&self) -> u32
+    {
+        self.version
+    }
+
+    /// Number of classes defined in the file
+    pub fn class_count(&self) -> usize
+    {
+        self.class_defs_size as usize
+    }
+
+    /// Disassembles every class in the file
+    pub fn classes(&self) -> Result<Vec<SmaliClass>, SmaliError>
+    {
+        (0..self.class_count()).map(|i| self.class(i)).collect()
+    }
+
+    /// Disassembles the class with the given class_def index
+    pub fn class(&self, index: usize) -> Result<SmaliClass, SmaliError>
+    {
+        if index >= self.class_count()
+        {
+            return Err(SmaliError::InvalidDex { offset: self.class_defs_off as usize, message: format!("no class_def {}", index) });
+        }
+        let offset = u32::try_from(index).ok().and_then(|i| i.checked_mul(32)).and_then(|o| o.checked_add(self.class_defs_off))
+            .ok_or_else(|| SmaliError::InvalidDex { offset: self.class_defs_off as usize, message: format!("class_def {} out of range", index) })?;
+        let mut r = self.reader(offset);
+        let name = self.class_type(r.u32()?)?;
+        let access_flags = r.u32()?;
+        let super_idx = r.u32()?;
+        let interfaces_off = r.u32()?;
+        let source_idx = r.u32()?;
+        let annotations_off = r.u32()?;
+        let class_data_off = r.u32()?;
+        let static_values_off = r.u32()?;
+
+        let mut class = SmaliClass {
+            name,
+            modifiers: Modifier::from_access_flags(access_flags, ModifierTarget::Class),
+            source: if source_idx == NO_INDEX { None } else { Some(self.string(source_idx)?.to_string()) },
+            super_class: if super_idx == NO_INDEX { ObjectIdentifier::from_java_type("java.lang.Object") }
+                         else { self.class_type(super_idx)? },
+            implements: self.type_list(&self.data, interfaces_off)?.iter().map(|t| class_identifier(t)).collect::<Result<Vec<ObjectIdentifier>, SmaliError>>()?,
+            annotations: vec![],
+            fields: vec![],
+            methods: vec![],
+            unparsed: vec![],
+            layout: None,
+            file_path: None
+        };
+
+        // Method and field indexes with their annotation offsets
+        let mut field_annotations = vec![];
+        let mut method_annotations = vec![];
+        let mut parameter_annotations = vec![];
+        if annotations_off != 0
+        {
+            let mut a = self.reader(annotations_off);
+            let class_annotations_off = a.u32()?;
+            let fields_size = a.u32()?;
+            let methods_size = a.u32()?;
+            let parameters_size = a.u32()?;
+            for _ in 0..fields_size { field_annotations.push((a.u32()?, a.u32()?)); }
+            for _ in 0..methods_size { method_annotations.push((a.u32()?, a.u32()?)); }
+            for _ in 0..parameters_size { parameter_annotations.push((a.u32()?, a.u32()?)); }
+            class.annotations = read_annotation_set(self, class_annotations_off)?;
+        }
+        let find = |list: &Vec<(u32, u32)>, idx: u32| list.iter().find(|(i, _)| *i == idx).map(|(_, off)| *off);
+
+        if class_data_off != 0
+        {
+            let mut d = self.reader(class_data_off);
+            let static_fields = d.uleb128()?;
+            let instance_fields = d.uleb128()?;
+            let direct_methods = d.uleb128()?;
+            let virtual_methods = d.uleb128()?;
+
+            let static_values = if static_values_off != 0 { read_array(self, &mut self.reader(static_values_off))? } else { vec![] };
+
+            let mut idx = 0;
+            for n in 0..static_fields + instance_fields
+            {
+                if n == static_fields { idx = 0; }
+                idx += d.uleb128()?;
+                let flags = d.uleb128()?;
+                let f = self.field(idx)?;
+                class.fields.push(SmaliField {
+                    name: f.name,
+                    modifiers: Modifier::from_access_flags(flags, ModifierTarget::Field),
+                    signature: f.signature,
+                    initial_value: if n < static_fields { static_values.get(n as usize).cloned() } else { None },
+                    annotations: match find(&field_annotations, idx) { Some(off) => read_annotation_set(self, off)?, None => vec![] }
+                });
+            }
+
+            for n in 0..direct_methods + virtual_methods
+            {
+                if n == 0 || n == direct_methods { idx = 0; }
+                idx += d.uleb128()?;
+                let flags = d.uleb128()?;
+                let code_off = d.uleb128()?;
+                let m = self.method(idx)?;
+                let mut modifiers = Modifier::from_access_flags(flags, ModifierTarget::Method);
+                let constructor = modifiers.contains(&Modifier::Constructor);
+                modifiers.retain(|m| *m != Modifier::Constructor);
+
+                let mut method = SmaliMethod {
+                    name: m.name,
+                    modifiers,
+                    constructor,
+                    signature: m.signature,
+                    registers: RegisterCount::Locals(0),
+                    params: vec![],
+                    annotations: match find(&method_annotations, idx) { Some(off) => read_annotation_set(self, off)?, None => vec![] },
+                    instructions: vec![]
+                };
+                let names = if code_off != 0 { code::read_code(self, code_off, &mut method)? } else { vec![] };
+                let annotations = match find(&parameter_annotations, idx) { Some(off) => self.parameter_annotations(off)?, None => vec![] };
+                self.add_params(&mut method, names, annotations);
+                class.methods.push(method);
+            }
+        }
+
+        Ok(class)
+    }
+
+    /* Combines parameter names from the debug info with parameter annotations into .param entries */
+    fn add_params(&self, method: &mut SmaliMethod, names: Vec<Option<String>>, mut annotations: Vec<Vec<SmaliAnnotation>>)
+    {
+        let mut register = if method.is_static() { 0 } else { 1 };
+        for (i, a) in method.signature.args.iter().enumerate()
+        {
+            let name = names.get(i).cloned().flatten();
+            let annotations = if i < annotations.len() { std::mem::take(&mut annotations[i]) } else { vec![] };
+            if name.is_some() || !annotations.is_empty()
+            {
+                method.params.push(SmaliParameter { register: Register::P(register), name, annotations });
+            }
+            register += if a.is_wide() { 2 } else { 1 };
+        }
+    }
+
+    /* An annotation_set_ref_list, one annotation set per parameter */
+    fn parameter_annotations(&self, offset: u32) -> Result<Vec<Vec<SmaliAnnotation>>, SmaliError>
+    {
+        let mut r = self.reader(offset);
+        let size = r.u32()?;
+        let mut sets = vec![];
+        for _ in 0..size { sets.push(read_annotation_set(self, r.u32()?)?); }
+        Ok(sets)
+    }
+
+    fn type_list(&self, data: &[u8], offset: u32) -> Result<Vec<String>, SmaliError>
+    {
+        if offset == 0 { return Ok(vec![]); }
+        let mut r = ByteReader::new(data, offset as usize);
+        let size = r.u32()?;
+        let mut types = vec![];
+        for _ in 0..size { types.push(self.type_descriptor(r.u16()? as u32)?.to_string()); }
+        Ok(types)
+    }
+
+    pub(crate) fn reader(&self, offset: u32) -> ByteReader<'_>
+    {
+        ByteReader::new(&self.data, offset as usize)
+    }
+
+    fn index_error(&self, pool: &str, idx: u32) -> SmaliError
+    {
+        SmaliError::InvalidDex { offset: 0, message: format!("{} index {} out of range", pool, idx) }
+    }
+
+    pub(crate) fn string(&self, idx: u32) -> Result<&str, SmaliError>
+    {
+        self.strings.get(idx as usize).map(|s| s.as_str()).ok_or_else(|| self.index_error("string", idx))
+    }
+
+    pub(crate) fn type_descriptor(&self, idx: u32) -> Result<&str, SmaliError>
+    {
+        self.types.get(idx as usize).map(|s| s.as_str()).ok_or_else(|| self.index_error("type", idx))
+    }
+
+    pub(crate) fn type_signature(&self, idx: u32) -> Result<TypeSignature, SmaliError>
+    {
+        self.type_descriptor(idx).map(TypeSignature::from_jni)
+    }
+
+    /* A type that has to be a class, such as a field's class or a caught exception */
+    pub(crate) fn class_type(&self, idx: u32) -> Result<ObjectIdentifier, SmaliError>
+    {
+        class_identifier(self.type_descriptor(idx)?)
+    }
+
+    pub(crate) fn proto(&self, idx: u32) -> Result<MethodSignature, SmaliError>
+    {
+        self.protos.get(idx as usize).cloned().ok_or_else(|| self.index_error("proto", idx))
+    }
+
+    pub(crate) fn field(&self, idx: u32) -> Result<FieldRef, SmaliError>
+    {
+        self.fields.get(idx as usize).cloned().ok_or_else(|| self.index_error("field", idx))
+    }
+
+    pub(crate) fn method(&self, idx: u32) -> Result<MethodRef, SmaliError>
+    {
+        self.methods.get(idx as usize).cloned().ok_or_else(|| self.index_error("method", idx))
+    }
+
+    pub(crate) fn method_handle(&self, idx: u32) -> Result<MethodHandle, SmaliError>
+    {
+        self.method_handles.get(idx as usize).cloned().ok_or_else(|| self.index_error("method handle", idx))
+    }
+
+    /* Call sites are written as name("method", proto, extra args...)@bootstrap method */
+    pub(crate) fn call_site(&self, idx: u32) -> Result<String, SmaliError>
+    {
+        let offset = *self.call_site_offsets.get(idx as usize).ok_or_else(|| self.index_error("call site", idx))?;
+        let values = read_array(self, &mut self.reader(offset))?;
+        if values.len() < 3 { return Err(SmaliError::InvalidDex { offset: offset as usize, message: "call site too short".to_string() }); }
+
+        let bootstrap = values[0].to_string();
+        let bootstrap = bootstrap.split_once('@').map_or(bootstrap.as_str(), |(_, m)| m);
+        let args: Vec<String> = values[1..].iter().map(|v| v.to_string()).collect();
+        Ok(format!("call_site_{}({})@{}", idx, args.join(", "), bootstrap))
+    }
+}
+
+/* Whether a type descriptor is well formed, every type is checked when the file is read so they can be turned into
+   a TypeSignature without panicking */
+fn valid_descriptor(d: &str) -> bool
+{
+    let element = d.trim_start_matches('[');
+    match element.as_bytes() {
+        [b'Z' | b'B' | b'C' | b'S' | b'I' | b'J' | b'F' | b'D'] => true,
+        [b'V'] => element.len() == d.len(),
+        [b'L', .., b';'] => element.len() > 2,
+        _ => false
+    }
+}
+
+fn class_identifier(descriptor: &str) -> Result<ObjectIdentifier, SmaliError>
+{
+    if descriptor.starts_with('L') { Ok(ObjectIdentifier::from_jni_type(descriptor)) }
+    else { Err(SmaliError::InvalidDex { offset: 0, message: format!("{} is not a class", descriptor) }) }
+}
+
+/// Reads every class from a DEX file
+///
+/// # Examples
+///
+/// ```no_run
+///  use std::path::Path;
+///  use smali::dex::read_dex_file;
+///
+///  let classes = read_dex_file(Path::new("classes.dex"))?;
+///  println!("{:} classes loaded.", classes.len());
+/// # Ok::<(), smali::types::SmaliError>(())
+/// ```
+pub fn read_dex_file(path: &Path) -> Result<Vec<SmaliClass>, SmaliError>
+{
+    DexFile::read_from_file(path)?.classes()
+}
+
+/// The DEX format versions that can be written
+///
+/// Version 038 added `invoke-polymorphic`, `invoke-custom`, method handles and call sites, and 039 added
+/// `const-method-handle` and `const-method-type`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DexVersion {
+    V035,
+    V037,
+    V038,
+    V039
+}
+
+impl DexVersion {
+    /// The version number as written in the header e.g. 35
+    pub fn number(&self) -> u32
+    {
+        match self {
+            DexVersion::V035 => 35,
+            DexVersion::V037 => 37,
+            DexVersion::V038 => 38,
+            DexVersion::V039 => 39
+        }
+    }
+}
+
+/// Assembles classes into a DEX file
+///
+/// The classes can come from smali or from another DEX file. Every string, type, proto, field and method they
+/// reference is pooled, and the checksum and signature are computed, so the result can be loaded as is.
+///
+/// # Examples
+///
+/// ```
+///  use smali::dex::{write_dex, DexFile, DexVersion};
+///  use smali::types::SmaliClass;
+///
+///  let c = SmaliClass::from_smali(".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n.method public static f()I\n    .locals 1\n    const/4 v0, 0x1\n    return v0\n.end method\n")?;
+///  let bytes = write_dex(&[c], DexVersion::V035)?;
+///
+///  let dex = DexFile::from_bytes(bytes)?;
+///  assert_eq!(dex.class(0)?.methods[0].name, "f");
+/// # Ok::<(), smali::types::SmaliError>(())
+/// ```
+pub fn write_dex(classes: &[SmaliClass], version: DexVersion) -> Result<Vec<u8>, SmaliError>
+{
+    writer::write_dex(&classes.iter().collect::<Vec<&SmaliClass>>(), version)
+}
+
+/// Assembles classes and writes them to a DEX file, see [`write_dex`]
+pub fn write_dex_file(path: &Path, classes: &[SmaliClass], version: DexVersion) -> Result<(), SmaliError>
+{
+    let bytes = write_dex(classes, version)?;
+    fs::write(path, bytes).map_err(|e| SmaliError::Io { path: Some(path.to_path_buf()), error: e })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::dex::checksum::{adler32, sha1};
+    use crate::dex::{write_dex, DexFile, DexVersion, MultidexPlanner};
+    use crate::instructions::DexInstruction::{Const4, Return};
+    use crate::instructions::Register::V;
+    use crate::types::{AnnotationVisibility, EncodedValue, Modifier, ObjectIdentifier, SmaliClass, SmaliInstruction, TypeSignature};
+
+    // A hand assembled DEX with one class: public class Test { public static int f() { return 1; } }
+    fn minimal_dex() -> Vec<u8>
+    {
+        let mut d = vec![0u8; 0x70];
+        d[0..8].copy_from_slice(b"dex\n035\0");
+        let put = |d: &mut Vec<u8>, at: usize, v: u32| d[at..at + 4].copy_from_slice(&v.to_le_bytes());
+        put(&mut d, 0x28, 0x12345678);
+        for (at, size, off) in [(0x38, 5, 0x70), (0x40, 3, 0x84), (0x48, 1, 0x90), (0x58, 1, 0x9c), (0x60, 1, 0xa4)]
+        {
+            put(&mut d, at, size);
+            put(&mut d, at + 4, off);
+        }
+
+        let strings = ["LTest;", "Ljava/lang/Object;", "I", "f", "Test.java"];
+        let mut string_off = 0xe0;
+        for s in strings { d.extend((string_off as u32).to_le_bytes()); string_off += s.len() + 2; }
+        for t in [0u32, 1, 2] { d.extend(t.to_le_bytes()); }
+        for v in [2u32, 2, 0] { d.extend(v.to_le_bytes()); }
+        d.extend([0, 0, 0, 0, 3, 0, 0, 0]);
+        for v in [0u32, 1, 1, 0, 4, 0, 0xd8, 0] { d.extend(v.to_le_bytes()); }
+
+        // code_item: 1 register, const/4 v0, 0x1 and return v0
+        d.extend([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0x12, 0x10, 0x0f, 0x00]);
+        // class_data_item: one direct method, public static
+        d.extend([0, 0, 1, 0, 0, 0x09, 0xc4, 0x01]);
+        for s in strings { d.push(s.len() as u8); d.extend(s.as_bytes()); d.push(0); }
+        d
+    }
+
+    #[test]
+    fn read_minimal_dex() {
+        let dex = DexFile::from_bytes(minimal_dex()).unwrap();
+        assert_eq!(dex.version(), 35);
+        assert_eq!(dex.class_count(), 1);
+
+        let c = dex.class(0).unwrap();
+        assert_eq!(c.name.as_jni_type(), "LTest;");
+        assert_eq!(c.source.as_deref(), Some("Test.java"));
+        assert_eq!(c.super_class.as_java_type(), "java.lang.Object");
+
+        let m = &c.methods[0];
+        assert_eq!(m.name, "f");
+        assert_eq!(m.modifiers, vec![Modifier::Public, Modifier::Static]);
+        assert_eq!(m.locals(), 1);
+        assert!(matches!(m.instructions[0], SmaliInstruction::Instruction(Const4 { dest: V(0), value: 1 })));
+        assert!(matches!(m.instructions[1], SmaliInstruction::Instruction(Return { src: V(0) })));
+        assert!(c.to_smali().contains(".method public static f()I\n    .locals 1\n    const/4 v0, 0x1\n    return v0\n.end method\n"));
+
+        assert!(DexFile::from_bytes(b"not a dex file".to_vec()).is_err());
+    }
+
+    #[test]
+    fn read_malformed_dex() {
+        // A type descriptor that isn't a type
+        let mut d = minimal_dex();
+        let at = d.windows(3).position(|w| w == [1, b'I', 0]).unwrap() + 1;
+        d[at] = b'[';
+        assert!(DexFile::from_bytes(d).is_err());
+
+        // A class_def naming a primitive type as the class
+        let mut d = minimal_dex();
+        d[0xa4] = 2;
+        assert!(DexFile::from_bytes(d).unwrap().class(0).is_err());
+
+        // A class_def offset that doesn't fit in 32 bits
+        let mut d = minimal_dex();
+        d[0x60..0x64].copy_from_slice(&0x1000_0000u32.to_le_bytes());
+        assert!(DexFile::from_bytes(d).unwrap().class(0x0800_0000).is_err());
+    }
+
+    #[test]
+    fn read_debug_info_tries_and_annotations() {
+        let smali = r#".class public Lcom/cool/Test;
+.super Ljava/lang/Object;
+.source "Test.java"
+
+# annotations
+.annotation runtime Lcom/cool/Marker;
+    name = "test"
+    values = {
+        0x1,
+        0x2
+    }
+.end annotation
+
+.field public static final MAX:I = 0x10
+    .annotation build Lcom/cool/Marker;
+    .end annotation
+.end field
+
+.method public static read(Ljava/lang/String;J)I
+    .locals 2
+    .param p0, "path"    # Ljava/lang/String;
+        .annotation runtime Lcom/cool/NonNull;
+        .end annotation
+    .end param
+    .annotation system Ldalvik/annotation/Throws;
+        value = {
+            Ljava/io/IOException;
+        }
+    .end annotation
+
+    .line 12
+    :try_start_0
+    invoke-static {p0}, Lcom/cool/Test;->open(Ljava/lang/String;)I
+
+    move-result v0
+    :try_end_0
+    .catch Ljava/io/IOException; {:try_start_0 .. :try_end_0} :catch_0
+    .catchall {:try_start_0 .. :try_end_0} :catchall_0
+
+    .line 13
+    .local v0, "fd":I
+    return v0
+
+    .end local v0    # "fd":I
+    :catch_0
+    move-exception v1
+
+    .line 15
+    const/4 v0, -0x1
+
+    return v0
+
+    :catchall_0
+    move-exception v1
+
+    throw v1
+.end method
+"#;
+        let mut classes = vec![SmaliClass::from_smali(smali).unwrap()];
+        classes[0].layout = None;
+        let dex = DexFile::from_bytes(write_dex(&classes, DexVersion::V035).unwrap()).unwrap();
+        let d = dex.class(0).unwrap();
+
+        assert_eq!(d.source.as_deref(), Some("Test.java"));
+        assert_eq!(d.annotations.len(), 1);
+        assert_eq!(d.annotations[0].visibility, AnnotationVisibility::Runtime);
+        assert_eq!(d.annotations[0].elements[0].value, EncodedValue::String("test".to_string()));
+        assert_eq!(d.annotations[0].elements[1].value, EncodedValue::Array(vec![EncodedValue::Int(1), EncodedValue::Int(2)]));
+        assert_eq!(d.fields[0].initial_value, Some(EncodedValue::Int(16)));
+        assert_eq!(d.fields[0].annotations[0].visibility, AnnotationVisibility::Build);
+
+        let m = &d.methods[0];
+        assert_eq!(m.params.len(), 1);
+        assert_eq!(m.params[0].name.as_deref(), Some("path"));
+        assert_eq!(m.params[0].annotations[0].annotation_type.to_jni(), "Lcom/cool/NonNull;");
+        assert_eq!(m.annotations[0].annotation_type.to_jni(), "Ldalvik/annotation/Throws;");
+        assert_eq!(m.annotations[0].elements[0].value, EncodedValue::Array(vec![EncodedValue::Type(TypeSignature::from_jni("Ljava/io/IOException;"))]));
+
+        let lines: Vec<u32> = m.instructions.iter().filter_map(|i| match i { SmaliInstruction::Line(l) => Some(*l), _ => None }).collect();
+        assert_eq!(lines, vec![12, 13, 15]);
+        assert!(m.instructions.iter().any(|i| matches!(i, SmaliInstruction::Local { register: V(0), name: Some(n), signature: Some(TypeSignature::Int), .. } if n == "fd")));
+        assert!(m.instructions.iter().any(|i| matches!(i, SmaliInstruction::EndLocal(V(0)))));
+
+        let catches: Vec<(Option<String>, &str, &str, &str)> = m.instructions.iter().filter_map(|i| match i {
+            SmaliInstruction::TryCatch { exception, start, end, handler } => Some((exception.as_ref().map(|e| e.as_jni_type()), start.as_str(), end.as_str(), handler.as_str())),
+            _ => None
+        }).collect();
+        assert_eq!(catches, vec![
+            (Some("Ljava/io/IOException;".to_string()), "try_start_0", "try_end_0", "catch_0"),
+            (None, "try_start_0", "try_end_0", "catchall_0")
+        ]);
+        assert_eq!(d.to_smali(), classes[0].to_smali());
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        let hex: String = sha1(b"abc").iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let mut classes = vec![];
+        for f in ["tests/OkHttpClient.smali", "tests/Request.smali", "tests/Response.smali"]
+        {
+            let mut c = SmaliClass::read_from_file(Path::new(f)).unwrap();
+            c.layout = None;
+            classes.push(c);
+        }
+
+        let bytes = write_dex(&classes, DexVersion::V035).unwrap();
+        assert_eq!(adler32(&bytes[12..]).to_le_bytes(), bytes[8..12]);
+        assert_eq!(sha1(&bytes[32..]), bytes[12..32]);
+
+        let dex = DexFile::from_bytes(bytes).unwrap();
+        assert_eq!(dex.version(), 35);
+        for c in dex.classes().unwrap()
+        {
+            let original = classes.iter().find(|o| o.name == c.name).unwrap();
+            assert_eq!(c.to_smali(), original.to_smali());
+        }
+    }
+
+    #[test]
+    fn multidex_plan() {
+        let classes: Vec<SmaliClass> = ["tests/OkHttpClient.smali", "tests/Request.smali", "tests/Response.smali"].iter()
+            .map(|f| SmaliClass::read_from_file(Path::new(f)).unwrap()).collect();
+
+        let mut planner = MultidexPlanner::new();
+        assert_eq!(planner.plan(&classes).unwrap().len(), 1);
+
+        planner.max_references = 350;
+        planner.main_dex_classes.push(ObjectIdentifier::from_java_type("okhttp3.Response"));
+        let plan = planner.plan(&classes).unwrap();
+        assert_eq!(plan.len(), 2);
+        assert_eq!(plan[0][0].name.as_java_type(), "okhttp3.Response");
+        assert_eq!(plan.iter().map(|d| d.len()).sum::<usize>(), 3);
+
+        planner.max_references = 10;
+        assert!(planner.plan(&classes).is_err());
+    }
+
+    #[test]
+    fn multidex_plan_types_and_strings() {
+        // Each class references 30 types of its own, but hardly any methods or fields
+        let class = |n: usize, body: &dyn Fn(usize) -> String| {
+            let code: String = (0..30).map(body).collect();
+            SmaliClass::from_smali(&format!(".class public Lcom/t/C{n};\n.super Ljava/lang/Object;\n\n\
+                                             .method public static f()V\n    .locals 1\n{code}    return-void\n.end method\n")).unwrap()
+        };
+        let classes: Vec<SmaliClass> = (0..3).map(|n| class(n, &|i| format!("    const-class v0, Lcom/t/T{n}_{i};\n"))).collect();
+        let mut planner = MultidexPlanner::new();
+        planner.max_references = 50;
+        assert_eq!(planner.plan(&classes).unwrap().len(), 3);
+        planner.max_references = 70;
+        assert_eq!(planner.plan(&classes).unwrap().len(), 2);
+
+        // Strings only count when a const-string has to reach them
+        let classes: Vec<SmaliClass> = (0..3).map(|n| class(n, &|i| format!("    const-string v0, \"s{n}_{i}\"\n"))).collect();
+        planner.max_references = 50;
+        assert_eq!(planner.plan(&classes).unwrap().len(), 3);
+        let classes: Vec<SmaliClass> = (0..3).map(|n| class(n, &|i| format!("    const-string/jumbo v0, \"s{n}_{i}\"\n"))).collect();
+        assert_eq!(planner.plan(&classes).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::fs;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use crate::dex::writer::{write_dex, Collector};
+use crate::dex::DexVersion;
+use crate::instructions::DexInstruction;
+use crate::types::{FieldRef, MethodRef, MethodSignature, ObjectIdentifier, SmaliClass, SmaliError, SmaliInstruction};
+
+/// A test on a class, such as whether it belongs in the main DEX file
+pub type ClassPredicate<'a> = Box<dyn Fn(&SmaliClass) -> bool + 'a>;
+
+/// Splits classes over `classes.dex`, `classes2.dex`, ... so that no file goes over the reference limits
+///
+/// Every DEX file can reference at most 65,536 methods, fields, types and protos, and `const-string` can only
+/// reach the first 65,536 strings, so files with a `const-string` are held to that many strings too while files
+/// that only use `const-string/jumbo` are not. Classes are added to the current file in order until one doesn't
+/// fit, then a new file is started. Classes in the main DEX keep list, given
+/// by name or by a predicate, are placed first so they all end up in `classes.dex`.
+///
+/// # Examples
+///
+/// ```no_run
+///  use std::path::Path;
+///  use smali::dex::{DexVersion, MultidexPlanner};
+///  use smali::find_smali_files;
+///  use smali::types::ObjectIdentifier;
+///
+///  let classes = find_smali_files(Path::new("app/smali"))?;
+///  let mut planner = MultidexPlanner::new();
+///  planner.main_dex_classes.push(ObjectIdentifier::from_java_type("com.app.MainApplication"));
+///  planner.main_dex_predicate = Some(Box::new(|c| c.name.as_java_type().starts_with("androidx.multidex.")));
+///
+///  let files = planner.write(&classes, Path::new("build"), DexVersion::V035)?;
+///  println!("{:} DEX files written.", files.len());
+/// # Ok::<(), smali::types::SmaliError>(())
+/// ```
+pub struct MultidexPlanner<'a> {
+    /// Classes that must be in `classes.dex`
+    pub main_dex_classes: Vec<ObjectIdentifier>,
+    /// Classes that must be in `classes.dex`, chosen by a predicate
+    pub main_dex_predicate: Option<ClassPredicate<'a>>,
+    /// The most method, field, type or proto references allowed in one DEX file, and strings if it has a `const-string`
+    pub max_references: usize
+}
+
+impl Default for MultidexPlanner<'_> {
+    fn default() -> Self {
+        MultidexPlanner { main_dex_classes: vec![], main_dex_predicate: None, max_references: 0x10000 }
+    }
+}
+
+/* The references of a class, or of everything placed in one DEX file */
+#[derive(Default)]
+struct References {
+    strings: HashSet<String>,
+    types: HashSet<String>,
+    protos: HashSet<MethodSignature>,
+    fields: HashSet<FieldRef>,
+    methods: HashSet<MethodRef>,
+    /* Is there a const-string, which has a 16 bit string index */
+    const_string: bool
+}
+
+/* The size of the union of two sets */
+fn union_len<T: Hash + Eq>(a: &HashSet<T>, b: &HashSet<T>) -> usize
+{
+    a.len() + b.difference(a).count()
+}
+
+impl References {
+    fn of(c: &SmaliClass) -> Result<References, SmaliError>
+    {
+        let mut collector = Collector::default();
+        collector.class(c)?;
+        let const_string = c.methods.iter().flat_map(|m| m.instructions.iter())
+            .any(|i| matches!(i, SmaliInstruction::Instruction(DexInstruction::ConstString { .. })));
+        Ok(References {
+            strings: collector.strings,
+            types: collector.types,
+            protos: collector.protos,
+            fields: collector.fields,
+            methods: collector.methods,
+            const_string
+        })
+    }
+
+    fn fits(&self, other: &References, max: usize) -> bool
+    {
+        union_len(&self.types, &other.types) <= max
+            && union_len(&self.protos, &other.protos) <= max
+            && union_len(&self.fields, &other.fields) <= max
+            && union_len(&self.methods, &other.methods) <= max
+            && (!(self.const_string || other.const_string) || union_len(&self.strings, &other.strings) <= max)
+    }
+
+    fn add(&mut self, other: References)
+    {
+        self.strings.extend(other.strings);
+        self.types.extend(other.types);
+        self.protos.extend(other.protos);
+        self.fields.extend(other.fields);
+        self.methods.extend(other.methods);
+        self.const_string |= other.const_string;
+    }
+}
+
+impl<'a> MultidexPlanner<'a> {
+    pub fn new() -> MultidexPlanner<'a>
+    {
+        MultidexPlanner::default()
+    }
+
+    /// Is the class on the main DEX keep list
+    pub fn is_main_dex(&self, c: &SmaliClass) -> bool
+    {
+        self.main_dex_classes.contains(&c.name) || self.main_dex_predicate.as_ref().is_some_and(|p| p(c))
+    }
+
+    /// Works out which classes go in each DEX file, the first list is `classes.dex`
+    pub fn plan<'c>(&self, classes: &'c [SmaliClass]) -> Result<Vec<Vec<&'c SmaliClass>>, SmaliError>
+    {
+        let (main, rest): (Vec<&SmaliClass>, Vec<&SmaliClass>) = classes.iter().partition(|c| self.is_main_dex(c));
+        let mut dex_files: Vec<Vec<&SmaliClass>> = vec![vec![]];
+        let mut current = References::default();
+        for c in main.iter().chain(rest.iter())
+        {
+            let refs = References::of(c)?;
+            if !current.fits(&refs, self.max_references)
+            {
+                if self.is_main_dex(c)
+                {
+                    return Err(SmaliError::Unsupported(format!("the main DEX classes need more than {} references", self.max_references)));
+                }
+                if !dex_files.last().is_some_and(|d| d.is_empty())
+                {
+                    dex_files.push(vec![]);
+                    current = References::default();
+                }
+                if !current.fits(&refs, self.max_references)
+                {
+                    return Err(SmaliError::Unsupported(format!("{} alone needs more than {} references", c.name.as_jni_type(), self.max_references)));
+                }
+            }
+            current.add(refs);
+            if let Some(d) = dex_files.last_mut() { d.push(c); }
+        }
+        Ok(dex_files)
+    }
+
+    /// Assembles the classes into `classes.dex`, `classes2.dex`, ... in a directory, returning the paths written
+    pub fn write(&self, classes: &[SmaliClass], dir: &Path, version: DexVersion) -> Result<Vec<PathBuf>, SmaliError>
+    {
+        let mut paths = vec![];
+        for (i, dex) in self.plan(classes)?.iter().enumerate()
+        {
+            let path = dir.join(if i == 0 { "classes.dex".to_string() } else { format!("classes{}.dex", i + 1) });
+            let bytes = write_dex(dex, version)?;
+            fs::write(&path, bytes).map_err(|e| SmaliError::Io { path: Some(path.clone()), error: e })?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
@@ -0,0 +1,113 @@
+use crate::types::SmaliError;
+
+/* Little endian reader over the bytes of a DEX file, every read is bounds checked */
+pub(crate) struct ByteReader<'a> {
+    data: &'a [u8],
+    pub pos: usize
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8], pos: usize) -> ByteReader<'a>
+    {
+        ByteReader { data, pos }
+    }
+
+    pub fn error(&self, message: &str) -> SmaliError
+    {
+        SmaliError::InvalidDex { offset: self.pos, message: message.to_string() }
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], SmaliError>
+    {
+        match self.pos.checked_add(n).and_then(|end| self.data.get(self.pos..end)) {
+            Some(b) => { self.pos += n; Ok(b) }
+            None => Err(self.error("unexpected end of file"))
+        }
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SmaliError>
+    {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SmaliError>
+    {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SmaliError>
+    {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn uleb128(&mut self) -> Result<u32, SmaliError>
+    {
+        let mut result: u32 = 0;
+        for i in 0..5
+        {
+            let b = self.u8()?;
+            result |= ((b & 0x7f) as u32) << (i * 7);
+            if b & 0x80 == 0 { return Ok(result); }
+        }
+        Err(self.error("invalid uleb128"))
+    }
+
+    pub fn sleb128(&mut self) -> Result<i32, SmaliError>
+    {
+        let mut result: i32 = 0;
+        for i in 0..5
+        {
+            let b = self.u8()?;
+            result |= ((b & 0x7f) as i32) << (i * 7);
+            if b & 0x80 == 0
+            {
+                // Sign extend from the last bit read
+                let shift = 32 - 7 * (i + 1);
+                return Ok(if shift > 0 { (result << shift) >> shift } else { result });
+            }
+        }
+        Err(self.error("invalid sleb128"))
+    }
+
+    /* uleb128 of the value plus one, so that NO_INDEX (-1) is encoded as 0 */
+    pub fn uleb128p1(&mut self) -> Result<Option<u32>, SmaliError>
+    {
+        Ok(self.uleb128()?.checked_sub(1))
+    }
+
+    /* A little endian value of 1 to 8 bytes as used by encoded values */
+    pub fn sized(&mut self, size: usize) -> Result<u64, SmaliError>
+    {
+        Ok(self.bytes(size)?.iter().rev().fold(0, |v, b| (v << 8) | *b as u64))
+    }
+}
+
+/* Decodes a MUTF-8 string, DEX's modified UTF-8 with encoded nulls and surrogate pairs as two 3 byte sequences */
+pub(crate) fn decode_mutf8(bytes: &[u8]) -> Result<String, SmaliError>
+{
+    let mut units: Vec<u16> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    let bad = || SmaliError::InvalidDex { offset: 0, message: "invalid MUTF-8 string".to_string() };
+    while i < bytes.len()
+    {
+        let b = bytes[i] as u16;
+        if b < 0x80 { units.push(b); i += 1; }
+        else if b & 0xe0 == 0xc0
+        {
+            let b2 = *bytes.get(i + 1).ok_or_else(bad)? as u16;
+            units.push(((b & 0x1f) << 6) | (b2 & 0x3f));
+            i += 2;
+        }
+        else if b & 0xf0 == 0xe0
+        {
+            let b2 = *bytes.get(i + 1).ok_or_else(bad)? as u16;
+            let b3 = *bytes.get(i + 2).ok_or_else(bad)? as u16;
+            units.push(((b & 0x0f) << 12) | ((b2 & 0x3f) << 6) | (b3 & 0x3f));
+            i += 3;
+        }
+        else { return Err(bad()); }
+    }
+    Ok(String::from_utf16_lossy(&units))
+}
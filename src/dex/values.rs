@@ -0,0 +1,125 @@
+use crate::dex::reader::ByteReader;
+use crate::dex::DexFile;
+use crate::types::{AnnotationElement, AnnotationVisibility, EncodedValue, SmaliAnnotation, SmaliError, TypeSignature};
+
+fn sign_extend(v: u64, size: usize) -> i64
+{
+    let shift = 64 - size * 8;
+    ((v << shift) as i64) >> shift
+}
+
+pub(crate) fn read_value(dex: &DexFile, r: &mut ByteReader) -> Result<EncodedValue, SmaliError>
+{
+    let header = r.u8()?;
+    let arg = (header >> 5) as usize;
+    let size = arg + 1;
+    let value = match header & 0x1f {
+        0x00 => EncodedValue::Byte(sign_extend(r.sized(1)?, 1) as i8),
+        0x02 => EncodedValue::Short(sign_extend(r.sized(size)?, size) as i16),
+        0x03 => EncodedValue::Char(r.sized(size)? as u16),
+        0x04 => EncodedValue::Int(sign_extend(r.sized(size)?, size) as i32),
+        0x06 => EncodedValue::Long(sign_extend(r.sized(size)?, size)),
+        // Floats are zero extended to the right
+        0x10 if size > 4 => return Err(r.error("float value too long")),
+        0x10 => EncodedValue::Float(f32::from_bits((r.sized(size)? << ((4 - size) * 8)) as u32)),
+        0x11 => EncodedValue::Double(f64::from_bits(r.sized(size)? << ((8 - size) * 8))),
+        0x15 => EncodedValue::MethodType(dex.proto(r.sized(size)? as u32)?),
+        0x16 => EncodedValue::MethodHandle(dex.method_handle(r.sized(size)? as u32)?),
+        0x17 => EncodedValue::String(dex.string(r.sized(size)? as u32)?.to_string()),
+        0x18 => EncodedValue::Type(dex.type_signature(r.sized(size)? as u32)?),
+        0x19 => EncodedValue::Field(dex.field(r.sized(size)? as u32)?),
+        0x1a => EncodedValue::Method(dex.method(r.sized(size)? as u32)?),
+        0x1b => EncodedValue::Enum(dex.field(r.sized(size)? as u32)?),
+        0x1c => EncodedValue::Array(read_array(dex, r)?),
+        0x1d => EncodedValue::Annotation(read_annotation(dex, r, AnnotationVisibility::System)?),
+        0x1e => EncodedValue::Null,
+        0x1f => EncodedValue::Boolean(arg != 0),
+        _ => return Err(r.error("unknown encoded value type"))
+    };
+    Ok(value)
+}
+
+pub(crate) fn read_array(dex: &DexFile, r: &mut ByteReader) -> Result<Vec<EncodedValue>, SmaliError>
+{
+    let size = r.uleb128()?;
+    (0..size).map(|_| read_value(dex, r)).collect()
+}
+
+/* An encoded_annotation, the visibility comes from the enclosing annotation_item */
+pub(crate) fn read_annotation(dex: &DexFile, r: &mut ByteReader, visibility: AnnotationVisibility) -> Result<SmaliAnnotation, SmaliError>
+{
+    let type_idx = r.uleb128()?;
+    let size = r.uleb128()?;
+    let mut elements = vec![];
+    for _ in 0..size
+    {
+        let name = dex.string(r.uleb128()?)?.to_string();
+        let value = read_value(dex, r)?;
+        elements.push(AnnotationElement { name, value });
+    }
+    Ok(SmaliAnnotation {
+        visibility,
+        annotation_type: dex.type_signature(type_idx)?,
+        elements
+    })
+}
+
+/* An annotation_set_item, a list of offsets to annotation_items */
+pub(crate) fn read_annotation_set(dex: &DexFile, offset: u32) -> Result<Vec<SmaliAnnotation>, SmaliError>
+{
+    if offset == 0 { return Ok(vec![]); }
+    let mut r = dex.reader(offset);
+    let size = r.u32()?;
+    let mut annotations = vec![];
+    for _ in 0..size
+    {
+        let mut a = dex.reader(r.u32()?);
+        let visibility = match a.u8()? {
+            0 => AnnotationVisibility::Build,
+            1 => AnnotationVisibility::Runtime,
+            _ => AnnotationVisibility::System
+        };
+        annotations.push(read_annotation(dex, &mut a, visibility)?);
+    }
+    Ok(annotations)
+}
+
+impl EncodedValue {
+    /* The zero value of a type, used for static fields without an initial value */
+    pub(crate) fn default_for(t: &TypeSignature) -> EncodedValue
+    {
+        match t {
+            TypeSignature::Bool => EncodedValue::Boolean(false),
+            TypeSignature::Byte => EncodedValue::Byte(0),
+            TypeSignature::Short => EncodedValue::Short(0),
+            TypeSignature::Char => EncodedValue::Char(0),
+            TypeSignature::Int => EncodedValue::Int(0),
+            TypeSignature::Long => EncodedValue::Long(0),
+            TypeSignature::Float => EncodedValue::Float(0.0),
+            TypeSignature::Double => EncodedValue::Double(0.0),
+            _ => EncodedValue::Null
+        }
+    }
+
+    /* Integer literals take the type of the field they initialise, so `0x1` works for a long field */
+    pub(crate) fn coerce(self, t: &TypeSignature) -> EncodedValue
+    {
+        let v = match self {
+            EncodedValue::Byte(v) => v as i64,
+            EncodedValue::Short(v) => v as i64,
+            EncodedValue::Char(v) => v as i64,
+            EncodedValue::Int(v) => v as i64,
+            EncodedValue::Long(v) => v,
+            c => return c
+        };
+        match t {
+            TypeSignature::Bool => EncodedValue::Boolean(v != 0),
+            TypeSignature::Byte => EncodedValue::Byte(v as i8),
+            TypeSignature::Short => EncodedValue::Short(v as i16),
+            TypeSignature::Char => EncodedValue::Char(v as u16),
+            TypeSignature::Int => EncodedValue::Int(v as i32),
+            TypeSignature::Long => EncodedValue::Long(v),
+            _ => self
+        }
+    }
+}
@@ -0,0 +1,1534 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use crate::dex::checksum::{adler32, sha1};
+use crate::dex::{DexVersion, ENDIAN_CONSTANT, NO_INDEX, TYPE_CALL_SITE_ID_ITEM, TYPE_METHOD_HANDLE_ITEM};
+use crate::instructions::{DexInstruction, Format, Opcode, Operand, Payload, Register};
+use crate::smali_parse::{parse_methodref, unescape_string};
+use crate::types::{AnnotationElement, EncodedValue, FieldRef, MethodHandle, MethodRef, MethodSignature, SmaliAnnotation, SmaliClass, SmaliError, SmaliField,
+                   SmaliInstruction, SmaliMethod, AnnotationVisibility, TypeSignature};
+
+const HEADER_SIZE: u32 = 0x70;
+const OBJECT: &str = "Ljava/lang/Object;";
+
+// map_list item types
+const TYPE_HEADER_ITEM: u16 = 0x0000;
+const TYPE_STRING_ID_ITEM: u16 = 0x0001;
+const TYPE_TYPE_ID_ITEM: u16 = 0x0002;
+const TYPE_PROTO_ID_ITEM: u16 = 0x0003;
+const TYPE_FIELD_ID_ITEM: u16 = 0x0004;
+const TYPE_METHOD_ID_ITEM: u16 = 0x0005;
+const TYPE_CLASS_DEF_ITEM: u16 = 0x0006;
+const TYPE_MAP_LIST: u16 = 0x1000;
+const TYPE_TYPE_LIST: u16 = 0x1001;
+const TYPE_ANNOTATION_SET_REF_LIST: u16 = 0x1002;
+const TYPE_ANNOTATION_SET_ITEM: u16 = 0x1003;
+const TYPE_CLASS_DATA_ITEM: u16 = 0x2000;
+const TYPE_CODE_ITEM: u16 = 0x2001;
+const TYPE_STRING_DATA_ITEM: u16 = 0x2002;
+const TYPE_DEBUG_INFO_ITEM: u16 = 0x2003;
+const TYPE_ANNOTATION_ITEM: u16 = 0x2004;
+const TYPE_ENCODED_ARRAY_ITEM: u16 = 0x2005;
+const TYPE_ANNOTATIONS_DIRECTORY_ITEM: u16 = 0x2006;
+
+fn uleb128(out: &mut Vec<u8>, mut v: u32)
+{
+    loop
+    {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 { out.push(b); return; }
+        out.push(b | 0x80);
+    }
+}
+
+fn sleb128(out: &mut Vec<u8>, mut v: i32)
+{
+    loop
+    {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if (v == 0 && b & 0x40 == 0) || (v == -1 && b & 0x40 != 0) { out.push(b); return; }
+        out.push(b | 0x80);
+    }
+}
+
+fn uleb128p1(out: &mut Vec<u8>, v: Option<u32>)
+{
+    uleb128(out, v.map_or(0, |v| v + 1));
+}
+
+fn u16_le(out: &mut Vec<u8>, v: u16)
+{
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn u32_le(out: &mut Vec<u8>, v: u32)
+{
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+/* Encodes a string as MUTF-8, returning its length in UTF-16 code units with the bytes */
+fn encode_mutf8(s: &str) -> (u32, Vec<u8>)
+{
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut len = 0;
+    for u in s.encode_utf16()
+    {
+        len += 1;
+        match u {
+            0x01..=0x7f => bytes.push(u as u8),
+            0x00 | 0x80..=0x7ff => bytes.extend_from_slice(&[0xc0 | (u >> 6) as u8, 0x80 | (u & 0x3f) as u8]),
+            _ => bytes.extend_from_slice(&[0xe0 | (u >> 12) as u8, 0x80 | ((u >> 6) & 0x3f) as u8, 0x80 | (u & 0x3f) as u8])
+        }
+    }
+    (len, bytes)
+}
+
+/* Strings are sorted by their UTF-16 code units, not by their UTF-8 bytes */
+fn utf16_cmp(a: &str, b: &str) -> Ordering
+{
+    a.encode_utf16().cmp(b.encode_utf16())
+}
+
+fn shorty(p: &MethodSignature) -> String
+{
+    let short = |t: &TypeSignature| match t {
+        TypeSignature::Object(_) | TypeSignature::Array(_) => 'L',
+        t => t.to_jni().chars().next().unwrap_or('V')
+    };
+    std::iter::once(&p.return_type).chain(p.args.iter()).map(short).collect()
+}
+
+/* The file name of a `.source "File.java"` directive within a method */
+fn source_directive(d: &str) -> Option<String>
+{
+    let f = d.strip_prefix(".source")?.trim();
+    Some(unescape_string(f.strip_prefix('"')?.strip_suffix('"')?))
+}
+
+/* A call site written as name("method", proto, extra args...)@bootstrap method */
+#[derive(Clone)]
+struct CallSite {
+    bootstrap: MethodRef,
+    name: String,
+    proto: MethodSignature,
+    args: Vec<EncodedValue>
+}
+
+fn parse_call_site(text: &str) -> Result<CallSite, SmaliError>
+{
+    let bad = || SmaliError::Unsupported(format!("call site `{}`", text));
+    let open = text.find('(').ok_or_else(bad)?;
+
+    // Split the arguments on the commas outside of strings and nested brackets
+    let mut args = vec![];
+    let (mut depth, mut quoted, mut escaped, mut start) = (0, false, false, open + 1);
+    let mut close = None;
+    for (i, c) in text.char_indices().skip(open + 1)
+    {
+        if quoted
+        {
+            if escaped { escaped = false; } else if c == '\\' { escaped = true; } else if c == '"' { quoted = false; }
+            continue;
+        }
+        match c {
+            '"' => quoted = true,
+            '(' | '{' => depth += 1,
+            ')' if depth == 0 => { close = Some(i); break; }
+            ')' | '}' => depth -= 1,
+            ',' if depth == 0 => { args.push(&text[start..i]); start = i + 1; }
+            _ => {}
+        }
+    }
+    let close = close.ok_or_else(bad)?;
+    if !text[start..close].trim().is_empty() { args.push(&text[start..close]); }
+
+    let (rest, bootstrap) = parse_methodref(text[close + 1..].strip_prefix('@').ok_or_else(bad)?).map_err(|_| bad())?;
+    if !rest.trim().is_empty() || args.len() < 2 { return Err(bad()); }
+    let mut values = args.into_iter().map(EncodedValue::parse).collect::<Result<Vec<EncodedValue>, SmaliError>>()?.into_iter();
+    match (values.next(), values.next()) {
+        (Some(EncodedValue::String(name)), Some(EncodedValue::MethodType(proto))) => Ok(CallSite { bootstrap, name, proto, args: values.collect() }),
+        _ => Err(bad())
+    }
+}
+
+/* Everything referenced by the classes, before the pools are sorted */
+#[derive(Default)]
+pub(crate) struct Collector {
+    pub(crate) strings: HashSet<String>,
+    pub(crate) types: HashSet<String>,
+    pub(crate) protos: HashSet<MethodSignature>,
+    pub(crate) fields: HashSet<FieldRef>,
+    pub(crate) methods: HashSet<MethodRef>,
+    method_handles: Vec<MethodHandle>,
+    call_sites: Vec<(String, CallSite)>
+}
+
+impl Collector {
+    fn string(&mut self, s: &str)
+    {
+        if !self.strings.contains(s) { self.strings.insert(s.to_string()); }
+    }
+
+    fn descriptor(&mut self, t: &str)
+    {
+        self.string(t);
+        if !self.types.contains(t) { self.types.insert(t.to_string()); }
+    }
+
+    fn proto(&mut self, p: &MethodSignature)
+    {
+        if self.protos.contains(p) { return; }
+        self.string(&shorty(p));
+        self.descriptor(&p.return_type.to_jni());
+        for a in &p.args { self.descriptor(&a.to_jni()); }
+        self.protos.insert(p.clone());
+    }
+
+    fn field(&mut self, f: &FieldRef)
+    {
+        if self.fields.contains(f) { return; }
+        self.descriptor(&f.class.as_jni_type());
+        self.string(&f.name);
+        self.descriptor(&f.signature.to_jni());
+        self.fields.insert(f.clone());
+    }
+
+    fn method(&mut self, m: &MethodRef)
+    {
+        if self.methods.contains(m) { return; }
+        self.descriptor(&m.class.to_jni());
+        self.string(&m.name);
+        self.proto(&m.signature);
+        self.methods.insert(m.clone());
+    }
+
+    fn method_handle(&mut self, h: &MethodHandle)
+    {
+        if self.method_handles.contains(h) { return; }
+        match h {
+            MethodHandle::StaticPut(f) | MethodHandle::StaticGet(f) | MethodHandle::InstancePut(f) | MethodHandle::InstanceGet(f) => self.field(f),
+            MethodHandle::InvokeStatic(m) | MethodHandle::InvokeInstance(m) | MethodHandle::InvokeConstructor(m)
+            | MethodHandle::InvokeDirect(m) | MethodHandle::InvokeInterface(m) => self.method(m)
+        }
+        self.method_handles.push(h.clone());
+    }
+
+    fn call_site(&mut self, text: &str) -> Result<(), SmaliError>
+    {
+        if self.call_sites.iter().any(|(t, _)| t == text) { return Ok(()); }
+        let c = parse_call_site(text)?;
+        self.method_handle(&MethodHandle::InvokeStatic(c.bootstrap.clone()));
+        self.string(&c.name);
+        self.proto(&c.proto);
+        for a in &c.args { self.constant(a); }
+        self.call_sites.push((text.to_string(), c));
+        Ok(())
+    }
+
+    fn constant(&mut self, c: &EncodedValue)
+    {
+        match c {
+            EncodedValue::MethodType(p) => self.proto(p),
+            EncodedValue::MethodHandle(h) => self.method_handle(h),
+            EncodedValue::String(s) => self.string(s),
+            EncodedValue::Type(t) => self.descriptor(&t.to_jni()),
+            EncodedValue::Field(f) | EncodedValue::Enum(f) => self.field(f),
+            EncodedValue::Method(m) => self.method(m),
+            EncodedValue::Array(a) => for v in a { self.constant(v); },
+            EncodedValue::Annotation(a) => self.annotation(a),
+            _ => {}
+        }
+    }
+
+    fn annotation(&mut self, a: &SmaliAnnotation)
+    {
+        self.descriptor(&a.annotation_type.to_jni());
+        for e in &a.elements
+        {
+            self.string(&e.name);
+            self.constant(&e.value);
+        }
+    }
+
+    pub(crate) fn class(&mut self, c: &SmaliClass) -> Result<(), SmaliError>
+    {
+        let name = c.name.as_jni_type();
+        if !c.unparsed.is_empty()
+        {
+            return Err(SmaliError::Other(format!("{}: class has members that could not be parsed", name)));
+        }
+        self.descriptor(&name);
+        if name != OBJECT { self.descriptor(&c.super_class.as_jni_type()); }
+        for i in &c.implements { self.descriptor(&i.as_jni_type()); }
+        if let Some(s) = &c.source { self.string(s); }
+        for a in &c.annotations { self.annotation(a); }
+
+        for f in &c.fields
+        {
+            self.field(&FieldRef { class: c.name.clone(), name: f.name.clone(), signature: f.signature.clone() });
+            if let Some(v) = &f.initial_value { self.constant(v); }
+            for a in &f.annotations { self.annotation(a); }
+        }
+
+        for m in &c.methods
+        {
+            self.method(&MethodRef { class: TypeSignature::Object(c.name.clone()), name: m.name.clone(), signature: m.signature.clone() });
+            for a in &m.annotations { self.annotation(a); }
+            for p in &m.params
+            {
+                if let Some(n) = &p.name { self.string(n); }
+                for a in &p.annotations { self.annotation(a); }
+            }
+            for i in &m.instructions
+            {
+                match i {
+                    SmaliInstruction::Instruction(d) => {
+                        for o in d.operands()
+                        {
+                            match o {
+                                Operand::String(s) => self.string(&s),
+                                Operand::Type(t) => self.descriptor(&t.to_jni()),
+                                Operand::Field(f) => self.field(&f),
+                                Operand::Method(m) => self.method(&m),
+                                Operand::Proto(p) => self.proto(&p),
+                                Operand::CallSite(s) => self.call_site(&s)?,
+                                Operand::MethodHandle(h) => self.method_handle(&h),
+                                _ => {}
+                            }
+                        }
+                    }
+                    SmaliInstruction::TryCatch { exception: Some(e), .. } => self.descriptor(&e.as_jni_type()),
+                    SmaliInstruction::Local { name, signature, generic, .. } => {
+                        if let Some(n) = name { self.string(n); }
+                        if let Some(t) = signature { self.descriptor(&t.to_jni()); }
+                        if let Some(g) = generic { self.string(g); }
+                    }
+                    SmaliInstruction::Directive(d) => if let Some(f) = source_directive(d) { self.string(&f); },
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* Sorts every pool into the order required by the DEX format */
+    fn finish(self) -> Result<Pools, SmaliError>
+    {
+        let mut strings: Vec<String> = self.strings.into_iter().collect();
+        strings.sort_by(|a, b| utf16_cmp(a, b));
+        let string_ids: HashMap<String, u32> = strings.iter().enumerate().map(|(i, s)| (s.clone(), i as u32)).collect();
+
+        let mut types: Vec<String> = self.types.into_iter().collect();
+        types.sort_by(|a, b| utf16_cmp(a, b));
+        let type_ids: HashMap<String, u32> = types.iter().enumerate().map(|(i, s)| (s.clone(), i as u32)).collect();
+
+        let ty = |t: &TypeSignature| type_ids.get(&t.to_jni()).copied();
+        let mut protos: Vec<MethodSignature> = self.protos.into_iter().collect();
+        protos.sort_by_cached_key(|p| (ty(&p.return_type), p.args.iter().map(ty).collect::<Vec<Option<u32>>>()));
+        let proto_ids: HashMap<MethodSignature, u32> = protos.iter().enumerate().map(|(i, p)| (p.clone(), i as u32)).collect();
+
+        let mut fields: Vec<FieldRef> = self.fields.into_iter().collect();
+        fields.sort_by_cached_key(|f| (type_ids.get(&f.class.as_jni_type()).copied(), string_ids.get(&f.name).copied(), ty(&f.signature)));
+        let field_ids: HashMap<FieldRef, u32> = fields.iter().enumerate().map(|(i, f)| (f.clone(), i as u32)).collect();
+
+        let mut methods: Vec<MethodRef> = self.methods.into_iter().collect();
+        methods.sort_by_cached_key(|m| (ty(&m.class), string_ids.get(&m.name).copied(), proto_ids.get(&m.signature).copied()));
+        let method_ids: HashMap<MethodRef, u32> = methods.iter().enumerate().map(|(i, m)| (m.clone(), i as u32)).collect();
+
+        // Indexes of these pools are stored in 16 bits
+        for (pool, size) in [("type", types.len()), ("proto", protos.len()), ("field", fields.len()), ("method", methods.len())]
+        {
+            if size > 0x10000
+            {
+                return Err(SmaliError::Unsupported(format!("{} {} references in one DEX file, the limit is 65536", size, pool)));
+            }
+        }
+
+        Ok(Pools {
+            handle_ids: self.method_handles.iter().enumerate().map(|(i, h)| (h.clone(), i as u32)).collect(),
+            call_site_ids: self.call_sites.iter().enumerate().map(|(i, (t, _))| (t.clone(), i as u32)).collect(),
+            method_handles: self.method_handles,
+            call_sites: self.call_sites.into_iter().map(|(_, c)| c).collect(),
+            strings,
+            string_ids,
+            types,
+            type_ids,
+            protos,
+            proto_ids,
+            fields,
+            field_ids,
+            methods,
+            method_ids
+        })
+    }
+}
+
+/* The sorted pools with a lookup from each item to its index */
+struct Pools {
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+    types: Vec<String>,
+    type_ids: HashMap<String, u32>,
+    protos: Vec<MethodSignature>,
+    proto_ids: HashMap<MethodSignature, u32>,
+    fields: Vec<FieldRef>,
+    field_ids: HashMap<FieldRef, u32>,
+    methods: Vec<MethodRef>,
+    method_ids: HashMap<MethodRef, u32>,
+    method_handles: Vec<MethodHandle>,
+    handle_ids: HashMap<MethodHandle, u32>,
+    call_sites: Vec<CallSite>,
+    call_site_ids: HashMap<String, u32>
+}
+
+impl Pools {
+    fn string(&self, s: &str) -> Result<u32, SmaliError>
+    {
+        self.string_ids.get(s).copied().ok_or_else(|| missing("string", s))
+    }
+
+    fn descriptor(&self, t: &str) -> Result<u32, SmaliError>
+    {
+        self.type_ids.get(t).copied().ok_or_else(|| missing("type", t))
+    }
+
+    fn type_idx(&self, t: &TypeSignature) -> Result<u32, SmaliError>
+    {
+        self.descriptor(&t.to_jni())
+    }
+
+    fn proto(&self, p: &MethodSignature) -> Result<u32, SmaliError>
+    {
+        self.proto_ids.get(p).copied().ok_or_else(|| missing("proto", &p.to_jni()))
+    }
+
+    fn field(&self, f: &FieldRef) -> Result<u32, SmaliError>
+    {
+        self.field_ids.get(f).copied().ok_or_else(|| missing("field", &f.to_jni()))
+    }
+
+    fn method(&self, m: &MethodRef) -> Result<u32, SmaliError>
+    {
+        self.method_ids.get(m).copied().ok_or_else(|| missing("method", &m.to_jni()))
+    }
+
+    fn method_handle(&self, h: &MethodHandle) -> Result<u32, SmaliError>
+    {
+        self.handle_ids.get(h).copied().ok_or_else(|| missing("method handle", &h.to_jni()))
+    }
+
+    fn operand(&self, o: &Operand) -> Result<u32, SmaliError>
+    {
+        match o {
+            Operand::String(s) => self.string(s),
+            Operand::Type(t) => self.type_idx(t),
+            Operand::Field(f) => self.field(f),
+            Operand::Method(m) => self.method(m),
+            Operand::Proto(p) => self.proto(p),
+            Operand::CallSite(c) => self.call_site_ids.get(c).copied().ok_or_else(|| missing("call site", c)),
+            Operand::MethodHandle(h) => self.method_handle(h),
+            _ => Ok(0)
+        }
+    }
+}
+
+/* Everything referenced is collected before the pools are sorted, so a lookup can only miss if the collector skipped it */
+fn missing(pool: &str, item: &str) -> SmaliError
+{
+    SmaliError::Other(format!("{} is missing from the {} pool", item, pool))
+}
+
+/* encoded_value helpers: signed values are sign extended, indexes and chars are zero extended */
+fn sized_signed(out: &mut Vec<u8>, value_type: u8, v: i64)
+{
+    let mut size = 1;
+    while size < 8 && ((v << (64 - size * 8)) >> (64 - size * 8)) != v { size += 1; }
+    out.push(((size as u8 - 1) << 5) | value_type);
+    out.extend_from_slice(&v.to_le_bytes()[..size]);
+}
+
+fn sized_unsigned(out: &mut Vec<u8>, value_type: u8, v: u64)
+{
+    let size = (8 - v.leading_zeros() as usize / 8).max(1);
+    out.push(((size as u8 - 1) << 5) | value_type);
+    out.extend_from_slice(&v.to_le_bytes()[..size]);
+}
+
+/* Floating point values are zero extended to the right, so trailing zero bytes are dropped */
+fn sized_float(out: &mut Vec<u8>, value_type: u8, bits: u64, width: usize)
+{
+    let mut size = width;
+    while size > 1 && (bits >> ((width - size) * 8)) & 0xff == 0 { size -= 1; }
+    out.push(((size as u8 - 1) << 5) | value_type);
+    out.extend_from_slice(&(bits >> ((width - size) * 8)).to_le_bytes()[..size]);
+}
+
+fn write_value(out: &mut Vec<u8>, c: &EncodedValue, pools: &Pools) -> Result<(), SmaliError>
+{
+    match c {
+        EncodedValue::Byte(v) => { out.push(0x00); out.push(*v as u8); }
+        EncodedValue::Short(v) => sized_signed(out, 0x02, *v as i64),
+        EncodedValue::Char(v) => sized_unsigned(out, 0x03, *v as u64),
+        EncodedValue::Int(v) => sized_signed(out, 0x04, *v as i64),
+        EncodedValue::Long(v) => sized_signed(out, 0x06, *v),
+        EncodedValue::Float(v) => sized_float(out, 0x10, v.to_bits() as u64, 4),
+        EncodedValue::Double(v) => sized_float(out, 0x11, v.to_bits(), 8),
+        EncodedValue::MethodType(p) => sized_unsigned(out, 0x15, pools.proto(p)? as u64),
+        EncodedValue::MethodHandle(h) => sized_unsigned(out, 0x16, pools.method_handle(h)? as u64),
+        EncodedValue::String(s) => sized_unsigned(out, 0x17, pools.string(s)? as u64),
+        EncodedValue::Type(t) => sized_unsigned(out, 0x18, pools.type_idx(t)? as u64),
+        EncodedValue::Field(f) => sized_unsigned(out, 0x19, pools.field(f)? as u64),
+        EncodedValue::Method(m) => sized_unsigned(out, 0x1a, pools.method(m)? as u64),
+        EncodedValue::Enum(f) => sized_unsigned(out, 0x1b, pools.field(f)? as u64),
+        EncodedValue::Array(a) => {
+            out.push(0x1c);
+            write_array(out, a, pools)?;
+        }
+        EncodedValue::Annotation(a) => {
+            out.push(0x1d);
+            write_encoded_annotation(out, a, pools)?;
+        }
+        EncodedValue::Null => out.push(0x1e),
+        EncodedValue::Boolean(b) => out.push(((*b as u8) << 5) | 0x1f)
+    }
+    Ok(())
+}
+
+fn write_array(out: &mut Vec<u8>, values: &[EncodedValue], pools: &Pools) -> Result<(), SmaliError>
+{
+    uleb128(out, values.len() as u32);
+    for v in values { write_value(out, v, pools)?; }
+    Ok(())
+}
+
+/* Elements are sorted by the index of their name */
+fn write_encoded_annotation(out: &mut Vec<u8>, a: &SmaliAnnotation, pools: &Pools) -> Result<(), SmaliError>
+{
+    let mut sorted = a.elements.iter().map(|e| Ok((pools.string(&e.name)?, e))).collect::<Result<Vec<(u32, &AnnotationElement)>, SmaliError>>()?;
+    sorted.sort_by_key(|(name, _)| *name);
+    uleb128(out, pools.type_idx(&a.annotation_type)?);
+    uleb128(out, sorted.len() as u32);
+    for (name, e) in sorted
+    {
+        uleb128(out, name);
+        write_value(out, &e.value, pools)?;
+    }
+    Ok(())
+}
+
+fn required_version(opcode: Opcode) -> u32
+{
+    match opcode {
+        Opcode::InvokePolymorphic | Opcode::InvokePolymorphicRange | Opcode::InvokeCustom | Opcode::InvokeCustomRange => 38,
+        Opcode::ConstMethodHandle | Opcode::ConstMethodType => 39,
+        _ => 35
+    }
+}
+
+fn payload_size(p: &Payload) -> u32
+{
+    match p {
+        Payload::PackedSwitch(s) => 4 + s.targets.len() as u32 * 2,
+        Payload::SparseSwitch(s) => 2 + s.entries.len() as u32 * 4,
+        Payload::ArrayData(a) => 4 + (a.element_width * a.values.len() as u32).div_ceil(2)
+    }
+}
+
+/* An assembled code_item, the offsets are filled in as the data section is written */
+struct Code {
+    registers: u16,
+    ins: u16,
+    outs: u16,
+    insns: Vec<u16>,
+    tries: Vec<(u32, u16, usize)>,
+    handlers: Vec<Vec<(Option<u32>, u32)>>,
+    debug: Option<Vec<u8>>,
+    debug_off: u32,
+    code_off: u32
+}
+
+/* The registers, labels and pools needed to encode the instructions of one method */
+struct MethodContext<'a> {
+    locals: u32,
+    labels: HashMap<&'a str, u32>,
+    pools: &'a Pools
+}
+
+impl MethodContext<'_> {
+    fn reg(&self, r: &Register) -> u32
+    {
+        match r {
+            Register::V(n) => *n as u32,
+            Register::P(n) => self.locals + *n as u32
+        }
+    }
+
+    fn label(&self, l: &str) -> Result<u32, String>
+    {
+        self.labels.get(l).copied().ok_or_else(|| format!("label :{} is not defined", l))
+    }
+}
+
+/* Encodes an instruction at addr into its code units */
+fn encode_instruction(d: &DexInstruction, addr: u32, cx: &MethodContext) -> Result<Vec<u16>, String>
+{
+    let opcode = d.opcode();
+    let name = opcode.mnemonic();
+    let op = opcode.code() as u16;
+
+    let mut regs = vec![];
+    let mut list = vec![];
+    let mut range = (0, 0);
+    let mut lit = 0i64;
+    let mut target = 0i64;
+    let mut indexes = vec![];
+    for o in d.operands()
+    {
+        match &o {
+            Operand::Register(r) => regs.push(cx.reg(r)),
+            Operand::RegisterList(l) => list = l.iter().map(|r| cx.reg(r)).collect(),
+            Operand::RegisterRange(r) => {
+                let (start, end) = (cx.reg(&r.start), cx.reg(&r.end));
+                if end < start { return Err(format!("empty register range for {}", name)); }
+                range = (start, end - start + 1);
+            }
+            Operand::Literal(v) => lit = *v,
+            Operand::Label(l) => target = cx.label(l)? as i64 - addr as i64,
+            o => indexes.push(cx.pools.operand(o).map_err(|e| e.to_string())?)
+        }
+    }
+
+    let reg = |i: usize, bits: u32| -> Result<u16, String> {
+        let r = regs[i];
+        if r >= 1 << bits { return Err(format!("register v{} is out of range for {}", r, name)); }
+        Ok(r as u16)
+    };
+    let literal = |min: i64, max: i64| -> Result<i64, String> {
+        if lit < min || lit > max { return Err(format!("literal {} is out of range for {}", lit, name)); }
+        Ok(lit)
+    };
+    let offset = |min: i64, max: i64| -> Result<i64, String> {
+        if target < min || target > max { return Err(format!("branch offset {} is out of range for {}", target, name)); }
+        Ok(target)
+    };
+    let index16 = |i: usize| -> Result<u16, String> {
+        if indexes[i] > 0xffff { return Err(format!("index {} is too large for {}", indexes[i], name)); }
+        Ok(indexes[i] as u16)
+    };
+    let wide = |v: u32| [v as u16, (v >> 16) as u16];
+    let arguments = |list: &[u32]| -> Result<(u16, u16), String> {
+        if list.len() > 5 || list.iter().any(|r| *r > 0xf) { return Err(format!("invalid register list for {}", name)); }
+        let r = |i: usize| list.get(i).copied().unwrap_or(0) as u16;
+        Ok((r(4) << 8 | (list.len() as u16) << 12, r(0) | r(1) << 4 | r(2) << 8 | r(3) << 12))
+    };
+    let count = |range: (u32, u32)| -> Result<(u16, u16), String> {
+        if range.1 > 0xff || range.0 > 0xffff { return Err(format!("invalid register range for {}", name)); }
+        Ok(((range.1 as u16) << 8, range.0 as u16))
+    };
+
+    let units = match opcode.format() {
+        Format::F10x => vec![op],
+        Format::F12x => vec![op | reg(0, 4)? << 8 | reg(1, 4)? << 12],
+        Format::F11n => vec![op | reg(0, 4)? << 8 | (literal(-8, 7)? as u16 & 0xf) << 12],
+        Format::F11x => vec![op | reg(0, 8)? << 8],
+        Format::F10t => vec![op | (offset(i8::MIN as i64, i8::MAX as i64)? as u8 as u16) << 8],
+        Format::F20t => vec![op, offset(i16::MIN as i64, i16::MAX as i64)? as u16],
+        Format::F22x => vec![op | reg(0, 8)? << 8, reg(1, 16)?],
+        Format::F21t => vec![op | reg(0, 8)? << 8, offset(i16::MIN as i64, i16::MAX as i64)? as u16],
+        Format::F21s => vec![op | reg(0, 8)? << 8, literal(i16::MIN as i64, i16::MAX as i64)? as u16],
+        Format::F21h => {
+            let shift = if opcode == Opcode::ConstHigh16 { 16 } else { 48 };
+            if lit & ((1 << shift) - 1) != 0 || (shift == 16 && literal(i32::MIN as i64, i32::MAX as i64).is_err())
+            {
+                return Err(format!("literal {} can't be encoded by {}", lit, name));
+            }
+            vec![op | reg(0, 8)? << 8, (lit >> shift) as u16]
+        }
+        Format::F21c => vec![op | reg(0, 8)? << 8, index16(0)?],
+        Format::F23x => vec![op | reg(0, 8)? << 8, reg(1, 8)? | reg(2, 8)? << 8],
+        Format::F22b => vec![op | reg(0, 8)? << 8, reg(1, 8)? | (literal(i8::MIN as i64, i8::MAX as i64)? as u8 as u16) << 8],
+        Format::F22t => vec![op | reg(0, 4)? << 8 | reg(1, 4)? << 12, offset(i16::MIN as i64, i16::MAX as i64)? as u16],
+        Format::F22s => vec![op | reg(0, 4)? << 8 | reg(1, 4)? << 12, literal(i16::MIN as i64, i16::MAX as i64)? as u16],
+        Format::F22c => vec![op | reg(0, 4)? << 8 | reg(1, 4)? << 12, index16(0)?],
+        Format::F30t => {
+            let [lo, hi] = wide(offset(i32::MIN as i64, i32::MAX as i64)? as u32);
+            vec![op, lo, hi]
+        }
+        Format::F32x => vec![op, reg(0, 16)?, reg(1, 16)?],
+        Format::F31i => {
+            let [lo, hi] = wide(literal(i32::MIN as i64, u32::MAX as i64)? as u32);
+            vec![op | reg(0, 8)? << 8, lo, hi]
+        }
+        Format::F31t => {
+            let [lo, hi] = wide(offset(i32::MIN as i64, i32::MAX as i64)? as u32);
+            vec![op | reg(0, 8)? << 8, lo, hi]
+        }
+        Format::F31c => {
+            let [lo, hi] = wide(indexes[0]);
+            vec![op | reg(0, 8)? << 8, lo, hi]
+        }
+        Format::F35c => {
+            let (a, b) = arguments(&list)?;
+            vec![op | a, index16(0)?, b]
+        }
+        Format::F3rc => {
+            let (a, b) = count(range)?;
+            vec![op | a, index16(0)?, b]
+        }
+        Format::F45cc => {
+            let (a, b) = arguments(&list)?;
+            vec![op | a, index16(0)?, b, index16(1)?]
+        }
+        Format::F4rcc => {
+            let (a, b) = count(range)?;
+            vec![op | a, index16(0)?, b, index16(1)?]
+        }
+        Format::F51l => {
+            let v = lit as u64;
+            vec![op | reg(0, 8)? << 8, v as u16, (v >> 16) as u16, (v >> 32) as u16, (v >> 48) as u16]
+        }
+    };
+    Ok(units)
+}
+
+/* Encodes a payload, switch targets are relative to the switch instruction at base */
+fn encode_payload(p: &Payload, base: u32, cx: &MethodContext) -> Result<Vec<u16>, String>
+{
+    let mut units = vec![];
+    let int = |units: &mut Vec<u16>, v: i32| units.extend_from_slice(&[v as u16, (v as u32 >> 16) as u16]);
+    let relative = |l: &str| -> Result<i32, String> { Ok((cx.label(l)? as i64 - base as i64) as i32) };
+    match p {
+        Payload::PackedSwitch(s) => {
+            units.extend_from_slice(&[0x0100, s.targets.len() as u16]);
+            int(&mut units, s.first_key);
+            for t in &s.targets { int(&mut units, relative(t)?); }
+        }
+        Payload::SparseSwitch(s) => {
+            // Keys must be sorted
+            let mut entries: Vec<&(i32, String)> = s.entries.iter().collect();
+            entries.sort_by_key(|(k, _)| *k);
+            units.extend_from_slice(&[0x0200, entries.len() as u16]);
+            for (k, _) in &entries { int(&mut units, *k); }
+            for (_, t) in &entries { int(&mut units, relative(t)?); }
+        }
+        Payload::ArrayData(a) => {
+            let width = a.element_width as usize;
+            if ![1, 2, 4, 8].contains(&width) { return Err(format!("invalid array-data element width {}", width)); }
+            units.extend_from_slice(&[0x0300, width as u16]);
+            int(&mut units, a.values.len() as i32);
+            let mut bytes: Vec<u8> = a.values.iter().flat_map(|v| v.to_le_bytes()[..width].to_vec()).collect();
+            if bytes.len() % 2 == 1 { bytes.push(0); }
+            units.extend(bytes.chunks(2).map(|b| u16::from_le_bytes([b[0], b[1]])));
+        }
+    }
+    Ok(units)
+}
+
+/* Index of the argument held in a parameter register */
+fn parameter_index(m: &SmaliMethod, register: Register) -> Option<usize>
+{
+    let p = match register {
+        Register::P(p) => p as u32,
+        Register::V(v) => (v as u32).checked_sub(m.locals())?
+    };
+    let mut next = if m.is_static() { 0 } else { 1 };
+    for (i, a) in m.signature.args.iter().enumerate()
+    {
+        if next == p { return Some(i); }
+        next += if a.is_wide() { 2 } else { 1 };
+    }
+    None
+}
+
+/* Assembles a method body, None for abstract and native methods */
+fn assemble_code(m: &SmaliMethod, pools: &Pools) -> Result<Option<Code>, String>
+{
+    if m.instructions.is_empty() { return Ok(None); }
+    let ins = m.parameter_registers();
+    let locals = m.locals();
+    let registers = locals + ins;
+    if registers > 0xffff { return Err(format!("{} registers is too many", registers)); }
+
+    // Lay out the instructions, payloads are aligned to 32 bits and labels take the address of what follows them
+    let mut cx = MethodContext { locals, labels: HashMap::new(), pools };
+    let mut addrs = Vec::with_capacity(m.instructions.len());
+    let mut pending = vec![];
+    let mut addr: u32 = 0;
+    for i in &m.instructions
+    {
+        match i {
+            SmaliInstruction::Label(l) => pending.push(l.as_str()),
+            SmaliInstruction::Instruction(_) | SmaliInstruction::Payload(_) => {
+                if matches!(i, SmaliInstruction::Payload(_)) && addr % 2 == 1 { addr += 1; }
+                for l in pending.drain(..)
+                {
+                    if cx.labels.insert(l, addr).is_some() { return Err(format!("label :{} is defined twice", l)); }
+                }
+            }
+            _ => {}
+        }
+        addrs.push(addr);
+        match i {
+            SmaliInstruction::Instruction(d) => addr += d.opcode().format().size() as u32,
+            SmaliInstruction::Payload(p) => addr += payload_size(p),
+            _ => {}
+        }
+    }
+    for l in pending
+    {
+        if cx.labels.insert(l, addr).is_some() { return Err(format!("label :{} is defined twice", l)); }
+    }
+
+    // Switch targets are relative to the switch, not the payload
+    let mut switches: HashMap<u32, u32> = HashMap::new();
+    for (i, item) in m.instructions.iter().enumerate()
+    {
+        if let SmaliInstruction::Instruction(DexInstruction::PackedSwitch { payload, .. } | DexInstruction::SparseSwitch { payload, .. }) = item
+        {
+            switches.insert(cx.label(payload)?, addrs[i]);
+        }
+    }
+
+    let mut insns: Vec<u16> = Vec::with_capacity(addr as usize);
+    let mut outs = 0;
+    let mut debug_events = vec![];
+    for (i, item) in m.instructions.iter().enumerate()
+    {
+        match item {
+            SmaliInstruction::Instruction(d) => {
+                let opcode = d.opcode();
+                insns.extend(encode_instruction(d, addrs[i], &cx)?);
+                if matches!(opcode.format(), Format::F35c | Format::F3rc | Format::F45cc | Format::F4rcc) && opcode.reference_kind() != Some(crate::instructions::OperandKind::Type)
+                {
+                    let words = d.operands().iter().map(|o| match o {
+                        Operand::RegisterList(l) => l.len() as u32,
+                        Operand::RegisterRange(r) => (cx.reg(&r.end) + 1).saturating_sub(cx.reg(&r.start)),
+                        _ => 0
+                    }).sum();
+                    outs = outs.max(words);
+                }
+            }
+            SmaliInstruction::Payload(p) => {
+                insns.resize(addrs[i] as usize, 0);
+                insns.extend(encode_payload(p, *switches.get(&addrs[i]).unwrap_or(&addrs[i]), &cx)?);
+            }
+            SmaliInstruction::Line(_) | SmaliInstruction::Local { .. } | SmaliInstruction::EndLocal(_) | SmaliInstruction::RestartLocal(_)
+            | SmaliInstruction::Prologue | SmaliInstruction::Epilogue => debug_events.push((addrs[i], item)),
+            SmaliInstruction::Directive(d) => {
+                if source_directive(d).is_none() { return Err(format!("unsupported directive `{}`", d)); }
+                debug_events.push((addrs[i], item));
+            }
+            SmaliInstruction::Label(_) | SmaliInstruction::TryCatch { .. } => {}
+        }
+    }
+
+    let (tries, handlers) = assemble_tries(m, &cx)?;
+
+    let mut names = vec![None; m.signature.args.len()];
+    for p in &m.params
+    {
+        if let (Some(i), Some(n)) = (parameter_index(m, p.register), &p.name) { names[i] = Some(pools.string(n).map_err(|e| e.to_string())?); }
+    }
+    let debug = if debug_events.is_empty() && names.iter().all(|n| n.is_none()) { None }
+                else { Some(assemble_debug_info(&debug_events, &names, &cx).map_err(|e| e.to_string())?) };
+
+    Ok(Some(Code {
+        registers: registers as u16,
+        ins: ins as u16,
+        outs: outs as u16,
+        insns,
+        tries,
+        handlers,
+        debug,
+        debug_off: 0,
+        code_off: 0
+    }))
+}
+
+/* Converts the .catch directives to try_items, splitting overlapping ranges as a try_item can't overlap another */
+#[allow(clippy::type_complexity)]
+fn assemble_tries(m: &SmaliMethod, cx: &MethodContext) -> Result<(Vec<(u32, u16, usize)>, Vec<Vec<(Option<u32>, u32)>>), String>
+{
+    let mut ranges: Vec<(u32, u32, Vec<(Option<u32>, u32)>)> = vec![];
+    for i in &m.instructions
+    {
+        if let SmaliInstruction::TryCatch { exception, start, end, handler } = i
+        {
+            let (start, end) = (cx.label(start)?, cx.label(end)?);
+            let exception = exception.as_ref().map(|e| cx.pools.descriptor(&e.as_jni_type())).transpose().map_err(|e| e.to_string())?;
+            let handler = (exception, cx.label(handler)?);
+            match ranges.iter_mut().find(|(s, e, _)| *s == start && *e == end) {
+                Some((_, _, h)) => h.push(handler),
+                None => ranges.push((start, end, vec![handler]))
+            }
+        }
+    }
+
+    let mut bounds: Vec<u32> = ranges.iter().flat_map(|(s, e, _)| [*s, *e]).collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut tries = vec![];
+    let mut handler_lists: Vec<Vec<(Option<u32>, u32)>> = vec![];
+    for w in bounds.windows(2)
+    {
+        let (start, end) = (w[0], w[1]);
+        let mut handlers: Vec<(Option<u32>, u32)> = vec![];
+        for (_, _, h) in ranges.iter().filter(|(s, e, _)| *s <= start && *e >= end)
+        {
+            for (t, a) in h
+            {
+                if !handlers.iter().any(|(x, _)| x == t) { handlers.push((*t, *a)); }
+            }
+        }
+        if handlers.is_empty() { continue; }
+        // The catch-all handler has to come last
+        handlers.sort_by_key(|(t, _)| t.is_none());
+        if end - start > 0xffff { return Err("try block is too long".to_string()); }
+
+        let list = match handler_lists.iter().position(|l| *l == handlers) {
+            Some(i) => i,
+            None => { handler_lists.push(handlers); handler_lists.len() - 1 }
+        };
+        tries.push((start, (end - start) as u16, list));
+    }
+    Ok((tries, handler_lists))
+}
+
+/* Encodes the debug directives as a debug_info_item state machine program */
+fn assemble_debug_info(events: &[(u32, &SmaliInstruction)], names: &[Option<u32>], cx: &MethodContext) -> Result<Vec<u8>, SmaliError>
+{
+    let mut out = vec![];
+    let line_start = events.iter().find_map(|(_, e)| match e { SmaliInstruction::Line(l) => Some(*l), _ => None }).unwrap_or(0);
+    uleb128(&mut out, line_start);
+    uleb128(&mut out, names.len() as u32);
+    for n in names { uleb128p1(&mut out, *n); }
+
+    let (mut addr, mut line) = (0u32, line_start as i64);
+    for (at, e) in events
+    {
+        if let SmaliInstruction::Line(l) = e
+        {
+            // Special opcodes move both the line and address, if the deltas are small enough
+            let (mut dl, mut da) = (*l as i64 - line, at - addr);
+            if !(-4..=10).contains(&dl)
+            {
+                out.push(0x02);
+                sleb128(&mut out, dl as i32);
+                dl = 0;
+            }
+            if (dl + 4) as u32 + da * 15 + 0x0a > 0xff
+            {
+                out.push(0x01);
+                uleb128(&mut out, da);
+                da = 0;
+            }
+            out.push(((dl + 4) as u32 + da * 15 + 0x0a) as u8);
+            line = *l as i64;
+            addr = *at;
+            continue;
+        }
+
+        if *at > addr
+        {
+            out.push(0x01);
+            uleb128(&mut out, at - addr);
+            addr = *at;
+        }
+        match e {
+            SmaliInstruction::Local { register, name, signature, generic } => {
+                out.push(if generic.is_some() { 0x04 } else { 0x03 });
+                uleb128(&mut out, cx.reg(register));
+                uleb128p1(&mut out, name.as_ref().map(|n| cx.pools.string(n)).transpose()?);
+                uleb128p1(&mut out, signature.as_ref().map(|t| cx.pools.type_idx(t)).transpose()?);
+                if let Some(g) = generic { uleb128p1(&mut out, Some(cx.pools.string(g)?)); }
+            }
+            SmaliInstruction::EndLocal(r) => { out.push(0x05); uleb128(&mut out, cx.reg(r)); }
+            SmaliInstruction::RestartLocal(r) => { out.push(0x06); uleb128(&mut out, cx.reg(r)); }
+            SmaliInstruction::Prologue => out.push(0x07),
+            SmaliInstruction::Epilogue => out.push(0x08),
+            SmaliInstruction::Directive(d) => {
+                out.push(0x09);
+                uleb128p1(&mut out, source_directive(d).map(|f| cx.pools.string(&f)).transpose()?);
+            }
+            _ => {}
+        }
+    }
+    out.push(0x00);
+    Ok(out)
+}
+
+/* Orders the classes so that superclasses and interfaces defined in the file come before their subclasses */
+fn order_classes<'a>(classes: &[&'a SmaliClass]) -> Result<Vec<&'a SmaliClass>, SmaliError>
+{
+    let mut by_name = HashMap::new();
+    for (i, c) in classes.iter().enumerate()
+    {
+        if by_name.insert(c.name.as_jni_type(), i).is_some()
+        {
+            return Err(SmaliError::Other(format!("{} is defined more than once", c.name.as_jni_type())));
+        }
+    }
+
+    fn visit<'a>(i: usize, classes: &[&'a SmaliClass], by_name: &HashMap<String, usize>, state: &mut Vec<u8>, out: &mut Vec<&'a SmaliClass>) -> Result<(), SmaliError>
+    {
+        match state[i] {
+            2 => return Ok(()),
+            1 => return Err(SmaliError::Other(format!("{} inherits from itself", classes[i].name.as_jni_type()))),
+            _ => state[i] = 1
+        }
+        let c = classes[i];
+        let supertypes = std::iter::once(&c.super_class).chain(c.implements.iter()).filter(|_| c.name.as_jni_type() != OBJECT);
+        for s in supertypes
+        {
+            if let Some(j) = by_name.get(&s.as_jni_type()) { visit(*j, classes, by_name, state, out)?; }
+        }
+        state[i] = 2;
+        out.push(c);
+        Ok(())
+    }
+
+    let mut state = vec![0; classes.len()];
+    let mut out = Vec::with_capacity(classes.len());
+    for i in 0..classes.len() { visit(i, classes, &by_name, &mut state, &mut out)?; }
+    Ok(out)
+}
+
+/* A class with its members sorted by index and split the way class_data_item stores them */
+struct ClassEntry<'a> {
+    class: &'a SmaliClass,
+    static_fields: Vec<(u32, &'a SmaliField)>,
+    instance_fields: Vec<(u32, &'a SmaliField)>,
+    direct_methods: Vec<(u32, &'a SmaliMethod, Option<Code>)>,
+    virtual_methods: Vec<(u32, &'a SmaliMethod, Option<Code>)>,
+    interfaces_off: u32,
+    static_values_off: u32,
+    annotations_off: u32,
+    class_data_off: u32
+}
+
+impl<'a> ClassEntry<'a> {
+    fn new(class: &'a SmaliClass, pools: &Pools, version: u32) -> Result<ClassEntry<'a>, SmaliError>
+    {
+        let name = class.name.as_jni_type();
+        let mut entry = ClassEntry {
+            class,
+            static_fields: vec![],
+            instance_fields: vec![],
+            direct_methods: vec![],
+            virtual_methods: vec![],
+            interfaces_off: 0,
+            static_values_off: 0,
+            annotations_off: 0,
+            class_data_off: 0
+        };
+
+        let mut seen = HashSet::new();
+        for f in &class.fields
+        {
+            let idx = pools.field(&FieldRef { class: class.name.clone(), name: f.name.clone(), signature: f.signature.clone() })?;
+            if !seen.insert(idx) { return Err(SmaliError::Other(format!("{}: field {} is defined twice", name, f.name))); }
+            if f.modifiers.contains(&crate::types::Modifier::Static) { entry.static_fields.push((idx, f)); }
+            else { entry.instance_fields.push((idx, f)); }
+        }
+
+        let mut seen = HashSet::new();
+        for m in &class.methods
+        {
+            let idx = pools.method(&MethodRef { class: TypeSignature::Object(class.name.clone()), name: m.name.clone(), signature: m.signature.clone() })?;
+            let context = || format!("{}->{}{}", name, m.name, m.signature.to_jni());
+            if !seen.insert(idx) { return Err(SmaliError::Other(format!("{}: method is defined twice", context()))); }
+            for i in &m.instructions
+            {
+                if let SmaliInstruction::Instruction(d) = i
+                {
+                    let needs = required_version(d.opcode());
+                    if needs > version
+                    {
+                        return Err(SmaliError::Unsupported(format!("{}: {} needs DEX version {:03}", context(), d.opcode().mnemonic(), needs)));
+                    }
+                }
+            }
+            let code = assemble_code(m, pools).map_err(|e| SmaliError::Other(format!("{}: {}", context(), e)))?;
+            let direct = m.constructor || m.name.starts_with('<')
+                || m.modifiers.iter().any(|m| matches!(m, crate::types::Modifier::Static | crate::types::Modifier::Private));
+            if direct { entry.direct_methods.push((idx, m, code)); } else { entry.virtual_methods.push((idx, m, code)); }
+        }
+
+        entry.static_fields.sort_by_key(|(i, _)| *i);
+        entry.instance_fields.sort_by_key(|(i, _)| *i);
+        entry.direct_methods.sort_by_key(|(i, _, _)| *i);
+        entry.virtual_methods.sort_by_key(|(i, _, _)| *i);
+        Ok(entry)
+    }
+
+    /* Initial values of the static fields, trailing default values are left out */
+    fn static_values(&self) -> Vec<EncodedValue>
+    {
+        let last = match self.static_fields.iter().rposition(|(_, f)| f.initial_value.is_some()) {
+            Some(l) => l,
+            None => return vec![]
+        };
+        self.static_fields[..=last].iter().map(|(_, f)| match &f.initial_value {
+            Some(v) => v.clone().coerce(&f.signature),
+            None => EncodedValue::default_for(&f.signature)
+        }).collect()
+    }
+
+    fn methods(&self) -> impl Iterator<Item = &(u32, &'a SmaliMethod, Option<Code>)>
+    {
+        self.direct_methods.iter().chain(self.virtual_methods.iter())
+    }
+
+    fn methods_mut(&mut self) -> impl Iterator<Item = &mut (u32, &'a SmaliMethod, Option<Code>)>
+    {
+        self.direct_methods.iter_mut().chain(self.virtual_methods.iter_mut())
+    }
+}
+
+/* The annotation_items of a class grouped into sets, each set sorted by type */
+#[derive(Default)]
+struct ClassAnnotations {
+    class: Vec<u32>,
+    fields: Vec<(u32, Vec<u32>)>,
+    methods: Vec<(u32, Vec<u32>)>,
+    parameters: Vec<(u32, Vec<Vec<u32>>)>
+}
+
+impl ClassAnnotations {
+    fn is_empty(&self) -> bool
+    {
+        self.class.is_empty() && self.fields.is_empty() && self.methods.is_empty() && self.parameters.is_empty()
+    }
+}
+
+/* The data section as it is written, remembering where each section starts for the map_list */
+struct Data {
+    bytes: Vec<u8>,
+    base: u32,
+    map: Vec<(u16, u32, u32)>
+}
+
+impl Data {
+    fn offset(&self) -> u32
+    {
+        self.base + self.bytes.len() as u32
+    }
+
+    fn align(&mut self)
+    {
+        while !self.bytes.len().is_multiple_of(4) { self.bytes.push(0); }
+    }
+
+    fn section(&mut self, item_type: u16, count: usize, start: u32)
+    {
+        if count > 0 { self.map.push((item_type, count as u32, start)); }
+    }
+}
+
+/* Writes a deduplicated item, returning its offset */
+fn intern<K: std::hash::Hash + Eq>(data: &mut Data, seen: &mut HashMap<K, u32>, key: K, aligned: bool, bytes: &[u8]) -> u32
+{
+    if let Some(off) = seen.get(&key) { return *off; }
+    if aligned { data.align(); }
+    let off = data.offset();
+    data.bytes.extend_from_slice(bytes);
+    seen.insert(key, off);
+    off
+}
+
+fn annotation_item(a: &SmaliAnnotation, pools: &Pools) -> Result<(u32, Vec<u8>), SmaliError>
+{
+    let mut bytes = vec![match a.visibility {
+        AnnotationVisibility::Build => 0,
+        AnnotationVisibility::Runtime => 1,
+        AnnotationVisibility::System => 2
+    }];
+    write_encoded_annotation(&mut bytes, a, pools)?;
+    Ok((pools.type_idx(&a.annotation_type)?, bytes))
+}
+
+pub(crate) fn write_dex(classes: &[&SmaliClass], version: DexVersion) -> Result<Vec<u8>, SmaliError>
+{
+    let v = version.number();
+    let classes = order_classes(classes)?;
+    let mut collector = Collector::default();
+    for c in &classes { collector.class(c)?; }
+    if v < 38 && (!collector.method_handles.is_empty() || !collector.call_sites.is_empty())
+    {
+        return Err(SmaliError::Unsupported(format!("method handles and call sites need DEX version 038, not {:03}", v)));
+    }
+    let pools = collector.finish()?;
+    let mut entries = classes.iter().map(|c| ClassEntry::new(c, &pools, v)).collect::<Result<Vec<ClassEntry>, SmaliError>>()?;
+
+    let ids_size = HEADER_SIZE as usize + pools.strings.len() * 4 + pools.types.len() * 4 + pools.protos.len() * 12
+        + pools.fields.len() * 8 + pools.methods.len() * 8 + entries.len() * 32 + pools.call_sites.len() * 4 + pools.method_handles.len() * 8;
+    let mut data = Data { bytes: vec![], base: ids_size as u32, map: vec![] };
+
+    let start = data.offset();
+    let mut string_offs = Vec::with_capacity(pools.strings.len());
+    for s in &pools.strings
+    {
+        string_offs.push(data.offset());
+        let (len, bytes) = encode_mutf8(s);
+        uleb128(&mut data.bytes, len);
+        data.bytes.extend_from_slice(&bytes);
+        data.bytes.push(0);
+    }
+    data.section(TYPE_STRING_DATA_ITEM, pools.strings.len(), start);
+
+    // type_lists for the proto parameters and class interfaces
+    data.align();
+    let start = data.offset();
+    let mut type_lists: HashMap<Vec<u16>, u32> = HashMap::new();
+    let mut type_list = |data: &mut Data, list: Vec<u16>| -> u32 {
+        if list.is_empty() { return 0; }
+        let mut bytes = vec![];
+        u32_le(&mut bytes, list.len() as u32);
+        for t in &list { u16_le(&mut bytes, *t); }
+        intern(data, &mut type_lists, list, true, &bytes)
+    };
+    let mut proto_params = vec![];
+    for p in &pools.protos
+    {
+        let list = p.args.iter().map(|a| Ok(pools.type_idx(a)? as u16)).collect::<Result<Vec<u16>, SmaliError>>()?;
+        proto_params.push(type_list(&mut data, list));
+    }
+    for e in entries.iter_mut()
+    {
+        let list = e.class.implements.iter().map(|i| Ok(pools.descriptor(&i.as_jni_type())? as u16)).collect::<Result<Vec<u16>, SmaliError>>()?;
+        e.interfaces_off = type_list(&mut data, list);
+    }
+    data.section(TYPE_TYPE_LIST, type_lists.len(), start);
+
+    // encoded_array_items for the call sites and static values
+    let start = data.offset();
+    let mut arrays: HashMap<Vec<u8>, u32> = HashMap::new();
+    let mut call_site_offs = vec![];
+    for c in &pools.call_sites
+    {
+        let mut values = vec![EncodedValue::MethodHandle(MethodHandle::InvokeStatic(c.bootstrap.clone())),
+                              EncodedValue::String(c.name.clone()), EncodedValue::MethodType(c.proto.clone())];
+        values.extend(c.args.iter().cloned());
+        let mut bytes = vec![];
+        write_array(&mut bytes, &values, &pools)?;
+        call_site_offs.push(intern(&mut data, &mut arrays, bytes.clone(), false, &bytes));
+    }
+    for e in entries.iter_mut()
+    {
+        let values = e.static_values();
+        if values.is_empty() { continue; }
+        let mut bytes = vec![];
+        write_array(&mut bytes, &values, &pools)?;
+        e.static_values_off = intern(&mut data, &mut arrays, bytes.clone(), false, &bytes);
+    }
+    data.section(TYPE_ENCODED_ARRAY_ITEM, arrays.len(), start);
+
+    // annotation_items, grouped by the set they belong to
+    let start = data.offset();
+    let mut items: HashMap<Vec<u8>, u32> = HashMap::new();
+    let mut item_set = |data: &mut Data, annotations: &[SmaliAnnotation]| -> Result<Vec<u32>, SmaliError> {
+        let mut set = vec![];
+        for a in annotations
+        {
+            let (t, bytes) = annotation_item(a, &pools)?;
+            set.push((t, intern(data, &mut items, bytes.clone(), false, &bytes)));
+        }
+        set.sort_by_key(|(t, _)| *t);
+        Ok(set.into_iter().map(|(_, off)| off).collect())
+    };
+    let mut class_annotations = vec![];
+    for e in &entries
+    {
+        let mut a = ClassAnnotations { class: item_set(&mut data, &e.class.annotations)?, ..Default::default() };
+        for (idx, f) in e.static_fields.iter().chain(e.instance_fields.iter())
+        {
+            if !f.annotations.is_empty() { a.fields.push((*idx, item_set(&mut data, &f.annotations)?)); }
+        }
+        for (idx, m, _) in e.methods()
+        {
+            if !m.annotations.is_empty() { a.methods.push((*idx, item_set(&mut data, &m.annotations)?)); }
+            if m.params.iter().any(|p| !p.annotations.is_empty())
+            {
+                let mut sets = vec![vec![]; m.signature.args.len()];
+                for p in &m.params
+                {
+                    match parameter_index(m, p.register) {
+                        Some(i) => sets[i] = item_set(&mut data, &p.annotations)?,
+                        None if p.annotations.is_empty() => {}
+                        None => return Err(SmaliError::Other(format!("{}->{}{}: {} is not a parameter register",
+                                                                     e.class.name.as_jni_type(), m.name, m.signature.to_jni(), p.register)))
+                    }
+                }
+                a.parameters.push((*idx, sets));
+            }
+        }
+        a.fields.sort_by_key(|(i, _)| *i);
+        a.methods.sort_by_key(|(i, _)| *i);
+        a.parameters.sort_by_key(|(i, _)| *i);
+        class_annotations.push(a);
+    }
+    data.section(TYPE_ANNOTATION_ITEM, items.len(), start);
+
+    // annotation_set_items
+    data.align();
+    let start = data.offset();
+    let mut sets: HashMap<Vec<u32>, u32> = HashMap::new();
+    let mut set = |data: &mut Data, offs: &Vec<u32>| -> u32 {
+        if offs.is_empty() { return 0; }
+        let mut bytes = vec![];
+        u32_le(&mut bytes, offs.len() as u32);
+        for o in offs { u32_le(&mut bytes, *o); }
+        intern(data, &mut sets, offs.clone(), true, &bytes)
+    };
+    let class_sets: Vec<_> = class_annotations.iter().map(|a| (
+        set(&mut data, &a.class),
+        a.fields.iter().map(|(i, s)| (*i, set(&mut data, s))).collect::<Vec<(u32, u32)>>(),
+        a.methods.iter().map(|(i, s)| (*i, set(&mut data, s))).collect::<Vec<(u32, u32)>>(),
+        a.parameters.iter().map(|(i, p)| (*i, p.iter().map(|s| set(&mut data, s)).collect::<Vec<u32>>())).collect::<Vec<(u32, Vec<u32>)>>()
+    )).collect();
+    data.section(TYPE_ANNOTATION_SET_ITEM, sets.len(), start);
+
+    // annotation_set_ref_lists for the parameter annotations
+    let start = data.offset();
+    let mut ref_lists: HashMap<Vec<u32>, u32> = HashMap::new();
+    let mut class_refs = vec![];
+    for (_, _, _, parameters) in &class_sets
+    {
+        let refs: Vec<(u32, u32)> = parameters.iter().map(|(i, list)| {
+            let mut bytes = vec![];
+            u32_le(&mut bytes, list.len() as u32);
+            for o in list { u32_le(&mut bytes, *o); }
+            (*i, intern(&mut data, &mut ref_lists, list.clone(), true, &bytes))
+        }).collect();
+        class_refs.push(refs);
+    }
+    data.section(TYPE_ANNOTATION_SET_REF_LIST, ref_lists.len(), start);
+
+    // annotations_directory_items
+    data.align();
+    let start = data.offset();
+    let mut directories = 0;
+    for (n, e) in entries.iter_mut().enumerate()
+    {
+        if class_annotations[n].is_empty() { continue; }
+        let (class_set, fields, methods, _) = &class_sets[n];
+        let params = &class_refs[n];
+        e.annotations_off = data.offset();
+        for v in [*class_set, fields.len() as u32, methods.len() as u32, params.len() as u32] { u32_le(&mut data.bytes, v); }
+        for (i, off) in fields.iter().chain(methods.iter()).chain(params.iter())
+        {
+            u32_le(&mut data.bytes, *i);
+            u32_le(&mut data.bytes, *off);
+        }
+        directories += 1;
+    }
+    data.section(TYPE_ANNOTATIONS_DIRECTORY_ITEM, directories, start);
+
+    // debug_info_items
+    let start = data.offset();
+    let mut debug_items = 0;
+    for e in entries.iter_mut()
+    {
+        for (_, _, code) in e.methods_mut()
+        {
+            if let Some(Code { debug: Some(d), debug_off, .. }) = code
+            {
+                *debug_off = data.offset();
+                data.bytes.extend_from_slice(d);
+                debug_items += 1;
+            }
+        }
+    }
+    data.section(TYPE_DEBUG_INFO_ITEM, debug_items, start);
+
+    // code_items
+    data.align();
+    let start = data.offset();
+    let mut code_items = 0;
+    for e in entries.iter_mut()
+    {
+        for (_, _, code) in e.methods_mut()
+        {
+            let Some(c) = code else { continue };
+            data.align();
+            c.code_off = data.offset();
+            let out = &mut data.bytes;
+            for v in [c.registers, c.ins, c.outs, c.tries.len() as u16] { u16_le(out, v); }
+            u32_le(out, c.debug_off);
+            u32_le(out, c.insns.len() as u32);
+            for u in &c.insns { u16_le(out, *u); }
+            if !c.tries.is_empty()
+            {
+                if c.insns.len() % 2 == 1 { u16_le(out, 0); }
+
+                // The handler offsets are relative to the start of the encoded_catch_handler_list
+                let mut list = vec![];
+                uleb128(&mut list, c.handlers.len() as u32);
+                let mut handler_offs = vec![];
+                for h in &c.handlers
+                {
+                    handler_offs.push(list.len() as u16);
+                    let typed: Vec<&(Option<u32>, u32)> = h.iter().filter(|(t, _)| t.is_some()).collect();
+                    let catch_all = h.iter().find(|(t, _)| t.is_none());
+                    sleb128(&mut list, if catch_all.is_some() { -(typed.len() as i32) } else { typed.len() as i32 });
+                    for (t, a) in typed
+                    {
+                        uleb128(&mut list, t.unwrap_or(0));
+                        uleb128(&mut list, *a);
+                    }
+                    if let Some((_, a)) = catch_all { uleb128(&mut list, *a); }
+                }
+                for (start, count, h) in &c.tries
+                {
+                    u32_le(out, *start);
+                    u16_le(out, *count);
+                    u16_le(out, handler_offs[*h]);
+                }
+                out.extend_from_slice(&list);
+            }
+            code_items += 1;
+        }
+    }
+    data.section(TYPE_CODE_ITEM, code_items, start);
+
+    // class_data_items
+    let start = data.offset();
+    let mut class_data = 0;
+    for e in entries.iter_mut()
+    {
+        if e.static_fields.is_empty() && e.instance_fields.is_empty() && e.direct_methods.is_empty() && e.virtual_methods.is_empty() { continue; }
+        e.class_data_off = data.offset();
+        let out = &mut data.bytes;
+        for n in [e.static_fields.len(), e.instance_fields.len(), e.direct_methods.len(), e.virtual_methods.len()] { uleb128(out, n as u32); }
+        for fields in [&e.static_fields, &e.instance_fields]
+        {
+            let mut prev = 0;
+            for (idx, f) in fields
+            {
+                uleb128(out, idx - prev);
+                uleb128(out, f.access_flags());
+                prev = *idx;
+            }
+        }
+        for methods in [&e.direct_methods, &e.virtual_methods]
+        {
+            let mut prev = 0;
+            for (idx, m, code) in methods
+            {
+                uleb128(out, idx - prev);
+                uleb128(out, m.access_flags());
+                uleb128(out, code.as_ref().map_or(0, |c| c.code_off));
+                prev = *idx;
+            }
+        }
+        class_data += 1;
+    }
+    data.section(TYPE_CLASS_DATA_ITEM, class_data, start);
+
+    // The id sections go before the data, in the order of their offsets
+    let mut offset = HEADER_SIZE;
+    let mut ids = vec![(TYPE_HEADER_ITEM, 1, 0)];
+    for (item_type, count, size) in [(TYPE_STRING_ID_ITEM, pools.strings.len(), 4), (TYPE_TYPE_ID_ITEM, pools.types.len(), 4),
+                                     (TYPE_PROTO_ID_ITEM, pools.protos.len(), 12), (TYPE_FIELD_ID_ITEM, pools.fields.len(), 8),
+                                     (TYPE_METHOD_ID_ITEM, pools.methods.len(), 8), (TYPE_CLASS_DEF_ITEM, entries.len(), 32),
+                                     (TYPE_CALL_SITE_ID_ITEM, pools.call_sites.len(), 4), (TYPE_METHOD_HANDLE_ITEM, pools.method_handles.len(), 8)]
+    {
+        if count > 0 { ids.push((item_type, count as u32, offset)); }
+        offset += (count * size) as u32;
+    }
+    let section_off = |item_type: u16| ids.iter().find(|(t, _, _)| *t == item_type).map_or(0, |(_, _, off)| *off);
+
+    data.align();
+    let map_off = data.offset();
+    let mut map: Vec<(u16, u32, u32)> = ids.clone();
+    map.append(&mut data.map);
+    map.push((TYPE_MAP_LIST, 1, map_off));
+    u32_le(&mut data.bytes, map.len() as u32);
+    for (item_type, count, off) in &map
+    {
+        u16_le(&mut data.bytes, *item_type);
+        u16_le(&mut data.bytes, 0);
+        u32_le(&mut data.bytes, *count);
+        u32_le(&mut data.bytes, *off);
+    }
+
+    let file_size = data.offset();
+    let mut out = Vec::with_capacity(file_size as usize);
+    out.extend_from_slice(format!("dex\n{:03}\0", v).as_bytes());
+    out.extend_from_slice(&[0; 24]);
+    u32_le(&mut out, file_size);
+    u32_le(&mut out, HEADER_SIZE);
+    u32_le(&mut out, ENDIAN_CONSTANT);
+    u32_le(&mut out, 0);
+    u32_le(&mut out, 0);
+    u32_le(&mut out, map_off);
+    for (item_type, count) in [(TYPE_STRING_ID_ITEM, pools.strings.len()), (TYPE_TYPE_ID_ITEM, pools.types.len()),
+                               (TYPE_PROTO_ID_ITEM, pools.protos.len()), (TYPE_FIELD_ID_ITEM, pools.fields.len()),
+                               (TYPE_METHOD_ID_ITEM, pools.methods.len()), (TYPE_CLASS_DEF_ITEM, entries.len())]
+    {
+        u32_le(&mut out, count as u32);
+        u32_le(&mut out, section_off(item_type));
+    }
+    u32_le(&mut out, file_size - data.base);
+    u32_le(&mut out, data.base);
+
+    for off in &string_offs { u32_le(&mut out, *off); }
+    for t in &pools.types { u32_le(&mut out, pools.string(t)?); }
+    for (p, params) in pools.protos.iter().zip(&proto_params)
+    {
+        u32_le(&mut out, pools.string(&shorty(p))?);
+        u32_le(&mut out, pools.type_idx(&p.return_type)?);
+        u32_le(&mut out, *params);
+    }
+    for f in &pools.fields
+    {
+        u16_le(&mut out, pools.descriptor(&f.class.as_jni_type())? as u16);
+        u16_le(&mut out, pools.type_idx(&f.signature)? as u16);
+        u32_le(&mut out, pools.string(&f.name)?);
+    }
+    for m in &pools.methods
+    {
+        u16_le(&mut out, pools.type_idx(&m.class)? as u16);
+        u16_le(&mut out, pools.proto(&m.signature)? as u16);
+        u32_le(&mut out, pools.string(&m.name)?);
+    }
+    for e in &entries
+    {
+        let c = e.class;
+        let name = c.name.as_jni_type();
+        u32_le(&mut out, pools.descriptor(&name)?);
+        u32_le(&mut out, c.access_flags());
+        u32_le(&mut out, if name == OBJECT { NO_INDEX } else { pools.descriptor(&c.super_class.as_jni_type())? });
+        u32_le(&mut out, e.interfaces_off);
+        u32_le(&mut out, match &c.source { Some(s) => pools.string(s)?, None => NO_INDEX });
+        u32_le(&mut out, e.annotations_off);
+        u32_le(&mut out, e.class_data_off);
+        u32_le(&mut out, e.static_values_off);
+    }
+    for off in &call_site_offs { u32_le(&mut out, *off); }
+    for h in &pools.method_handles
+    {
+        let (kind, id) = match h {
+            MethodHandle::StaticPut(f) => (0x00, pools.field(f)?),
+            MethodHandle::StaticGet(f) => (0x01, pools.field(f)?),
+            MethodHandle::InstancePut(f) => (0x02, pools.field(f)?),
+            MethodHandle::InstanceGet(f) => (0x03, pools.field(f)?),
+            MethodHandle::InvokeStatic(m) => (0x04, pools.method(m)?),
+            MethodHandle::InvokeInstance(m) => (0x05, pools.method(m)?),
+            MethodHandle::InvokeConstructor(m) => (0x06, pools.method(m)?),
+            MethodHandle::InvokeDirect(m) => (0x07, pools.method(m)?),
+            MethodHandle::InvokeInterface(m) => (0x08, pools.method(m)?)
+        };
+        for v in [kind, 0, id as u16, 0] { u16_le(&mut out, v); }
+    }
+    out.append(&mut data.bytes);
+
+    // The signature covers everything after itself, the checksum everything after itself including the signature
+    let signature = sha1(&out[32..]);
+    out[12..32].copy_from_slice(&signature);
+    let checksum = adler32(&out[12..]);
+    out[8..12].copy_from_slice(&checksum.to_le_bytes());
+    Ok(out)
+}
@@ -0,0 +1,431 @@
+//! Structure based fingerprints of classes and methods, for finding a class again after obfuscation renames it
+//!
+//! A fingerprint keeps what obfuscators such as ProGuard and R8 leave alone: member counts, access flags, the shapes
+//! of types and signatures with app classes written as `L?;`, string constants, runs of opcodes and calls into
+//! framework classes. A [`Matcher`] scores candidate classes against a fingerprint taken from a known build.
+//!
+//! Fingerprints can be stored as text, e.g. to ship with a tool, and read back with [`ClassFingerprint::parse`].
+//!
+//! # Examples
+//!
+//! ```
+//!  use smali::fingerprint::{ClassFingerprint, Matcher};
+//!  use smali::types::SmaliClass;
+//!
+//!  let original = ".class public Lcom/scottyab/rootbeer/RootBeer;\n.super Ljava/lang/Object;\n\n\
+//!                  .method public detectTestKeys()Z\n    .locals 2\n    sget-object v0, Landroid/os/Build;->TAGS:Ljava/lang/String;\n\
+//!                  \x20   const-string v1, \"test-keys\"\n    invoke-virtual {v0, v1}, Ljava/lang/String;->contains(Ljava/lang/CharSequence;)Z\n\
+//!                  \x20   move-result v0\n    return v0\n.end method\n";
+//!  let stored = ClassFingerprint::of(&SmaliClass::from_smali(original)?).to_string();
+//!
+//!  // A later, obfuscated, build
+//!  let classes = vec![
+//!      SmaliClass::from_smali(&original.replace("Lcom/scottyab/rootbeer/RootBeer;", "La/b/c;").replace("detectTestKeys", "a"))?,
+//!      SmaliClass::from_smali(".class public La/b/d;\n.super Ljava/lang/Object;\n")?
+//!  ];
+//!  let matcher = Matcher::new(ClassFingerprint::parse(&stored)?);
+//!  let best = matcher.best_match(&classes).unwrap();
+//!  assert_eq!(best.class.name.as_jni_type(), "La/b/c;");
+//!  assert_eq!(best.score, 1.0);
+//! # Ok::<(), smali::types::SmaliError>(())
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use nom::IResult;
+use crate::instructions::{DexInstruction, Operand};
+use crate::smali_parse::{string_literal, unescape_string};
+use crate::smali_write::escape_string;
+use crate::types::{MethodSignature, ObjectIdentifier, SmaliClass, SmaliError, SmaliInstruction, SmaliMethod, TypeSignature};
+
+/// Packages treated as part of the platform, their names survive obfuscation
+pub const FRAMEWORK_PACKAGES: &[&str] = &[
+    "java/", "javax/", "android/", "androidx/", "dalvik/", "kotlin/", "kotlinx/",
+    "org/json/", "org/w3c/", "org/xml/", "org/apache/http/"
+];
+
+/// The number of opcodes in each n-gram
+pub const NGRAM: usize = 3;
+
+/// Is the class part of the platform rather than the app, see [`FRAMEWORK_PACKAGES`]
+pub fn is_framework_class(o: &ObjectIdentifier) -> bool
+{
+    let jni = o.as_jni_type();
+    FRAMEWORK_PACKAGES.iter().any(|p| jni[1..].starts_with(p))
+}
+
+/// Writes a type as JNI with app classes replaced by `L?;`
+///
+/// # Examples
+///
+/// ```
+///  use smali::fingerprint::normalise_type;
+///  use smali::types::TypeSignature;
+///
+///  assert_eq!(normalise_type(&TypeSignature::from_jni("[La/b;")), "[L?;");
+///  assert_eq!(normalise_type(&TypeSignature::from_jni("Ljava/lang/String;")), "Ljava/lang/String;");
+/// ```
+pub fn normalise_type(t: &TypeSignature) -> String
+{
+    match t {
+        TypeSignature::Array(a) => format!("[{}", normalise_type(a)),
+        TypeSignature::Object(o) if !is_framework_class(o) => "L?;".to_string(),
+        t => t.to_jni()
+    }
+}
+
+/// Writes a method signature as JNI with app classes replaced by `L?;`
+pub fn normalise_signature(s: &MethodSignature) -> String
+{
+    let args: String = s.args.iter().map(normalise_type).collect();
+    format!("({}){}", args, normalise_type(&s.return_type))
+}
+
+/// The fingerprint of a method
+///
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MethodFingerprint {
+    /// The normalised signature, see [`normalise_signature`]
+    pub shape: String,
+    /// Access flags, including the constructor flag
+    pub access_flags: u32,
+    /// The number of instructions
+    pub instructions: usize,
+    /// String constants used
+    pub strings: BTreeSet<String>,
+    /// Framework methods called, as JNI
+    pub calls: BTreeSet<String>,
+    /// How often each run of [`NGRAM`] opcodes appears, as mnemonics separated by spaces
+    pub ngrams: BTreeMap<String, usize>
+}
+
+impl MethodFingerprint {
+    pub fn of(m: &SmaliMethod) -> MethodFingerprint
+    {
+        let code: Vec<&DexInstruction> = m.instructions.iter()
+            .filter_map(|i| if let SmaliInstruction::Instruction(d) = i { Some(d) } else { None })
+            .collect();
+        let mut strings = BTreeSet::new();
+        let mut calls = BTreeSet::new();
+        for o in code.iter().flat_map(|d| d.operands())
+        {
+            match o {
+                Operand::String(s) => { strings.insert(s); }
+                Operand::Method(r) if matches!(&r.class, TypeSignature::Object(c) if is_framework_class(c)) => { calls.insert(r.to_jni()); }
+                _ => {}
+            }
+        }
+        let mnemonics: Vec<&str> = code.iter().map(|d| d.opcode().mnemonic()).collect();
+        let mut ngrams = BTreeMap::new();
+        for w in mnemonics.windows(NGRAM.min(mnemonics.len()).max(1))
+        {
+            *ngrams.entry(w.join(" ")).or_insert(0) += 1;
+        }
+        MethodFingerprint {
+            shape: normalise_signature(&m.signature),
+            access_flags: m.access_flags(),
+            instructions: code.len(),
+            strings,
+            calls,
+            ngrams
+        }
+    }
+
+    /// How alike two methods are, from 0 to 1
+    pub fn similarity(&self, other: &MethodFingerprint) -> f64
+    {
+        let same = |a: bool| if a { 1.0 } else { 0.0 };
+        0.3 * same(self.shape == other.shape)
+            + 0.1 * same(self.access_flags == other.access_flags)
+            + 0.15 * ratio(self.instructions, other.instructions)
+            + 0.25 * weighted_jaccard(&self.ngrams, &other.ngrams)
+            + 0.1 * jaccard(&self.strings, &other.strings)
+            + 0.1 * jaccard(&self.calls, &other.calls)
+    }
+}
+
+/// The fingerprint of a class
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassFingerprint {
+    /// The name of the class fingerprinted, for reference only as it isn't matched
+    pub name: String,
+    /// Access flags
+    pub access_flags: u32,
+    /// The normalised superclass
+    pub super_class: String,
+    /// The normalised interfaces, sorted
+    pub interfaces: Vec<String>,
+    /// The access flags and normalised type of each field, sorted
+    pub fields: Vec<(u32, String)>,
+    /// The fingerprint of each method, sorted so the order of the methods in the class doesn't matter
+    pub methods: Vec<MethodFingerprint>
+}
+
+/* Smaller over larger, 1 when both are 0 */
+fn ratio(a: usize, b: usize) -> f64
+{
+    if a == b { 1.0 } else { a.min(b) as f64 / a.max(b) as f64 }
+}
+
+/* Intersection over union, 1 when both are empty */
+fn jaccard<T: Ord>(a: &BTreeSet<T>, b: &BTreeSet<T>) -> f64
+{
+    let union = a.union(b).count();
+    if union == 0 { 1.0 } else { a.intersection(b).count() as f64 / union as f64 }
+}
+
+/* Jaccard over counts, 1 when both are empty */
+fn weighted_jaccard<T: Ord>(a: &BTreeMap<T, usize>, b: &BTreeMap<T, usize>) -> f64
+{
+    let (mut min, mut max) = (0, 0);
+    for k in a.keys().chain(b.keys().filter(|k| !a.contains_key(k)))
+    {
+        let (x, y) = (a.get(k).copied().unwrap_or(0), b.get(k).copied().unwrap_or(0));
+        min += x.min(y);
+        max += x.max(y);
+    }
+    if max == 0 { 1.0 } else { min as f64 / max as f64 }
+}
+
+/* Jaccard over lists that may repeat items */
+fn multiset_jaccard<T: Ord + Clone>(a: &[T], b: &[T]) -> f64
+{
+    let count = |l: &[T]| l.iter().fold(BTreeMap::new(), |mut m, t| { *m.entry(t.clone()).or_insert(0) += 1; m });
+    weighted_jaccard(&count(a), &count(b))
+}
+
+impl ClassFingerprint {
+    pub fn of(c: &SmaliClass) -> ClassFingerprint
+    {
+        let mut interfaces: Vec<String> = c.implements.iter().map(|i| normalise_type(&TypeSignature::Object(i.clone()))).collect();
+        interfaces.sort();
+        let mut fields: Vec<(u32, String)> = c.fields.iter().map(|f| (f.access_flags(), normalise_type(&f.signature))).collect();
+        fields.sort();
+        let mut methods: Vec<MethodFingerprint> = c.methods.iter().map(MethodFingerprint::of).collect();
+        methods.sort();
+        ClassFingerprint {
+            name: c.name.as_jni_type(),
+            access_flags: c.access_flags(),
+            super_class: normalise_type(&TypeSignature::Object(c.super_class.clone())),
+            interfaces,
+            fields,
+            methods
+        }
+    }
+
+    /// Every string constant used by the class
+    pub fn strings(&self) -> BTreeSet<&String>
+    {
+        self.methods.iter().flat_map(|m| &m.strings).collect()
+    }
+
+    /// Every framework method called by the class
+    pub fn calls(&self) -> BTreeSet<&String>
+    {
+        self.methods.iter().flat_map(|m| &m.calls).collect()
+    }
+
+    /// How alike two classes are, from 0 to 1
+    ///
+    /// Methods are paired off best first, so a class that gains or loses a method still scores well.
+    pub fn similarity(&self, other: &ClassFingerprint) -> f64
+    {
+        let counts = (ratio(self.fields.len(), other.fields.len()) + ratio(self.methods.len(), other.methods.len())) / 2.0;
+        let supers = if self.super_class == other.super_class { 1.0 } else { 0.0 };
+        let interfaces = multiset_jaccard(&self.interfaces, &other.interfaces);
+        0.15 * counts
+            + 0.05 * supers
+            + 0.05 * interfaces
+            + 0.1 * multiset_jaccard(&self.fields, &other.fields)
+            + 0.4 * self.method_similarity(other)
+            + 0.15 * jaccard(&self.strings(), &other.strings())
+            + 0.1 * jaccard(&self.calls(), &other.calls())
+    }
+
+    /* Pairs off methods greedily by similarity, unpaired methods count as 0 */
+    fn method_similarity(&self, other: &ClassFingerprint) -> f64
+    {
+        let most = self.methods.len().max(other.methods.len());
+        if most == 0 { return 1.0; }
+        let mut pairs = vec![];
+        for (i, a) in self.methods.iter().enumerate()
+        {
+            for (j, b) in other.methods.iter().enumerate() { pairs.push((a.similarity(b), i, j)); }
+        }
+        pairs.sort_by(|x, y| y.0.total_cmp(&x.0));
+        let (mut used_self, mut used_other) = (vec![false; self.methods.len()], vec![false; other.methods.len()]);
+        let mut total = 0.0;
+        for (s, i, j) in pairs
+        {
+            if used_self[i] || used_other[j] { continue; }
+            used_self[i] = true;
+            used_other[j] = true;
+            total += s;
+        }
+        total / most as f64
+    }
+
+    /// Reads a fingerprint written with [`Display`](fmt::Display)
+    pub fn parse(text: &str) -> Result<ClassFingerprint, SmaliError>
+    {
+        let mut fp = ClassFingerprint { name: String::new(), access_flags: 0, super_class: String::new(), interfaces: vec![], fields: vec![], methods: vec![] };
+        for (n, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim_end_matches('\r')))
+        {
+            let trimmed = line.trim();
+            if trimmed.is_empty() { continue; }
+            let (word, arg) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+            let err = |expected: &str| SmaliError::at_line(n, line, expected);
+            let flags = |s: &str| u32::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|_| err("access flags"));
+            let method = |fp: &ClassFingerprint| -> Result<usize, SmaliError> {
+                fp.methods.len().checked_sub(1).ok_or_else(|| err("a method before its details"))
+            };
+            match word {
+                "class" => fp.name = arg.to_string(),
+                "flags" => fp.access_flags = flags(arg)?,
+                "super" => fp.super_class = arg.to_string(),
+                "implements" => fp.interfaces.push(arg.to_string()),
+                "field" => {
+                    let (f, t) = arg.split_once(' ').ok_or_else(|| err("access flags and a type"))?;
+                    fp.fields.push((flags(f)?, t.to_string()));
+                }
+                "method" => {
+                    let parts: Vec<&str> = arg.split(' ').collect();
+                    let [f, shape, count] = parts[..] else { return Err(err("access flags, a signature and an instruction count")) };
+                    fp.methods.push(MethodFingerprint {
+                        shape: shape.to_string(),
+                        access_flags: flags(f)?,
+                        instructions: count.parse().map_err(|_| err("an instruction count"))?,
+                        strings: BTreeSet::new(),
+                        calls: BTreeSet::new(),
+                        ngrams: BTreeMap::new()
+                    });
+                }
+                "string" => {
+                    let k = method(&fp)?;
+                    let s = match string_literal(arg) {
+                        IResult::Ok(("", s)) => unescape_string(s),
+                        _ => return Err(err("a quoted string"))
+                    };
+                    fp.methods[k].strings.insert(s);
+                }
+                "call" => {
+                    let k = method(&fp)?;
+                    fp.methods[k].calls.insert(arg.to_string());
+                }
+                "ngram" => {
+                    let k = method(&fp)?;
+                    let (count, gram) = arg.split_once(' ').ok_or_else(|| err("a count and opcodes"))?;
+                    let count = count.parse().map_err(|_| err("a count"))?;
+                    fp.methods[k].ngrams.insert(gram.to_string(), count);
+                }
+                _ => return Err(err("a fingerprint line"))
+            }
+        }
+        if fp.super_class.is_empty() { return Err(SmaliError::new("Fingerprint has no superclass")); }
+        fp.methods.sort();
+        Ok(fp)
+    }
+}
+
+impl fmt::Display for ClassFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "class {}", self.name)?;
+        writeln!(f, "flags {:#x}", self.access_flags)?;
+        writeln!(f, "super {}", self.super_class)?;
+        for i in &self.interfaces { writeln!(f, "implements {}", i)?; }
+        for (flags, t) in &self.fields { writeln!(f, "field {:#x} {}", flags, t)?; }
+        for m in &self.methods
+        {
+            writeln!(f, "method {:#x} {} {}", m.access_flags, m.shape, m.instructions)?;
+            for s in &m.strings { writeln!(f, "    string \"{}\"", escape_string(s))?; }
+            for c in &m.calls { writeln!(f, "    call {}", c)?; }
+            for (g, n) in &m.ngrams { writeln!(f, "    ngram {} {}", n, g)?; }
+        }
+        Ok(())
+    }
+}
+
+/// A class scored by a [`Matcher`]
+///
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate<'a> {
+    pub class: &'a SmaliClass,
+    /// From 0 to 1, see [`ClassFingerprint::similarity`]
+    pub score: f64
+}
+
+/// Scores classes against a stored fingerprint
+///
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    pub fingerprint: ClassFingerprint,
+    /// The lowest score counted as a match, 0.8 by default
+    pub threshold: f64
+}
+
+impl Matcher {
+    pub fn new(fingerprint: ClassFingerprint) -> Matcher
+    {
+        Matcher { fingerprint, threshold: 0.8 }
+    }
+
+    /// Scores one class
+    pub fn score(&self, c: &SmaliClass) -> f64
+    {
+        self.fingerprint.similarity(&ClassFingerprint::of(c))
+    }
+
+    /// Every class scoring at least the threshold, best first
+    pub fn candidates<'a, I: IntoIterator<Item = &'a SmaliClass>>(&self, classes: I) -> Vec<Candidate<'a>>
+    {
+        let mut found: Vec<Candidate<'a>> = classes.into_iter()
+            .map(|class| Candidate { class, score: self.score(class) })
+            .filter(|c| c.score >= self.threshold)
+            .collect();
+        found.sort_by(|a, b| b.score.total_cmp(&a.score));
+        found
+    }
+
+    /// The best scoring class, if any reach the threshold
+    pub fn best_match<'a, I: IntoIterator<Item = &'a SmaliClass>>(&self, classes: I) -> Option<Candidate<'a>>
+    {
+        self.candidates(classes).into_iter().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::find_smali_files;
+    use crate::fingerprint::{ClassFingerprint, Matcher};
+    use crate::types::SmaliClass;
+
+    #[test]
+    fn fingerprints_survive_obfuscation() {
+        let mut classes = find_smali_files(Path::new("tests")).unwrap();
+        let request = classes.iter().find(|c| c.name.as_jni_type() == "Lokhttp3/Request;").unwrap();
+        let fp = ClassFingerprint::of(request);
+        assert_eq!(ClassFingerprint::parse(&fp.to_string()).unwrap(), fp);
+
+        // Rename the class and its methods, and drop one method
+        let text = fs::read_to_string(request.file_path.as_ref().unwrap()).unwrap()
+            .replace("Lokhttp3/Request;", "Lz/a;")
+            .replace("->url()", "->a()").replace(".method public final url()", ".method public final a()");
+        let mut obfuscated = SmaliClass::from_smali(&text).unwrap();
+        obfuscated.methods.reverse();
+        obfuscated.methods.pop();
+        classes.push(obfuscated);
+
+        let matcher = Matcher::new(fp);
+        let found = matcher.candidates(&classes);
+        assert_eq!(found.len(), 2, "{:?}", found.iter().map(|c| (c.class.name.as_jni_type(), c.score)).collect::<Vec<_>>());
+        assert_eq!(found[0].class.name.as_jni_type(), "Lokhttp3/Request;");
+        assert_eq!(found[0].score, 1.0);
+        assert_eq!(found[1].class.name.as_jni_type(), "Lz/a;");
+        assert!(found[1].score > 0.9);
+
+        assert!(ClassFingerprint::parse("class La;\nstring \"x\"\n").is_err());
+    }
+}
@@ -0,0 +1,450 @@
+//! Class hierarchy queries over a set of loaded classes
+//!
+//! A [`ClassHierarchy`] answers questions such as "every subclass of X" or "who implements `Lokhttp3/Interceptor;`",
+//! and works out which method a virtual call ends up in. Classes that aren't in the tree, such as the Android
+//! framework, can be described with a [`ClassStub`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//!  use std::path::Path;
+//!  use smali::find_smali_files;
+//!  use smali::hierarchy::ClassHierarchy;
+//!  use smali::types::ObjectIdentifier;
+//!
+//!  let classes = find_smali_files(Path::new("smali"))?;
+//!  let hierarchy = ClassHierarchy::new(&classes);
+//!  for c in hierarchy.implementors(&ObjectIdentifier::from_java_type("okhttp3.Interceptor"))
+//!  {
+//!      println!("Interceptor: {}", c.as_java_type());
+//!  }
+//! # Ok::<(), smali::types::SmaliError>(())
+//! ```
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use crate::types::{MethodSignature, Modifier, ObjectIdentifier, SmaliClass, SmaliMethod, TypeSignature};
+
+/// The outline of a class that isn't in the loaded tree, e.g. an Android framework class
+///
+/// # Examples
+///
+/// ```
+///  use smali::hierarchy::ClassStub;
+///  use smali::types::{MethodSignature, ObjectIdentifier};
+///
+///  let mut stub = ClassStub::new(ObjectIdentifier::from_java_type("android.app.Activity"), Some(ObjectIdentifier::from_java_type("android.content.Context")));
+///  stub.methods.push(("onCreate".to_string(), MethodSignature::from_jni("(Landroid/os/Bundle;)V")));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClassStub {
+    /// The name of the class
+    pub name: ObjectIdentifier,
+    /// The superclass, None for `java.lang.Object` or when it isn't known
+    pub super_class: Option<ObjectIdentifier>,
+    /// List of all the interfaces the class implements, or extends for an interface
+    pub implements: Vec<ObjectIdentifier>,
+    /// Is this an interface
+    pub interface: bool,
+    /// The virtual methods declared by the class, as name and signature
+    pub methods: Vec<(String, MethodSignature)>
+}
+
+impl ClassStub {
+    /// A stub for a class with no interfaces or methods
+    pub fn new(name: ObjectIdentifier, super_class: Option<ObjectIdentifier>) -> ClassStub
+    {
+        ClassStub { name, super_class, implements: vec![], interface: false, methods: vec![] }
+    }
+
+    /// A stub for an interface with no methods
+    pub fn interface(name: ObjectIdentifier, extends: Vec<ObjectIdentifier>) -> ClassStub
+    {
+        ClassStub { name, super_class: Some(object()), implements: extends, interface: true, methods: vec![] }
+    }
+
+    /// A stub with the outline of a class, e.g. one loaded from the smali of `android.jar`
+    pub fn from_class(c: &SmaliClass) -> ClassStub
+    {
+        ClassStub {
+            name: c.name.clone(),
+            super_class: if c.name == object() { None } else { Some(c.super_class.clone()) },
+            implements: c.implements.clone(),
+            interface: c.modifiers.contains(&Modifier::Interface),
+            methods: c.methods.iter().filter(|m| !m.is_static() && !m.constructor).map(|m| (m.name.clone(), m.signature.clone())).collect()
+        }
+    }
+
+    /// Stubs for the core `java.lang` classes and interfaces that most apps extend or implement
+    pub fn java_lang() -> Vec<ClassStub>
+    {
+        let id = ObjectIdentifier::from_java_type;
+        let sig = MethodSignature::from_jni;
+        let method = |name: &str, s: &str| (name.to_string(), sig(s));
+
+        let mut obj = ClassStub::new(object(), None);
+        obj.methods = vec![method("equals", "(Ljava/lang/Object;)Z"), method("hashCode", "()I"), method("toString", "()Ljava/lang/String;"),
+                           method("clone", "()Ljava/lang/Object;"), method("finalize", "()V")];
+        let mut runnable = ClassStub::interface(id("java.lang.Runnable"), vec![]);
+        runnable.methods = vec![method("run", "()V")];
+        let mut closeable = ClassStub::interface(id("java.io.Closeable"), vec![id("java.lang.AutoCloseable")]);
+        closeable.methods = vec![method("close", "()V")];
+        let mut auto_closeable = ClassStub::interface(id("java.lang.AutoCloseable"), vec![]);
+        auto_closeable.methods = vec![method("close", "()V")];
+        let mut comparable = ClassStub::interface(id("java.lang.Comparable"), vec![]);
+        comparable.methods = vec![method("compareTo", "(Ljava/lang/Object;)I")];
+        let mut throwable = ClassStub::new(id("java.lang.Throwable"), Some(object()));
+        throwable.implements = vec![id("java.io.Serializable")];
+        throwable.methods = vec![method("getMessage", "()Ljava/lang/String;"), method("getCause", "()Ljava/lang/Throwable;")];
+        let mut thread = ClassStub::new(id("java.lang.Thread"), Some(object()));
+        thread.implements = vec![id("java.lang.Runnable")];
+        thread.methods = vec![method("run", "()V"), method("start", "()V")];
+        let mut enumeration = ClassStub::new(id("java.lang.Enum"), Some(object()));
+        enumeration.implements = vec![id("java.lang.Comparable"), id("java.io.Serializable")];
+        let mut string = ClassStub::new(id("java.lang.String"), Some(object()));
+        string.implements = vec![id("java.io.Serializable"), id("java.lang.Comparable"), id("java.lang.CharSequence")];
+
+        vec![
+            obj, runnable, closeable, auto_closeable, comparable, throwable, thread, enumeration, string,
+            ClassStub::interface(id("java.lang.Cloneable"), vec![]),
+            ClassStub::interface(id("java.io.Serializable"), vec![]),
+            ClassStub::interface(id("java.lang.CharSequence"), vec![]),
+            ClassStub::new(id("java.lang.Exception"), Some(id("java.lang.Throwable"))),
+            ClassStub::new(id("java.lang.RuntimeException"), Some(id("java.lang.Exception"))),
+            ClassStub::new(id("java.lang.Error"), Some(id("java.lang.Throwable")))
+        ]
+    }
+}
+
+/// The method a call resolves to
+#[derive(Debug, Clone)]
+pub struct ResolvedMethod<'a> {
+    /// The class declaring the method
+    pub class: ObjectIdentifier,
+    /// The method, None when it's declared by a [`ClassStub`]
+    pub method: Option<&'a SmaliMethod>
+}
+
+struct Node<'a> {
+    super_class: Option<ObjectIdentifier>,
+    implements: Vec<ObjectIdentifier>,
+    interface: bool,
+    class: Option<&'a SmaliClass>,
+    stub_methods: Vec<(String, MethodSignature)>
+}
+
+impl<'a> Node<'a> {
+    /* The method declared here with this name and signature, and if it has a body */
+    fn declares(&self, name: &str, signature: &MethodSignature, private: bool) -> Option<(Option<&'a SmaliMethod>, bool)>
+    {
+        match self.class {
+            Some(c) => c.methods.iter()
+                .find(|m| m.name == name && m.signature == *signature && !m.is_static() && !m.constructor
+                          && (private || !m.modifiers.contains(&Modifier::Private)))
+                .map(|m| (Some(m), !m.modifiers.contains(&Modifier::Abstract))),
+            None => self.stub_methods.iter().find(|(n, s)| n == name && s == signature).map(|_| (None, !self.interface))
+        }
+    }
+}
+
+fn object() -> ObjectIdentifier
+{
+    ObjectIdentifier::from_java_type("java.lang.Object")
+}
+
+fn sorted(mut v: Vec<ObjectIdentifier>) -> Vec<ObjectIdentifier>
+{
+    v.sort_by_key(|o| o.as_jni_type());
+    v
+}
+
+/// The superclasses, subclasses and interfaces of a set of classes
+///
+/// The `java.lang` stubs from [`ClassStub::java_lang`] are included, classes in the tree take priority over stubs.
+///
+/// # Examples
+///
+/// ```
+///  use smali::hierarchy::ClassHierarchy;
+///  use smali::types::{MethodSignature, ObjectIdentifier, SmaliClass};
+///
+///  let a = SmaliClass::from_smali(".class public Lcom/cool/A;\n.super Ljava/lang/Object;\n.implements Ljava/lang/Runnable;\n\n\
+///                                  .method public run()V\n    .locals 0\n    return-void\n.end method\n")?;
+///  let b = SmaliClass::from_smali(".class public Lcom/cool/B;\n.super Lcom/cool/A;\n")?;
+///  let classes = vec![a, b];
+///  let hierarchy = ClassHierarchy::new(&classes);
+///
+///  let runnable = ObjectIdentifier::from_java_type("java.lang.Runnable");
+///  assert_eq!(hierarchy.implementors(&runnable).len(), 3); // A, B and java.lang.Thread
+///  let r = hierarchy.resolve_method(&classes[1].name, "run", &MethodSignature::from_jni("()V")).unwrap();
+///  assert_eq!(r.class, classes[0].name);
+/// # Ok::<(), smali::types::SmaliError>(())
+/// ```
+pub struct ClassHierarchy<'a> {
+    nodes: HashMap<ObjectIdentifier, Node<'a>>,
+    children: HashMap<ObjectIdentifier, Vec<ObjectIdentifier>>
+}
+
+impl<'a> ClassHierarchy<'a> {
+    /// Builds the hierarchy of a set of classes, e.g. `&Vec<SmaliClass>` or [`SmaliProject::classes`](crate::project::SmaliProject::classes)
+    pub fn new<I: IntoIterator<Item = &'a SmaliClass>>(classes: I) -> ClassHierarchy<'a>
+    {
+        let mut h = ClassHierarchy { nodes: HashMap::new(), children: HashMap::new() };
+        for c in classes
+        {
+            let node = Node {
+                super_class: if c.name == object() { None } else { Some(c.super_class.clone()) },
+                implements: c.implements.clone(),
+                interface: c.modifiers.contains(&Modifier::Interface),
+                class: Some(c),
+                stub_methods: vec![]
+            };
+            h.insert(c.name.clone(), node);
+        }
+        for s in ClassStub::java_lang() { h.add_stub(s); }
+        h
+    }
+
+    fn insert(&mut self, name: ObjectIdentifier, node: Node<'a>)
+    {
+        if self.nodes.contains_key(&name) { return; }
+        for parent in node.super_class.iter().chain(node.implements.iter())
+        {
+            self.children.entry(parent.clone()).or_default().push(name.clone());
+        }
+        self.nodes.insert(name, node);
+    }
+
+    /// Adds a class that isn't in the tree, ignored if the class is already known
+    pub fn add_stub(&mut self, stub: ClassStub)
+    {
+        let node = Node { super_class: stub.super_class, implements: stub.implements, interface: stub.interface, class: None, stub_methods: stub.methods };
+        self.insert(stub.name, node);
+    }
+
+    /// Asks `provider` for a stub of every missing class until there are no more it can supply
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///  use smali::hierarchy::{ClassHierarchy, ClassStub};
+    ///  use smali::types::{ObjectIdentifier, SmaliClass};
+    ///
+    ///  let classes = vec![SmaliClass::from_smali(".class public Lcom/cool/Main;\n.super Landroid/app/Activity;\n")?];
+    ///  let mut hierarchy = ClassHierarchy::new(&classes);
+    ///  hierarchy.resolve_stubs(|c| match c.as_java_type().as_str() {
+    ///      "android.app.Activity" => Some(ClassStub::new(c.clone(), Some(ObjectIdentifier::from_java_type("android.content.Context")))),
+    ///      _ => None
+    ///  });
+    ///  assert_eq!(hierarchy.missing(), vec![ObjectIdentifier::from_java_type("android.content.Context")]);
+    /// # Ok::<(), smali::types::SmaliError>(())
+    /// ```
+    pub fn resolve_stubs<F: Fn(&ObjectIdentifier) -> Option<ClassStub>>(&mut self, provider: F)
+    {
+        let mut asked = HashSet::new();
+        loop
+        {
+            let missing: Vec<_> = self.missing().into_iter().filter(|m| asked.insert(m.clone())).collect();
+            if missing.is_empty() { break; }
+            for m in missing
+            {
+                if let Some(s) = provider(&m) { self.add_stub(s); }
+            }
+        }
+    }
+
+    /// The supertypes referenced by known classes that aren't in the tree or stubs
+    pub fn missing(&self) -> Vec<ObjectIdentifier>
+    {
+        sorted(self.children.keys().filter(|c| !self.nodes.contains_key(c)).cloned().collect())
+    }
+
+    /// Is the class in the tree or stubbed
+    pub fn contains(&self, class: &ObjectIdentifier) -> bool
+    {
+        self.nodes.contains_key(class)
+    }
+
+    /// The loaded class, None for stubs and unknown classes
+    pub fn class(&self, class: &ObjectIdentifier) -> Option<&'a SmaliClass>
+    {
+        self.nodes.get(class).and_then(|n| n.class)
+    }
+
+    /// Is the class a known interface
+    pub fn is_interface(&self, class: &ObjectIdentifier) -> bool
+    {
+        self.nodes.get(class).is_some_and(|n| n.interface)
+    }
+
+    /// The direct superclass of a known class
+    pub fn super_class(&self, class: &ObjectIdentifier) -> Option<&ObjectIdentifier>
+    {
+        self.nodes.get(class).and_then(|n| n.super_class.as_ref())
+    }
+
+    /// The chain of superclasses, nearest first, ending at `java.lang.Object` or the first unknown class
+    pub fn superclasses(&self, class: &ObjectIdentifier) -> Vec<ObjectIdentifier>
+    {
+        let mut v: Vec<ObjectIdentifier> = vec![];
+        let mut c = self.super_class(class);
+        while let Some(s) = c
+        {
+            // Guard against broken trees with a cycle
+            if v.contains(s) || s == class { break; }
+            v.push(s.clone());
+            c = self.super_class(s);
+        }
+        v
+    }
+
+    /// Every superclass and interface of a class, nearest first
+    pub fn supertypes(&self, class: &ObjectIdentifier) -> Vec<ObjectIdentifier>
+    {
+        let mut seen = HashSet::from([class.clone()]);
+        let mut result = vec![];
+        let mut queue = VecDeque::from([class.clone()]);
+        while let Some(c) = queue.pop_front()
+        {
+            if let Some(n) = self.nodes.get(&c)
+            {
+                for p in n.super_class.iter().chain(n.implements.iter())
+                {
+                    if seen.insert(p.clone()) { result.push(p.clone()); queue.push_back(p.clone()); }
+                }
+            }
+        }
+        result
+    }
+
+    /// Every class and interface extending or implementing a type, directly or not, sorted by name
+    pub fn subtypes(&self, class: &ObjectIdentifier) -> Vec<ObjectIdentifier>
+    {
+        let mut seen = HashSet::from([class.clone()]);
+        let mut stack = vec![class.clone()];
+        while let Some(c) = stack.pop()
+        {
+            for child in self.children.get(&c).into_iter().flatten()
+            {
+                if seen.insert(child.clone()) { stack.push(child.clone()); }
+            }
+        }
+        seen.remove(class);
+        sorted(seen.into_iter().collect())
+    }
+
+    /// The direct subclasses of a class, or classes directly implementing an interface, sorted by name
+    pub fn direct_subtypes(&self, class: &ObjectIdentifier) -> Vec<ObjectIdentifier>
+    {
+        sorted(self.children.get(class).cloned().unwrap_or_default())
+    }
+
+    /// Every class (not interface) that implements an interface, directly or through a supertype, sorted by name
+    pub fn implementors(&self, interface: &ObjectIdentifier) -> Vec<ObjectIdentifier>
+    {
+        self.subtypes(interface).into_iter().filter(|c| !self.is_interface(c)).collect()
+    }
+
+    /// Is `class` the same as, or a subtype of, `parent`
+    pub fn is_subtype(&self, class: &ObjectIdentifier, parent: &ObjectIdentifier) -> bool
+    {
+        class == parent || *parent == object() || self.supertypes(class).contains(parent)
+    }
+
+    /// The method a virtual call on an instance of `class` resolves to
+    ///
+    /// The superclasses are searched first, then the default methods of the interfaces, falling back to the
+    /// abstract interface declaration.
+    pub fn resolve_method(&self, class: &ObjectIdentifier, name: &str, signature: &MethodSignature) -> Option<ResolvedMethod<'a>>
+    {
+        // Private methods are only found on the class itself
+        let mut chain = vec![class.clone()];
+        chain.extend(self.superclasses(class));
+        for (i, c) in chain.iter().enumerate()
+        {
+            if let Some((method, _)) = self.nodes.get(c).and_then(|n| n.declares(name, signature, i == 0))
+            {
+                return Some(ResolvedMethod { class: c.clone(), method });
+            }
+        }
+
+        let mut fallback = None;
+        for c in self.supertypes(class).iter().filter(|c| self.is_interface(c))
+        {
+            match self.nodes[c].declares(name, signature, false) {
+                Some((method, true)) => return Some(ResolvedMethod { class: c.clone(), method }),
+                Some((method, false)) if fallback.is_none() => fallback = Some(ResolvedMethod { class: c.clone(), method }),
+                _ => {}
+            }
+        }
+        fallback
+    }
+}
+
+impl TypeSignature {
+    /// Can a value of this type be assigned to a variable of type `to`, class types are checked with the hierarchy
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///  use smali::hierarchy::ClassHierarchy;
+    ///  use smali::types::{SmaliClass, TypeSignature};
+    ///
+    ///  let classes: Vec<SmaliClass> = vec![];
+    ///  let hierarchy = ClassHierarchy::new(&classes);
+    ///  let t = TypeSignature::from_jni("Ljava/lang/Thread;");
+    ///  assert!(t.is_assignable(&TypeSignature::from_jni("Ljava/lang/Runnable;"), &hierarchy));
+    ///  assert!(TypeSignature::from_jni("[Ljava/lang/Thread;").is_assignable(&TypeSignature::from_jni("[Ljava/lang/Object;"), &hierarchy));
+    ///  assert!(!TypeSignature::from_jni("[I").is_assignable(&TypeSignature::from_jni("[J"), &hierarchy));
+    /// ```
+    pub fn is_assignable(&self, to: &TypeSignature, hierarchy: &ClassHierarchy) -> bool
+    {
+        match (self, to) {
+            (TypeSignature::Object(from), TypeSignature::Object(to)) => hierarchy.is_subtype(from, to),
+            (TypeSignature::Array(_), TypeSignature::Object(to)) => {
+                ["Ljava/lang/Object;", "Ljava/lang/Cloneable;", "Ljava/io/Serializable;"].contains(&to.as_jni_type().as_str())
+            }
+            (TypeSignature::Array(from), TypeSignature::Array(to)) => match (from.as_ref(), to.as_ref()) {
+                (TypeSignature::Object(_) | TypeSignature::Array(_), TypeSignature::Object(_) | TypeSignature::Array(_)) => from.is_assignable(to, hierarchy),
+                _ => from == to
+            },
+            _ => self == to
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::find_smali_files;
+    use crate::hierarchy::{ClassHierarchy, ClassStub};
+    use crate::types::{MethodSignature, ObjectIdentifier, TypeSignature};
+
+    #[test]
+    fn hierarchy_queries() {
+        let classes = find_smali_files(Path::new("tests")).unwrap();
+        let mut hierarchy = ClassHierarchy::new(&classes);
+        let id = ObjectIdentifier::from_java_type;
+
+        let client = id("okhttp3.OkHttpClient");
+        assert_eq!(hierarchy.super_class(&client), Some(&id("java.lang.Object")));
+        assert!(hierarchy.supertypes(&client).contains(&id("java.lang.Cloneable")));
+        assert_eq!(hierarchy.implementors(&id("java.lang.AutoCloseable")), vec![id("okhttp3.Response")]);
+        assert_eq!(hierarchy.missing(), vec![id("okhttp3.Call$Factory"), id("okhttp3.WebSocket$Factory")]);
+
+        hierarchy.resolve_stubs(|c| Some(ClassStub::interface(c.clone(), vec![])));
+        assert!(hierarchy.missing().is_empty());
+        assert_eq!(hierarchy.direct_subtypes(&id("okhttp3.Call$Factory")), vec![client.clone()]);
+        assert!(TypeSignature::Object(client.clone()).is_assignable(&TypeSignature::from_jni("Lokhttp3/Call$Factory;"), &hierarchy));
+        assert!(!TypeSignature::from_jni("Lokhttp3/Request;").is_assignable(&TypeSignature::Object(client.clone()), &hierarchy));
+
+        // Overridden in the class, inherited from the Object stub, then through an interface
+        let to_string = MethodSignature::from_jni("()Ljava/lang/String;");
+        let r = hierarchy.resolve_method(&id("okhttp3.Request"), "toString", &to_string).unwrap();
+        assert_eq!((r.class, r.method.map(|m| m.name.as_str())), (id("okhttp3.Request"), Some("toString")));
+        let r = hierarchy.resolve_method(&client, "toString", &to_string).unwrap();
+        assert_eq!((r.class, r.method.is_none()), (id("java.lang.Object"), true));
+        let r = hierarchy.resolve_method(&id("java.io.Closeable"), "close", &MethodSignature::from_jni("()V")).unwrap();
+        assert_eq!(r.class, id("java.io.Closeable"));
+        assert!(hierarchy.resolve_method(&client, "missing", &to_string).is_none());
+    }
+}
@@ -0,0 +1,517 @@
+//! Injecting calls to static hook methods at method entry, before returns and around call sites
+//!
+//! The hook code gets its own scratch registers, added with [`SmaliMethod::add_registers`] so the method's own registers
+//! keep their numbers and `.locals` is adjusted automatically.
+//!
+//! # Examples
+//!
+//! ```
+//!  use smali::hook::{Hook, HookArgs, HookPoint};
+//!  use smali::types::{MethodRef, SmaliClass};
+//!
+//!  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n\
+//!               .method public static check(Ljava/lang/String;I)Z\n    .locals 1\n    const/4 v0, 0x1\n    return v0\n.end method\n";
+//!  let mut c = SmaliClass::from_smali(smali)?;
+//!  let enter = Hook::new(HookPoint::Entry, HookArgs::Boxed, MethodRef::from_jni("Lhook/Logger;->onEnter(Ljava/lang/Object;[Ljava/lang/Object;)V"));
+//!  assert_eq!(c.add_hook(|m| m.name == "check", &enter)?, 1);
+//!  let smali = c.to_smali();
+//!  assert!(smali.contains("invoke-static/range {p1 .. p1}, Ljava/lang/Integer;->valueOf(I)Ljava/lang/Integer;"));
+//!  assert!(smali.contains("invoke-static/range {v3 .. v4}, Lhook/Logger;->onEnter(Ljava/lang/Object;[Ljava/lang/Object;)V"));
+//! # Ok::<(), smali::types::SmaliError>(())
+//! ```
+
+use crate::instructions::{DexInstruction, Opcode, Operand, Register, RegisterRange};
+use crate::instructions::Register::V;
+use crate::types::{MethodRef, MethodSignature, ObjectIdentifier, SmaliClass, SmaliError, SmaliInstruction, SmaliMethod, TypeSignature};
+
+/// Where a hook is called
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookPoint {
+    /// At the start of the method, passing `this` and the parameters. Constructors are hooked just after they call the
+    /// superclass constructor, so `this` can be used.
+    Entry,
+    /// Before every return, passing the value returned
+    Exit,
+    /// Before every call to a method, passing the receiver and the arguments of the call. The receiver of a constructor
+    /// isn't initialised yet, so Boxed hooks are passed null for it and Raw hooks can't be used.
+    BeforeCall(MethodRef),
+    /// After every call to a method whose result is used, passing the result
+    AfterCall(MethodRef)
+}
+
+/// How values are passed to a hook
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookArgs {
+    /// The hook takes no arguments
+    Nothing,
+    /// The values as they are, so the hook takes the same types, any reference type is allowed for `this`
+    Raw,
+    /// Primitives are boxed, e.g. `int` to `Integer`. At entry and before calls the hook takes `this` or the receiver
+    /// (null if static) and an `Object[]` of the arguments, at exit and after calls it takes an `Object`.
+    Boxed
+}
+
+/// A call to a static hook method
+///
+/// Hooks at [`HookPoint::Exit`] and [`HookPoint::AfterCall`] that return a value (the value's type, or `Object` when
+/// boxed) replace the value with what they return. Other hooks must return void.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub point: HookPoint,
+    pub args: HookArgs,
+    /// The static method to call
+    pub method: MethodRef
+}
+
+impl Hook {
+    pub fn new(point: HookPoint, args: HookArgs, method: MethodRef) -> Hook
+    {
+        Hook { point, args, method }
+    }
+}
+
+/* The box class and unboxing method of a primitive type */
+fn boxing(t: &TypeSignature) -> Option<(&'static str, &'static str)>
+{
+    match t {
+        TypeSignature::Int => Some(("Ljava/lang/Integer;", "intValue")),
+        TypeSignature::Bool => Some(("Ljava/lang/Boolean;", "booleanValue")),
+        TypeSignature::Byte => Some(("Ljava/lang/Byte;", "byteValue")),
+        TypeSignature::Char => Some(("Ljava/lang/Character;", "charValue")),
+        TypeSignature::Short => Some(("Ljava/lang/Short;", "shortValue")),
+        TypeSignature::Long => Some(("Ljava/lang/Long;", "longValue")),
+        TypeSignature::Float => Some(("Ljava/lang/Float;", "floatValue")),
+        TypeSignature::Double => Some(("Ljava/lang/Double;", "doubleValue")),
+        _ => None
+    }
+}
+
+/* A value to pass to a hook and the register it's in */
+type Value = (TypeSignature, Register);
+
+/* Code to insert before instruction indexes */
+type Sites = Vec<(usize, Vec<DexInstruction>)>;
+
+fn object() -> TypeSignature
+{
+    TypeSignature::Object(ObjectIdentifier::from_java_type("java.lang.Object"))
+}
+
+fn is_reference(t: &TypeSignature) -> bool
+{
+    matches!(t, TypeSignature::Object(_) | TypeSignature::Array(_))
+}
+
+fn width(t: &TypeSignature) -> u16
+{
+    if t.is_wide() { 2 } else { 1 }
+}
+
+/* The register after r, for the high half of a wide value */
+fn high(r: Register) -> Register
+{
+    match r {
+        Register::V(n) => Register::V(n + 1),
+        Register::P(n) => Register::P(n + 1)
+    }
+}
+
+/* Copies a value into a scratch register, the source can be any register */
+fn copy(t: &TypeSignature, dest: u16, src: Register) -> DexInstruction
+{
+    match t {
+        _ if t.is_wide() => DexInstruction::MoveWideFrom16 { dest: V(dest), src },
+        _ if is_reference(t) => DexInstruction::MoveObjectFrom16 { dest: V(dest), src },
+        _ => DexInstruction::MoveFrom16 { dest: V(dest), src }
+    }
+}
+
+/* Copies a value back from a scratch register, the destination can be any register */
+fn copy_back(t: &TypeSignature, dest: Register, src: u16) -> DexInstruction
+{
+    match t {
+        _ if t.is_wide() => DexInstruction::MoveWide16 { dest, src: V(src) },
+        _ if is_reference(t) => DexInstruction::MoveObject16 { dest, src: V(src) },
+        _ => DexInstruction::Move16 { dest, src: V(src) }
+    }
+}
+
+fn move_result(t: &TypeSignature, dest: u16) -> DexInstruction
+{
+    match t {
+        _ if t.is_wide() => DexInstruction::MoveResultWide { dest: V(dest) },
+        _ if is_reference(t) => DexInstruction::MoveResultObject { dest: V(dest) },
+        _ => DexInstruction::MoveResult { dest: V(dest) }
+    }
+}
+
+/* Calls a static method with the values in consecutive registers from base */
+fn invoke(method: &MethodRef, base: u16, count: u16) -> DexInstruction
+{
+    if count == 0 { DexInstruction::InvokeStatic { registers: vec![], method: method.clone() } }
+    else { DexInstruction::InvokeStaticRange { range: RegisterRange { start: V(base), end: V(base + count - 1) }, method: method.clone() } }
+}
+
+/* Puts the value of a register in scratch register dest as an object, boxing primitives */
+fn box_value(t: &TypeSignature, dest: u16, src: Register) -> Vec<DexInstruction>
+{
+    match boxing(t) {
+        Some((class, _)) => {
+            let end = if t.is_wide() { high(src) } else { src };
+            let method = MethodRef {
+                class: TypeSignature::from_jni(class),
+                name: "valueOf".to_string(),
+                signature: MethodSignature { args: vec![t.clone()], return_type: TypeSignature::from_jni(class) }
+            };
+            vec![DexInstruction::InvokeStaticRange { range: RegisterRange { start: src, end }, method },
+                 DexInstruction::MoveResultObject { dest: V(dest) }]
+        }
+        None => vec![copy(t, dest, src)]
+    }
+}
+
+/* Turns the object in a scratch register back into a value of type t */
+fn unbox_value(t: &TypeSignature, reg: u16) -> Vec<DexInstruction>
+{
+    match boxing(t) {
+        Some((class, unbox)) => {
+            let method = MethodRef {
+                class: TypeSignature::from_jni(class),
+                name: unbox.to_string(),
+                signature: MethodSignature { args: vec![], return_type: t.clone() }
+            };
+            vec![DexInstruction::CheckCast { src: V(reg), class: TypeSignature::from_jni(class) },
+                 DexInstruction::InvokeVirtualRange { range: RegisterRange { start: V(reg), end: V(reg) }, method },
+                 move_result(t, reg)]
+        }
+        None if *t == object() => vec![],
+        None => vec![DexInstruction::CheckCast { src: V(reg), class: t.clone() }]
+    }
+}
+
+/* Builds the code passing a list of values to a hook, with the number of scratch registers from base it needs. The
+   first value is `this` or the receiver when `receiver` is set, and is passed as null for Boxed hooks when it's None. */
+fn pass_arguments(hook: &Hook, base: u16, receiver: Option<Option<Value>>, values: &[Value]) -> Result<(Vec<DexInstruction>, u16), SmaliError>
+{
+    let mut expected: Vec<TypeSignature> = receiver.iter().flatten().map(|r| r.0.clone()).collect();
+    expected.extend(values.iter().map(|v| v.0.clone()));
+    let args = &hook.method.signature.args;
+    let mut code = vec![];
+    let count = match hook.args {
+        HookArgs::Nothing => {
+            if !args.is_empty() { return Err(mismatch(hook, "")); }
+            0
+        }
+        HookArgs::Raw => {
+            // Any reference type will do for `this`, it may be a superclass or interface
+            let this = receiver.as_ref().is_some_and(|r| r.is_some());
+            let fits = args.len() == expected.len() && args.iter().zip(expected.iter()).enumerate()
+                .all(|(k, (a, e))| a == e || (k == 0 && this && is_reference(a)));
+            if !fits { return Err(mismatch(hook, &expected.iter().map(|t| t.to_jni()).collect::<String>())); }
+            let mut n = 0;
+            for (t, r) in receiver.iter().flatten().chain(values.iter())
+            {
+                code.push(copy(t, base + n, *r));
+                n += width(t);
+            }
+            n
+        }
+        HookArgs::Boxed if receiver.is_some() => {
+            if *args != [object(), TypeSignature::Array(Box::new(object()))] { return Err(mismatch(hook, "Ljava/lang/Object;[Ljava/lang/Object;")); }
+
+            // The receiver goes in the first register and the boxed values after it, which then hold the array
+            match receiver.unwrap() {
+                Some((t, r)) => code.push(copy(&t, base, r)),
+                None => code.push(DexInstruction::Const16 { dest: V(base), value: 0 })
+            }
+            let n = values.len() as u16;
+            for (k, (t, r)) in values.iter().enumerate() { code.extend(box_value(t, base + 1 + k as u16, *r)); }
+            let class = TypeSignature::Array(Box::new(object()));
+            code.push(if n == 0 { DexInstruction::FilledNewArray { registers: vec![], class } }
+                      else { DexInstruction::FilledNewArrayRange { range: RegisterRange { start: V(base + 1), end: V(base + n) }, class } });
+            code.push(DexInstruction::MoveResultObject { dest: V(base + 1) });
+            code.push(invoke(&hook.method, base, 2));
+            return Ok((code, 1 + n.max(1)));
+        }
+        HookArgs::Boxed => {
+            if *args != [object()] { return Err(mismatch(hook, "Ljava/lang/Object;")); }
+            match values.first() {
+                Some((t, r)) => code.extend(box_value(t, base, *r)),
+                None => code.push(DexInstruction::Const16 { dest: V(base), value: 0 })
+            }
+            1
+        }
+    };
+    if count > 0xff { return Err(SmaliError::new(&format!("Too many argument registers to pass to hook {}", hook.method.to_jni()))); }
+    code.push(invoke(&hook.method, base, count));
+    Ok((code, count))
+}
+
+fn mismatch(hook: &Hook, expected: &str) -> SmaliError
+{
+    SmaliError::new(&format!("Hook {} should take ({})", hook.method.to_jni(), expected))
+}
+
+/* The code for a hook passed a value that it may replace, e.g. a return value in register r */
+fn pass_value(hook: &Hook, base: u16, value: Option<Value>) -> Result<(Vec<DexInstruction>, u16), SmaliError>
+{
+    let (mut code, count) = pass_arguments(hook, base, None, &value.iter().cloned().collect::<Vec<_>>())?;
+    let ret = &hook.method.signature.return_type;
+
+    // Boxed hooks are passed null for void returns so the same hook works for every method
+    if *ret == TypeSignature::Void || (value.is_none() && hook.args == HookArgs::Boxed) { return Ok((code, count)); }
+    let Some((t, r)) = value else { return Err(SmaliError::new(&format!("Hook {} should return void", hook.method.to_jni()))) };
+    let boxed = hook.args == HookArgs::Boxed;
+    if (boxed && *ret != object()) || (!boxed && *ret != t)
+    {
+        return Err(SmaliError::new(&format!("Hook {} should return void or {}", hook.method.to_jni(), if boxed { object().to_jni() } else { t.to_jni() })));
+    }
+    code.push(move_result(ret, base));
+    if boxed { code.extend(unbox_value(&t, base)); }
+    code.push(copy_back(&t, r, base));
+    Ok((code, count.max(width(&t))))
+}
+
+/* The next instruction after index i, skipping labels and debug directives */
+fn next_instruction(m: &SmaliMethod, i: usize) -> Option<(usize, &DexInstruction)>
+{
+    m.instructions.iter().enumerate().skip(i + 1)
+        .find_map(|(k, x)| match x {
+            SmaliInstruction::Instruction(d) => Some(Some((k, d))),
+            SmaliInstruction::Payload(_) => Some(None),
+            _ => None
+        })
+        .flatten()
+}
+
+/* The receiver and arguments of a call, with their types */
+fn call_arguments(d: &DexInstruction, method: &MethodRef) -> (Option<Value>, Vec<Value>)
+{
+    let regs: Vec<Register> = d.operands().into_iter().flat_map(|o| match o {
+        Operand::RegisterList(l) => l,
+        Operand::RegisterRange(r) => r.registers(),
+        _ => vec![]
+    }).collect();
+    let is_static = matches!(d.opcode(), Opcode::InvokeStatic | Opcode::InvokeStaticRange);
+    let mut regs = regs.into_iter();
+    let receiver = if is_static { None } else { regs.next().map(|r| (method.class.clone(), r)) };
+    // The object being constructed can't be used before its constructor is called
+    let receiver = receiver.filter(|_| method.name != "<init>");
+    let mut values = vec![];
+    for a in &method.signature.args
+    {
+        if let Some(r) = regs.next() { values.push((a.clone(), r)); }
+        if a.is_wide() { regs.next(); }
+    }
+    (receiver, values)
+}
+
+fn calls(d: &DexInstruction, target: &MethodRef) -> bool
+{
+    d.opcode().mnemonic().starts_with("invoke") && d.operands().iter().any(|o| matches!(o, Operand::Method(m) if m == target))
+}
+
+impl SmaliMethod {
+    /// Adds a call to a hook, returning the number of places it was added
+    ///
+    /// Methods without code and call sites with no result for [`HookPoint::AfterCall`] are left alone. Fails without
+    /// changing the method if the hook's signature doesn't fit, or the method has too many registers to add scratch ones.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///  use smali::hook::{Hook, HookArgs, HookPoint};
+    ///  use smali::types::{MethodRef, SmaliClass};
+    ///
+    ///  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n\
+    ///               .method public isRooted()Z\n    .locals 1\n    const/4 v0, 0x1\n    return v0\n.end method\n";
+    ///  let mut c = SmaliClass::from_smali(smali)?;
+    ///  let hook = Hook::new(HookPoint::Exit, HookArgs::Raw, MethodRef::from_jni("Lhook/Root;->onExit(Z)Z"));
+    ///  assert_eq!(c.methods[0].add_hook(&hook)?, 1);
+    ///  assert!(c.to_smali().contains("    .locals 3\n    const/4 v0, 0x1\n    move/from16 v2, v0\n\
+    ///                                 \x20   invoke-static/range {v2 .. v2}, Lhook/Root;->onExit(Z)Z\n    move-result v2\n    move/16 v0, v2\n    return v0\n"));
+    /// # Ok::<(), smali::types::SmaliError>(())
+    /// ```
+    pub fn add_hook(&mut self, hook: &Hook) -> Result<usize, SmaliError>
+    {
+        let this = if self.is_static() { None } else { Some(object()) };
+        self.add_hook_with_this(hook, this)
+    }
+
+    fn add_hook_with_this(&mut self, hook: &Hook, this: Option<TypeSignature>) -> Result<usize, SmaliError>
+    {
+        let (sites, scratch) = self.hook_sites(hook, &this, 0, Register::P(0))?;
+        if sites.is_empty() { return Ok(0); }
+
+        // The scratch registers are added after the locals, where the hook code needs them to fit in 8 bits
+        let (mut base, mut this_reg) = (0, Register::P(0));
+        if scratch > 0
+        {
+            let locals = self.locals();
+            if locals + self.parameter_registers() + scratch as u32 > 0x100
+            {
+                return Err(SmaliError::new(&format!("Method {} has too many registers for hook {}", self.name, hook.method.to_jni())));
+            }
+            base = self.add_registers(scratch)?[0].number();
+            this_reg = Register::V(locals as u16);
+        }
+        let (sites, _) = self.hook_sites(hook, &this, base, this_reg)?;
+        let hooked = sites.len();
+        for (at, code) in sites.into_iter().rev()
+        {
+            self.instructions.splice(at..at, code.into_iter().map(SmaliInstruction::Instruction));
+        }
+        Ok(hooked)
+    }
+
+    /* The code to insert before each index for a hook, with scratch registers from base, and the number of scratch
+       registers it needs. this_reg is where the constructor finds `this`. */
+    fn hook_sites(&self, hook: &Hook, this: &Option<TypeSignature>, base: u16, this_reg: Register) -> Result<(Sites, u16), SmaliError>
+    {
+        let mut sites = vec![];
+        let mut scratch = 0;
+        if !self.instructions.iter().any(|i| matches!(i, SmaliInstruction::Instruction(_))) { return Ok((sites, scratch)); }
+        let void = || if hook.method.signature.return_type == TypeSignature::Void { Ok(()) }
+                      else { Err(SmaliError::new(&format!("Hook {} should return void", hook.method.to_jni()))) };
+
+        match &hook.point {
+            HookPoint::Entry => {
+                void()?;
+                let receiver = this.clone().map(|t| (t, Register::P(0)));
+                let mut values = vec![];
+                let mut p = receiver.is_some() as u16;
+                for a in &self.signature.args
+                {
+                    values.push((a.clone(), Register::P(p)));
+                    p += width(a);
+                }
+                let (code, count) = pass_arguments(hook, base, Some(receiver), &values)?;
+
+                // `this` can't be used until the superclass constructor has been called
+                let at = if self.constructor && this.is_some() && hook.args != HookArgs::Nothing
+                {
+                    let init = self.instructions.iter().position(|i| matches!(i, SmaliInstruction::Instruction(DexInstruction::InvokeDirect { registers, method })
+                        if method.name == "<init>" && registers.first() == Some(&this_reg)));
+                    match init {
+                        Some(i) => i + 1,
+                        None => return Err(SmaliError::new(&format!("No superclass constructor call found in {}", self.name)))
+                    }
+                }
+                else { self.instructions.iter().position(|i| matches!(i, SmaliInstruction::Instruction(_) | SmaliInstruction::Label(_))).unwrap_or(0) };
+                sites.push((at, code));
+                scratch = count;
+            }
+            HookPoint::Exit => {
+                for (i, x) in self.instructions.iter().enumerate()
+                {
+                    let SmaliInstruction::Instruction(d) = x else { continue };
+                    let value = match d {
+                        DexInstruction::ReturnVoid => None,
+                        DexInstruction::Return { src } | DexInstruction::ReturnWide { src } | DexInstruction::ReturnObject { src } => Some((self.signature.return_type.clone(), *src)),
+                        _ => continue
+                    };
+                    let (code, count) = pass_value(hook, base, value)?;
+                    sites.push((i, code));
+                    scratch = scratch.max(count);
+                }
+            }
+            HookPoint::BeforeCall(target) => {
+                void()?;
+                for (i, x) in self.instructions.iter().enumerate()
+                {
+                    let SmaliInstruction::Instruction(d) = x else { continue };
+                    if !calls(d, target) { continue; }
+                    if target.name == "<init>" && hook.args == HookArgs::Raw
+                    {
+                        return Err(SmaliError::new(&format!("Hook {} can't be passed the uninitialised receiver of {}", hook.method.to_jni(), target.to_jni())));
+                    }
+                    let (receiver, values) = call_arguments(d, target);
+                    let (code, count) = pass_arguments(hook, base, Some(receiver), &values)?;
+                    sites.push((i, code));
+                    scratch = scratch.max(count);
+                }
+            }
+            HookPoint::AfterCall(target) => {
+                for (i, x) in self.instructions.iter().enumerate()
+                {
+                    let SmaliInstruction::Instruction(d) = x else { continue };
+                    if !calls(d, target) { continue; }
+                    let Some((k, DexInstruction::MoveResult { dest } | DexInstruction::MoveResultWide { dest } | DexInstruction::MoveResultObject { dest })) = next_instruction(self, i) else { continue };
+                    let (code, count) = pass_value(hook, base, Some((target.signature.return_type.clone(), *dest)))?;
+                    sites.push((k + 1, code));
+                    scratch = scratch.max(count);
+                }
+            }
+        }
+        Ok((sites, scratch))
+    }
+}
+
+impl SmaliClass {
+    /// Adds a hook to every method with code the filter accepts, returning the number of places it was added
+    ///
+    /// Fails at the first method the hook can't be added to, leaving the methods before it hooked.
+    pub fn add_hook<F: Fn(&SmaliMethod) -> bool>(&mut self, filter: F, hook: &Hook) -> Result<usize, SmaliError>
+    {
+        let this = TypeSignature::Object(self.name.clone());
+        let mut count = 0;
+        for m in self.methods.iter_mut().filter(|m| filter(m))
+        {
+            let this = if m.is_static() { None } else { Some(this.clone()) };
+            count += m.add_hook_with_this(hook, this)?;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::find_smali_files;
+    use crate::hierarchy::ClassHierarchy;
+    use crate::hook::{Hook, HookArgs, HookPoint};
+    use crate::types::{MethodRef, SmaliClass};
+    use crate::verify::verify_class;
+
+    #[test]
+    fn hooks_keep_methods_valid() {
+        let mut classes = find_smali_files(Path::new("tests")).unwrap();
+        let check = MethodRef::from_jni("Lkotlin/jvm/internal/Intrinsics;->checkNotNullParameter(Ljava/lang/Object;Ljava/lang/String;)V");
+        let header = MethodRef::from_jni("Ljava/lang/Object;->toString()Ljava/lang/String;");
+        let init = MethodRef::from_jni("Ljava/lang/IllegalStateException;-><init>(Ljava/lang/String;)V");
+        let hooks = [
+            Hook::new(HookPoint::Entry, HookArgs::Boxed, MethodRef::from_jni("Lhook/Logger;->onEnter(Ljava/lang/Object;[Ljava/lang/Object;)V")),
+            Hook::new(HookPoint::Exit, HookArgs::Boxed, MethodRef::from_jni("Lhook/Logger;->onExit(Ljava/lang/Object;)Ljava/lang/Object;")),
+            Hook::new(HookPoint::BeforeCall(check.clone()), HookArgs::Raw, MethodRef::from_jni("Lhook/Logger;->onCheck(Ljava/lang/Object;Ljava/lang/String;)V")),
+            Hook::new(HookPoint::AfterCall(header), HookArgs::Raw, MethodRef::from_jni("Lhook/Logger;->onHeader(Ljava/lang/String;)Ljava/lang/String;")),
+            Hook::new(HookPoint::BeforeCall(init.clone()), HookArgs::Boxed, MethodRef::from_jni("Lhook/Logger;->onNew(Ljava/lang/Object;[Ljava/lang/Object;)V"))
+        ];
+        let mut count = [0; 5];
+        for c in classes.iter_mut()
+        {
+            for (k, h) in hooks.iter().enumerate() { count[k] += c.add_hook(|_| true, h).unwrap(); }
+        }
+        assert!(count.iter().all(|c| *c > 0), "{:?}", count);
+
+        // Hooked classes still verify, and survive being written and parsed again
+        let classes: Vec<SmaliClass> = classes.iter().map(|c| SmaliClass::from_smali(&c.to_smali()).unwrap()).collect();
+        let hierarchy = ClassHierarchy::new(&classes);
+        for c in &classes
+        {
+            let errors: Vec<String> = verify_class(c, Some(&hierarchy)).iter().filter(|d| d.is_error()).map(|d| d.to_string()).collect();
+            assert!(errors.is_empty(), "{}: {:?}", c.name.as_jni_type(), errors);
+        }
+
+        // Signatures that don't fit are rejected
+        let bad = Hook::new(HookPoint::BeforeCall(check), HookArgs::Raw, MethodRef::from_jni("Lhook/Logger;->onCheck(I)V"));
+        let mut classes = classes;
+        assert!(classes.iter_mut().any(|c| c.add_hook(|_| true, &bad).is_err()));
+
+        // A constructor's receiver can only be passed as null
+        let raw = Hook::new(HookPoint::BeforeCall(init), HookArgs::Raw, MethodRef::from_jni("Lhook/Logger;->onNew(Ljava/lang/Object;Ljava/lang/String;)V"));
+        assert!(classes.iter_mut().any(|c| c.add_hook(|_| true, &raw).is_err()));
+    }
+}
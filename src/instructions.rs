@@ -0,0 +1,2612 @@
+//! Typed Dalvik instructions
+//!
+//! Every opcode has its own variant in [`DexInstruction`] with parsed registers, literals, labels and
+//! references. The [`Opcode`] of an instruction carries its mnemonic, DEX opcode byte and [`Format`],
+//! and [`Operand`] gives a uniform view of the operands for code that doesn't care about the
+//! specific opcode.
+//!
+//! # Examples
+//!
+//! ```
+//!  use smali::instructions::{DexInstruction, Opcode, Register};
+//!
+//!  let i = DexInstruction::Const4 { dest: Register::V(0), value: 0 };
+//!  assert_eq!(i.opcode(), Opcode::Const4);
+//!  assert_eq!(i.opcode().mnemonic(), "const/4");
+//! ```
+
+use std::fmt;
+use std::vec::IntoIter;
+use crate::smali_write::write_instruction;
+use crate::types::{FieldRef, MethodHandle, MethodRef, MethodSignature, TypeSignature};
+
+/// A Dalvik register, either a `v` register or a `p` (parameter) register
+///
+/// # Examples
+///
+/// ```
+///  use smali::instructions::Register;
+///
+///  assert_eq!(Register::P(1).to_string(), "p1");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Register {
+    V(u16),
+    P(u16)
+}
+
+impl Register {
+    /// The register number without its prefix
+    pub fn number(&self) -> u16
+    {
+        match self {
+            Register::V(n) | Register::P(n) => *n
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(n) => write!(f, "v{}", n),
+            Register::P(n) => write!(f, "p{}", n)
+        }
+    }
+}
+
+/// A contiguous range of registers as used by the `/range` instructions e.g. `{v0 .. v5}`
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RegisterRange {
+    pub start: Register,
+    pub end: Register,
+}
+
+impl RegisterRange {
+    /// Expands the range into the individual registers it covers
+    pub fn registers(&self) -> Vec<Register>
+    {
+        (self.start.number()..=self.end.number()).map(|n| match self.start {
+            Register::V(_) => Register::V(n),
+            Register::P(_) => Register::P(n)
+        }).collect()
+    }
+}
+
+impl fmt::Display for RegisterRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{{} .. {}}}", self.start, self.end)
+    }
+}
+
+/// Dalvik instruction formats, named as in the Dalvik bytecode specification
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    F10x,
+    F12x,
+    F11n,
+    F11x,
+    F10t,
+    F20t,
+    F22x,
+    F21t,
+    F21s,
+    F21h,
+    F21c,
+    F23x,
+    F22b,
+    F22t,
+    F22s,
+    F22c,
+    F30t,
+    F32x,
+    F31i,
+    F31t,
+    F31c,
+    F35c,
+    F3rc,
+    F45cc,
+    F4rcc,
+    F51l
+}
+
+impl Format {
+    /// Size of an instruction in this format in 16 bit code units
+    pub fn size(&self) -> usize
+    {
+        match self {
+            Format::F10x | Format::F12x | Format::F11n | Format::F11x | Format::F10t => 1,
+            Format::F20t | Format::F22x | Format::F21t | Format::F21s | Format::F21h | Format::F21c
+            | Format::F23x | Format::F22b | Format::F22t | Format::F22s | Format::F22c => 2,
+            Format::F30t | Format::F32x | Format::F31i | Format::F31t | Format::F31c | Format::F35c | Format::F3rc => 3,
+            Format::F45cc | Format::F4rcc => 4,
+            Format::F51l => 5
+        }
+    }
+
+    /// Bits available for each register operand in this format, every register of a list has 4 bits and of a range 16
+    pub fn register_bits(&self) -> Vec<u32>
+    {
+        match self {
+            Format::F10x | Format::F10t | Format::F20t | Format::F30t => vec![],
+            Format::F12x | Format::F22t | Format::F22s | Format::F22c => vec![4, 4],
+            Format::F11n => vec![4],
+            Format::F11x | Format::F21t | Format::F21s | Format::F21h | Format::F21c | Format::F31i | Format::F31t
+            | Format::F31c | Format::F51l => vec![8],
+            Format::F22x => vec![8, 16],
+            Format::F32x => vec![16, 16],
+            Format::F23x => vec![8, 8, 8],
+            Format::F22b => vec![8, 8],
+            Format::F35c | Format::F45cc => vec![4],
+            Format::F3rc | Format::F4rcc => vec![16]
+        }
+    }
+}
+
+/// The kinds of operand an instruction can take
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperandKind {
+    Register,
+    RegisterList,
+    RegisterRange,
+    Literal,
+    Label,
+    String,
+    Type,
+    Field,
+    Method,
+    Proto,
+    CallSite,
+    MethodHandle
+}
+
+/// A single instruction operand, see [`DexInstruction::operands`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Register(Register),
+    RegisterList(Vec<Register>),
+    RegisterRange(RegisterRange),
+    Literal(i64),
+    Label(String),
+    String(String),
+    Type(TypeSignature),
+    Field(FieldRef),
+    Method(MethodRef),
+    Proto(MethodSignature),
+    /// A call site reference, kept as its smali text
+    CallSite(String),
+    MethodHandle(MethodHandle)
+}
+
+/// The table of a `packed-switch` instruction, case `first_key + n` jumps to `targets[n]`
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedSwitch {
+    pub first_key: i32,
+    /// Target labels, without the leading `:`
+    pub targets: Vec<String>,
+}
+
+/// The table of a `sparse-switch` instruction as (key, target label) pairs
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseSwitch {
+    pub entries: Vec<(i32, String)>,
+}
+
+/// The constant array of a `fill-array-data` instruction
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArrayData {
+    /// Size of each element in bytes: 1, 2, 4 or 8
+    pub element_width: u32,
+    /// Element values, sign extended
+    pub values: Vec<i64>,
+}
+
+/// A data payload referenced by a `packed-switch`, `sparse-switch` or `fill-array-data` instruction,
+/// these are placed within the method's instructions after the label the instruction refers to.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    PackedSwitch(PackedSwitch),
+    SparseSwitch(SparseSwitch),
+    ArrayData(ArrayData)
+}
+
+/// Every Dalvik opcode, named after its smali mnemonic
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Nop,
+    Move,
+    MoveFrom16,
+    Move16,
+    MoveWide,
+    MoveWideFrom16,
+    MoveWide16,
+    MoveObject,
+    MoveObjectFrom16,
+    MoveObject16,
+    MoveResult,
+    MoveResultWide,
+    MoveResultObject,
+    MoveException,
+    ReturnVoid,
+    Return,
+    ReturnWide,
+    ReturnObject,
+    Const4,
+    Const16,
+    Const,
+    ConstHigh16,
+    ConstWide16,
+    ConstWide32,
+    ConstWide,
+    ConstWideHigh16,
+    ConstString,
+    ConstStringJumbo,
+    ConstClass,
+    MonitorEnter,
+    MonitorExit,
+    CheckCast,
+    InstanceOf,
+    ArrayLength,
+    NewInstance,
+    NewArray,
+    FilledNewArray,
+    FilledNewArrayRange,
+    FillArrayData,
+    Throw,
+    Goto,
+    Goto16,
+    Goto32,
+    PackedSwitch,
+    SparseSwitch,
+    CmplFloat,
+    CmpgFloat,
+    CmplDouble,
+    CmpgDouble,
+    CmpLong,
+    IfEq,
+    IfNe,
+    IfLt,
+    IfGe,
+    IfGt,
+    IfLe,
+    IfEqz,
+    IfNez,
+    IfLtz,
+    IfGez,
+    IfGtz,
+    IfLez,
+    Aget,
+    AgetWide,
+    AgetObject,
+    AgetBoolean,
+    AgetByte,
+    AgetChar,
+    AgetShort,
+    Aput,
+    AputWide,
+    AputObject,
+    AputBoolean,
+    AputByte,
+    AputChar,
+    AputShort,
+    Iget,
+    IgetWide,
+    IgetObject,
+    IgetBoolean,
+    IgetByte,
+    IgetChar,
+    IgetShort,
+    Iput,
+    IputWide,
+    IputObject,
+    IputBoolean,
+    IputByte,
+    IputChar,
+    IputShort,
+    Sget,
+    SgetWide,
+    SgetObject,
+    SgetBoolean,
+    SgetByte,
+    SgetChar,
+    SgetShort,
+    Sput,
+    SputWide,
+    SputObject,
+    SputBoolean,
+    SputByte,
+    SputChar,
+    SputShort,
+    InvokeVirtual,
+    InvokeSuper,
+    InvokeDirect,
+    InvokeStatic,
+    InvokeInterface,
+    InvokeVirtualRange,
+    InvokeSuperRange,
+    InvokeDirectRange,
+    InvokeStaticRange,
+    InvokeInterfaceRange,
+    NegInt,
+    NotInt,
+    NegLong,
+    NotLong,
+    NegFloat,
+    NegDouble,
+    IntToLong,
+    IntToFloat,
+    IntToDouble,
+    LongToInt,
+    LongToFloat,
+    LongToDouble,
+    FloatToInt,
+    FloatToLong,
+    FloatToDouble,
+    DoubleToInt,
+    DoubleToLong,
+    DoubleToFloat,
+    IntToByte,
+    IntToChar,
+    IntToShort,
+    AddInt,
+    SubInt,
+    MulInt,
+    DivInt,
+    RemInt,
+    AndInt,
+    OrInt,
+    XorInt,
+    ShlInt,
+    ShrInt,
+    UshrInt,
+    AddLong,
+    SubLong,
+    MulLong,
+    DivLong,
+    RemLong,
+    AndLong,
+    OrLong,
+    XorLong,
+    ShlLong,
+    ShrLong,
+    UshrLong,
+    AddFloat,
+    SubFloat,
+    MulFloat,
+    DivFloat,
+    RemFloat,
+    AddDouble,
+    SubDouble,
+    MulDouble,
+    DivDouble,
+    RemDouble,
+    AddInt2Addr,
+    SubInt2Addr,
+    MulInt2Addr,
+    DivInt2Addr,
+    RemInt2Addr,
+    AndInt2Addr,
+    OrInt2Addr,
+    XorInt2Addr,
+    ShlInt2Addr,
+    ShrInt2Addr,
+    UshrInt2Addr,
+    AddLong2Addr,
+    SubLong2Addr,
+    MulLong2Addr,
+    DivLong2Addr,
+    RemLong2Addr,
+    AndLong2Addr,
+    OrLong2Addr,
+    XorLong2Addr,
+    ShlLong2Addr,
+    ShrLong2Addr,
+    UshrLong2Addr,
+    AddFloat2Addr,
+    SubFloat2Addr,
+    MulFloat2Addr,
+    DivFloat2Addr,
+    RemFloat2Addr,
+    AddDouble2Addr,
+    SubDouble2Addr,
+    MulDouble2Addr,
+    DivDouble2Addr,
+    RemDouble2Addr,
+    AddIntLit16,
+    RsubInt,
+    MulIntLit16,
+    DivIntLit16,
+    RemIntLit16,
+    AndIntLit16,
+    OrIntLit16,
+    XorIntLit16,
+    AddIntLit8,
+    RsubIntLit8,
+    MulIntLit8,
+    DivIntLit8,
+    RemIntLit8,
+    AndIntLit8,
+    OrIntLit8,
+    XorIntLit8,
+    ShlIntLit8,
+    ShrIntLit8,
+    UshrIntLit8,
+    InvokePolymorphic,
+    InvokePolymorphicRange,
+    InvokeCustom,
+    InvokeCustomRange,
+    ConstMethodHandle,
+    ConstMethodType,
+}
+
+impl Opcode {
+    /// The smali mnemonic e.g. `invoke-virtual/range`
+    pub fn mnemonic(&self) -> &'static str
+    {
+        match self {
+            Opcode::Nop => "nop",
+            Opcode::Move => "move",
+            Opcode::MoveFrom16 => "move/from16",
+            Opcode::Move16 => "move/16",
+            Opcode::MoveWide => "move-wide",
+            Opcode::MoveWideFrom16 => "move-wide/from16",
+            Opcode::MoveWide16 => "move-wide/16",
+            Opcode::MoveObject => "move-object",
+            Opcode::MoveObjectFrom16 => "move-object/from16",
+            Opcode::MoveObject16 => "move-object/16",
+            Opcode::MoveResult => "move-result",
+            Opcode::MoveResultWide => "move-result-wide",
+            Opcode::MoveResultObject => "move-result-object",
+            Opcode::MoveException => "move-exception",
+            Opcode::ReturnVoid => "return-void",
+            Opcode::Return => "return",
+            Opcode::ReturnWide => "return-wide",
+            Opcode::ReturnObject => "return-object",
+            Opcode::Const4 => "const/4",
+            Opcode::Const16 => "const/16",
+            Opcode::Const => "const",
+            Opcode::ConstHigh16 => "const/high16",
+            Opcode::ConstWide16 => "const-wide/16",
+            Opcode::ConstWide32 => "const-wide/32",
+            Opcode::ConstWide => "const-wide",
+            Opcode::ConstWideHigh16 => "const-wide/high16",
+            Opcode::ConstString => "const-string",
+            Opcode::ConstStringJumbo => "const-string/jumbo",
+            Opcode::ConstClass => "const-class",
+            Opcode::MonitorEnter => "monitor-enter",
+            Opcode::MonitorExit => "monitor-exit",
+            Opcode::CheckCast => "check-cast",
+            Opcode::InstanceOf => "instance-of",
+            Opcode::ArrayLength => "array-length",
+            Opcode::NewInstance => "new-instance",
+            Opcode::NewArray => "new-array",
+            Opcode::FilledNewArray => "filled-new-array",
+            Opcode::FilledNewArrayRange => "filled-new-array/range",
+            Opcode::FillArrayData => "fill-array-data",
+            Opcode::Throw => "throw",
+            Opcode::Goto => "goto",
+            Opcode::Goto16 => "goto/16",
+            Opcode::Goto32 => "goto/32",
+            Opcode::PackedSwitch => "packed-switch",
+            Opcode::SparseSwitch => "sparse-switch",
+            Opcode::CmplFloat => "cmpl-float",
+            Opcode::CmpgFloat => "cmpg-float",
+            Opcode::CmplDouble => "cmpl-double",
+            Opcode::CmpgDouble => "cmpg-double",
+            Opcode::CmpLong => "cmp-long",
+            Opcode::IfEq => "if-eq",
+            Opcode::IfNe => "if-ne",
+            Opcode::IfLt => "if-lt",
+            Opcode::IfGe => "if-ge",
+            Opcode::IfGt => "if-gt",
+            Opcode::IfLe => "if-le",
+            Opcode::IfEqz => "if-eqz",
+            Opcode::IfNez => "if-nez",
+            Opcode::IfLtz => "if-ltz",
+            Opcode::IfGez => "if-gez",
+            Opcode::IfGtz => "if-gtz",
+            Opcode::IfLez => "if-lez",
+            Opcode::Aget => "aget",
+            Opcode::AgetWide => "aget-wide",
+            Opcode::AgetObject => "aget-object",
+            Opcode::AgetBoolean => "aget-boolean",
+            Opcode::AgetByte => "aget-byte",
+            Opcode::AgetChar => "aget-char",
+            Opcode::AgetShort => "aget-short",
+            Opcode::Aput => "aput",
+            Opcode::AputWide => "aput-wide",
+            Opcode::AputObject => "aput-object",
+            Opcode::AputBoolean => "aput-boolean",
+            Opcode::AputByte => "aput-byte",
+            Opcode::AputChar => "aput-char",
+            Opcode::AputShort => "aput-short",
+            Opcode::Iget => "iget",
+            Opcode::IgetWide => "iget-wide",
+            Opcode::IgetObject => "iget-object",
+            Opcode::IgetBoolean => "iget-boolean",
+            Opcode::IgetByte => "iget-byte",
+            Opcode::IgetChar => "iget-char",
+            Opcode::IgetShort => "iget-short",
+            Opcode::Iput => "iput",
+            Opcode::IputWide => "iput-wide",
+            Opcode::IputObject => "iput-object",
+            Opcode::IputBoolean => "iput-boolean",
+            Opcode::IputByte => "iput-byte",
+            Opcode::IputChar => "iput-char",
+            Opcode::IputShort => "iput-short",
+            Opcode::Sget => "sget",
+            Opcode::SgetWide => "sget-wide",
+            Opcode::SgetObject => "sget-object",
+            Opcode::SgetBoolean => "sget-boolean",
+            Opcode::SgetByte => "sget-byte",
+            Opcode::SgetChar => "sget-char",
+            Opcode::SgetShort => "sget-short",
+            Opcode::Sput => "sput",
+            Opcode::SputWide => "sput-wide",
+            Opcode::SputObject => "sput-object",
+            Opcode::SputBoolean => "sput-boolean",
+            Opcode::SputByte => "sput-byte",
+            Opcode::SputChar => "sput-char",
+            Opcode::SputShort => "sput-short",
+            Opcode::InvokeVirtual => "invoke-virtual",
+            Opcode::InvokeSuper => "invoke-super",
+            Opcode::InvokeDirect => "invoke-direct",
+            Opcode::InvokeStatic => "invoke-static",
+            Opcode::InvokeInterface => "invoke-interface",
+            Opcode::InvokeVirtualRange => "invoke-virtual/range",
+            Opcode::InvokeSuperRange => "invoke-super/range",
+            Opcode::InvokeDirectRange => "invoke-direct/range",
+            Opcode::InvokeStaticRange => "invoke-static/range",
+            Opcode::InvokeInterfaceRange => "invoke-interface/range",
+            Opcode::NegInt => "neg-int",
+            Opcode::NotInt => "not-int",
+            Opcode::NegLong => "neg-long",
+            Opcode::NotLong => "not-long",
+            Opcode::NegFloat => "neg-float",
+            Opcode::NegDouble => "neg-double",
+            Opcode::IntToLong => "int-to-long",
+            Opcode::IntToFloat => "int-to-float",
+            Opcode::IntToDouble => "int-to-double",
+            Opcode::LongToInt => "long-to-int",
+            Opcode::LongToFloat => "long-to-float",
+            Opcode::LongToDouble => "long-to-double",
+            Opcode::FloatToInt => "float-to-int",
+            Opcode::FloatToLong => "float-to-long",
+            Opcode::FloatToDouble => "float-to-double",
+            Opcode::DoubleToInt => "double-to-int",
+            Opcode::DoubleToLong => "double-to-long",
+            Opcode::DoubleToFloat => "double-to-float",
+            Opcode::IntToByte => "int-to-byte",
+            Opcode::IntToChar => "int-to-char",
+            Opcode::IntToShort => "int-to-short",
+            Opcode::AddInt => "add-int",
+            Opcode::SubInt => "sub-int",
+            Opcode::MulInt => "mul-int",
+            Opcode::DivInt => "div-int",
+            Opcode::RemInt => "rem-int",
+            Opcode::AndInt => "and-int",
+            Opcode::OrInt => "or-int",
+            Opcode::XorInt => "xor-int",
+            Opcode::ShlInt => "shl-int",
+            Opcode::ShrInt => "shr-int",
+            Opcode::UshrInt => "ushr-int",
+            Opcode::AddLong => "add-long",
+            Opcode::SubLong => "sub-long",
+            Opcode::MulLong => "mul-long",
+            Opcode::DivLong => "div-long",
+            Opcode::RemLong => "rem-long",
+            Opcode::AndLong => "and-long",
+            Opcode::OrLong => "or-long",
+            Opcode::XorLong => "xor-long",
+            Opcode::ShlLong => "shl-long",
+            Opcode::ShrLong => "shr-long",
+            Opcode::UshrLong => "ushr-long",
+            Opcode::AddFloat => "add-float",
+            Opcode::SubFloat => "sub-float",
+            Opcode::MulFloat => "mul-float",
+            Opcode::DivFloat => "div-float",
+            Opcode::RemFloat => "rem-float",
+            Opcode::AddDouble => "add-double",
+            Opcode::SubDouble => "sub-double",
+            Opcode::MulDouble => "mul-double",
+            Opcode::DivDouble => "div-double",
+            Opcode::RemDouble => "rem-double",
+            Opcode::AddInt2Addr => "add-int/2addr",
+            Opcode::SubInt2Addr => "sub-int/2addr",
+            Opcode::MulInt2Addr => "mul-int/2addr",
+            Opcode::DivInt2Addr => "div-int/2addr",
+            Opcode::RemInt2Addr => "rem-int/2addr",
+            Opcode::AndInt2Addr => "and-int/2addr",
+            Opcode::OrInt2Addr => "or-int/2addr",
+            Opcode::XorInt2Addr => "xor-int/2addr",
+            Opcode::ShlInt2Addr => "shl-int/2addr",
+            Opcode::ShrInt2Addr => "shr-int/2addr",
+            Opcode::UshrInt2Addr => "ushr-int/2addr",
+            Opcode::AddLong2Addr => "add-long/2addr",
+            Opcode::SubLong2Addr => "sub-long/2addr",
+            Opcode::MulLong2Addr => "mul-long/2addr",
+            Opcode::DivLong2Addr => "div-long/2addr",
+            Opcode::RemLong2Addr => "rem-long/2addr",
+            Opcode::AndLong2Addr => "and-long/2addr",
+            Opcode::OrLong2Addr => "or-long/2addr",
+            Opcode::XorLong2Addr => "xor-long/2addr",
+            Opcode::ShlLong2Addr => "shl-long/2addr",
+            Opcode::ShrLong2Addr => "shr-long/2addr",
+            Opcode::UshrLong2Addr => "ushr-long/2addr",
+            Opcode::AddFloat2Addr => "add-float/2addr",
+            Opcode::SubFloat2Addr => "sub-float/2addr",
+            Opcode::MulFloat2Addr => "mul-float/2addr",
+            Opcode::DivFloat2Addr => "div-float/2addr",
+            Opcode::RemFloat2Addr => "rem-float/2addr",
+            Opcode::AddDouble2Addr => "add-double/2addr",
+            Opcode::SubDouble2Addr => "sub-double/2addr",
+            Opcode::MulDouble2Addr => "mul-double/2addr",
+            Opcode::DivDouble2Addr => "div-double/2addr",
+            Opcode::RemDouble2Addr => "rem-double/2addr",
+            Opcode::AddIntLit16 => "add-int/lit16",
+            Opcode::RsubInt => "rsub-int",
+            Opcode::MulIntLit16 => "mul-int/lit16",
+            Opcode::DivIntLit16 => "div-int/lit16",
+            Opcode::RemIntLit16 => "rem-int/lit16",
+            Opcode::AndIntLit16 => "and-int/lit16",
+            Opcode::OrIntLit16 => "or-int/lit16",
+            Opcode::XorIntLit16 => "xor-int/lit16",
+            Opcode::AddIntLit8 => "add-int/lit8",
+            Opcode::RsubIntLit8 => "rsub-int/lit8",
+            Opcode::MulIntLit8 => "mul-int/lit8",
+            Opcode::DivIntLit8 => "div-int/lit8",
+            Opcode::RemIntLit8 => "rem-int/lit8",
+            Opcode::AndIntLit8 => "and-int/lit8",
+            Opcode::OrIntLit8 => "or-int/lit8",
+            Opcode::XorIntLit8 => "xor-int/lit8",
+            Opcode::ShlIntLit8 => "shl-int/lit8",
+            Opcode::ShrIntLit8 => "shr-int/lit8",
+            Opcode::UshrIntLit8 => "ushr-int/lit8",
+            Opcode::InvokePolymorphic => "invoke-polymorphic",
+            Opcode::InvokePolymorphicRange => "invoke-polymorphic/range",
+            Opcode::InvokeCustom => "invoke-custom",
+            Opcode::InvokeCustomRange => "invoke-custom/range",
+            Opcode::ConstMethodHandle => "const-method-handle",
+            Opcode::ConstMethodType => "const-method-type",
+        }
+    }
+
+    /// Looks up an opcode by its smali mnemonic
+    pub fn from_mnemonic(s: &str) -> Option<Opcode>
+    {
+        match s {
+            "nop" => Some(Opcode::Nop),
+            "move" => Some(Opcode::Move),
+            "move/from16" => Some(Opcode::MoveFrom16),
+            "move/16" => Some(Opcode::Move16),
+            "move-wide" => Some(Opcode::MoveWide),
+            "move-wide/from16" => Some(Opcode::MoveWideFrom16),
+            "move-wide/16" => Some(Opcode::MoveWide16),
+            "move-object" => Some(Opcode::MoveObject),
+            "move-object/from16" => Some(Opcode::MoveObjectFrom16),
+            "move-object/16" => Some(Opcode::MoveObject16),
+            "move-result" => Some(Opcode::MoveResult),
+            "move-result-wide" => Some(Opcode::MoveResultWide),
+            "move-result-object" => Some(Opcode::MoveResultObject),
+            "move-exception" => Some(Opcode::MoveException),
+            "return-void" => Some(Opcode::ReturnVoid),
+            "return" => Some(Opcode::Return),
+            "return-wide" => Some(Opcode::ReturnWide),
+            "return-object" => Some(Opcode::ReturnObject),
+            "const/4" => Some(Opcode::Const4),
+            "const/16" => Some(Opcode::Const16),
+            "const" => Some(Opcode::Const),
+            "const/high16" => Some(Opcode::ConstHigh16),
+            "const-wide/16" => Some(Opcode::ConstWide16),
+            "const-wide/32" => Some(Opcode::ConstWide32),
+            "const-wide" => Some(Opcode::ConstWide),
+            "const-wide/high16" => Some(Opcode::ConstWideHigh16),
+            "const-string" => Some(Opcode::ConstString),
+            "const-string/jumbo" => Some(Opcode::ConstStringJumbo),
+            "const-class" => Some(Opcode::ConstClass),
+            "monitor-enter" => Some(Opcode::MonitorEnter),
+            "monitor-exit" => Some(Opcode::MonitorExit),
+            "check-cast" => Some(Opcode::CheckCast),
+            "instance-of" => Some(Opcode::InstanceOf),
+            "array-length" => Some(Opcode::ArrayLength),
+            "new-instance" => Some(Opcode::NewInstance),
+            "new-array" => Some(Opcode::NewArray),
+            "filled-new-array" => Some(Opcode::FilledNewArray),
+            "filled-new-array/range" => Some(Opcode::FilledNewArrayRange),
+            "fill-array-data" => Some(Opcode::FillArrayData),
+            "throw" => Some(Opcode::Throw),
+            "goto" => Some(Opcode::Goto),
+            "goto/16" => Some(Opcode::Goto16),
+            "goto/32" => Some(Opcode::Goto32),
+            "packed-switch" => Some(Opcode::PackedSwitch),
+            "sparse-switch" => Some(Opcode::SparseSwitch),
+            "cmpl-float" => Some(Opcode::CmplFloat),
+            "cmpg-float" => Some(Opcode::CmpgFloat),
+            "cmpl-double" => Some(Opcode::CmplDouble),
+            "cmpg-double" => Some(Opcode::CmpgDouble),
+            "cmp-long" => Some(Opcode::CmpLong),
+            "if-eq" => Some(Opcode::IfEq),
+            "if-ne" => Some(Opcode::IfNe),
+            "if-lt" => Some(Opcode::IfLt),
+            "if-ge" => Some(Opcode::IfGe),
+            "if-gt" => Some(Opcode::IfGt),
+            "if-le" => Some(Opcode::IfLe),
+            "if-eqz" => Some(Opcode::IfEqz),
+            "if-nez" => Some(Opcode::IfNez),
+            "if-ltz" => Some(Opcode::IfLtz),
+            "if-gez" => Some(Opcode::IfGez),
+            "if-gtz" => Some(Opcode::IfGtz),
+            "if-lez" => Some(Opcode::IfLez),
+            "aget" => Some(Opcode::Aget),
+            "aget-wide" => Some(Opcode::AgetWide),
+            "aget-object" => Some(Opcode::AgetObject),
+            "aget-boolean" => Some(Opcode::AgetBoolean),
+            "aget-byte" => Some(Opcode::AgetByte),
+            "aget-char" => Some(Opcode::AgetChar),
+            "aget-short" => Some(Opcode::AgetShort),
+            "aput" => Some(Opcode::Aput),
+            "aput-wide" => Some(Opcode::AputWide),
+            "aput-object" => Some(Opcode::AputObject),
+            "aput-boolean" => Some(Opcode::AputBoolean),
+            "aput-byte" => Some(Opcode::AputByte),
+            "aput-char" => Some(Opcode::AputChar),
+            "aput-short" => Some(Opcode::AputShort),
+            "iget" => Some(Opcode::Iget),
+            "iget-wide" => Some(Opcode::IgetWide),
+            "iget-object" => Some(Opcode::IgetObject),
+            "iget-boolean" => Some(Opcode::IgetBoolean),
+            "iget-byte" => Some(Opcode::IgetByte),
+            "iget-char" => Some(Opcode::IgetChar),
+            "iget-short" => Some(Opcode::IgetShort),
+            "iput" => Some(Opcode::Iput),
+            "iput-wide" => Some(Opcode::IputWide),
+            "iput-object" => Some(Opcode::IputObject),
+            "iput-boolean" => Some(Opcode::IputBoolean),
+            "iput-byte" => Some(Opcode::IputByte),
+            "iput-char" => Some(Opcode::IputChar),
+            "iput-short" => Some(Opcode::IputShort),
+            "sget" => Some(Opcode::Sget),
+            "sget-wide" => Some(Opcode::SgetWide),
+            "sget-object" => Some(Opcode::SgetObject),
+            "sget-boolean" => Some(Opcode::SgetBoolean),
+            "sget-byte" => Some(Opcode::SgetByte),
+            "sget-char" => Some(Opcode::SgetChar),
+            "sget-short" => Some(Opcode::SgetShort),
+            "sput" => Some(Opcode::Sput),
+            "sput-wide" => Some(Opcode::SputWide),
+            "sput-object" => Some(Opcode::SputObject),
+            "sput-boolean" => Some(Opcode::SputBoolean),
+            "sput-byte" => Some(Opcode::SputByte),
+            "sput-char" => Some(Opcode::SputChar),
+            "sput-short" => Some(Opcode::SputShort),
+            "invoke-virtual" => Some(Opcode::InvokeVirtual),
+            "invoke-super" => Some(Opcode::InvokeSuper),
+            "invoke-direct" => Some(Opcode::InvokeDirect),
+            "invoke-static" => Some(Opcode::InvokeStatic),
+            "invoke-interface" => Some(Opcode::InvokeInterface),
+            "invoke-virtual/range" => Some(Opcode::InvokeVirtualRange),
+            "invoke-super/range" => Some(Opcode::InvokeSuperRange),
+            "invoke-direct/range" => Some(Opcode::InvokeDirectRange),
+            "invoke-static/range" => Some(Opcode::InvokeStaticRange),
+            "invoke-interface/range" => Some(Opcode::InvokeInterfaceRange),
+            "neg-int" => Some(Opcode::NegInt),
+            "not-int" => Some(Opcode::NotInt),
+            "neg-long" => Some(Opcode::NegLong),
+            "not-long" => Some(Opcode::NotLong),
+            "neg-float" => Some(Opcode::NegFloat),
+            "neg-double" => Some(Opcode::NegDouble),
+            "int-to-long" => Some(Opcode::IntToLong),
+            "int-to-float" => Some(Opcode::IntToFloat),
+            "int-to-double" => Some(Opcode::IntToDouble),
+            "long-to-int" => Some(Opcode::LongToInt),
+            "long-to-float" => Some(Opcode::LongToFloat),
+            "long-to-double" => Some(Opcode::LongToDouble),
+            "float-to-int" => Some(Opcode::FloatToInt),
+            "float-to-long" => Some(Opcode::FloatToLong),
+            "float-to-double" => Some(Opcode::FloatToDouble),
+            "double-to-int" => Some(Opcode::DoubleToInt),
+            "double-to-long" => Some(Opcode::DoubleToLong),
+            "double-to-float" => Some(Opcode::DoubleToFloat),
+            "int-to-byte" => Some(Opcode::IntToByte),
+            "int-to-char" => Some(Opcode::IntToChar),
+            "int-to-short" => Some(Opcode::IntToShort),
+            "add-int" => Some(Opcode::AddInt),
+            "sub-int" => Some(Opcode::SubInt),
+            "mul-int" => Some(Opcode::MulInt),
+            "div-int" => Some(Opcode::DivInt),
+            "rem-int" => Some(Opcode::RemInt),
+            "and-int" => Some(Opcode::AndInt),
+            "or-int" => Some(Opcode::OrInt),
+            "xor-int" => Some(Opcode::XorInt),
+            "shl-int" => Some(Opcode::ShlInt),
+            "shr-int" => Some(Opcode::ShrInt),
+            "ushr-int" => Some(Opcode::UshrInt),
+            "add-long" => Some(Opcode::AddLong),
+            "sub-long" => Some(Opcode::SubLong),
+            "mul-long" => Some(Opcode::MulLong),
+            "div-long" => Some(Opcode::DivLong),
+            "rem-long" => Some(Opcode::RemLong),
+            "and-long" => Some(Opcode::AndLong),
+            "or-long" => Some(Opcode::OrLong),
+            "xor-long" => Some(Opcode::XorLong),
+            "shl-long" => Some(Opcode::ShlLong),
+            "shr-long" => Some(Opcode::ShrLong),
+            "ushr-long" => Some(Opcode::UshrLong),
+            "add-float" => Some(Opcode::AddFloat),
+            "sub-float" => Some(Opcode::SubFloat),
+            "mul-float" => Some(Opcode::MulFloat),
+            "div-float" => Some(Opcode::DivFloat),
+            "rem-float" => Some(Opcode::RemFloat),
+            "add-double" => Some(Opcode::AddDouble),
+            "sub-double" => Some(Opcode::SubDouble),
+            "mul-double" => Some(Opcode::MulDouble),
+            "div-double" => Some(Opcode::DivDouble),
+            "rem-double" => Some(Opcode::RemDouble),
+            "add-int/2addr" => Some(Opcode::AddInt2Addr),
+            "sub-int/2addr" => Some(Opcode::SubInt2Addr),
+            "mul-int/2addr" => Some(Opcode::MulInt2Addr),
+            "div-int/2addr" => Some(Opcode::DivInt2Addr),
+            "rem-int/2addr" => Some(Opcode::RemInt2Addr),
+            "and-int/2addr" => Some(Opcode::AndInt2Addr),
+            "or-int/2addr" => Some(Opcode::OrInt2Addr),
+            "xor-int/2addr" => Some(Opcode::XorInt2Addr),
+            "shl-int/2addr" => Some(Opcode::ShlInt2Addr),
+            "shr-int/2addr" => Some(Opcode::ShrInt2Addr),
+            "ushr-int/2addr" => Some(Opcode::UshrInt2Addr),
+            "add-long/2addr" => Some(Opcode::AddLong2Addr),
+            "sub-long/2addr" => Some(Opcode::SubLong2Addr),
+            "mul-long/2addr" => Some(Opcode::MulLong2Addr),
+            "div-long/2addr" => Some(Opcode::DivLong2Addr),
+            "rem-long/2addr" => Some(Opcode::RemLong2Addr),
+            "and-long/2addr" => Some(Opcode::AndLong2Addr),
+            "or-long/2addr" => Some(Opcode::OrLong2Addr),
+            "xor-long/2addr" => Some(Opcode::XorLong2Addr),
+            "shl-long/2addr" => Some(Opcode::ShlLong2Addr),
+            "shr-long/2addr" => Some(Opcode::ShrLong2Addr),
+            "ushr-long/2addr" => Some(Opcode::UshrLong2Addr),
+            "add-float/2addr" => Some(Opcode::AddFloat2Addr),
+            "sub-float/2addr" => Some(Opcode::SubFloat2Addr),
+            "mul-float/2addr" => Some(Opcode::MulFloat2Addr),
+            "div-float/2addr" => Some(Opcode::DivFloat2Addr),
+            "rem-float/2addr" => Some(Opcode::RemFloat2Addr),
+            "add-double/2addr" => Some(Opcode::AddDouble2Addr),
+            "sub-double/2addr" => Some(Opcode::SubDouble2Addr),
+            "mul-double/2addr" => Some(Opcode::MulDouble2Addr),
+            "div-double/2addr" => Some(Opcode::DivDouble2Addr),
+            "rem-double/2addr" => Some(Opcode::RemDouble2Addr),
+            "add-int/lit16" => Some(Opcode::AddIntLit16),
+            "rsub-int" => Some(Opcode::RsubInt),
+            "mul-int/lit16" => Some(Opcode::MulIntLit16),
+            "div-int/lit16" => Some(Opcode::DivIntLit16),
+            "rem-int/lit16" => Some(Opcode::RemIntLit16),
+            "and-int/lit16" => Some(Opcode::AndIntLit16),
+            "or-int/lit16" => Some(Opcode::OrIntLit16),
+            "xor-int/lit16" => Some(Opcode::XorIntLit16),
+            "add-int/lit8" => Some(Opcode::AddIntLit8),
+            "rsub-int/lit8" => Some(Opcode::RsubIntLit8),
+            "mul-int/lit8" => Some(Opcode::MulIntLit8),
+            "div-int/lit8" => Some(Opcode::DivIntLit8),
+            "rem-int/lit8" => Some(Opcode::RemIntLit8),
+            "and-int/lit8" => Some(Opcode::AndIntLit8),
+            "or-int/lit8" => Some(Opcode::OrIntLit8),
+            "xor-int/lit8" => Some(Opcode::XorIntLit8),
+            "shl-int/lit8" => Some(Opcode::ShlIntLit8),
+            "shr-int/lit8" => Some(Opcode::ShrIntLit8),
+            "ushr-int/lit8" => Some(Opcode::UshrIntLit8),
+            "invoke-polymorphic" => Some(Opcode::InvokePolymorphic),
+            "invoke-polymorphic/range" => Some(Opcode::InvokePolymorphicRange),
+            "invoke-custom" => Some(Opcode::InvokeCustom),
+            "invoke-custom/range" => Some(Opcode::InvokeCustomRange),
+            "const-method-handle" => Some(Opcode::ConstMethodHandle),
+            "const-method-type" => Some(Opcode::ConstMethodType),
+            _ => None
+        }
+    }
+
+    /// The opcode byte as encoded in a DEX code item
+    pub fn code(&self) -> u8
+    {
+        match self {
+            Opcode::Nop => 0x00,
+            Opcode::Move => 0x01,
+            Opcode::MoveFrom16 => 0x02,
+            Opcode::Move16 => 0x03,
+            Opcode::MoveWide => 0x04,
+            Opcode::MoveWideFrom16 => 0x05,
+            Opcode::MoveWide16 => 0x06,
+            Opcode::MoveObject => 0x07,
+            Opcode::MoveObjectFrom16 => 0x08,
+            Opcode::MoveObject16 => 0x09,
+            Opcode::MoveResult => 0x0a,
+            Opcode::MoveResultWide => 0x0b,
+            Opcode::MoveResultObject => 0x0c,
+            Opcode::MoveException => 0x0d,
+            Opcode::ReturnVoid => 0x0e,
+            Opcode::Return => 0x0f,
+            Opcode::ReturnWide => 0x10,
+            Opcode::ReturnObject => 0x11,
+            Opcode::Const4 => 0x12,
+            Opcode::Const16 => 0x13,
+            Opcode::Const => 0x14,
+            Opcode::ConstHigh16 => 0x15,
+            Opcode::ConstWide16 => 0x16,
+            Opcode::ConstWide32 => 0x17,
+            Opcode::ConstWide => 0x18,
+            Opcode::ConstWideHigh16 => 0x19,
+            Opcode::ConstString => 0x1a,
+            Opcode::ConstStringJumbo => 0x1b,
+            Opcode::ConstClass => 0x1c,
+            Opcode::MonitorEnter => 0x1d,
+            Opcode::MonitorExit => 0x1e,
+            Opcode::CheckCast => 0x1f,
+            Opcode::InstanceOf => 0x20,
+            Opcode::ArrayLength => 0x21,
+            Opcode::NewInstance => 0x22,
+            Opcode::NewArray => 0x23,
+            Opcode::FilledNewArray => 0x24,
+            Opcode::FilledNewArrayRange => 0x25,
+            Opcode::FillArrayData => 0x26,
+            Opcode::Throw => 0x27,
+            Opcode::Goto => 0x28,
+            Opcode::Goto16 => 0x29,
+            Opcode::Goto32 => 0x2a,
+            Opcode::PackedSwitch => 0x2b,
+            Opcode::SparseSwitch => 0x2c,
+            Opcode::CmplFloat => 0x2d,
+            Opcode::CmpgFloat => 0x2e,
+            Opcode::CmplDouble => 0x2f,
+            Opcode::CmpgDouble => 0x30,
+            Opcode::CmpLong => 0x31,
+            Opcode::IfEq => 0x32,
+            Opcode::IfNe => 0x33,
+            Opcode::IfLt => 0x34,
+            Opcode::IfGe => 0x35,
+            Opcode::IfGt => 0x36,
+            Opcode::IfLe => 0x37,
+            Opcode::IfEqz => 0x38,
+            Opcode::IfNez => 0x39,
+            Opcode::IfLtz => 0x3a,
+            Opcode::IfGez => 0x3b,
+            Opcode::IfGtz => 0x3c,
+            Opcode::IfLez => 0x3d,
+            Opcode::Aget => 0x44,
+            Opcode::AgetWide => 0x45,
+            Opcode::AgetObject => 0x46,
+            Opcode::AgetBoolean => 0x47,
+            Opcode::AgetByte => 0x48,
+            Opcode::AgetChar => 0x49,
+            Opcode::AgetShort => 0x4a,
+            Opcode::Aput => 0x4b,
+            Opcode::AputWide => 0x4c,
+            Opcode::AputObject => 0x4d,
+            Opcode::AputBoolean => 0x4e,
+            Opcode::AputByte => 0x4f,
+            Opcode::AputChar => 0x50,
+            Opcode::AputShort => 0x51,
+            Opcode::Iget => 0x52,
+            Opcode::IgetWide => 0x53,
+            Opcode::IgetObject => 0x54,
+            Opcode::IgetBoolean => 0x55,
+            Opcode::IgetByte => 0x56,
+            Opcode::IgetChar => 0x57,
+            Opcode::IgetShort => 0x58,
+            Opcode::Iput => 0x59,
+            Opcode::IputWide => 0x5a,
+            Opcode::IputObject => 0x5b,
+            Opcode::IputBoolean => 0x5c,
+            Opcode::IputByte => 0x5d,
+            Opcode::IputChar => 0x5e,
+            Opcode::IputShort => 0x5f,
+            Opcode::Sget => 0x60,
+            Opcode::SgetWide => 0x61,
+            Opcode::SgetObject => 0x62,
+            Opcode::SgetBoolean => 0x63,
+            Opcode::SgetByte => 0x64,
+            Opcode::SgetChar => 0x65,
+            Opcode::SgetShort => 0x66,
+            Opcode::Sput => 0x67,
+            Opcode::SputWide => 0x68,
+            Opcode::SputObject => 0x69,
+            Opcode::SputBoolean => 0x6a,
+            Opcode::SputByte => 0x6b,
+            Opcode::SputChar => 0x6c,
+            Opcode::SputShort => 0x6d,
+            Opcode::InvokeVirtual => 0x6e,
+            Opcode::InvokeSuper => 0x6f,
+            Opcode::InvokeDirect => 0x70,
+            Opcode::InvokeStatic => 0x71,
+            Opcode::InvokeInterface => 0x72,
+            Opcode::InvokeVirtualRange => 0x74,
+            Opcode::InvokeSuperRange => 0x75,
+            Opcode::InvokeDirectRange => 0x76,
+            Opcode::InvokeStaticRange => 0x77,
+            Opcode::InvokeInterfaceRange => 0x78,
+            Opcode::NegInt => 0x7b,
+            Opcode::NotInt => 0x7c,
+            Opcode::NegLong => 0x7d,
+            Opcode::NotLong => 0x7e,
+            Opcode::NegFloat => 0x7f,
+            Opcode::NegDouble => 0x80,
+            Opcode::IntToLong => 0x81,
+            Opcode::IntToFloat => 0x82,
+            Opcode::IntToDouble => 0x83,
+            Opcode::LongToInt => 0x84,
+            Opcode::LongToFloat => 0x85,
+            Opcode::LongToDouble => 0x86,
+            Opcode::FloatToInt => 0x87,
+            Opcode::FloatToLong => 0x88,
+            Opcode::FloatToDouble => 0x89,
+            Opcode::DoubleToInt => 0x8a,
+            Opcode::DoubleToLong => 0x8b,
+            Opcode::DoubleToFloat => 0x8c,
+            Opcode::IntToByte => 0x8d,
+            Opcode::IntToChar => 0x8e,
+            Opcode::IntToShort => 0x8f,
+            Opcode::AddInt => 0x90,
+            Opcode::SubInt => 0x91,
+            Opcode::MulInt => 0x92,
+            Opcode::DivInt => 0x93,
+            Opcode::RemInt => 0x94,
+            Opcode::AndInt => 0x95,
+            Opcode::OrInt => 0x96,
+            Opcode::XorInt => 0x97,
+            Opcode::ShlInt => 0x98,
+            Opcode::ShrInt => 0x99,
+            Opcode::UshrInt => 0x9a,
+            Opcode::AddLong => 0x9b,
+            Opcode::SubLong => 0x9c,
+            Opcode::MulLong => 0x9d,
+            Opcode::DivLong => 0x9e,
+            Opcode::RemLong => 0x9f,
+            Opcode::AndLong => 0xa0,
+            Opcode::OrLong => 0xa1,
+            Opcode::XorLong => 0xa2,
+            Opcode::ShlLong => 0xa3,
+            Opcode::ShrLong => 0xa4,
+            Opcode::UshrLong => 0xa5,
+            Opcode::AddFloat => 0xa6,
+            Opcode::SubFloat => 0xa7,
+            Opcode::MulFloat => 0xa8,
+            Opcode::DivFloat => 0xa9,
+            Opcode::RemFloat => 0xaa,
+            Opcode::AddDouble => 0xab,
+            Opcode::SubDouble => 0xac,
+            Opcode::MulDouble => 0xad,
+            Opcode::DivDouble => 0xae,
+            Opcode::RemDouble => 0xaf,
+            Opcode::AddInt2Addr => 0xb0,
+            Opcode::SubInt2Addr => 0xb1,
+            Opcode::MulInt2Addr => 0xb2,
+            Opcode::DivInt2Addr => 0xb3,
+            Opcode::RemInt2Addr => 0xb4,
+            Opcode::AndInt2Addr => 0xb5,
+            Opcode::OrInt2Addr => 0xb6,
+            Opcode::XorInt2Addr => 0xb7,
+            Opcode::ShlInt2Addr => 0xb8,
+            Opcode::ShrInt2Addr => 0xb9,
+            Opcode::UshrInt2Addr => 0xba,
+            Opcode::AddLong2Addr => 0xbb,
+            Opcode::SubLong2Addr => 0xbc,
+            Opcode::MulLong2Addr => 0xbd,
+            Opcode::DivLong2Addr => 0xbe,
+            Opcode::RemLong2Addr => 0xbf,
+            Opcode::AndLong2Addr => 0xc0,
+            Opcode::OrLong2Addr => 0xc1,
+            Opcode::XorLong2Addr => 0xc2,
+            Opcode::ShlLong2Addr => 0xc3,
+            Opcode::ShrLong2Addr => 0xc4,
+            Opcode::UshrLong2Addr => 0xc5,
+            Opcode::AddFloat2Addr => 0xc6,
+            Opcode::SubFloat2Addr => 0xc7,
+            Opcode::MulFloat2Addr => 0xc8,
+            Opcode::DivFloat2Addr => 0xc9,
+            Opcode::RemFloat2Addr => 0xca,
+            Opcode::AddDouble2Addr => 0xcb,
+            Opcode::SubDouble2Addr => 0xcc,
+            Opcode::MulDouble2Addr => 0xcd,
+            Opcode::DivDouble2Addr => 0xce,
+            Opcode::RemDouble2Addr => 0xcf,
+            Opcode::AddIntLit16 => 0xd0,
+            Opcode::RsubInt => 0xd1,
+            Opcode::MulIntLit16 => 0xd2,
+            Opcode::DivIntLit16 => 0xd3,
+            Opcode::RemIntLit16 => 0xd4,
+            Opcode::AndIntLit16 => 0xd5,
+            Opcode::OrIntLit16 => 0xd6,
+            Opcode::XorIntLit16 => 0xd7,
+            Opcode::AddIntLit8 => 0xd8,
+            Opcode::RsubIntLit8 => 0xd9,
+            Opcode::MulIntLit8 => 0xda,
+            Opcode::DivIntLit8 => 0xdb,
+            Opcode::RemIntLit8 => 0xdc,
+            Opcode::AndIntLit8 => 0xdd,
+            Opcode::OrIntLit8 => 0xde,
+            Opcode::XorIntLit8 => 0xdf,
+            Opcode::ShlIntLit8 => 0xe0,
+            Opcode::ShrIntLit8 => 0xe1,
+            Opcode::UshrIntLit8 => 0xe2,
+            Opcode::InvokePolymorphic => 0xfa,
+            Opcode::InvokePolymorphicRange => 0xfb,
+            Opcode::InvokeCustom => 0xfc,
+            Opcode::InvokeCustomRange => 0xfd,
+            Opcode::ConstMethodHandle => 0xfe,
+            Opcode::ConstMethodType => 0xff,
+        }
+    }
+
+    /// Looks up an opcode by its DEX opcode byte, unused opcodes return None
+    pub fn from_code(code: u8) -> Option<Opcode>
+    {
+        match code {
+            0x00 => Some(Opcode::Nop),
+            0x01 => Some(Opcode::Move),
+            0x02 => Some(Opcode::MoveFrom16),
+            0x03 => Some(Opcode::Move16),
+            0x04 => Some(Opcode::MoveWide),
+            0x05 => Some(Opcode::MoveWideFrom16),
+            0x06 => Some(Opcode::MoveWide16),
+            0x07 => Some(Opcode::MoveObject),
+            0x08 => Some(Opcode::MoveObjectFrom16),
+            0x09 => Some(Opcode::MoveObject16),
+            0x0a => Some(Opcode::MoveResult),
+            0x0b => Some(Opcode::MoveResultWide),
+            0x0c => Some(Opcode::MoveResultObject),
+            0x0d => Some(Opcode::MoveException),
+            0x0e => Some(Opcode::ReturnVoid),
+            0x0f => Some(Opcode::Return),
+            0x10 => Some(Opcode::ReturnWide),
+            0x11 => Some(Opcode::ReturnObject),
+            0x12 => Some(Opcode::Const4),
+            0x13 => Some(Opcode::Const16),
+            0x14 => Some(Opcode::Const),
+            0x15 => Some(Opcode::ConstHigh16),
+            0x16 => Some(Opcode::ConstWide16),
+            0x17 => Some(Opcode::ConstWide32),
+            0x18 => Some(Opcode::ConstWide),
+            0x19 => Some(Opcode::ConstWideHigh16),
+            0x1a => Some(Opcode::ConstString),
+            0x1b => Some(Opcode::ConstStringJumbo),
+            0x1c => Some(Opcode::ConstClass),
+            0x1d => Some(Opcode::MonitorEnter),
+            0x1e => Some(Opcode::MonitorExit),
+            0x1f => Some(Opcode::CheckCast),
+            0x20 => Some(Opcode::InstanceOf),
+            0x21 => Some(Opcode::ArrayLength),
+            0x22 => Some(Opcode::NewInstance),
+            0x23 => Some(Opcode::NewArray),
+            0x24 => Some(Opcode::FilledNewArray),
+            0x25 => Some(Opcode::FilledNewArrayRange),
+            0x26 => Some(Opcode::FillArrayData),
+            0x27 => Some(Opcode::Throw),
+            0x28 => Some(Opcode::Goto),
+            0x29 => Some(Opcode::Goto16),
+            0x2a => Some(Opcode::Goto32),
+            0x2b => Some(Opcode::PackedSwitch),
+            0x2c => Some(Opcode::SparseSwitch),
+            0x2d => Some(Opcode::CmplFloat),
+            0x2e => Some(Opcode::CmpgFloat),
+            0x2f => Some(Opcode::CmplDouble),
+            0x30 => Some(Opcode::CmpgDouble),
+            0x31 => Some(Opcode::CmpLong),
+            0x32 => Some(Opcode::IfEq),
+            0x33 => Some(Opcode::IfNe),
+            0x34 => Some(Opcode::IfLt),
+            0x35 => Some(Opcode::IfGe),
+            0x36 => Some(Opcode::IfGt),
+            0x37 => Some(Opcode::IfLe),
+            0x38 => Some(Opcode::IfEqz),
+            0x39 => Some(Opcode::IfNez),
+            0x3a => Some(Opcode::IfLtz),
+            0x3b => Some(Opcode::IfGez),
+            0x3c => Some(Opcode::IfGtz),
+            0x3d => Some(Opcode::IfLez),
+            0x44 => Some(Opcode::Aget),
+            0x45 => Some(Opcode::AgetWide),
+            0x46 => Some(Opcode::AgetObject),
+            0x47 => Some(Opcode::AgetBoolean),
+            0x48 => Some(Opcode::AgetByte),
+            0x49 => Some(Opcode::AgetChar),
+            0x4a => Some(Opcode::AgetShort),
+            0x4b => Some(Opcode::Aput),
+            0x4c => Some(Opcode::AputWide),
+            0x4d => Some(Opcode::AputObject),
+            0x4e => Some(Opcode::AputBoolean),
+            0x4f => Some(Opcode::AputByte),
+            0x50 => Some(Opcode::AputChar),
+            0x51 => Some(Opcode::AputShort),
+            0x52 => Some(Opcode::Iget),
+            0x53 => Some(Opcode::IgetWide),
+            0x54 => Some(Opcode::IgetObject),
+            0x55 => Some(Opcode::IgetBoolean),
+            0x56 => Some(Opcode::IgetByte),
+            0x57 => Some(Opcode::IgetChar),
+            0x58 => Some(Opcode::IgetShort),
+            0x59 => Some(Opcode::Iput),
+            0x5a => Some(Opcode::IputWide),
+            0x5b => Some(Opcode::IputObject),
+            0x5c => Some(Opcode::IputBoolean),
+            0x5d => Some(Opcode::IputByte),
+            0x5e => Some(Opcode::IputChar),
+            0x5f => Some(Opcode::IputShort),
+            0x60 => Some(Opcode::Sget),
+            0x61 => Some(Opcode::SgetWide),
+            0x62 => Some(Opcode::SgetObject),
+            0x63 => Some(Opcode::SgetBoolean),
+            0x64 => Some(Opcode::SgetByte),
+            0x65 => Some(Opcode::SgetChar),
+            0x66 => Some(Opcode::SgetShort),
+            0x67 => Some(Opcode::Sput),
+            0x68 => Some(Opcode::SputWide),
+            0x69 => Some(Opcode::SputObject),
+            0x6a => Some(Opcode::SputBoolean),
+            0x6b => Some(Opcode::SputByte),
+            0x6c => Some(Opcode::SputChar),
+            0x6d => Some(Opcode::SputShort),
+            0x6e => Some(Opcode::InvokeVirtual),
+            0x6f => Some(Opcode::InvokeSuper),
+            0x70 => Some(Opcode::InvokeDirect),
+            0x71 => Some(Opcode::InvokeStatic),
+            0x72 => Some(Opcode::InvokeInterface),
+            0x74 => Some(Opcode::InvokeVirtualRange),
+            0x75 => Some(Opcode::InvokeSuperRange),
+            0x76 => Some(Opcode::InvokeDirectRange),
+            0x77 => Some(Opcode::InvokeStaticRange),
+            0x78 => Some(Opcode::InvokeInterfaceRange),
+            0x7b => Some(Opcode::NegInt),
+            0x7c => Some(Opcode::NotInt),
+            0x7d => Some(Opcode::NegLong),
+            0x7e => Some(Opcode::NotLong),
+            0x7f => Some(Opcode::NegFloat),
+            0x80 => Some(Opcode::NegDouble),
+            0x81 => Some(Opcode::IntToLong),
+            0x82 => Some(Opcode::IntToFloat),
+            0x83 => Some(Opcode::IntToDouble),
+            0x84 => Some(Opcode::LongToInt),
+            0x85 => Some(Opcode::LongToFloat),
+            0x86 => Some(Opcode::LongToDouble),
+            0x87 => Some(Opcode::FloatToInt),
+            0x88 => Some(Opcode::FloatToLong),
+            0x89 => Some(Opcode::FloatToDouble),
+            0x8a => Some(Opcode::DoubleToInt),
+            0x8b => Some(Opcode::DoubleToLong),
+            0x8c => Some(Opcode::DoubleToFloat),
+            0x8d => Some(Opcode::IntToByte),
+            0x8e => Some(Opcode::IntToChar),
+            0x8f => Some(Opcode::IntToShort),
+            0x90 => Some(Opcode::AddInt),
+            0x91 => Some(Opcode::SubInt),
+            0x92 => Some(Opcode::MulInt),
+            0x93 => Some(Opcode::DivInt),
+            0x94 => Some(Opcode::RemInt),
+            0x95 => Some(Opcode::AndInt),
+            0x96 => Some(Opcode::OrInt),
+            0x97 => Some(Opcode::XorInt),
+            0x98 => Some(Opcode::ShlInt),
+            0x99 => Some(Opcode::ShrInt),
+            0x9a => Some(Opcode::UshrInt),
+            0x9b => Some(Opcode::AddLong),
+            0x9c => Some(Opcode::SubLong),
+            0x9d => Some(Opcode::MulLong),
+            0x9e => Some(Opcode::DivLong),
+            0x9f => Some(Opcode::RemLong),
+            0xa0 => Some(Opcode::AndLong),
+            0xa1 => Some(Opcode::OrLong),
+            0xa2 => Some(Opcode::XorLong),
+            0xa3 => Some(Opcode::ShlLong),
+            0xa4 => Some(Opcode::ShrLong),
+            0xa5 => Some(Opcode::UshrLong),
+            0xa6 => Some(Opcode::AddFloat),
+            0xa7 => Some(Opcode::SubFloat),
+            0xa8 => Some(Opcode::MulFloat),
+            0xa9 => Some(Opcode::DivFloat),
+            0xaa => Some(Opcode::RemFloat),
+            0xab => Some(Opcode::AddDouble),
+            0xac => Some(Opcode::SubDouble),
+            0xad => Some(Opcode::MulDouble),
+            0xae => Some(Opcode::DivDouble),
+            0xaf => Some(Opcode::RemDouble),
+            0xb0 => Some(Opcode::AddInt2Addr),
+            0xb1 => Some(Opcode::SubInt2Addr),
+            0xb2 => Some(Opcode::MulInt2Addr),
+            0xb3 => Some(Opcode::DivInt2Addr),
+            0xb4 => Some(Opcode::RemInt2Addr),
+            0xb5 => Some(Opcode::AndInt2Addr),
+            0xb6 => Some(Opcode::OrInt2Addr),
+            0xb7 => Some(Opcode::XorInt2Addr),
+            0xb8 => Some(Opcode::ShlInt2Addr),
+            0xb9 => Some(Opcode::ShrInt2Addr),
+            0xba => Some(Opcode::UshrInt2Addr),
+            0xbb => Some(Opcode::AddLong2Addr),
+            0xbc => Some(Opcode::SubLong2Addr),
+            0xbd => Some(Opcode::MulLong2Addr),
+            0xbe => Some(Opcode::DivLong2Addr),
+            0xbf => Some(Opcode::RemLong2Addr),
+            0xc0 => Some(Opcode::AndLong2Addr),
+            0xc1 => Some(Opcode::OrLong2Addr),
+            0xc2 => Some(Opcode::XorLong2Addr),
+            0xc3 => Some(Opcode::ShlLong2Addr),
+            0xc4 => Some(Opcode::ShrLong2Addr),
+            0xc5 => Some(Opcode::UshrLong2Addr),
+            0xc6 => Some(Opcode::AddFloat2Addr),
+            0xc7 => Some(Opcode::SubFloat2Addr),
+            0xc8 => Some(Opcode::MulFloat2Addr),
+            0xc9 => Some(Opcode::DivFloat2Addr),
+            0xca => Some(Opcode::RemFloat2Addr),
+            0xcb => Some(Opcode::AddDouble2Addr),
+            0xcc => Some(Opcode::SubDouble2Addr),
+            0xcd => Some(Opcode::MulDouble2Addr),
+            0xce => Some(Opcode::DivDouble2Addr),
+            0xcf => Some(Opcode::RemDouble2Addr),
+            0xd0 => Some(Opcode::AddIntLit16),
+            0xd1 => Some(Opcode::RsubInt),
+            0xd2 => Some(Opcode::MulIntLit16),
+            0xd3 => Some(Opcode::DivIntLit16),
+            0xd4 => Some(Opcode::RemIntLit16),
+            0xd5 => Some(Opcode::AndIntLit16),
+            0xd6 => Some(Opcode::OrIntLit16),
+            0xd7 => Some(Opcode::XorIntLit16),
+            0xd8 => Some(Opcode::AddIntLit8),
+            0xd9 => Some(Opcode::RsubIntLit8),
+            0xda => Some(Opcode::MulIntLit8),
+            0xdb => Some(Opcode::DivIntLit8),
+            0xdc => Some(Opcode::RemIntLit8),
+            0xdd => Some(Opcode::AndIntLit8),
+            0xde => Some(Opcode::OrIntLit8),
+            0xdf => Some(Opcode::XorIntLit8),
+            0xe0 => Some(Opcode::ShlIntLit8),
+            0xe1 => Some(Opcode::ShrIntLit8),
+            0xe2 => Some(Opcode::UshrIntLit8),
+            0xfa => Some(Opcode::InvokePolymorphic),
+            0xfb => Some(Opcode::InvokePolymorphicRange),
+            0xfc => Some(Opcode::InvokeCustom),
+            0xfd => Some(Opcode::InvokeCustomRange),
+            0xfe => Some(Opcode::ConstMethodHandle),
+            0xff => Some(Opcode::ConstMethodType),
+            _ => None
+        }
+    }
+
+    /// The instruction format, which determines both the operands and the binary encoding
+    pub fn format(&self) -> Format
+    {
+        match self {
+            Opcode::Nop | Opcode::ReturnVoid => Format::F10x,
+            Opcode::Move | Opcode::MoveWide | Opcode::MoveObject | Opcode::ArrayLength | Opcode::NegInt
+            | Opcode::NotInt | Opcode::NegLong | Opcode::NotLong | Opcode::NegFloat | Opcode::NegDouble
+            | Opcode::IntToLong | Opcode::IntToFloat | Opcode::IntToDouble | Opcode::LongToInt
+            | Opcode::LongToFloat | Opcode::LongToDouble | Opcode::FloatToInt | Opcode::FloatToLong
+            | Opcode::FloatToDouble | Opcode::DoubleToInt | Opcode::DoubleToLong | Opcode::DoubleToFloat
+            | Opcode::IntToByte | Opcode::IntToChar | Opcode::IntToShort | Opcode::AddInt2Addr
+            | Opcode::SubInt2Addr | Opcode::MulInt2Addr | Opcode::DivInt2Addr | Opcode::RemInt2Addr
+            | Opcode::AndInt2Addr | Opcode::OrInt2Addr | Opcode::XorInt2Addr | Opcode::ShlInt2Addr
+            | Opcode::ShrInt2Addr | Opcode::UshrInt2Addr | Opcode::AddLong2Addr | Opcode::SubLong2Addr
+            | Opcode::MulLong2Addr | Opcode::DivLong2Addr | Opcode::RemLong2Addr | Opcode::AndLong2Addr
+            | Opcode::OrLong2Addr | Opcode::XorLong2Addr | Opcode::ShlLong2Addr | Opcode::ShrLong2Addr
+            | Opcode::UshrLong2Addr | Opcode::AddFloat2Addr | Opcode::SubFloat2Addr | Opcode::MulFloat2Addr
+            | Opcode::DivFloat2Addr | Opcode::RemFloat2Addr | Opcode::AddDouble2Addr | Opcode::SubDouble2Addr
+            | Opcode::MulDouble2Addr | Opcode::DivDouble2Addr | Opcode::RemDouble2Addr => Format::F12x,
+            Opcode::MoveFrom16 | Opcode::MoveWideFrom16 | Opcode::MoveObjectFrom16 => Format::F22x,
+            Opcode::Move16 | Opcode::MoveWide16 | Opcode::MoveObject16 => Format::F32x,
+            Opcode::MoveResult | Opcode::MoveResultWide | Opcode::MoveResultObject | Opcode::MoveException
+            | Opcode::Return | Opcode::ReturnWide | Opcode::ReturnObject | Opcode::MonitorEnter
+            | Opcode::MonitorExit | Opcode::Throw => Format::F11x,
+            Opcode::Const4 => Format::F11n,
+            Opcode::Const16 | Opcode::ConstWide16 => Format::F21s,
+            Opcode::Const | Opcode::ConstWide32 => Format::F31i,
+            Opcode::ConstHigh16 | Opcode::ConstWideHigh16 => Format::F21h,
+            Opcode::ConstWide => Format::F51l,
+            Opcode::ConstString | Opcode::ConstClass | Opcode::CheckCast | Opcode::NewInstance | Opcode::Sget
+            | Opcode::SgetWide | Opcode::SgetObject | Opcode::SgetBoolean | Opcode::SgetByte | Opcode::SgetChar
+            | Opcode::SgetShort | Opcode::Sput | Opcode::SputWide | Opcode::SputObject | Opcode::SputBoolean
+            | Opcode::SputByte | Opcode::SputChar | Opcode::SputShort | Opcode::ConstMethodHandle
+            | Opcode::ConstMethodType => Format::F21c,
+            Opcode::ConstStringJumbo => Format::F31c,
+            Opcode::InstanceOf | Opcode::NewArray | Opcode::Iget | Opcode::IgetWide | Opcode::IgetObject
+            | Opcode::IgetBoolean | Opcode::IgetByte | Opcode::IgetChar | Opcode::IgetShort | Opcode::Iput
+            | Opcode::IputWide | Opcode::IputObject | Opcode::IputBoolean | Opcode::IputByte | Opcode::IputChar
+            | Opcode::IputShort => Format::F22c,
+            Opcode::FilledNewArray | Opcode::InvokeVirtual | Opcode::InvokeSuper | Opcode::InvokeDirect
+            | Opcode::InvokeStatic | Opcode::InvokeInterface | Opcode::InvokeCustom => Format::F35c,
+            Opcode::FilledNewArrayRange | Opcode::InvokeVirtualRange | Opcode::InvokeSuperRange
+            | Opcode::InvokeDirectRange | Opcode::InvokeStaticRange | Opcode::InvokeInterfaceRange
+            | Opcode::InvokeCustomRange => Format::F3rc,
+            Opcode::FillArrayData | Opcode::PackedSwitch | Opcode::SparseSwitch => Format::F31t,
+            Opcode::Goto => Format::F10t,
+            Opcode::Goto16 => Format::F20t,
+            Opcode::Goto32 => Format::F30t,
+            Opcode::CmplFloat | Opcode::CmpgFloat | Opcode::CmplDouble | Opcode::CmpgDouble | Opcode::CmpLong
+            | Opcode::Aget | Opcode::AgetWide | Opcode::AgetObject | Opcode::AgetBoolean | Opcode::AgetByte
+            | Opcode::AgetChar | Opcode::AgetShort | Opcode::Aput | Opcode::AputWide | Opcode::AputObject
+            | Opcode::AputBoolean | Opcode::AputByte | Opcode::AputChar | Opcode::AputShort | Opcode::AddInt
+            | Opcode::SubInt | Opcode::MulInt | Opcode::DivInt | Opcode::RemInt | Opcode::AndInt | Opcode::OrInt
+            | Opcode::XorInt | Opcode::ShlInt | Opcode::ShrInt | Opcode::UshrInt | Opcode::AddLong
+            | Opcode::SubLong | Opcode::MulLong | Opcode::DivLong | Opcode::RemLong | Opcode::AndLong
+            | Opcode::OrLong | Opcode::XorLong | Opcode::ShlLong | Opcode::ShrLong | Opcode::UshrLong
+            | Opcode::AddFloat | Opcode::SubFloat | Opcode::MulFloat | Opcode::DivFloat | Opcode::RemFloat
+            | Opcode::AddDouble | Opcode::SubDouble | Opcode::MulDouble | Opcode::DivDouble | Opcode::RemDouble => Format::F23x,
+            Opcode::IfEq | Opcode::IfNe | Opcode::IfLt | Opcode::IfGe | Opcode::IfGt | Opcode::IfLe => Format::F22t,
+            Opcode::IfEqz | Opcode::IfNez | Opcode::IfLtz | Opcode::IfGez | Opcode::IfGtz | Opcode::IfLez => Format::F21t,
+            Opcode::AddIntLit16 | Opcode::RsubInt | Opcode::MulIntLit16 | Opcode::DivIntLit16
+            | Opcode::RemIntLit16 | Opcode::AndIntLit16 | Opcode::OrIntLit16 | Opcode::XorIntLit16 => Format::F22s,
+            Opcode::AddIntLit8 | Opcode::RsubIntLit8 | Opcode::MulIntLit8 | Opcode::DivIntLit8
+            | Opcode::RemIntLit8 | Opcode::AndIntLit8 | Opcode::OrIntLit8 | Opcode::XorIntLit8
+            | Opcode::ShlIntLit8 | Opcode::ShrIntLit8 | Opcode::UshrIntLit8 => Format::F22b,
+            Opcode::InvokePolymorphic => Format::F45cc,
+            Opcode::InvokePolymorphicRange => Format::F4rcc,
+        }
+    }
+
+    /// Which register operands name the low register of a wide (long or double) pair
+    ///
+    /// Registers in lists and ranges are all named explicitly so are never wide.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///  use smali::instructions::Opcode;
+    ///
+    ///  assert_eq!(Opcode::ShlLong.wide_registers(), [true, true, false]);
+    ///  assert_eq!(Opcode::LongToInt.wide_registers(), [false, true]);
+    /// ```
+    pub fn wide_registers(&self) -> Vec<bool>
+    {
+        let m = self.mnemonic();
+        let wide = |t: &str| t == "long" || t == "double";
+        let (base, suffix) = m.split_once('/').unwrap_or((m, ""));
+        if let Some((from, to)) = base.split_once("-to-") { return vec![wide(to), wide(from)]; }
+        let (name, t) = base.split_once('-').unwrap_or((base, ""));
+        let count = self.operand_kinds().iter().filter(|k| **k == OperandKind::Register).count();
+        match name {
+            _ if base.ends_with("-wide") => {
+                // Only the value of array and field accesses is wide
+                let mut v = vec![false; count];
+                if let Some(w) = v.first_mut() { *w = true; }
+                if name == "move" { v.fill(true); }
+                v
+            }
+            "cmp" | "cmpl" | "cmpg" => vec![false, wide(t), wide(t)],
+            "shl" | "shr" | "ushr" if suffix == "2addr" => vec![wide(t), false],
+            "shl" | "shr" | "ushr" if suffix.is_empty() => vec![wide(t), wide(t), false],
+            "neg" | "not" | "add" | "sub" | "mul" | "div" | "rem" | "and" | "or" | "xor" if wide(t) => vec![true; count],
+            _ => vec![false; count]
+        }
+    }
+
+    /// The kind of constant pool item referenced by this opcode, if any
+    pub fn reference_kind(&self) -> Option<OperandKind>
+    {
+        match self {
+            Opcode::ConstString | Opcode::ConstStringJumbo => Some(OperandKind::String),
+            Opcode::ConstClass | Opcode::CheckCast | Opcode::InstanceOf | Opcode::NewInstance | Opcode::NewArray
+            | Opcode::FilledNewArray | Opcode::FilledNewArrayRange => Some(OperandKind::Type),
+            Opcode::Iget | Opcode::IgetWide | Opcode::IgetObject | Opcode::IgetBoolean | Opcode::IgetByte
+            | Opcode::IgetChar | Opcode::IgetShort | Opcode::Iput | Opcode::IputWide | Opcode::IputObject
+            | Opcode::IputBoolean | Opcode::IputByte | Opcode::IputChar | Opcode::IputShort | Opcode::Sget
+            | Opcode::SgetWide | Opcode::SgetObject | Opcode::SgetBoolean | Opcode::SgetByte | Opcode::SgetChar
+            | Opcode::SgetShort | Opcode::Sput | Opcode::SputWide | Opcode::SputObject | Opcode::SputBoolean
+            | Opcode::SputByte | Opcode::SputChar | Opcode::SputShort => Some(OperandKind::Field),
+            Opcode::InvokeVirtual | Opcode::InvokeSuper | Opcode::InvokeDirect | Opcode::InvokeStatic
+            | Opcode::InvokeInterface | Opcode::InvokeVirtualRange | Opcode::InvokeSuperRange
+            | Opcode::InvokeDirectRange | Opcode::InvokeStaticRange | Opcode::InvokeInterfaceRange
+            | Opcode::InvokePolymorphic | Opcode::InvokePolymorphicRange => Some(OperandKind::Method),
+            Opcode::InvokeCustom | Opcode::InvokeCustomRange => Some(OperandKind::CallSite),
+            Opcode::ConstMethodHandle => Some(OperandKind::MethodHandle),
+            Opcode::ConstMethodType => Some(OperandKind::Proto),
+            _ => None
+        }
+    }
+
+    /// The operands this opcode takes, in smali order
+    pub fn operand_kinds(&self) -> Vec<OperandKind>
+    {
+        let reference = self.reference_kind();
+        match self.format() {
+            Format::F10x => vec![],
+            Format::F11x => vec![OperandKind::Register],
+            Format::F12x | Format::F22x | Format::F32x => vec![OperandKind::Register, OperandKind::Register],
+            Format::F11n | Format::F21s | Format::F21h | Format::F31i | Format::F51l => vec![OperandKind::Register, OperandKind::Literal],
+            Format::F10t | Format::F20t | Format::F30t => vec![OperandKind::Label],
+            Format::F21t | Format::F31t => vec![OperandKind::Register, OperandKind::Label],
+            Format::F23x => vec![OperandKind::Register, OperandKind::Register, OperandKind::Register],
+            Format::F22b | Format::F22s => vec![OperandKind::Register, OperandKind::Register, OperandKind::Literal],
+            Format::F22t => vec![OperandKind::Register, OperandKind::Register, OperandKind::Label],
+            Format::F21c | Format::F31c => vec![OperandKind::Register, reference.unwrap()],
+            Format::F22c => vec![OperandKind::Register, OperandKind::Register, reference.unwrap()],
+            Format::F35c => vec![OperandKind::RegisterList, reference.unwrap()],
+            Format::F3rc => vec![OperandKind::RegisterRange, reference.unwrap()],
+            Format::F45cc => vec![OperandKind::RegisterList, OperandKind::Method, OperandKind::Proto],
+            Format::F4rcc => vec![OperandKind::RegisterRange, OperandKind::Method, OperandKind::Proto]
+        }
+    }
+}
+
+/// A single Dalvik instruction, one variant per opcode
+///
+/// Labels are stored without the leading `:` and strings are stored unescaped.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum DexInstruction {
+    // Moves and returns
+    Nop,
+    Move { dest: Register, src: Register },
+    MoveFrom16 { dest: Register, src: Register },
+    Move16 { dest: Register, src: Register },
+    MoveWide { dest: Register, src: Register },
+    MoveWideFrom16 { dest: Register, src: Register },
+    MoveWide16 { dest: Register, src: Register },
+    MoveObject { dest: Register, src: Register },
+    MoveObjectFrom16 { dest: Register, src: Register },
+    MoveObject16 { dest: Register, src: Register },
+    MoveResult { dest: Register },
+    MoveResultWide { dest: Register },
+    MoveResultObject { dest: Register },
+    MoveException { dest: Register },
+    ReturnVoid,
+    Return { src: Register },
+    ReturnWide { src: Register },
+    ReturnObject { src: Register },
+
+    // Constants
+    Const4 { dest: Register, value: i8 },
+    Const16 { dest: Register, value: i16 },
+    Const { dest: Register, value: i32 },
+    ConstHigh16 { dest: Register, value: i32 },
+    ConstWide16 { dest: Register, value: i16 },
+    ConstWide32 { dest: Register, value: i32 },
+    ConstWide { dest: Register, value: i64 },
+    ConstWideHigh16 { dest: Register, value: i64 },
+    ConstString { dest: Register, value: String },
+    ConstStringJumbo { dest: Register, value: String },
+    ConstClass { dest: Register, class: TypeSignature },
+
+    // Monitors, type checks and object creation
+    MonitorEnter { src: Register },
+    MonitorExit { src: Register },
+    CheckCast { src: Register, class: TypeSignature },
+    InstanceOf { dest: Register, src: Register, class: TypeSignature },
+    ArrayLength { dest: Register, array: Register },
+    NewInstance { dest: Register, class: TypeSignature },
+    NewArray { dest: Register, size: Register, class: TypeSignature },
+    FilledNewArray { registers: Vec<Register>, class: TypeSignature },
+    FilledNewArrayRange { range: RegisterRange, class: TypeSignature },
+
+    // Payload instructions, throw and branches
+    FillArrayData { array: Register, payload: String },
+    Throw { src: Register },
+    Goto { target: String },
+    Goto16 { target: String },
+    Goto32 { target: String },
+    PackedSwitch { src: Register, payload: String },
+    SparseSwitch { src: Register, payload: String },
+
+    // Comparisons
+    CmplFloat { dest: Register, src1: Register, src2: Register },
+    CmpgFloat { dest: Register, src1: Register, src2: Register },
+    CmplDouble { dest: Register, src1: Register, src2: Register },
+    CmpgDouble { dest: Register, src1: Register, src2: Register },
+    CmpLong { dest: Register, src1: Register, src2: Register },
+
+    // Conditional branches
+    IfEq { src1: Register, src2: Register, target: String },
+    IfNe { src1: Register, src2: Register, target: String },
+    IfLt { src1: Register, src2: Register, target: String },
+    IfGe { src1: Register, src2: Register, target: String },
+    IfGt { src1: Register, src2: Register, target: String },
+    IfLe { src1: Register, src2: Register, target: String },
+    IfEqz { src: Register, target: String },
+    IfNez { src: Register, target: String },
+    IfLtz { src: Register, target: String },
+    IfGez { src: Register, target: String },
+    IfGtz { src: Register, target: String },
+    IfLez { src: Register, target: String },
+
+    // Array access
+    Aget { dest: Register, array: Register, index: Register },
+    AgetWide { dest: Register, array: Register, index: Register },
+    AgetObject { dest: Register, array: Register, index: Register },
+    AgetBoolean { dest: Register, array: Register, index: Register },
+    AgetByte { dest: Register, array: Register, index: Register },
+    AgetChar { dest: Register, array: Register, index: Register },
+    AgetShort { dest: Register, array: Register, index: Register },
+    Aput { src: Register, array: Register, index: Register },
+    AputWide { src: Register, array: Register, index: Register },
+    AputObject { src: Register, array: Register, index: Register },
+    AputBoolean { src: Register, array: Register, index: Register },
+    AputByte { src: Register, array: Register, index: Register },
+    AputChar { src: Register, array: Register, index: Register },
+    AputShort { src: Register, array: Register, index: Register },
+
+    // Instance field access
+    Iget { dest: Register, object: Register, field: FieldRef },
+    IgetWide { dest: Register, object: Register, field: FieldRef },
+    IgetObject { dest: Register, object: Register, field: FieldRef },
+    IgetBoolean { dest: Register, object: Register, field: FieldRef },
+    IgetByte { dest: Register, object: Register, field: FieldRef },
+    IgetChar { dest: Register, object: Register, field: FieldRef },
+    IgetShort { dest: Register, object: Register, field: FieldRef },
+    Iput { src: Register, object: Register, field: FieldRef },
+    IputWide { src: Register, object: Register, field: FieldRef },
+    IputObject { src: Register, object: Register, field: FieldRef },
+    IputBoolean { src: Register, object: Register, field: FieldRef },
+    IputByte { src: Register, object: Register, field: FieldRef },
+    IputChar { src: Register, object: Register, field: FieldRef },
+    IputShort { src: Register, object: Register, field: FieldRef },
+
+    // Static field access
+    Sget { dest: Register, field: FieldRef },
+    SgetWide { dest: Register, field: FieldRef },
+    SgetObject { dest: Register, field: FieldRef },
+    SgetBoolean { dest: Register, field: FieldRef },
+    SgetByte { dest: Register, field: FieldRef },
+    SgetChar { dest: Register, field: FieldRef },
+    SgetShort { dest: Register, field: FieldRef },
+    Sput { src: Register, field: FieldRef },
+    SputWide { src: Register, field: FieldRef },
+    SputObject { src: Register, field: FieldRef },
+    SputBoolean { src: Register, field: FieldRef },
+    SputByte { src: Register, field: FieldRef },
+    SputChar { src: Register, field: FieldRef },
+    SputShort { src: Register, field: FieldRef },
+
+    // Method invocation
+    InvokeVirtual { registers: Vec<Register>, method: MethodRef },
+    InvokeSuper { registers: Vec<Register>, method: MethodRef },
+    InvokeDirect { registers: Vec<Register>, method: MethodRef },
+    InvokeStatic { registers: Vec<Register>, method: MethodRef },
+    InvokeInterface { registers: Vec<Register>, method: MethodRef },
+    InvokeVirtualRange { range: RegisterRange, method: MethodRef },
+    InvokeSuperRange { range: RegisterRange, method: MethodRef },
+    InvokeDirectRange { range: RegisterRange, method: MethodRef },
+    InvokeStaticRange { range: RegisterRange, method: MethodRef },
+    InvokeInterfaceRange { range: RegisterRange, method: MethodRef },
+
+    // Unary operations and conversions
+    NegInt { dest: Register, src: Register },
+    NotInt { dest: Register, src: Register },
+    NegLong { dest: Register, src: Register },
+    NotLong { dest: Register, src: Register },
+    NegFloat { dest: Register, src: Register },
+    NegDouble { dest: Register, src: Register },
+    IntToLong { dest: Register, src: Register },
+    IntToFloat { dest: Register, src: Register },
+    IntToDouble { dest: Register, src: Register },
+    LongToInt { dest: Register, src: Register },
+    LongToFloat { dest: Register, src: Register },
+    LongToDouble { dest: Register, src: Register },
+    FloatToInt { dest: Register, src: Register },
+    FloatToLong { dest: Register, src: Register },
+    FloatToDouble { dest: Register, src: Register },
+    DoubleToInt { dest: Register, src: Register },
+    DoubleToLong { dest: Register, src: Register },
+    DoubleToFloat { dest: Register, src: Register },
+    IntToByte { dest: Register, src: Register },
+    IntToChar { dest: Register, src: Register },
+    IntToShort { dest: Register, src: Register },
+
+    // Binary operations
+    AddInt { dest: Register, src1: Register, src2: Register },
+    SubInt { dest: Register, src1: Register, src2: Register },
+    MulInt { dest: Register, src1: Register, src2: Register },
+    DivInt { dest: Register, src1: Register, src2: Register },
+    RemInt { dest: Register, src1: Register, src2: Register },
+    AndInt { dest: Register, src1: Register, src2: Register },
+    OrInt { dest: Register, src1: Register, src2: Register },
+    XorInt { dest: Register, src1: Register, src2: Register },
+    ShlInt { dest: Register, src1: Register, src2: Register },
+    ShrInt { dest: Register, src1: Register, src2: Register },
+    UshrInt { dest: Register, src1: Register, src2: Register },
+    AddLong { dest: Register, src1: Register, src2: Register },
+    SubLong { dest: Register, src1: Register, src2: Register },
+    MulLong { dest: Register, src1: Register, src2: Register },
+    DivLong { dest: Register, src1: Register, src2: Register },
+    RemLong { dest: Register, src1: Register, src2: Register },
+    AndLong { dest: Register, src1: Register, src2: Register },
+    OrLong { dest: Register, src1: Register, src2: Register },
+    XorLong { dest: Register, src1: Register, src2: Register },
+    ShlLong { dest: Register, src1: Register, src2: Register },
+    ShrLong { dest: Register, src1: Register, src2: Register },
+    UshrLong { dest: Register, src1: Register, src2: Register },
+    AddFloat { dest: Register, src1: Register, src2: Register },
+    SubFloat { dest: Register, src1: Register, src2: Register },
+    MulFloat { dest: Register, src1: Register, src2: Register },
+    DivFloat { dest: Register, src1: Register, src2: Register },
+    RemFloat { dest: Register, src1: Register, src2: Register },
+    AddDouble { dest: Register, src1: Register, src2: Register },
+    SubDouble { dest: Register, src1: Register, src2: Register },
+    MulDouble { dest: Register, src1: Register, src2: Register },
+    DivDouble { dest: Register, src1: Register, src2: Register },
+    RemDouble { dest: Register, src1: Register, src2: Register },
+
+    // Binary operations with the result stored in the first source register
+    AddInt2Addr { dest: Register, src: Register },
+    SubInt2Addr { dest: Register, src: Register },
+    MulInt2Addr { dest: Register, src: Register },
+    DivInt2Addr { dest: Register, src: Register },
+    RemInt2Addr { dest: Register, src: Register },
+    AndInt2Addr { dest: Register, src: Register },
+    OrInt2Addr { dest: Register, src: Register },
+    XorInt2Addr { dest: Register, src: Register },
+    ShlInt2Addr { dest: Register, src: Register },
+    ShrInt2Addr { dest: Register, src: Register },
+    UshrInt2Addr { dest: Register, src: Register },
+    AddLong2Addr { dest: Register, src: Register },
+    SubLong2Addr { dest: Register, src: Register },
+    MulLong2Addr { dest: Register, src: Register },
+    DivLong2Addr { dest: Register, src: Register },
+    RemLong2Addr { dest: Register, src: Register },
+    AndLong2Addr { dest: Register, src: Register },
+    OrLong2Addr { dest: Register, src: Register },
+    XorLong2Addr { dest: Register, src: Register },
+    ShlLong2Addr { dest: Register, src: Register },
+    ShrLong2Addr { dest: Register, src: Register },
+    UshrLong2Addr { dest: Register, src: Register },
+    AddFloat2Addr { dest: Register, src: Register },
+    SubFloat2Addr { dest: Register, src: Register },
+    MulFloat2Addr { dest: Register, src: Register },
+    DivFloat2Addr { dest: Register, src: Register },
+    RemFloat2Addr { dest: Register, src: Register },
+    AddDouble2Addr { dest: Register, src: Register },
+    SubDouble2Addr { dest: Register, src: Register },
+    MulDouble2Addr { dest: Register, src: Register },
+    DivDouble2Addr { dest: Register, src: Register },
+    RemDouble2Addr { dest: Register, src: Register },
+
+    // Binary operations with a 16 bit literal
+    AddIntLit16 { dest: Register, src: Register, literal: i16 },
+    RsubInt { dest: Register, src: Register, literal: i16 },
+    MulIntLit16 { dest: Register, src: Register, literal: i16 },
+    DivIntLit16 { dest: Register, src: Register, literal: i16 },
+    RemIntLit16 { dest: Register, src: Register, literal: i16 },
+    AndIntLit16 { dest: Register, src: Register, literal: i16 },
+    OrIntLit16 { dest: Register, src: Register, literal: i16 },
+    XorIntLit16 { dest: Register, src: Register, literal: i16 },
+
+    // Binary operations with an 8 bit literal
+    AddIntLit8 { dest: Register, src: Register, literal: i8 },
+    RsubIntLit8 { dest: Register, src: Register, literal: i8 },
+    MulIntLit8 { dest: Register, src: Register, literal: i8 },
+    DivIntLit8 { dest: Register, src: Register, literal: i8 },
+    RemIntLit8 { dest: Register, src: Register, literal: i8 },
+    AndIntLit8 { dest: Register, src: Register, literal: i8 },
+    OrIntLit8 { dest: Register, src: Register, literal: i8 },
+    XorIntLit8 { dest: Register, src: Register, literal: i8 },
+    ShlIntLit8 { dest: Register, src: Register, literal: i8 },
+    ShrIntLit8 { dest: Register, src: Register, literal: i8 },
+    UshrIntLit8 { dest: Register, src: Register, literal: i8 },
+
+    // Polymorphic and custom invocation, method handles and method types
+    InvokePolymorphic { registers: Vec<Register>, method: MethodRef, proto: MethodSignature },
+    InvokePolymorphicRange { range: RegisterRange, method: MethodRef, proto: MethodSignature },
+    InvokeCustom { registers: Vec<Register>, call_site: String },
+    InvokeCustomRange { range: RegisterRange, call_site: String },
+    ConstMethodHandle { dest: Register, handle: MethodHandle },
+    ConstMethodType { dest: Register, proto: MethodSignature },
+}
+
+impl DexInstruction {
+    /// The opcode of this instruction
+    pub fn opcode(&self) -> Opcode
+    {
+        match self {
+            DexInstruction::Nop => Opcode::Nop,
+            DexInstruction::Move { .. } => Opcode::Move,
+            DexInstruction::MoveFrom16 { .. } => Opcode::MoveFrom16,
+            DexInstruction::Move16 { .. } => Opcode::Move16,
+            DexInstruction::MoveWide { .. } => Opcode::MoveWide,
+            DexInstruction::MoveWideFrom16 { .. } => Opcode::MoveWideFrom16,
+            DexInstruction::MoveWide16 { .. } => Opcode::MoveWide16,
+            DexInstruction::MoveObject { .. } => Opcode::MoveObject,
+            DexInstruction::MoveObjectFrom16 { .. } => Opcode::MoveObjectFrom16,
+            DexInstruction::MoveObject16 { .. } => Opcode::MoveObject16,
+            DexInstruction::MoveResult { .. } => Opcode::MoveResult,
+            DexInstruction::MoveResultWide { .. } => Opcode::MoveResultWide,
+            DexInstruction::MoveResultObject { .. } => Opcode::MoveResultObject,
+            DexInstruction::MoveException { .. } => Opcode::MoveException,
+            DexInstruction::ReturnVoid => Opcode::ReturnVoid,
+            DexInstruction::Return { .. } => Opcode::Return,
+            DexInstruction::ReturnWide { .. } => Opcode::ReturnWide,
+            DexInstruction::ReturnObject { .. } => Opcode::ReturnObject,
+            DexInstruction::Const4 { .. } => Opcode::Const4,
+            DexInstruction::Const16 { .. } => Opcode::Const16,
+            DexInstruction::Const { .. } => Opcode::Const,
+            DexInstruction::ConstHigh16 { .. } => Opcode::ConstHigh16,
+            DexInstruction::ConstWide16 { .. } => Opcode::ConstWide16,
+            DexInstruction::ConstWide32 { .. } => Opcode::ConstWide32,
+            DexInstruction::ConstWide { .. } => Opcode::ConstWide,
+            DexInstruction::ConstWideHigh16 { .. } => Opcode::ConstWideHigh16,
+            DexInstruction::ConstString { .. } => Opcode::ConstString,
+            DexInstruction::ConstStringJumbo { .. } => Opcode::ConstStringJumbo,
+            DexInstruction::ConstClass { .. } => Opcode::ConstClass,
+            DexInstruction::MonitorEnter { .. } => Opcode::MonitorEnter,
+            DexInstruction::MonitorExit { .. } => Opcode::MonitorExit,
+            DexInstruction::CheckCast { .. } => Opcode::CheckCast,
+            DexInstruction::InstanceOf { .. } => Opcode::InstanceOf,
+            DexInstruction::ArrayLength { .. } => Opcode::ArrayLength,
+            DexInstruction::NewInstance { .. } => Opcode::NewInstance,
+            DexInstruction::NewArray { .. } => Opcode::NewArray,
+            DexInstruction::FilledNewArray { .. } => Opcode::FilledNewArray,
+            DexInstruction::FilledNewArrayRange { .. } => Opcode::FilledNewArrayRange,
+            DexInstruction::FillArrayData { .. } => Opcode::FillArrayData,
+            DexInstruction::Throw { .. } => Opcode::Throw,
+            DexInstruction::Goto { .. } => Opcode::Goto,
+            DexInstruction::Goto16 { .. } => Opcode::Goto16,
+            DexInstruction::Goto32 { .. } => Opcode::Goto32,
+            DexInstruction::PackedSwitch { .. } => Opcode::PackedSwitch,
+            DexInstruction::SparseSwitch { .. } => Opcode::SparseSwitch,
+            DexInstruction::CmplFloat { .. } => Opcode::CmplFloat,
+            DexInstruction::CmpgFloat { .. } => Opcode::CmpgFloat,
+            DexInstruction::CmplDouble { .. } => Opcode::CmplDouble,
+            DexInstruction::CmpgDouble { .. } => Opcode::CmpgDouble,
+            DexInstruction::CmpLong { .. } => Opcode::CmpLong,
+            DexInstruction::IfEq { .. } => Opcode::IfEq,
+            DexInstruction::IfNe { .. } => Opcode::IfNe,
+            DexInstruction::IfLt { .. } => Opcode::IfLt,
+            DexInstruction::IfGe { .. } => Opcode::IfGe,
+            DexInstruction::IfGt { .. } => Opcode::IfGt,
+            DexInstruction::IfLe { .. } => Opcode::IfLe,
+            DexInstruction::IfEqz { .. } => Opcode::IfEqz,
+            DexInstruction::IfNez { .. } => Opcode::IfNez,
+            DexInstruction::IfLtz { .. } => Opcode::IfLtz,
+            DexInstruction::IfGez { .. } => Opcode::IfGez,
+            DexInstruction::IfGtz { .. } => Opcode::IfGtz,
+            DexInstruction::IfLez { .. } => Opcode::IfLez,
+            DexInstruction::Aget { .. } => Opcode::Aget,
+            DexInstruction::AgetWide { .. } => Opcode::AgetWide,
+            DexInstruction::AgetObject { .. } => Opcode::AgetObject,
+            DexInstruction::AgetBoolean { .. } => Opcode::AgetBoolean,
+            DexInstruction::AgetByte { .. } => Opcode::AgetByte,
+            DexInstruction::AgetChar { .. } => Opcode::AgetChar,
+            DexInstruction::AgetShort { .. } => Opcode::AgetShort,
+            DexInstruction::Aput { .. } => Opcode::Aput,
+            DexInstruction::AputWide { .. } => Opcode::AputWide,
+            DexInstruction::AputObject { .. } => Opcode::AputObject,
+            DexInstruction::AputBoolean { .. } => Opcode::AputBoolean,
+            DexInstruction::AputByte { .. } => Opcode::AputByte,
+            DexInstruction::AputChar { .. } => Opcode::AputChar,
+            DexInstruction::AputShort { .. } => Opcode::AputShort,
+            DexInstruction::Iget { .. } => Opcode::Iget,
+            DexInstruction::IgetWide { .. } => Opcode::IgetWide,
+            DexInstruction::IgetObject { .. } => Opcode::IgetObject,
+            DexInstruction::IgetBoolean { .. } => Opcode::IgetBoolean,
+            DexInstruction::IgetByte { .. } => Opcode::IgetByte,
+            DexInstruction::IgetChar { .. } => Opcode::IgetChar,
+            DexInstruction::IgetShort { .. } => Opcode::IgetShort,
+            DexInstruction::Iput { .. } => Opcode::Iput,
+            DexInstruction::IputWide { .. } => Opcode::IputWide,
+            DexInstruction::IputObject { .. } => Opcode::IputObject,
+            DexInstruction::IputBoolean { .. } => Opcode::IputBoolean,
+            DexInstruction::IputByte { .. } => Opcode::IputByte,
+            DexInstruction::IputChar { .. } => Opcode::IputChar,
+            DexInstruction::IputShort { .. } => Opcode::IputShort,
+            DexInstruction::Sget { .. } => Opcode::Sget,
+            DexInstruction::SgetWide { .. } => Opcode::SgetWide,
+            DexInstruction::SgetObject { .. } => Opcode::SgetObject,
+            DexInstruction::SgetBoolean { .. } => Opcode::SgetBoolean,
+            DexInstruction::SgetByte { .. } => Opcode::SgetByte,
+            DexInstruction::SgetChar { .. } => Opcode::SgetChar,
+            DexInstruction::SgetShort { .. } => Opcode::SgetShort,
+            DexInstruction::Sput { .. } => Opcode::Sput,
+            DexInstruction::SputWide { .. } => Opcode::SputWide,
+            DexInstruction::SputObject { .. } => Opcode::SputObject,
+            DexInstruction::SputBoolean { .. } => Opcode::SputBoolean,
+            DexInstruction::SputByte { .. } => Opcode::SputByte,
+            DexInstruction::SputChar { .. } => Opcode::SputChar,
+            DexInstruction::SputShort { .. } => Opcode::SputShort,
+            DexInstruction::InvokeVirtual { .. } => Opcode::InvokeVirtual,
+            DexInstruction::InvokeSuper { .. } => Opcode::InvokeSuper,
+            DexInstruction::InvokeDirect { .. } => Opcode::InvokeDirect,
+            DexInstruction::InvokeStatic { .. } => Opcode::InvokeStatic,
+            DexInstruction::InvokeInterface { .. } => Opcode::InvokeInterface,
+            DexInstruction::InvokeVirtualRange { .. } => Opcode::InvokeVirtualRange,
+            DexInstruction::InvokeSuperRange { .. } => Opcode::InvokeSuperRange,
+            DexInstruction::InvokeDirectRange { .. } => Opcode::InvokeDirectRange,
+            DexInstruction::InvokeStaticRange { .. } => Opcode::InvokeStaticRange,
+            DexInstruction::InvokeInterfaceRange { .. } => Opcode::InvokeInterfaceRange,
+            DexInstruction::NegInt { .. } => Opcode::NegInt,
+            DexInstruction::NotInt { .. } => Opcode::NotInt,
+            DexInstruction::NegLong { .. } => Opcode::NegLong,
+            DexInstruction::NotLong { .. } => Opcode::NotLong,
+            DexInstruction::NegFloat { .. } => Opcode::NegFloat,
+            DexInstruction::NegDouble { .. } => Opcode::NegDouble,
+            DexInstruction::IntToLong { .. } => Opcode::IntToLong,
+            DexInstruction::IntToFloat { .. } => Opcode::IntToFloat,
+            DexInstruction::IntToDouble { .. } => Opcode::IntToDouble,
+            DexInstruction::LongToInt { .. } => Opcode::LongToInt,
+            DexInstruction::LongToFloat { .. } => Opcode::LongToFloat,
+            DexInstruction::LongToDouble { .. } => Opcode::LongToDouble,
+            DexInstruction::FloatToInt { .. } => Opcode::FloatToInt,
+            DexInstruction::FloatToLong { .. } => Opcode::FloatToLong,
+            DexInstruction::FloatToDouble { .. } => Opcode::FloatToDouble,
+            DexInstruction::DoubleToInt { .. } => Opcode::DoubleToInt,
+            DexInstruction::DoubleToLong { .. } => Opcode::DoubleToLong,
+            DexInstruction::DoubleToFloat { .. } => Opcode::DoubleToFloat,
+            DexInstruction::IntToByte { .. } => Opcode::IntToByte,
+            DexInstruction::IntToChar { .. } => Opcode::IntToChar,
+            DexInstruction::IntToShort { .. } => Opcode::IntToShort,
+            DexInstruction::AddInt { .. } => Opcode::AddInt,
+            DexInstruction::SubInt { .. } => Opcode::SubInt,
+            DexInstruction::MulInt { .. } => Opcode::MulInt,
+            DexInstruction::DivInt { .. } => Opcode::DivInt,
+            DexInstruction::RemInt { .. } => Opcode::RemInt,
+            DexInstruction::AndInt { .. } => Opcode::AndInt,
+            DexInstruction::OrInt { .. } => Opcode::OrInt,
+            DexInstruction::XorInt { .. } => Opcode::XorInt,
+            DexInstruction::ShlInt { .. } => Opcode::ShlInt,
+            DexInstruction::ShrInt { .. } => Opcode::ShrInt,
+            DexInstruction::UshrInt { .. } => Opcode::UshrInt,
+            DexInstruction::AddLong { .. } => Opcode::AddLong,
+            DexInstruction::SubLong { .. } => Opcode::SubLong,
+            DexInstruction::MulLong { .. } => Opcode::MulLong,
+            DexInstruction::DivLong { .. } => Opcode::DivLong,
+            DexInstruction::RemLong { .. } => Opcode::RemLong,
+            DexInstruction::AndLong { .. } => Opcode::AndLong,
+            DexInstruction::OrLong { .. } => Opcode::OrLong,
+            DexInstruction::XorLong { .. } => Opcode::XorLong,
+            DexInstruction::ShlLong { .. } => Opcode::ShlLong,
+            DexInstruction::ShrLong { .. } => Opcode::ShrLong,
+            DexInstruction::UshrLong { .. } => Opcode::UshrLong,
+            DexInstruction::AddFloat { .. } => Opcode::AddFloat,
+            DexInstruction::SubFloat { .. } => Opcode::SubFloat,
+            DexInstruction::MulFloat { .. } => Opcode::MulFloat,
+            DexInstruction::DivFloat { .. } => Opcode::DivFloat,
+            DexInstruction::RemFloat { .. } => Opcode::RemFloat,
+            DexInstruction::AddDouble { .. } => Opcode::AddDouble,
+            DexInstruction::SubDouble { .. } => Opcode::SubDouble,
+            DexInstruction::MulDouble { .. } => Opcode::MulDouble,
+            DexInstruction::DivDouble { .. } => Opcode::DivDouble,
+            DexInstruction::RemDouble { .. } => Opcode::RemDouble,
+            DexInstruction::AddInt2Addr { .. } => Opcode::AddInt2Addr,
+            DexInstruction::SubInt2Addr { .. } => Opcode::SubInt2Addr,
+            DexInstruction::MulInt2Addr { .. } => Opcode::MulInt2Addr,
+            DexInstruction::DivInt2Addr { .. } => Opcode::DivInt2Addr,
+            DexInstruction::RemInt2Addr { .. } => Opcode::RemInt2Addr,
+            DexInstruction::AndInt2Addr { .. } => Opcode::AndInt2Addr,
+            DexInstruction::OrInt2Addr { .. } => Opcode::OrInt2Addr,
+            DexInstruction::XorInt2Addr { .. } => Opcode::XorInt2Addr,
+            DexInstruction::ShlInt2Addr { .. } => Opcode::ShlInt2Addr,
+            DexInstruction::ShrInt2Addr { .. } => Opcode::ShrInt2Addr,
+            DexInstruction::UshrInt2Addr { .. } => Opcode::UshrInt2Addr,
+            DexInstruction::AddLong2Addr { .. } => Opcode::AddLong2Addr,
+            DexInstruction::SubLong2Addr { .. } => Opcode::SubLong2Addr,
+            DexInstruction::MulLong2Addr { .. } => Opcode::MulLong2Addr,
+            DexInstruction::DivLong2Addr { .. } => Opcode::DivLong2Addr,
+            DexInstruction::RemLong2Addr { .. } => Opcode::RemLong2Addr,
+            DexInstruction::AndLong2Addr { .. } => Opcode::AndLong2Addr,
+            DexInstruction::OrLong2Addr { .. } => Opcode::OrLong2Addr,
+            DexInstruction::XorLong2Addr { .. } => Opcode::XorLong2Addr,
+            DexInstruction::ShlLong2Addr { .. } => Opcode::ShlLong2Addr,
+            DexInstruction::ShrLong2Addr { .. } => Opcode::ShrLong2Addr,
+            DexInstruction::UshrLong2Addr { .. } => Opcode::UshrLong2Addr,
+            DexInstruction::AddFloat2Addr { .. } => Opcode::AddFloat2Addr,
+            DexInstruction::SubFloat2Addr { .. } => Opcode::SubFloat2Addr,
+            DexInstruction::MulFloat2Addr { .. } => Opcode::MulFloat2Addr,
+            DexInstruction::DivFloat2Addr { .. } => Opcode::DivFloat2Addr,
+            DexInstruction::RemFloat2Addr { .. } => Opcode::RemFloat2Addr,
+            DexInstruction::AddDouble2Addr { .. } => Opcode::AddDouble2Addr,
+            DexInstruction::SubDouble2Addr { .. } => Opcode::SubDouble2Addr,
+            DexInstruction::MulDouble2Addr { .. } => Opcode::MulDouble2Addr,
+            DexInstruction::DivDouble2Addr { .. } => Opcode::DivDouble2Addr,
+            DexInstruction::RemDouble2Addr { .. } => Opcode::RemDouble2Addr,
+            DexInstruction::AddIntLit16 { .. } => Opcode::AddIntLit16,
+            DexInstruction::RsubInt { .. } => Opcode::RsubInt,
+            DexInstruction::MulIntLit16 { .. } => Opcode::MulIntLit16,
+            DexInstruction::DivIntLit16 { .. } => Opcode::DivIntLit16,
+            DexInstruction::RemIntLit16 { .. } => Opcode::RemIntLit16,
+            DexInstruction::AndIntLit16 { .. } => Opcode::AndIntLit16,
+            DexInstruction::OrIntLit16 { .. } => Opcode::OrIntLit16,
+            DexInstruction::XorIntLit16 { .. } => Opcode::XorIntLit16,
+            DexInstruction::AddIntLit8 { .. } => Opcode::AddIntLit8,
+            DexInstruction::RsubIntLit8 { .. } => Opcode::RsubIntLit8,
+            DexInstruction::MulIntLit8 { .. } => Opcode::MulIntLit8,
+            DexInstruction::DivIntLit8 { .. } => Opcode::DivIntLit8,
+            DexInstruction::RemIntLit8 { .. } => Opcode::RemIntLit8,
+            DexInstruction::AndIntLit8 { .. } => Opcode::AndIntLit8,
+            DexInstruction::OrIntLit8 { .. } => Opcode::OrIntLit8,
+            DexInstruction::XorIntLit8 { .. } => Opcode::XorIntLit8,
+            DexInstruction::ShlIntLit8 { .. } => Opcode::ShlIntLit8,
+            DexInstruction::ShrIntLit8 { .. } => Opcode::ShrIntLit8,
+            DexInstruction::UshrIntLit8 { .. } => Opcode::UshrIntLit8,
+            DexInstruction::InvokePolymorphic { .. } => Opcode::InvokePolymorphic,
+            DexInstruction::InvokePolymorphicRange { .. } => Opcode::InvokePolymorphicRange,
+            DexInstruction::InvokeCustom { .. } => Opcode::InvokeCustom,
+            DexInstruction::InvokeCustomRange { .. } => Opcode::InvokeCustomRange,
+            DexInstruction::ConstMethodHandle { .. } => Opcode::ConstMethodHandle,
+            DexInstruction::ConstMethodType { .. } => Opcode::ConstMethodType,
+        }
+    }
+
+    /// The operands of this instruction in smali order
+    pub fn operands(&self) -> Vec<Operand>
+    {
+        match self {
+            DexInstruction::Nop => vec![],
+            DexInstruction::Move { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MoveFrom16 { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::Move16 { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MoveWide { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MoveWideFrom16 { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MoveWide16 { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MoveObject { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MoveObjectFrom16 { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MoveObject16 { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MoveResult { dest } => vec![Operand::Register(*dest)],
+            DexInstruction::MoveResultWide { dest } => vec![Operand::Register(*dest)],
+            DexInstruction::MoveResultObject { dest } => vec![Operand::Register(*dest)],
+            DexInstruction::MoveException { dest } => vec![Operand::Register(*dest)],
+            DexInstruction::ReturnVoid => vec![],
+            DexInstruction::Return { src } => vec![Operand::Register(*src)],
+            DexInstruction::ReturnWide { src } => vec![Operand::Register(*src)],
+            DexInstruction::ReturnObject { src } => vec![Operand::Register(*src)],
+            DexInstruction::Const4 { dest, value } => vec![Operand::Register(*dest), Operand::Literal(i64::from(*value))],
+            DexInstruction::Const16 { dest, value } => vec![Operand::Register(*dest), Operand::Literal(i64::from(*value))],
+            DexInstruction::Const { dest, value } => vec![Operand::Register(*dest), Operand::Literal(i64::from(*value))],
+            DexInstruction::ConstHigh16 { dest, value } => vec![Operand::Register(*dest), Operand::Literal(i64::from(*value))],
+            DexInstruction::ConstWide16 { dest, value } => vec![Operand::Register(*dest), Operand::Literal(i64::from(*value))],
+            DexInstruction::ConstWide32 { dest, value } => vec![Operand::Register(*dest), Operand::Literal(i64::from(*value))],
+            DexInstruction::ConstWide { dest, value } => vec![Operand::Register(*dest), Operand::Literal(*value)],
+            DexInstruction::ConstWideHigh16 { dest, value } => vec![Operand::Register(*dest), Operand::Literal(*value)],
+            DexInstruction::ConstString { dest, value } => vec![Operand::Register(*dest), Operand::String(value.clone())],
+            DexInstruction::ConstStringJumbo { dest, value } => vec![Operand::Register(*dest), Operand::String(value.clone())],
+            DexInstruction::ConstClass { dest, class } => vec![Operand::Register(*dest), Operand::Type(class.clone())],
+            DexInstruction::MonitorEnter { src } => vec![Operand::Register(*src)],
+            DexInstruction::MonitorExit { src } => vec![Operand::Register(*src)],
+            DexInstruction::CheckCast { src, class } => vec![Operand::Register(*src), Operand::Type(class.clone())],
+            DexInstruction::InstanceOf { dest, src, class } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Type(class.clone())],
+            DexInstruction::ArrayLength { dest, array } => vec![Operand::Register(*dest), Operand::Register(*array)],
+            DexInstruction::NewInstance { dest, class } => vec![Operand::Register(*dest), Operand::Type(class.clone())],
+            DexInstruction::NewArray { dest, size, class } => vec![Operand::Register(*dest), Operand::Register(*size), Operand::Type(class.clone())],
+            DexInstruction::FilledNewArray { registers, class } => vec![Operand::RegisterList(registers.clone()), Operand::Type(class.clone())],
+            DexInstruction::FilledNewArrayRange { range, class } => vec![Operand::RegisterRange(*range), Operand::Type(class.clone())],
+            DexInstruction::FillArrayData { array, payload } => vec![Operand::Register(*array), Operand::Label(payload.clone())],
+            DexInstruction::Throw { src } => vec![Operand::Register(*src)],
+            DexInstruction::Goto { target } => vec![Operand::Label(target.clone())],
+            DexInstruction::Goto16 { target } => vec![Operand::Label(target.clone())],
+            DexInstruction::Goto32 { target } => vec![Operand::Label(target.clone())],
+            DexInstruction::PackedSwitch { src, payload } => vec![Operand::Register(*src), Operand::Label(payload.clone())],
+            DexInstruction::SparseSwitch { src, payload } => vec![Operand::Register(*src), Operand::Label(payload.clone())],
+            DexInstruction::CmplFloat { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::CmpgFloat { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::CmplDouble { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::CmpgDouble { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::CmpLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::IfEq { src1, src2, target } => vec![Operand::Register(*src1), Operand::Register(*src2), Operand::Label(target.clone())],
+            DexInstruction::IfNe { src1, src2, target } => vec![Operand::Register(*src1), Operand::Register(*src2), Operand::Label(target.clone())],
+            DexInstruction::IfLt { src1, src2, target } => vec![Operand::Register(*src1), Operand::Register(*src2), Operand::Label(target.clone())],
+            DexInstruction::IfGe { src1, src2, target } => vec![Operand::Register(*src1), Operand::Register(*src2), Operand::Label(target.clone())],
+            DexInstruction::IfGt { src1, src2, target } => vec![Operand::Register(*src1), Operand::Register(*src2), Operand::Label(target.clone())],
+            DexInstruction::IfLe { src1, src2, target } => vec![Operand::Register(*src1), Operand::Register(*src2), Operand::Label(target.clone())],
+            DexInstruction::IfEqz { src, target } => vec![Operand::Register(*src), Operand::Label(target.clone())],
+            DexInstruction::IfNez { src, target } => vec![Operand::Register(*src), Operand::Label(target.clone())],
+            DexInstruction::IfLtz { src, target } => vec![Operand::Register(*src), Operand::Label(target.clone())],
+            DexInstruction::IfGez { src, target } => vec![Operand::Register(*src), Operand::Label(target.clone())],
+            DexInstruction::IfGtz { src, target } => vec![Operand::Register(*src), Operand::Label(target.clone())],
+            DexInstruction::IfLez { src, target } => vec![Operand::Register(*src), Operand::Label(target.clone())],
+            DexInstruction::Aget { dest, array, index } => vec![Operand::Register(*dest), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AgetWide { dest, array, index } => vec![Operand::Register(*dest), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AgetObject { dest, array, index } => vec![Operand::Register(*dest), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AgetBoolean { dest, array, index } => vec![Operand::Register(*dest), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AgetByte { dest, array, index } => vec![Operand::Register(*dest), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AgetChar { dest, array, index } => vec![Operand::Register(*dest), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AgetShort { dest, array, index } => vec![Operand::Register(*dest), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::Aput { src, array, index } => vec![Operand::Register(*src), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AputWide { src, array, index } => vec![Operand::Register(*src), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AputObject { src, array, index } => vec![Operand::Register(*src), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AputBoolean { src, array, index } => vec![Operand::Register(*src), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AputByte { src, array, index } => vec![Operand::Register(*src), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AputChar { src, array, index } => vec![Operand::Register(*src), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::AputShort { src, array, index } => vec![Operand::Register(*src), Operand::Register(*array), Operand::Register(*index)],
+            DexInstruction::Iget { dest, object, field } => vec![Operand::Register(*dest), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IgetWide { dest, object, field } => vec![Operand::Register(*dest), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IgetObject { dest, object, field } => vec![Operand::Register(*dest), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IgetBoolean { dest, object, field } => vec![Operand::Register(*dest), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IgetByte { dest, object, field } => vec![Operand::Register(*dest), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IgetChar { dest, object, field } => vec![Operand::Register(*dest), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IgetShort { dest, object, field } => vec![Operand::Register(*dest), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::Iput { src, object, field } => vec![Operand::Register(*src), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IputWide { src, object, field } => vec![Operand::Register(*src), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IputObject { src, object, field } => vec![Operand::Register(*src), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IputBoolean { src, object, field } => vec![Operand::Register(*src), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IputByte { src, object, field } => vec![Operand::Register(*src), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IputChar { src, object, field } => vec![Operand::Register(*src), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::IputShort { src, object, field } => vec![Operand::Register(*src), Operand::Register(*object), Operand::Field(field.clone())],
+            DexInstruction::Sget { dest, field } => vec![Operand::Register(*dest), Operand::Field(field.clone())],
+            DexInstruction::SgetWide { dest, field } => vec![Operand::Register(*dest), Operand::Field(field.clone())],
+            DexInstruction::SgetObject { dest, field } => vec![Operand::Register(*dest), Operand::Field(field.clone())],
+            DexInstruction::SgetBoolean { dest, field } => vec![Operand::Register(*dest), Operand::Field(field.clone())],
+            DexInstruction::SgetByte { dest, field } => vec![Operand::Register(*dest), Operand::Field(field.clone())],
+            DexInstruction::SgetChar { dest, field } => vec![Operand::Register(*dest), Operand::Field(field.clone())],
+            DexInstruction::SgetShort { dest, field } => vec![Operand::Register(*dest), Operand::Field(field.clone())],
+            DexInstruction::Sput { src, field } => vec![Operand::Register(*src), Operand::Field(field.clone())],
+            DexInstruction::SputWide { src, field } => vec![Operand::Register(*src), Operand::Field(field.clone())],
+            DexInstruction::SputObject { src, field } => vec![Operand::Register(*src), Operand::Field(field.clone())],
+            DexInstruction::SputBoolean { src, field } => vec![Operand::Register(*src), Operand::Field(field.clone())],
+            DexInstruction::SputByte { src, field } => vec![Operand::Register(*src), Operand::Field(field.clone())],
+            DexInstruction::SputChar { src, field } => vec![Operand::Register(*src), Operand::Field(field.clone())],
+            DexInstruction::SputShort { src, field } => vec![Operand::Register(*src), Operand::Field(field.clone())],
+            DexInstruction::InvokeVirtual { registers, method } => vec![Operand::RegisterList(registers.clone()), Operand::Method(method.clone())],
+            DexInstruction::InvokeSuper { registers, method } => vec![Operand::RegisterList(registers.clone()), Operand::Method(method.clone())],
+            DexInstruction::InvokeDirect { registers, method } => vec![Operand::RegisterList(registers.clone()), Operand::Method(method.clone())],
+            DexInstruction::InvokeStatic { registers, method } => vec![Operand::RegisterList(registers.clone()), Operand::Method(method.clone())],
+            DexInstruction::InvokeInterface { registers, method } => vec![Operand::RegisterList(registers.clone()), Operand::Method(method.clone())],
+            DexInstruction::InvokeVirtualRange { range, method } => vec![Operand::RegisterRange(*range), Operand::Method(method.clone())],
+            DexInstruction::InvokeSuperRange { range, method } => vec![Operand::RegisterRange(*range), Operand::Method(method.clone())],
+            DexInstruction::InvokeDirectRange { range, method } => vec![Operand::RegisterRange(*range), Operand::Method(method.clone())],
+            DexInstruction::InvokeStaticRange { range, method } => vec![Operand::RegisterRange(*range), Operand::Method(method.clone())],
+            DexInstruction::InvokeInterfaceRange { range, method } => vec![Operand::RegisterRange(*range), Operand::Method(method.clone())],
+            DexInstruction::NegInt { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::NotInt { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::NegLong { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::NotLong { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::NegFloat { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::NegDouble { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::IntToLong { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::IntToFloat { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::IntToDouble { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::LongToInt { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::LongToFloat { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::LongToDouble { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::FloatToInt { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::FloatToLong { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::FloatToDouble { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::DoubleToInt { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::DoubleToLong { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::DoubleToFloat { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::IntToByte { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::IntToChar { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::IntToShort { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::AddInt { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::SubInt { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::MulInt { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::DivInt { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::RemInt { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::AndInt { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::OrInt { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::XorInt { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::ShlInt { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::ShrInt { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::UshrInt { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::AddLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::SubLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::MulLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::DivLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::RemLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::AndLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::OrLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::XorLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::ShlLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::ShrLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::UshrLong { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::AddFloat { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::SubFloat { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::MulFloat { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::DivFloat { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::RemFloat { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::AddDouble { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::SubDouble { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::MulDouble { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::DivDouble { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::RemDouble { dest, src1, src2 } => vec![Operand::Register(*dest), Operand::Register(*src1), Operand::Register(*src2)],
+            DexInstruction::AddInt2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::SubInt2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MulInt2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::DivInt2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::RemInt2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::AndInt2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::OrInt2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::XorInt2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::ShlInt2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::ShrInt2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::UshrInt2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::AddLong2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::SubLong2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MulLong2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::DivLong2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::RemLong2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::AndLong2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::OrLong2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::XorLong2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::ShlLong2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::ShrLong2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::UshrLong2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::AddFloat2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::SubFloat2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MulFloat2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::DivFloat2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::RemFloat2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::AddDouble2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::SubDouble2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::MulDouble2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::DivDouble2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::RemDouble2Addr { dest, src } => vec![Operand::Register(*dest), Operand::Register(*src)],
+            DexInstruction::AddIntLit16 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::RsubInt { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::MulIntLit16 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::DivIntLit16 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::RemIntLit16 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::AndIntLit16 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::OrIntLit16 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::XorIntLit16 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::AddIntLit8 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::RsubIntLit8 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::MulIntLit8 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::DivIntLit8 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::RemIntLit8 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::AndIntLit8 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::OrIntLit8 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::XorIntLit8 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::ShlIntLit8 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::ShrIntLit8 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::UshrIntLit8 { dest, src, literal } => vec![Operand::Register(*dest), Operand::Register(*src), Operand::Literal(i64::from(*literal))],
+            DexInstruction::InvokePolymorphic { registers, method, proto } => vec![Operand::RegisterList(registers.clone()), Operand::Method(method.clone()), Operand::Proto(proto.clone())],
+            DexInstruction::InvokePolymorphicRange { range, method, proto } => vec![Operand::RegisterRange(*range), Operand::Method(method.clone()), Operand::Proto(proto.clone())],
+            DexInstruction::InvokeCustom { registers, call_site } => vec![Operand::RegisterList(registers.clone()), Operand::CallSite(call_site.clone())],
+            DexInstruction::InvokeCustomRange { range, call_site } => vec![Operand::RegisterRange(*range), Operand::CallSite(call_site.clone())],
+            DexInstruction::ConstMethodHandle { dest, handle } => vec![Operand::Register(*dest), Operand::MethodHandle(handle.clone())],
+            DexInstruction::ConstMethodType { dest, proto } => vec![Operand::Register(*dest), Operand::Proto(proto.clone())],
+        }
+    }
+
+    /// Builds an instruction from an opcode and its operands in smali order, returns None if the
+    /// operands don't match what the opcode expects or a literal is out of range
+    pub fn from_operands(opcode: Opcode, operands: Vec<Operand>) -> Option<DexInstruction>
+    {
+        let mut ops = OperandReader { operands: operands.into_iter() };
+        let instruction = match opcode {
+            Opcode::Nop => DexInstruction::Nop,
+            Opcode::Move => DexInstruction::Move { dest: ops.register()?, src: ops.register()? },
+            Opcode::MoveFrom16 => DexInstruction::MoveFrom16 { dest: ops.register()?, src: ops.register()? },
+            Opcode::Move16 => DexInstruction::Move16 { dest: ops.register()?, src: ops.register()? },
+            Opcode::MoveWide => DexInstruction::MoveWide { dest: ops.register()?, src: ops.register()? },
+            Opcode::MoveWideFrom16 => DexInstruction::MoveWideFrom16 { dest: ops.register()?, src: ops.register()? },
+            Opcode::MoveWide16 => DexInstruction::MoveWide16 { dest: ops.register()?, src: ops.register()? },
+            Opcode::MoveObject => DexInstruction::MoveObject { dest: ops.register()?, src: ops.register()? },
+            Opcode::MoveObjectFrom16 => DexInstruction::MoveObjectFrom16 { dest: ops.register()?, src: ops.register()? },
+            Opcode::MoveObject16 => DexInstruction::MoveObject16 { dest: ops.register()?, src: ops.register()? },
+            Opcode::MoveResult => DexInstruction::MoveResult { dest: ops.register()? },
+            Opcode::MoveResultWide => DexInstruction::MoveResultWide { dest: ops.register()? },
+            Opcode::MoveResultObject => DexInstruction::MoveResultObject { dest: ops.register()? },
+            Opcode::MoveException => DexInstruction::MoveException { dest: ops.register()? },
+            Opcode::ReturnVoid => DexInstruction::ReturnVoid,
+            Opcode::Return => DexInstruction::Return { src: ops.register()? },
+            Opcode::ReturnWide => DexInstruction::ReturnWide { src: ops.register()? },
+            Opcode::ReturnObject => DexInstruction::ReturnObject { src: ops.register()? },
+            Opcode::Const4 => DexInstruction::Const4 { dest: ops.register()?, value: ops.nibble()? },
+            Opcode::Const16 => DexInstruction::Const16 { dest: ops.register()?, value: ops.short()? },
+            Opcode::Const => DexInstruction::Const { dest: ops.register()?, value: ops.int()? },
+            Opcode::ConstHigh16 => DexInstruction::ConstHigh16 { dest: ops.register()?, value: ops.high16()? },
+            Opcode::ConstWide16 => DexInstruction::ConstWide16 { dest: ops.register()?, value: ops.short()? },
+            Opcode::ConstWide32 => DexInstruction::ConstWide32 { dest: ops.register()?, value: ops.int()? },
+            Opcode::ConstWide => DexInstruction::ConstWide { dest: ops.register()?, value: ops.long()? },
+            Opcode::ConstWideHigh16 => DexInstruction::ConstWideHigh16 { dest: ops.register()?, value: ops.wide_high16()? },
+            Opcode::ConstString => DexInstruction::ConstString { dest: ops.register()?, value: ops.string()? },
+            Opcode::ConstStringJumbo => DexInstruction::ConstStringJumbo { dest: ops.register()?, value: ops.string()? },
+            Opcode::ConstClass => DexInstruction::ConstClass { dest: ops.register()?, class: ops.type_signature()? },
+            Opcode::MonitorEnter => DexInstruction::MonitorEnter { src: ops.register()? },
+            Opcode::MonitorExit => DexInstruction::MonitorExit { src: ops.register()? },
+            Opcode::CheckCast => DexInstruction::CheckCast { src: ops.register()?, class: ops.type_signature()? },
+            Opcode::InstanceOf => DexInstruction::InstanceOf { dest: ops.register()?, src: ops.register()?, class: ops.type_signature()? },
+            Opcode::ArrayLength => DexInstruction::ArrayLength { dest: ops.register()?, array: ops.register()? },
+            Opcode::NewInstance => DexInstruction::NewInstance { dest: ops.register()?, class: ops.type_signature()? },
+            Opcode::NewArray => DexInstruction::NewArray { dest: ops.register()?, size: ops.register()?, class: ops.type_signature()? },
+            Opcode::FilledNewArray => DexInstruction::FilledNewArray { registers: ops.register_list()?, class: ops.type_signature()? },
+            Opcode::FilledNewArrayRange => DexInstruction::FilledNewArrayRange { range: ops.register_range()?, class: ops.type_signature()? },
+            Opcode::FillArrayData => DexInstruction::FillArrayData { array: ops.register()?, payload: ops.label()? },
+            Opcode::Throw => DexInstruction::Throw { src: ops.register()? },
+            Opcode::Goto => DexInstruction::Goto { target: ops.label()? },
+            Opcode::Goto16 => DexInstruction::Goto16 { target: ops.label()? },
+            Opcode::Goto32 => DexInstruction::Goto32 { target: ops.label()? },
+            Opcode::PackedSwitch => DexInstruction::PackedSwitch { src: ops.register()?, payload: ops.label()? },
+            Opcode::SparseSwitch => DexInstruction::SparseSwitch { src: ops.register()?, payload: ops.label()? },
+            Opcode::CmplFloat => DexInstruction::CmplFloat { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::CmpgFloat => DexInstruction::CmpgFloat { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::CmplDouble => DexInstruction::CmplDouble { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::CmpgDouble => DexInstruction::CmpgDouble { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::CmpLong => DexInstruction::CmpLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::IfEq => DexInstruction::IfEq { src1: ops.register()?, src2: ops.register()?, target: ops.label()? },
+            Opcode::IfNe => DexInstruction::IfNe { src1: ops.register()?, src2: ops.register()?, target: ops.label()? },
+            Opcode::IfLt => DexInstruction::IfLt { src1: ops.register()?, src2: ops.register()?, target: ops.label()? },
+            Opcode::IfGe => DexInstruction::IfGe { src1: ops.register()?, src2: ops.register()?, target: ops.label()? },
+            Opcode::IfGt => DexInstruction::IfGt { src1: ops.register()?, src2: ops.register()?, target: ops.label()? },
+            Opcode::IfLe => DexInstruction::IfLe { src1: ops.register()?, src2: ops.register()?, target: ops.label()? },
+            Opcode::IfEqz => DexInstruction::IfEqz { src: ops.register()?, target: ops.label()? },
+            Opcode::IfNez => DexInstruction::IfNez { src: ops.register()?, target: ops.label()? },
+            Opcode::IfLtz => DexInstruction::IfLtz { src: ops.register()?, target: ops.label()? },
+            Opcode::IfGez => DexInstruction::IfGez { src: ops.register()?, target: ops.label()? },
+            Opcode::IfGtz => DexInstruction::IfGtz { src: ops.register()?, target: ops.label()? },
+            Opcode::IfLez => DexInstruction::IfLez { src: ops.register()?, target: ops.label()? },
+            Opcode::Aget => DexInstruction::Aget { dest: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AgetWide => DexInstruction::AgetWide { dest: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AgetObject => DexInstruction::AgetObject { dest: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AgetBoolean => DexInstruction::AgetBoolean { dest: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AgetByte => DexInstruction::AgetByte { dest: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AgetChar => DexInstruction::AgetChar { dest: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AgetShort => DexInstruction::AgetShort { dest: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::Aput => DexInstruction::Aput { src: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AputWide => DexInstruction::AputWide { src: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AputObject => DexInstruction::AputObject { src: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AputBoolean => DexInstruction::AputBoolean { src: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AputByte => DexInstruction::AputByte { src: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AputChar => DexInstruction::AputChar { src: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::AputShort => DexInstruction::AputShort { src: ops.register()?, array: ops.register()?, index: ops.register()? },
+            Opcode::Iget => DexInstruction::Iget { dest: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IgetWide => DexInstruction::IgetWide { dest: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IgetObject => DexInstruction::IgetObject { dest: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IgetBoolean => DexInstruction::IgetBoolean { dest: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IgetByte => DexInstruction::IgetByte { dest: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IgetChar => DexInstruction::IgetChar { dest: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IgetShort => DexInstruction::IgetShort { dest: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::Iput => DexInstruction::Iput { src: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IputWide => DexInstruction::IputWide { src: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IputObject => DexInstruction::IputObject { src: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IputBoolean => DexInstruction::IputBoolean { src: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IputByte => DexInstruction::IputByte { src: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IputChar => DexInstruction::IputChar { src: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::IputShort => DexInstruction::IputShort { src: ops.register()?, object: ops.register()?, field: ops.field()? },
+            Opcode::Sget => DexInstruction::Sget { dest: ops.register()?, field: ops.field()? },
+            Opcode::SgetWide => DexInstruction::SgetWide { dest: ops.register()?, field: ops.field()? },
+            Opcode::SgetObject => DexInstruction::SgetObject { dest: ops.register()?, field: ops.field()? },
+            Opcode::SgetBoolean => DexInstruction::SgetBoolean { dest: ops.register()?, field: ops.field()? },
+            Opcode::SgetByte => DexInstruction::SgetByte { dest: ops.register()?, field: ops.field()? },
+            Opcode::SgetChar => DexInstruction::SgetChar { dest: ops.register()?, field: ops.field()? },
+            Opcode::SgetShort => DexInstruction::SgetShort { dest: ops.register()?, field: ops.field()? },
+            Opcode::Sput => DexInstruction::Sput { src: ops.register()?, field: ops.field()? },
+            Opcode::SputWide => DexInstruction::SputWide { src: ops.register()?, field: ops.field()? },
+            Opcode::SputObject => DexInstruction::SputObject { src: ops.register()?, field: ops.field()? },
+            Opcode::SputBoolean => DexInstruction::SputBoolean { src: ops.register()?, field: ops.field()? },
+            Opcode::SputByte => DexInstruction::SputByte { src: ops.register()?, field: ops.field()? },
+            Opcode::SputChar => DexInstruction::SputChar { src: ops.register()?, field: ops.field()? },
+            Opcode::SputShort => DexInstruction::SputShort { src: ops.register()?, field: ops.field()? },
+            Opcode::InvokeVirtual => DexInstruction::InvokeVirtual { registers: ops.register_list()?, method: ops.method()? },
+            Opcode::InvokeSuper => DexInstruction::InvokeSuper { registers: ops.register_list()?, method: ops.method()? },
+            Opcode::InvokeDirect => DexInstruction::InvokeDirect { registers: ops.register_list()?, method: ops.method()? },
+            Opcode::InvokeStatic => DexInstruction::InvokeStatic { registers: ops.register_list()?, method: ops.method()? },
+            Opcode::InvokeInterface => DexInstruction::InvokeInterface { registers: ops.register_list()?, method: ops.method()? },
+            Opcode::InvokeVirtualRange => DexInstruction::InvokeVirtualRange { range: ops.register_range()?, method: ops.method()? },
+            Opcode::InvokeSuperRange => DexInstruction::InvokeSuperRange { range: ops.register_range()?, method: ops.method()? },
+            Opcode::InvokeDirectRange => DexInstruction::InvokeDirectRange { range: ops.register_range()?, method: ops.method()? },
+            Opcode::InvokeStaticRange => DexInstruction::InvokeStaticRange { range: ops.register_range()?, method: ops.method()? },
+            Opcode::InvokeInterfaceRange => DexInstruction::InvokeInterfaceRange { range: ops.register_range()?, method: ops.method()? },
+            Opcode::NegInt => DexInstruction::NegInt { dest: ops.register()?, src: ops.register()? },
+            Opcode::NotInt => DexInstruction::NotInt { dest: ops.register()?, src: ops.register()? },
+            Opcode::NegLong => DexInstruction::NegLong { dest: ops.register()?, src: ops.register()? },
+            Opcode::NotLong => DexInstruction::NotLong { dest: ops.register()?, src: ops.register()? },
+            Opcode::NegFloat => DexInstruction::NegFloat { dest: ops.register()?, src: ops.register()? },
+            Opcode::NegDouble => DexInstruction::NegDouble { dest: ops.register()?, src: ops.register()? },
+            Opcode::IntToLong => DexInstruction::IntToLong { dest: ops.register()?, src: ops.register()? },
+            Opcode::IntToFloat => DexInstruction::IntToFloat { dest: ops.register()?, src: ops.register()? },
+            Opcode::IntToDouble => DexInstruction::IntToDouble { dest: ops.register()?, src: ops.register()? },
+            Opcode::LongToInt => DexInstruction::LongToInt { dest: ops.register()?, src: ops.register()? },
+            Opcode::LongToFloat => DexInstruction::LongToFloat { dest: ops.register()?, src: ops.register()? },
+            Opcode::LongToDouble => DexInstruction::LongToDouble { dest: ops.register()?, src: ops.register()? },
+            Opcode::FloatToInt => DexInstruction::FloatToInt { dest: ops.register()?, src: ops.register()? },
+            Opcode::FloatToLong => DexInstruction::FloatToLong { dest: ops.register()?, src: ops.register()? },
+            Opcode::FloatToDouble => DexInstruction::FloatToDouble { dest: ops.register()?, src: ops.register()? },
+            Opcode::DoubleToInt => DexInstruction::DoubleToInt { dest: ops.register()?, src: ops.register()? },
+            Opcode::DoubleToLong => DexInstruction::DoubleToLong { dest: ops.register()?, src: ops.register()? },
+            Opcode::DoubleToFloat => DexInstruction::DoubleToFloat { dest: ops.register()?, src: ops.register()? },
+            Opcode::IntToByte => DexInstruction::IntToByte { dest: ops.register()?, src: ops.register()? },
+            Opcode::IntToChar => DexInstruction::IntToChar { dest: ops.register()?, src: ops.register()? },
+            Opcode::IntToShort => DexInstruction::IntToShort { dest: ops.register()?, src: ops.register()? },
+            Opcode::AddInt => DexInstruction::AddInt { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::SubInt => DexInstruction::SubInt { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::MulInt => DexInstruction::MulInt { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::DivInt => DexInstruction::DivInt { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::RemInt => DexInstruction::RemInt { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::AndInt => DexInstruction::AndInt { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::OrInt => DexInstruction::OrInt { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::XorInt => DexInstruction::XorInt { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::ShlInt => DexInstruction::ShlInt { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::ShrInt => DexInstruction::ShrInt { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::UshrInt => DexInstruction::UshrInt { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::AddLong => DexInstruction::AddLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::SubLong => DexInstruction::SubLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::MulLong => DexInstruction::MulLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::DivLong => DexInstruction::DivLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::RemLong => DexInstruction::RemLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::AndLong => DexInstruction::AndLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::OrLong => DexInstruction::OrLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::XorLong => DexInstruction::XorLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::ShlLong => DexInstruction::ShlLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::ShrLong => DexInstruction::ShrLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::UshrLong => DexInstruction::UshrLong { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::AddFloat => DexInstruction::AddFloat { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::SubFloat => DexInstruction::SubFloat { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::MulFloat => DexInstruction::MulFloat { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::DivFloat => DexInstruction::DivFloat { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::RemFloat => DexInstruction::RemFloat { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::AddDouble => DexInstruction::AddDouble { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::SubDouble => DexInstruction::SubDouble { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::MulDouble => DexInstruction::MulDouble { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::DivDouble => DexInstruction::DivDouble { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::RemDouble => DexInstruction::RemDouble { dest: ops.register()?, src1: ops.register()?, src2: ops.register()? },
+            Opcode::AddInt2Addr => DexInstruction::AddInt2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::SubInt2Addr => DexInstruction::SubInt2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::MulInt2Addr => DexInstruction::MulInt2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::DivInt2Addr => DexInstruction::DivInt2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::RemInt2Addr => DexInstruction::RemInt2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::AndInt2Addr => DexInstruction::AndInt2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::OrInt2Addr => DexInstruction::OrInt2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::XorInt2Addr => DexInstruction::XorInt2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::ShlInt2Addr => DexInstruction::ShlInt2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::ShrInt2Addr => DexInstruction::ShrInt2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::UshrInt2Addr => DexInstruction::UshrInt2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::AddLong2Addr => DexInstruction::AddLong2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::SubLong2Addr => DexInstruction::SubLong2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::MulLong2Addr => DexInstruction::MulLong2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::DivLong2Addr => DexInstruction::DivLong2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::RemLong2Addr => DexInstruction::RemLong2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::AndLong2Addr => DexInstruction::AndLong2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::OrLong2Addr => DexInstruction::OrLong2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::XorLong2Addr => DexInstruction::XorLong2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::ShlLong2Addr => DexInstruction::ShlLong2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::ShrLong2Addr => DexInstruction::ShrLong2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::UshrLong2Addr => DexInstruction::UshrLong2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::AddFloat2Addr => DexInstruction::AddFloat2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::SubFloat2Addr => DexInstruction::SubFloat2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::MulFloat2Addr => DexInstruction::MulFloat2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::DivFloat2Addr => DexInstruction::DivFloat2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::RemFloat2Addr => DexInstruction::RemFloat2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::AddDouble2Addr => DexInstruction::AddDouble2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::SubDouble2Addr => DexInstruction::SubDouble2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::MulDouble2Addr => DexInstruction::MulDouble2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::DivDouble2Addr => DexInstruction::DivDouble2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::RemDouble2Addr => DexInstruction::RemDouble2Addr { dest: ops.register()?, src: ops.register()? },
+            Opcode::AddIntLit16 => DexInstruction::AddIntLit16 { dest: ops.register()?, src: ops.register()?, literal: ops.short()? },
+            Opcode::RsubInt => DexInstruction::RsubInt { dest: ops.register()?, src: ops.register()?, literal: ops.short()? },
+            Opcode::MulIntLit16 => DexInstruction::MulIntLit16 { dest: ops.register()?, src: ops.register()?, literal: ops.short()? },
+            Opcode::DivIntLit16 => DexInstruction::DivIntLit16 { dest: ops.register()?, src: ops.register()?, literal: ops.short()? },
+            Opcode::RemIntLit16 => DexInstruction::RemIntLit16 { dest: ops.register()?, src: ops.register()?, literal: ops.short()? },
+            Opcode::AndIntLit16 => DexInstruction::AndIntLit16 { dest: ops.register()?, src: ops.register()?, literal: ops.short()? },
+            Opcode::OrIntLit16 => DexInstruction::OrIntLit16 { dest: ops.register()?, src: ops.register()?, literal: ops.short()? },
+            Opcode::XorIntLit16 => DexInstruction::XorIntLit16 { dest: ops.register()?, src: ops.register()?, literal: ops.short()? },
+            Opcode::AddIntLit8 => DexInstruction::AddIntLit8 { dest: ops.register()?, src: ops.register()?, literal: ops.byte()? },
+            Opcode::RsubIntLit8 => DexInstruction::RsubIntLit8 { dest: ops.register()?, src: ops.register()?, literal: ops.byte()? },
+            Opcode::MulIntLit8 => DexInstruction::MulIntLit8 { dest: ops.register()?, src: ops.register()?, literal: ops.byte()? },
+            Opcode::DivIntLit8 => DexInstruction::DivIntLit8 { dest: ops.register()?, src: ops.register()?, literal: ops.byte()? },
+            Opcode::RemIntLit8 => DexInstruction::RemIntLit8 { dest: ops.register()?, src: ops.register()?, literal: ops.byte()? },
+            Opcode::AndIntLit8 => DexInstruction::AndIntLit8 { dest: ops.register()?, src: ops.register()?, literal: ops.byte()? },
+            Opcode::OrIntLit8 => DexInstruction::OrIntLit8 { dest: ops.register()?, src: ops.register()?, literal: ops.byte()? },
+            Opcode::XorIntLit8 => DexInstruction::XorIntLit8 { dest: ops.register()?, src: ops.register()?, literal: ops.byte()? },
+            Opcode::ShlIntLit8 => DexInstruction::ShlIntLit8 { dest: ops.register()?, src: ops.register()?, literal: ops.byte()? },
+            Opcode::ShrIntLit8 => DexInstruction::ShrIntLit8 { dest: ops.register()?, src: ops.register()?, literal: ops.byte()? },
+            Opcode::UshrIntLit8 => DexInstruction::UshrIntLit8 { dest: ops.register()?, src: ops.register()?, literal: ops.byte()? },
+            Opcode::InvokePolymorphic => DexInstruction::InvokePolymorphic { registers: ops.register_list()?, method: ops.method()?, proto: ops.proto()? },
+            Opcode::InvokePolymorphicRange => DexInstruction::InvokePolymorphicRange { range: ops.register_range()?, method: ops.method()?, proto: ops.proto()? },
+            Opcode::InvokeCustom => DexInstruction::InvokeCustom { registers: ops.register_list()?, call_site: ops.call_site()? },
+            Opcode::InvokeCustomRange => DexInstruction::InvokeCustomRange { range: ops.register_range()?, call_site: ops.call_site()? },
+            Opcode::ConstMethodHandle => DexInstruction::ConstMethodHandle { dest: ops.register()?, handle: ops.method_handle()? },
+            Opcode::ConstMethodType => DexInstruction::ConstMethodType { dest: ops.register()?, proto: ops.proto()? },
+        };
+        ops.finish(instruction)
+    }
+}
+
+impl fmt::Display for DexInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", write_instruction(self))
+    }
+}
+
+/* Hands out operands in order while building a DexInstruction, checking kinds and literal ranges */
+struct OperandReader {
+    operands: IntoIter<Operand>
+}
+
+impl OperandReader {
+    fn register(&mut self) -> Option<Register>
+    {
+        match self.operands.next()? { Operand::Register(r) => Some(r), _ => None }
+    }
+
+    fn register_list(&mut self) -> Option<Vec<Register>>
+    {
+        match self.operands.next()? { Operand::RegisterList(r) if r.len() <= 5 => Some(r), _ => None }
+    }
+
+    fn register_range(&mut self) -> Option<RegisterRange>
+    {
+        match self.operands.next()? { Operand::RegisterRange(r) => Some(r), _ => None }
+    }
+
+    fn label(&mut self) -> Option<String>
+    {
+        match self.operands.next()? { Operand::Label(l) => Some(l), _ => None }
+    }
+
+    fn string(&mut self) -> Option<String>
+    {
+        match self.operands.next()? { Operand::String(s) => Some(s), _ => None }
+    }
+
+    fn type_signature(&mut self) -> Option<TypeSignature>
+    {
+        match self.operands.next()? { Operand::Type(t) => Some(t), _ => None }
+    }
+
+    fn field(&mut self) -> Option<FieldRef>
+    {
+        match self.operands.next()? { Operand::Field(f) => Some(f), _ => None }
+    }
+
+    fn method(&mut self) -> Option<MethodRef>
+    {
+        match self.operands.next()? { Operand::Method(m) => Some(m), _ => None }
+    }
+
+    fn proto(&mut self) -> Option<MethodSignature>
+    {
+        match self.operands.next()? { Operand::Proto(p) => Some(p), _ => None }
+    }
+
+    fn call_site(&mut self) -> Option<String>
+    {
+        match self.operands.next()? { Operand::CallSite(c) => Some(c), _ => None }
+    }
+
+    fn method_handle(&mut self) -> Option<MethodHandle>
+    {
+        match self.operands.next()? { Operand::MethodHandle(m) => Some(m), _ => None }
+    }
+
+    // Literals may be written either signed or as the unsigned bit pattern e.g. 0xff for a byte
+    fn literal(&mut self, bits: u32) -> Option<i64>
+    {
+        match self.operands.next()? {
+            Operand::Literal(l) if bits == 64 => Some(l),
+            Operand::Literal(l) if l >= -(1 << (bits - 1)) && l < (1 << bits) => Some(l),
+            _ => None
+        }
+    }
+
+    fn nibble(&mut self) -> Option<i8>
+    {
+        let l = self.literal(4)?;
+        Some(((l as i8) << 4) >> 4)
+    }
+
+    fn byte(&mut self) -> Option<i8>
+    {
+        Some(self.literal(8)? as i8)
+    }
+
+    fn short(&mut self) -> Option<i16>
+    {
+        Some(self.literal(16)? as i16)
+    }
+
+    fn int(&mut self) -> Option<i32>
+    {
+        Some(self.literal(32)? as i32)
+    }
+
+    fn long(&mut self) -> Option<i64>
+    {
+        self.literal(64)
+    }
+
+    fn high16(&mut self) -> Option<i32>
+    {
+        self.int().filter(|v| v & 0xffff == 0)
+    }
+
+    fn wide_high16(&mut self) -> Option<i64>
+    {
+        self.long().filter(|v| v & 0xffff_ffff_ffff == 0)
+    }
+
+    fn finish(mut self, instruction: DexInstruction) -> Option<DexInstruction>
+    {
+        match self.operands.next() {
+            None => Some(instruction),
+            Some(_) => None
+        }
+    }
+}
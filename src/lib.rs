@@ -2,14 +2,27 @@
 //!
 //! A library for reading and writing Android smali files
 //!
-use std::collections::HashSet;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
 use nom::{IResult, multi::{many_till, many0}, sequence::terminated, combinator::eof};
 use smali_parse::{blank_line, parse_instruction};
 use types::SmaliInstruction;
-use crate::types::{SmaliClass, SmaliError};
+use crate::types::{LazySmaliClass, SmaliClass, SmaliError};
 
 pub mod types;
+pub mod instructions;
+pub mod dex;
+pub mod project;
+pub mod hierarchy;
+pub mod xref;
+pub mod cfg;
+pub mod verify;
+pub mod hook;
+pub mod patch;
+pub mod fingerprint;
+pub mod rename;
+pub mod mapping;
 mod smali_parse;
 mod smali_write;
 
@@ -17,36 +30,35 @@ mod smali_write;
 ///
 /// # Examples
 ///
-/// ```
+/// ```no_run
+///  use std::path::PathBuf;
 ///  use smali::find_smali_files;
 ///
-/// let mut p = PathBuf::from_str("smali")?;
-///  let mut classes = find_smali_files(&p)?;
+///  let p = PathBuf::from("smali");
+///  let classes = find_smali_files(&p)?;
 ///  println!("{:} smali classes loaded.", classes.len());
+/// # Ok::<(), smali::types::SmaliError>(())
 /// ```
-pub fn find_smali_files(dir: &PathBuf) -> Result<Vec<SmaliClass>, SmaliError>
+pub fn find_smali_files(dir: &Path) -> Result<Vec<SmaliClass>, SmaliError>
 {
     let mut results = vec![];
 
-    for p in dir.read_dir().unwrap()
+    let entries = dir.read_dir().map_err(|e| SmaliError::Io { path: Some(dir.to_path_buf()), error: e })?;
+    for p in entries.flatten()
     {
-        if let Ok(p) = p
+        // Directory: recurse sub-directory
+        if let Ok(f) = p.file_type()
         {
-            // Directory: recurse sub-directory
-            if let Ok(f) = p.file_type()
-            {
-                if f.is_dir() {
-                    let mut new_dir = dir.clone();
-                    new_dir.push(p.file_name());
-                    let dir_hs = find_smali_files(&new_dir)?;
-                    results.extend(dir_hs);
-                } else {
-                    // It's a smali file
-                    if p.file_name().to_str().unwrap().ends_with(".smali")
-                    {
-                        let dex_file = SmaliClass::read_from_file(&p.path())?;
-                        results.push(dex_file);
-                    }
+            if f.is_dir() {
+                let new_dir = dir.join(p.file_name());
+                let dir_hs = find_smali_files(&new_dir)?;
+                results.extend(dir_hs);
+            } else {
+                // It's a smali file
+                if p.file_name().to_str().unwrap().ends_with(".smali")
+                {
+                    let dex_file = SmaliClass::read_from_file(&p.path())?;
+                    results.push(dex_file);
                 }
             }
         }
@@ -55,12 +67,159 @@ pub fn find_smali_files(dir: &PathBuf) -> Result<Vec<SmaliClass>, SmaliError>
     Ok(results)
 }
 
+/// Lenient version of [`find_smali_files`] that loads as much as possible, returning the loaded classes
+/// along with a diagnostic for every member, file or directory that couldn't be read
+///
+/// Broken members are kept as raw text in [`SmaliClass::unparsed`](types::SmaliClass::unparsed), files with a broken class
+/// header are skipped.
+///
+/// # Examples
+///
+/// ```no_run
+///  use std::path::PathBuf;
+///  use smali::find_smali_files_lenient;
+///
+///  let (classes, diagnostics) = find_smali_files_lenient(&PathBuf::from("smali"));
+///  for d in &diagnostics { eprintln!("{}", d); }
+///  println!("{:} smali classes loaded, {:} errors.", classes.len(), diagnostics.len());
+/// ```
+pub fn find_smali_files_lenient(dir: &Path) -> (Vec<SmaliClass>, Vec<SmaliError>)
+{
+    let mut results = vec![];
+    let mut diagnostics = vec![];
+    find_smali_files_into(dir, &mut results, &mut diagnostics);
+    (results, diagnostics)
+}
+
+fn find_smali_files_into(dir: &Path, results: &mut Vec<SmaliClass>, diagnostics: &mut Vec<SmaliError>)
+{
+    let entries = match dir.read_dir() {
+        Ok(e) => e,
+        Err(e) => { diagnostics.push(SmaliError::Io { path: Some(dir.to_path_buf()), error: e }); return; }
+    };
+
+    for p in entries.flatten()
+    {
+        if let Ok(f) = p.file_type()
+        {
+            if f.is_dir() {
+                find_smali_files_into(&p.path(), results, diagnostics);
+            } else if p.file_name().to_string_lossy().ends_with(".smali") {
+                match SmaliClass::read_from_file_lenient(&p.path())
+                {
+                    Ok((c, d)) => { results.push(c); diagnostics.extend(d); }
+                    Err(e) => diagnostics.push(e)
+                }
+            }
+        }
+    }
+}
+
+/// Parallel version of [`find_smali_files`], parsing the files with a pool of `threads` threads
+///
+/// Classes are returned sorted by file path whatever order they finished in. A `threads` of 0 uses one
+/// thread per CPU. If any file fails, the error for the first broken file by path is returned.
+///
+/// # Examples
+///
+/// ```no_run
+///  use std::path::PathBuf;
+///  use smali::find_smali_files_parallel;
+///
+///  let classes = find_smali_files_parallel(&PathBuf::from("smali"), 0)?;
+///  println!("{:} smali classes loaded.", classes.len());
+/// # Ok::<(), smali::types::SmaliError>(())
+/// ```
+pub fn find_smali_files_parallel(dir: &Path, threads: usize) -> Result<Vec<SmaliClass>, SmaliError>
+{
+    let mut paths = vec![];
+    smali_paths(dir, &mut paths)?;
+    load_parallel(&paths, threads, SmaliClass::read_from_file)
+}
+
+/// Lazy version of [`find_smali_files_parallel`], only the class headers are parsed up front and the rest of each
+/// class is parsed the first time it's used, see [`LazySmaliClass`]
+///
+/// # Examples
+///
+/// ```no_run
+///  use std::path::PathBuf;
+///  use smali::find_smali_files_lazy;
+///
+///  let classes = find_smali_files_lazy(&PathBuf::from("smali"), 0)?;
+///  for c in classes.iter().filter(|c| c.super_class().as_java_type() == "android.app.Activity")
+///  {
+///      println!("{} has {} methods", c.name().as_java_type(), c.class()?.methods.len());
+///  }
+/// # Ok::<(), smali::types::SmaliError>(())
+/// ```
+pub fn find_smali_files_lazy(dir: &Path, threads: usize) -> Result<Vec<LazySmaliClass>, SmaliError>
+{
+    let mut paths = vec![];
+    smali_paths(dir, &mut paths)?;
+    load_parallel(&paths, threads, LazySmaliClass::read_from_file)
+}
+
+/* Every smali file below dir, sorted so results don't depend on the directory listing order */
+fn smali_paths(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<(), SmaliError>
+{
+    let entries = dir.read_dir().map_err(|e| SmaliError::Io { path: Some(dir.to_path_buf()), error: e })?;
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|e| e.file_name());
+
+    for p in entries
+    {
+        if let Ok(f) = p.file_type()
+        {
+            if f.is_dir() { smali_paths(&p.path(), paths)?; }
+            else if p.file_name().to_string_lossy().ends_with(".smali") { paths.push(p.path()); }
+        }
+    }
+    Ok(())
+}
+
+/* Loads every path on a pool of threads, keeping the results in path order */
+fn load_parallel<T, F>(paths: &[PathBuf], threads: usize, load: F) -> Result<Vec<T>, SmaliError>
+    where T: Send, F: Fn(&Path) -> Result<T, SmaliError> + Sync
+{
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n
+    }.min(paths.len()).max(1);
+
+    // Files are handed out in order, so every file before a failure has been loaded too
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let mut results: Vec<Option<Result<T, SmaliError>>> = paths.iter().map(|_| None).collect();
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..threads).map(|_| s.spawn(|| {
+            let mut done = vec![];
+            while !failed.load(Ordering::Relaxed)
+            {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= paths.len() { break; }
+                let r = load(&paths[i]);
+                if r.is_err() { failed.store(true, Ordering::Relaxed); }
+                done.push((i, r));
+            }
+            done
+        })).collect();
+
+        for w in workers
+        {
+            for (i, r) in w.join().expect("smali loader thread panicked") { results[i] = Some(r); }
+        }
+    });
+
+    results.into_iter().flatten().collect()
+}
+
 pub fn parse_fragment(input: &str) -> Result<Vec<SmaliInstruction>, SmaliError>
 {
     match many_till(terminated(parse_instruction, many0(blank_line)), eof)(input) {
         IResult::Ok((_, (instructions, _))) => Ok(instructions),
-        IResult::Err(nom::Err::Failure(e)) => Err(SmaliError { details: format!("parse error at {:?}", e.to_string()) }),
-        _ => Err(SmaliError { details: "unknown parse error".to_string() })
+        IResult::Err(nom::Err::Failure(e)) | IResult::Err(nom::Err::Error(e)) => Err(SmaliError::parse(input, e.input)),
+        IResult::Err(nom::Err::Incomplete(_)) => Err(SmaliError::parse(input, ""))
     }
 }
 
@@ -69,7 +228,11 @@ pub fn parse_fragment(input: &str) -> Result<Vec<SmaliInstruction>, SmaliError>
 mod tests {
     use std::fs;
     use std::path::Path;
-    use crate::types::{MethodSignature, ObjectIdentifier, SmaliClass, TypeSignature};
+    use crate::instructions::DexInstruction::{Const4, Return};
+    use crate::instructions::Register::V;
+    use crate::types::SmaliInstruction::Instruction;
+    use crate::types::{MethodSignature, ObjectIdentifier, RegisterCount, SmaliClass, SmaliError, TypeSignature, UnparsedKind};
+    use crate::{find_smali_files, find_smali_files_lazy, find_smali_files_parallel};
 
     #[test]
     fn object_identifier_to_jni() {
@@ -102,4 +265,185 @@ mod tests {
         let dex = SmaliClass::from_smali(&smali).unwrap();
         println!("{}\n", dex.to_smali());
     }
+
+    #[test]
+    fn registers_directive() {
+        let smali = ".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\n\n\
+                     .method public add(JI)J\n    .registers 6\n    int-to-long v0, p3\n    add-long/2addr v0, p1\n    return-wide v0\n.end method\n";
+        let mut c = SmaliClass::from_smali(smali).unwrap();
+        let m = &mut c.methods[0];
+        assert_eq!(m.registers, RegisterCount::Registers(6));
+        assert_eq!(m.parameter_registers(), 4);
+        assert_eq!(m.locals(), 2);
+        m.set_locals(3);
+        assert_eq!(m.registers, RegisterCount::Registers(7));
+        assert!(c.to_smali().contains("    .registers 7\n"));
+
+        assert!(SmaliClass::from_smali(&smali.replace(".registers 6", ".registers 4294967296")).is_err());
+        assert!(SmaliClass::from_smali(&smali.replace(".registers 6", ".locals 99999999999")).is_err());
+    }
+
+    #[test]
+    fn recompute_locals() {
+        let smali = ".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\n\n\
+                     .method public f(J)Z\n    .locals 1\n    .local v0, \"x\":J\n    const/4 v0, 0x0\n    iget p0, p0, Lcom/basic/Test;->a:I\n    return v0\n.end method\n";
+        let mut c = SmaliClass::from_smali(smali).unwrap();
+        let m = &mut c.methods[0];
+        assert_eq!(m.locals_needed(), 2);
+        assert_eq!(m.fix_locals().unwrap(), 2);
+        m.instructions.push(Instruction(Const4 { dest: V(15), value: 0 }));
+        assert!(m.fix_locals().is_err());
+        assert_eq!(m.locals(), 2);
+        m.instructions.pop();
+
+        assert_eq!(m.reserve_registers(2).unwrap(), [V(0), V(1)]);
+        assert_eq!(m.locals(), 4);
+        assert!(c.to_smali().contains("    .local v2, \"x\":J\n    const/4 v2, 0x0\n    iget p0, p0"));
+
+        let smali = ".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\n\n\
+                     .method public static f(I)I\n    .registers 2\n    move v0, v1\n    return v0\n.end method\n";
+        let mut c = SmaliClass::from_smali(smali).unwrap();
+        c.methods[0].use_parameter_registers();
+        c.methods[0].reserve_registers(1).unwrap();
+        assert!(c.to_smali().contains("    .registers 3\n    move v1, p0\n    return v1\n"));
+    }
+
+    #[test]
+    fn literal_comments() {
+        let smali = ".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\n\n\
+                     .method public f()V\n    .locals 2\n    const/high16 v0, 0x3f800000\n    const v0, 0x3dcccccd\n    const v0, 0x40490fdb\n\
+                     const v0, 0x7f0b001c\n    const/16 v0, 0x3e8\n    const-wide/high16 v0, 0x3ff0000000000000L\n\
+                     const-wide v0, 0x400921fb54442d18L\n    const-wide v0, 0x7fffffffffffffffL\n    return-void\n\
+                     :array_0\n    .array-data 4\n        0x3f800000\n        0x64\n    .end array-data\n\
+                     :array_1\n    .array-data 8\n        0x4000000000000000L\n    .end array-data\n.end method\n";
+        let mut c = SmaliClass::from_smali(smali).unwrap();
+        c.methods[0].set_locals(3);
+        let out = c.to_smali();
+        for l in ["    const/high16 v0, 0x3f800000    # 1.0f\n", "    const v0, 0x3dcccccd    # 0.1f\n", "    const v0, 0x40490fdb    # (float)Math.PI\n",
+                  "    const v0, 0x7f0b001c\n", "    const/16 v0, 0x3e8\n", "    const-wide/high16 v0, 0x3ff0000000000000L    # 1.0\n",
+                  "    const-wide v0, 0x400921fb54442d18L    # Math.PI\n", "    const-wide v0, 0x7fffffffffffffffL\n",
+                  "        0x3f800000    # 1.0f\n        0x64\n", "        0x4000000000000000L    # 2.0\n"]
+        {
+            assert!(out.contains(l), "{}", l);
+        }
+        let mut c = SmaliClass::from_smali(&out).unwrap();
+        c.methods[0].set_locals(2);
+        assert!(c.to_smali().contains("    const v0, 0x3dcccccd    # 0.1f\n    const v0, 0x40490fdb    # (float)Math.PI\n"));
+    }
+
+    #[test]
+    fn local_round_trip() {
+        let smali = ".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\n\n\
+                     .method public f(I)V\n    .locals 2\n    .local p1, null:V\n    .local v0\n    .local v1, \"x\":I\n    \
+                     .local v0, null:Ljava/util/List;, \"Ljava/util/List<I>;\"\n    return-void\n.end method\n";
+        let mut c = SmaliClass::from_smali(smali).unwrap();
+        c.methods[0].set_locals(3);
+        let out = c.to_smali();
+        assert!(out.contains("    .local p1, null:V\n    .local v0\n    .local v1, \"x\":I\n    .local v0, null:Ljava/util/List;, \"Ljava/util/List<I>;\"\n"));
+    }
+
+    #[test]
+    fn parse_error_location() {
+        let smali = ".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\n\n\
+                     .method public f()V\n    .locals 0\n    return-void\n.end method\n\n.field bad\n";
+        let e = SmaliClass::from_smali(smali).unwrap_err().with_path(Path::new("smali/com/basic/Test.smali"));
+        match &e
+        {
+            SmaliError::Parse { line, column, snippet, .. } => {
+                assert_eq!((*line, *column), (9, 1));
+                assert_eq!(snippet, ".field bad");
+            }
+            _ => panic!("{}", e)
+        }
+        assert_eq!(e.to_string(), "error: expected a class directive, field or method\n  --> smali/com/basic/Test.smali:9:1\n  |\n9 | .field bad\n  | ^^^^^^");
+
+        let e = SmaliClass::read_from_file(Path::new("tests/Missing.smali")).unwrap_err();
+        assert!(matches!(e, SmaliError::Io { path: Some(_), .. }));
+    }
+
+    #[test]
+    fn lenient_parse() {
+        let smali = ".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\n\n\
+                     .field private a:I\n\n\
+                     .method public f()V\n    .locals 0\n    bogus v0\n.end method\n\n\
+                     .method public g()V\n    .locals 0\n    return-void\n.end method\n";
+        assert!(SmaliClass::from_smali(smali).is_err());
+
+        let (c, diagnostics) = SmaliClass::from_smali_lenient(smali).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(diagnostics[0], SmaliError::Parse { line: 8, column: 5, .. }));
+        assert_eq!(c.fields.len(), 1);
+        assert_eq!(c.methods.len(), 1);
+        assert_eq!(c.methods[0].name, "g");
+        assert_eq!(c.unparsed.len(), 1);
+        assert_eq!(c.unparsed[0].kind, UnparsedKind::Method);
+
+        // The broken method is written back unchanged
+        let out = c.to_smali();
+        assert!(out.contains(".method public f()V\n    .locals 0\n    bogus v0\n.end method\n"));
+        let (c, diagnostics) = SmaliClass::from_smali_lenient(&out).unwrap();
+        assert_eq!((c.methods.len(), c.unparsed.len(), diagnostics.len()), (1, 1, 1));
+
+        let (c, diagnostics) = SmaliClass::from_smali_lenient(".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\nbogus\n").unwrap();
+        assert_eq!((c.unparsed[0].kind, diagnostics.len()), (UnparsedKind::Other, 1));
+        assert!(c.to_smali().ends_with(".super Ljava/lang/Object;\nbogus\n"));
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        for f in ["tests/OkHttpClient.smali", "tests/Request.smali", "tests/Response.smali"]
+        {
+            let original = fs::read_to_string(f).unwrap();
+            let c = SmaliClass::from_smali(&original).unwrap();
+            assert_eq!(c.to_smali(), original);
+        }
+
+        let smali = ".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\n# keep me\n\n\
+                     .field private a:I\n\n.field private b:I\n\n\
+                     .method public f()V\n    .locals 0\n\n    # comment\n    return-void\n.end method\n\n\
+                     .method public g()I\n    .locals 1\n\n    const/4 v0, 0x1\n\n    return v0\n.end method\n";
+        let mut c = SmaliClass::from_smali(smali).unwrap();
+        c.fields.remove(0);
+        c.methods[1].instructions[0] = Instruction(Const4 { dest: V(0), value: 0 });
+        c.methods[1].instructions.truncate(1);
+        c.methods[1].instructions.push(Instruction(Return { src: V(0) }));
+        let mut m = SmaliClass::from_smali(smali).unwrap().methods.remove(0);
+        m.name = "h".to_string();
+        c.methods.push(m);
+
+        assert_eq!(c.to_smali(), ".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\n# keep me\n\n\
+                                  .field private b:I\n\n\
+                                  .method public f()V\n    .locals 0\n\n    # comment\n    return-void\n.end method\n\n\
+                                  .method public g()I\n    .locals 1\n    const/4 v0, 0x0\n    return v0\n.end method\n\n\
+                                  .method public h()V\n    .locals 0\n    return-void\n.end method\n");
+
+        // Without the layout the class is written in the standard layout
+        c.layout = None;
+        assert!(c.to_smali().contains("\n# fields\n.field private b:I\n"));
+    }
+
+    #[test]
+    fn parallel_and_lazy_loading() {
+        let mut expected: Vec<String> = find_smali_files(Path::new("tests")).unwrap().iter().map(|c| c.to_smali()).collect();
+        expected.sort();
+        for threads in [0, 1, 4]
+        {
+            let classes = find_smali_files_parallel(Path::new("tests"), threads).unwrap();
+            let paths: Vec<_> = classes.iter().map(|c| c.file_path.clone().unwrap()).collect();
+            assert!(paths.windows(2).all(|w| w[0] < w[1]));
+            let mut smali: Vec<String> = classes.iter().map(|c| c.to_smali()).collect();
+            smali.sort();
+            assert_eq!(smali, expected);
+        }
+
+        let mut classes = find_smali_files_lazy(Path::new("tests"), 2).unwrap();
+        let c = classes.iter_mut().find(|c| c.name().as_java_type() == "okhttp3.Request").unwrap();
+        assert_eq!(c.super_class().as_java_type(), "java.lang.Object");
+        assert_eq!(c.source(), Some("Request.kt"));
+        assert!(!c.is_loaded());
+        c.class_mut().unwrap().source = None;
+        assert!(c.is_loaded());
+        assert_eq!(c.source(), None);
+        assert_eq!(c.file_path(), Some(Path::new("tests/Request.smali")));
+    }
 }
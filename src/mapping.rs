@@ -0,0 +1,306 @@
+//! ProGuard and R8 `mapping.txt` files, to deobfuscate classes or obfuscate them again
+//!
+//! A mapping lists each renamed class with its renamed fields and methods:
+//!
+//! ```text
+//! com.cool.RootCheck -> a.b:
+//! # {"id":"sourceFile","fileName":"RootCheck.kt"}
+//!     java.lang.String[] paths -> a
+//!     1:4:boolean isRooted(android.content.Context):12:15 -> b
+//! ```
+//!
+//! [`Mapping::deobfuscator`] and [`Mapping::obfuscator`] give a [`Renamer`] to apply to classes or a
+//! [`SmaliProject`](crate::project::SmaliProject), which also moves the class files.
+//!
+//! # Examples
+//!
+//! ```
+//!  use smali::mapping::Mapping;
+//!  use smali::types::SmaliClass;
+//!
+//!  let mapping = Mapping::parse("com.cool.RootCheck -> a.b:\n    boolean isRooted() -> b\n")?;
+//!  let mut classes = vec![SmaliClass::from_smali(".class public La/b;\n.super Ljava/lang/Object;\n\n\
+//!                                                 .method public b()Z\n    .locals 1\n    const/4 v0, 0x1\n    return v0\n.end method\n")?];
+//!  mapping.deobfuscator().apply(&mut classes);
+//!  assert_eq!(classes[0].name.as_java_type(), "com.cool.RootCheck");
+//!  assert_eq!(classes[0].methods[0].name, "isRooted");
+//!
+//!  mapping.obfuscator().apply(&mut classes);
+//!  assert_eq!(classes[0].name.as_java_type(), "a.b");
+//! # Ok::<(), smali::types::SmaliError>(())
+//! ```
+
+use std::fs;
+use std::path::Path;
+use crate::rename::Renamer;
+use crate::types::{FieldRef, MethodRef, MethodSignature, ObjectIdentifier, SmaliError, TypeSignature};
+
+/// A renamed field, its type uses the original class names
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldMapping {
+    pub field_type: TypeSignature,
+    pub original: String,
+    pub obfuscated: String
+}
+
+/// A renamed method, its signature uses the original class names
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodMapping {
+    pub signature: MethodSignature,
+    pub original: String,
+    pub obfuscated: String
+}
+
+/// A class of a mapping with its members
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassMapping {
+    pub original: ObjectIdentifier,
+    pub obfuscated: ObjectIdentifier,
+    /// The source file name, from R8's `sourceFile` comment
+    pub source: Option<String>,
+    pub fields: Vec<FieldMapping>,
+    pub methods: Vec<MethodMapping>
+}
+
+/// A parsed `mapping.txt`
+///
+#[derive(Debug, Clone, Default)]
+pub struct Mapping {
+    pub classes: Vec<ClassMapping>
+}
+
+/// Converts a Java type name as used in mappings, e.g. `java.lang.String[]`, to a type
+///
+/// # Examples
+///
+/// ```
+///  use smali::mapping::java_type;
+///  use smali::types::TypeSignature;
+///
+///  assert_eq!(java_type("int[][]"), TypeSignature::from_jni("[[I"));
+///  assert_eq!(java_type("com.cool.Item"), TypeSignature::from_jni("Lcom/cool/Item;"));
+/// ```
+pub fn java_type(s: &str) -> TypeSignature
+{
+    if let Some(element) = s.strip_suffix("[]") { return TypeSignature::Array(Box::new(java_type(element))); }
+    match s {
+        "boolean" => TypeSignature::Bool,
+        "byte" => TypeSignature::Byte,
+        "char" => TypeSignature::Char,
+        "short" => TypeSignature::Short,
+        "int" => TypeSignature::Int,
+        "long" => TypeSignature::Long,
+        "float" => TypeSignature::Float,
+        "double" => TypeSignature::Double,
+        "void" => TypeSignature::Void,
+        _ => TypeSignature::Object(ObjectIdentifier::from_java_type(s))
+    }
+}
+
+/* Splits `a -> b` */
+fn arrow(s: &str) -> Option<(&str, &str)>
+{
+    s.split_once(" -> ").map(|(a, b)| (a.trim(), b.trim()))
+}
+
+/* Parses `1:4:boolean isRooted(android.content.Context):12:15` into its line range, signature and name */
+fn method_line(s: &str) -> Option<(&str, MethodSignature, String)>
+{
+    // Line numbers come before the return type and after the arguments
+    let declaration = s.trim_start_matches(|c: char| c.is_ascii_digit() || c == ':');
+    let range = &s[..s.len() - declaration.len()];
+    let close = declaration.rfind(')')?;
+    let (declaration, args) = declaration[..close].split_once('(')?;
+    let (return_type, name) = declaration.trim().split_once(' ')?;
+    let args = args.split(',').filter(|a| !a.trim().is_empty()).map(|a| java_type(a.trim())).collect();
+    Some((range, MethodSignature { args, return_type: java_type(return_type) }, name.to_string()))
+}
+
+/* Adds the methods of a class, an inlined method is listed before the method it was inlined into with the same line
+   range and new name, and methods are listed once per range */
+fn add_methods(c: Option<&mut ClassMapping>, lines: &mut Vec<(String, MethodMapping)>)
+{
+    let Some(c) = c else { return };
+    for (k, (range, m)) in lines.iter().enumerate()
+    {
+        let inlined = !range.is_empty() && lines.get(k + 1).is_some_and(|(next, n)| next == range && n.obfuscated == m.obfuscated);
+        if !inlined && !m.original.contains('.') && !c.methods.iter().any(|x| x.original == m.original && x.signature == m.signature)
+        {
+            c.methods.push(m.clone());
+        }
+    }
+    lines.clear();
+}
+
+impl Mapping {
+    /// Parses the text of a mapping file
+    pub fn parse(text: &str) -> Result<Mapping, SmaliError>
+    {
+        let mut classes: Vec<ClassMapping> = vec![];
+        let mut methods = vec![];
+        for (n, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim_end_matches('\r')))
+        {
+            let trimmed = line.trim();
+            let err = |expected: &str| SmaliError::at_line(n, line, expected);
+            if trimmed.is_empty() { continue; }
+            if let Some(comment) = trimmed.strip_prefix('#')
+            {
+                // R8 records the source file in a JSON comment after the class
+                if let (Some(c), true) = (classes.last_mut(), comment.contains("\"sourceFile\""))
+                {
+                    c.source = comment.split_once("\"fileName\":").and_then(|(_, f)| f.trim().strip_prefix('"')?.split_once('"')).map(|(f, _)| f.to_string());
+                }
+                continue;
+            }
+            if !line.starts_with(char::is_whitespace)
+            {
+                let (original, obfuscated) = arrow(trimmed.strip_suffix(':').ok_or_else(|| err("a class ending with :"))?)
+                    .ok_or_else(|| err("a class and its new name"))?;
+                add_methods(classes.last_mut(), &mut methods);
+                classes.push(ClassMapping {
+                    original: ObjectIdentifier::from_java_type(original),
+                    obfuscated: ObjectIdentifier::from_java_type(obfuscated),
+                    source: None,
+                    fields: vec![],
+                    methods: vec![]
+                });
+                continue;
+            }
+
+            let c = classes.last_mut().ok_or_else(|| err("a class before its members"))?;
+            let (member, obfuscated) = arrow(trimmed).ok_or_else(|| err("a member and its new name"))?;
+            if member.contains('(')
+            {
+                let (range, signature, original) = method_line(member).ok_or_else(|| err("a method"))?;
+                methods.push((range.to_string(), MethodMapping { signature, original, obfuscated: obfuscated.to_string() }));
+            }
+            else
+            {
+                let (field_type, original) = member.split_once(' ').ok_or_else(|| err("a field type and name"))?;
+                c.fields.push(FieldMapping { field_type: java_type(field_type), original: original.to_string(), obfuscated: obfuscated.to_string() });
+            }
+        }
+        add_methods(classes.last_mut(), &mut methods);
+        Ok(Mapping { classes })
+    }
+
+    /// Reads and parses a mapping file
+    pub fn read_from_file(path: &Path) -> Result<Mapping, SmaliError>
+    {
+        let text = fs::read_to_string(path).map_err(|error| SmaliError::Io { path: Some(path.to_path_buf()), error })?;
+        Mapping::parse(&text).map_err(|e| e.with_path(path))
+    }
+
+    /// The renames turning obfuscated classes back into the originals, with their source file names
+    pub fn deobfuscator(&self) -> Renamer
+    {
+        // Member types are given with original names, which need obfuscating to match the classes being renamed
+        let mut obfuscate = Renamer::new();
+        for c in &self.classes { obfuscate.rename_class(&c.original, &c.obfuscated); }
+
+        let mut renamer = Renamer::new();
+        for c in &self.classes
+        {
+            renamer.rename_class(&c.obfuscated, &c.original);
+            if let Some(s) = &c.source { renamer.set_source(&c.obfuscated, Some(s)); }
+            for f in &c.fields
+            {
+                let field = FieldRef { class: c.obfuscated.clone(), name: f.obfuscated.clone(), signature: obfuscate.type_signature(&f.field_type) };
+                renamer.rename_field(&field, &f.original);
+            }
+            for m in &c.methods
+            {
+                let class = TypeSignature::Object(c.obfuscated.clone());
+                let method = MethodRef { class, name: m.obfuscated.clone(), signature: obfuscate.method_signature(&m.signature) };
+                renamer.rename_method(&method, &m.original);
+            }
+        }
+        renamer
+    }
+
+    /// The renames obfuscating the original classes as the mapping describes, with R8's `SourceFile` as the source
+    pub fn obfuscator(&self) -> Renamer
+    {
+        let mut renamer = Renamer::new();
+        for c in &self.classes
+        {
+            renamer.rename_class(&c.original, &c.obfuscated);
+            if c.source.is_some() { renamer.set_source(&c.original, Some("SourceFile")); }
+            for f in &c.fields
+            {
+                let field = FieldRef { class: c.original.clone(), name: f.original.clone(), signature: f.field_type.clone() };
+                renamer.rename_field(&field, &f.obfuscated);
+            }
+            for m in &c.methods
+            {
+                let method = MethodRef { class: TypeSignature::Object(c.original.clone()), name: m.original.clone(), signature: m.signature.clone() };
+                renamer.rename_method(&method, &m.obfuscated);
+            }
+        }
+        renamer
+    }
+
+    /// Looks up a class by its obfuscated name
+    pub fn class_by_obfuscated(&self, name: &ObjectIdentifier) -> Option<&ClassMapping>
+    {
+        self.classes.iter().find(|c| &c.obfuscated == name)
+    }
+
+    /// Looks up a class by its original name
+    pub fn class_by_original(&self, name: &ObjectIdentifier) -> Option<&ClassMapping>
+    {
+        self.classes.iter().find(|c| &c.original == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::find_smali_files;
+    use crate::mapping::Mapping;
+    use crate::types::{ObjectIdentifier, TypeSignature};
+
+    #[test]
+    fn mapping_round_trip() {
+        let text = "# compiler: R8\n\
+                    okhttp3.Request -> a.a:\n\
+                    # {\"id\":\"sourceFile\",\"fileName\":\"Request.kt\"}\n\
+                    \x20   okhttp3.HttpUrl url -> a\n\
+                    \x20   java.util.Map tags -> b\n\
+                    \x20   1:1:okhttp3.HttpUrl url():75:75 -> c\n\
+                    \x20   2:3:okhttp3.HttpUrl url():76:77 -> c\n\
+                    \x20   4:4:java.lang.String inlined():10:10 -> c\n\
+                    \x20   4:4:okhttp3.HttpUrl url():78:78 -> c\n\
+                    \x20   5:5:java.lang.String okhttp3.Other.inlined():10:10 -> c\n\
+                    \x20   5:5:okhttp3.HttpUrl url():79:79 -> c\n\
+                    \x20   1:5:java.lang.String header(java.lang.String):80:84 -> d\n\
+                    okhttp3.Headers -> a.b:\n";
+        let mapping = Mapping::parse(text).unwrap();
+        assert_eq!(mapping.classes.len(), 2);
+        assert_eq!(mapping.classes[0].methods.len(), 2);
+        assert_eq!(mapping.classes[0].methods[1].signature.args, [TypeSignature::from_jni("Ljava/lang/String;")]);
+        assert_eq!(mapping.classes[0].source.as_deref(), Some("Request.kt"));
+        assert!(Mapping::parse("    int a -> b\n").is_err());
+
+        // Methods with the same line range but different new names aren't inlined into each other
+        let separate = Mapping::parse("a.Main -> a:\n    1:1:void setUp():10:10 -> a\n    1:1:boolean isRooted():20:20 -> b\n").unwrap();
+        assert_eq!(separate.classes[0].methods.iter().map(|m| m.original.as_str()).collect::<Vec<_>>(), ["setUp", "isRooted"]);
+
+        let mut classes = find_smali_files(Path::new("tests")).unwrap();
+        let before: Vec<String> = classes.iter().map(|c| c.to_smali()).collect();
+        mapping.obfuscator().apply(&mut classes);
+        let obfuscated = classes.iter().find(|c| c.name.as_jni_type() == "La/a;").unwrap();
+        assert_eq!(obfuscated.source.as_deref(), Some("SourceFile"));
+        assert!(obfuscated.fields.iter().any(|f| f.name == "a" && f.signature == TypeSignature::from_jni("Lokhttp3/HttpUrl;")));
+        assert!(obfuscated.methods.iter().any(|m| m.name == "d"));
+        assert!(classes.iter().any(|c| c.to_smali().contains("Lokhttp3/Response;") && c.to_smali().contains("La/a;->c()Lokhttp3/HttpUrl;")));
+        assert!(classes.iter().all(|c| c.name != ObjectIdentifier::from_jni_type("Lokhttp3/Request;")));
+
+        // Members are found by their obfuscated types, Headers is renamed in header's signature
+        mapping.deobfuscator().apply(&mut classes);
+        assert_eq!(classes.iter().map(|c| c.to_smali()).collect::<Vec<String>>(), before);
+    }
+}
@@ -0,0 +1,680 @@
+//! Patches as data: a small language describing which classes and methods to change and how
+//!
+//! A patch file holds any number of patches, each listing class matchers, then method matchers, then the actions to
+//! apply to every matching method:
+//!
+//! ```text
+//! # Comments start with a #
+//! patch disable-rootbeer
+//!     fields 2
+//!     methods 25
+//!     method *()Z
+//!     return false
+//! end
+//!
+//! patch log-checks
+//!     class Lcom/cool/*
+//!     method check(Ljava/lang/String;)Z
+//!     insert-before invoke-static {*}, Lcom/cool/Util;->isRooted()Z
+//!         const-string v0, "checking root"
+//!         invoke-static {v0}, Lcom/cool/Log;->d(Ljava/lang/String;)V
+//!     end
+//!     remove invoke-static {*}, Lcom/cool/Debug;->*
+//! end
+//! ```
+//!
+//! Every matcher must match. The class matchers are
+//!
+//! | Matcher | Matches classes |
+//! |---|---|
+//! | `class <pattern>` | named e.g. `Lcom/cool/*` |
+//! | `super <pattern>` | whose superclass is named |
+//! | `implements <pattern>` | implementing an interface |
+//! | `fields <n>`, `methods <n>` | with exactly n fields or methods |
+//! | `has-method <pattern>` | with a method, as for `method` |
+//! | `has-string "<string>"` | using a string literal |
+//!
+//! and the method matchers
+//!
+//! | Matcher | Matches methods |
+//! |---|---|
+//! | `method <pattern>` | named e.g. `isRooted`, or with a signature e.g. `isRooted()Z` or `*()Z` |
+//! | `calls <pattern>` | calling a method e.g. `Lcom/cool/Util;->check*` |
+//! | `uses-string "<string>"` | using a string literal |
+//! | `contains <pattern>` | with an instruction, written as in smali e.g. `const-string v0, "su"` |
+//!
+//! The actions are
+//!
+//! | Action | Does |
+//! |---|---|
+//! | `return <value>` | replaces the method with one returning `void`, `true`, `false`, `null`, a number or a string |
+//! | `replace` .. `end` | replaces the method's code with smali, `.locals` is worked out if not given |
+//! | `insert-before <pattern>` .. `end` | inserts smali before each matching instruction |
+//! | `insert-after <pattern>` .. `end` | inserts smali after each matching instruction and its `move-result` |
+//! | `remove <pattern>` | removes each matching instruction and its `move-result` |
+//!
+//! Patterns use `*` for any text. Inserted smali gets `v` registers of its own, so its `v0` isn't the method's `v0`,
+//! and its `p` registers always hold the arguments the method was called with. Labels in it are renamed so the same
+//! code can be inserted more than once.
+//!
+//! # Examples
+//!
+//! ```
+//!  use smali::patch::PatchSet;
+//!  use smali::types::SmaliClass;
+//!
+//!  let smali = ".class public Lcom/cool/RootBeer;\n.super Ljava/lang/Object;\n\n\
+//!               .method public isRooted()Z\n    .locals 1\n    invoke-virtual {p0}, Lcom/cool/RootBeer;->detect()Z\n    move-result v0\n    return v0\n.end method\n";
+//!  let mut classes = vec![SmaliClass::from_smali(smali)?];
+//!  let patches = PatchSet::parse("patch disable\n    class Lcom/cool/Root*\n    method *()Z\n    return false\nend\n\n\
+//!                                 patch missing\n    class Lcom/other/*\nend\n")?;
+//!  let report = patches.apply(&mut classes);
+//!  assert_eq!(report.unmatched(), ["missing"]);
+//!  assert!(classes[0].to_smali().contains("    .locals 1\n    const/4 v0, 0x0\n    return v0\n"));
+//!  println!("{}", report);
+//! # Ok::<(), smali::types::SmaliError>(())
+//! ```
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::ops::DerefMut;
+use std::path::Path;
+use nom::bytes::complete::take_while1;
+use nom::character::complete::space0;
+use nom::IResult;
+use crate::instructions::{DexInstruction, Operand, Payload, Register};
+use crate::smali_parse::{parse_literal, string_literal, unescape_string};
+use crate::types::{rename_registers, ObjectIdentifier, RegisterCount, SmaliClass, SmaliError, SmaliInstruction, SmaliMethod, TypeSignature};
+
+/// Selects the classes a patch applies to
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClassMatcher {
+    Name(String),
+    Super(String),
+    Implements(String),
+    Fields(usize),
+    Methods(usize),
+    HasMethod(String),
+    HasString(String)
+}
+
+/// Selects the methods of the matched classes a patch changes
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum MethodMatcher {
+    Name(String),
+    Calls(String),
+    UsesString(String),
+    Contains(String)
+}
+
+/// A constant for [`PatchAction::Return`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReturnValue {
+    Void,
+    Bool(bool),
+    /// A number, converted for float and double methods
+    Int(i64),
+    Null,
+    String(String)
+}
+
+impl fmt::Display for ReturnValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReturnValue::Void => write!(f, "void"),
+            ReturnValue::Bool(b) => write!(f, "{}", b),
+            ReturnValue::Int(i) => write!(f, "{}", i),
+            ReturnValue::Null => write!(f, "null"),
+            ReturnValue::String(s) => write!(f, "{:?}", s)
+        }
+    }
+}
+
+/// Smali code to replace a method's code with or insert into it
+///
+#[derive(Debug, Clone)]
+pub struct Snippet {
+    pub instructions: Vec<SmaliInstruction>,
+    /// The `.locals` or `.registers` given in the code, if any
+    pub registers: Option<RegisterCount>,
+    /// The number of `v` registers the code uses
+    pub locals: u32
+}
+
+/// What a patch does to each matching method
+///
+#[derive(Debug, Clone)]
+pub enum PatchAction {
+    Return(ReturnValue),
+    Replace(Snippet),
+    InsertBefore(String, Snippet),
+    InsertAfter(String, Snippet),
+    Remove(String)
+}
+
+/// A named set of matchers and the actions to apply to every method they match
+///
+/// A patch without actions only reports what it matches, e.g. to check a fingerprint still finds its class.
+///
+#[derive(Debug, Clone)]
+pub struct Patch {
+    pub name: String,
+    pub classes: Vec<ClassMatcher>,
+    pub methods: Vec<MethodMatcher>,
+    pub actions: Vec<PatchAction>
+}
+
+/// The patches of a patch file, see the [module documentation](self) for the format
+///
+#[derive(Debug, Clone, Default)]
+pub struct PatchSet {
+    pub patches: Vec<Patch>
+}
+
+/// What one patch matched and changed
+///
+#[derive(Debug, Clone, Default)]
+pub struct PatchOutcome {
+    /// The name of the patch
+    pub name: String,
+    /// Every class the class matchers matched
+    pub classes: Vec<ObjectIdentifier>,
+    /// Every method matched, as `Lcom/cool/Class;->name(signature)`
+    pub methods: Vec<String>,
+    /// The number of changes made by each action: methods replaced, places code was inserted or instructions removed
+    pub changes: Vec<usize>,
+    /// Methods that couldn't be patched and why, these are left unchanged
+    pub errors: Vec<String>,
+
+    // Internal
+    method_matchers: bool
+}
+
+impl PatchOutcome {
+    /// Did the patch find what it was looking for, methods if it has method matchers and otherwise classes
+    pub fn is_matched(&self) -> bool
+    {
+        if self.method_matchers { !self.methods.is_empty() } else { !self.classes.is_empty() }
+    }
+}
+
+/// The outcome of applying a [`PatchSet`], in the order of the patches
+///
+#[derive(Debug, Clone, Default)]
+pub struct PatchReport {
+    pub outcomes: Vec<PatchOutcome>
+}
+
+impl PatchReport {
+    /// The names of the patches that didn't match anything
+    pub fn unmatched(&self) -> Vec<&str>
+    {
+        self.outcomes.iter().filter(|o| !o.is_matched()).map(|o| o.name.as_str()).collect()
+    }
+
+    /// Did every patch match, with no errors
+    pub fn is_ok(&self) -> bool
+    {
+        self.outcomes.iter().all(|o| o.is_matched() && o.errors.is_empty())
+    }
+}
+
+impl fmt::Display for PatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for o in &self.outcomes
+        {
+            if !o.is_matched() { writeln!(f, "{}: no match", o.name)?; continue; }
+            write!(f, "{}: {} classes", o.name, o.classes.len())?;
+            if o.method_matchers { write!(f, ", {} methods, {} changes", o.methods.len(), o.changes.iter().sum::<usize>())?; }
+            writeln!(f)?;
+            for (k, _) in o.changes.iter().enumerate().filter(|(_, c)| **c == 0) { writeln!(f, "  action {} changed nothing", k + 1)?; }
+            for e in &o.errors { writeln!(f, "  error: {}", e)?; }
+        }
+        Ok(())
+    }
+}
+
+/* Matches text against a pattern where * stands for any text */
+fn glob(pattern: &str, text: &str) -> bool
+{
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() == 1 { return pattern == text; }
+    if text.len() < first.len() + last.len() || !text.starts_with(first) || !text.ends_with(last) { return false; }
+    let mut rest = &text[first.len()..text.len() - last.len()];
+    for p in &parts[1..parts.len() - 1]
+    {
+        match rest.find(p) {
+            Some(i) => rest = &rest[i + p.len()..],
+            None => return false
+        }
+    }
+    true
+}
+
+/* Matches a method by name, or by name and signature if the pattern has one */
+fn method_glob(pattern: &str, m: &SmaliMethod) -> bool
+{
+    if pattern.contains('(') { glob(pattern, &format!("{}{}", m.name, m.signature.to_jni())) } else { glob(pattern, &m.name) }
+}
+
+fn dex_instructions(m: &SmaliMethod) -> impl Iterator<Item = &DexInstruction>
+{
+    m.instructions.iter().filter_map(|i| if let SmaliInstruction::Instruction(d) = i { Some(d) } else { None })
+}
+
+fn uses_string(m: &SmaliMethod, s: &str) -> bool
+{
+    dex_instructions(m).any(|d| d.operands().iter().any(|o| matches!(o, Operand::String(x) if x == s)))
+}
+
+impl ClassMatcher {
+    pub fn matches(&self, c: &SmaliClass) -> bool
+    {
+        match self {
+            ClassMatcher::Name(p) => glob(p, &c.name.as_jni_type()),
+            ClassMatcher::Super(p) => glob(p, &c.super_class.as_jni_type()),
+            ClassMatcher::Implements(p) => c.implements.iter().any(|i| glob(p, &i.as_jni_type())),
+            ClassMatcher::Fields(n) => c.fields.len() == *n,
+            ClassMatcher::Methods(n) => c.methods.len() == *n,
+            ClassMatcher::HasMethod(p) => c.methods.iter().any(|m| method_glob(p, m)),
+            ClassMatcher::HasString(s) => c.methods.iter().any(|m| uses_string(m, s))
+        }
+    }
+}
+
+impl MethodMatcher {
+    pub fn matches(&self, m: &SmaliMethod) -> bool
+    {
+        match self {
+            MethodMatcher::Name(p) => method_glob(p, m),
+            MethodMatcher::Calls(p) => dex_instructions(m).any(|d| d.operands().iter().any(|o| matches!(o, Operand::Method(r) if glob(p, &r.to_jni())))),
+            MethodMatcher::UsesString(s) => uses_string(m, s),
+            MethodMatcher::Contains(p) => dex_instructions(m).any(|d| glob(p, &d.to_string()))
+        }
+    }
+}
+
+/* The code returning a constant from a method */
+fn return_code(m: &SmaliMethod, value: &ReturnValue) -> Result<Vec<DexInstruction>, String>
+{
+    let v0 = Register::V(0);
+    let narrow = |v: i32| match v {
+        -8..=7 => DexInstruction::Const4 { dest: v0, value: v as i8 },
+        -32768..=32767 => DexInstruction::Const16 { dest: v0, value: v as i16 },
+        _ => DexInstruction::Const { dest: v0, value: v }
+    };
+    let wide = |v: i64| match (i16::try_from(v), i32::try_from(v)) {
+        (Ok(x), _) => DexInstruction::ConstWide16 { dest: v0, value: x },
+        (_, Ok(x)) => DexInstruction::ConstWide32 { dest: v0, value: x },
+        _ => DexInstruction::ConstWide { dest: v0, value: v }
+    };
+    let strings = ["Ljava/lang/String;", "Ljava/lang/CharSequence;", "Ljava/lang/Object;"];
+    let t = &m.signature.return_type;
+    let code = match (value, t) {
+        (ReturnValue::Void, TypeSignature::Void) => vec![DexInstruction::ReturnVoid],
+        (ReturnValue::Bool(b), TypeSignature::Bool) => vec![narrow(*b as i32), DexInstruction::Return { src: v0 }],
+        (ReturnValue::Int(i), TypeSignature::Int | TypeSignature::Short | TypeSignature::Byte | TypeSignature::Char) if i32::try_from(*i).is_ok() => {
+            vec![narrow(*i as i32), DexInstruction::Return { src: v0 }]
+        }
+        (ReturnValue::Int(i), TypeSignature::Float) => vec![narrow((*i as f32).to_bits() as i32), DexInstruction::Return { src: v0 }],
+        (ReturnValue::Int(i), TypeSignature::Long) => vec![wide(*i), DexInstruction::ReturnWide { src: v0 }],
+        (ReturnValue::Int(i), TypeSignature::Double) => vec![wide((*i as f64).to_bits() as i64), DexInstruction::ReturnWide { src: v0 }],
+        (ReturnValue::Null, TypeSignature::Object(_) | TypeSignature::Array(_)) => vec![narrow(0), DexInstruction::ReturnObject { src: v0 }],
+        (ReturnValue::String(s), TypeSignature::Object(o)) if strings.contains(&o.as_jni_type().as_str()) => {
+            vec![DexInstruction::ConstString { dest: v0, value: s.clone() }, DexInstruction::ReturnObject { src: v0 }]
+        }
+        _ => return Err(format!("can't return {} from a method returning {}", value, t.to_jni()))
+    };
+    Ok(code)
+}
+
+/* Adds a suffix to every label defined or used by some code */
+fn relabel(code: &mut [SmaliInstruction], suffix: &str)
+{
+    let label = |l: &mut String| l.push_str(suffix);
+    for i in code.iter_mut()
+    {
+        match i {
+            SmaliInstruction::Label(l) => label(l),
+            SmaliInstruction::TryCatch { start, end, handler, .. } => { label(start); label(end); label(handler); }
+            SmaliInstruction::Payload(Payload::PackedSwitch(p)) => p.targets.iter_mut().for_each(label),
+            SmaliInstruction::Payload(Payload::SparseSwitch(p)) => p.entries.iter_mut().for_each(|e| label(&mut e.1)),
+            SmaliInstruction::Instruction(d) => {
+                let operands = d.operands().into_iter().map(|o| match o {
+                    Operand::Label(l) => Operand::Label(format!("{}{}", l, suffix)),
+                    o => o
+                }).collect();
+                if let Some(relabelled) = DexInstruction::from_operands(d.opcode(), operands) { *d = relabelled; }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_move_result(i: Option<&SmaliInstruction>) -> bool
+{
+    matches!(i, Some(SmaliInstruction::Instruction(DexInstruction::MoveResult { .. } | DexInstruction::MoveResultWide { .. } | DexInstruction::MoveResultObject { .. })))
+}
+
+impl PatchAction {
+    /* Applies the action to a method, returning the number of changes made */
+    fn apply(&self, m: &mut SmaliMethod) -> Result<usize, SmaliError>
+    {
+        let has_code = dex_instructions(m).next().is_some();
+        match self {
+            PatchAction::Return(v) => {
+                if m.constructor { return Err(SmaliError::new("can't return a constant from a constructor")); }
+                if !has_code { return Err(SmaliError::new("the method has no code")); }
+                let code = return_code(m, v).map_err(|e| SmaliError::new(&e))?;
+                m.instructions = code.into_iter().map(SmaliInstruction::Instruction).collect();
+                m.fix_locals()?;
+                Ok(1)
+            }
+            PatchAction::Replace(s) => {
+                if !has_code { return Err(SmaliError::new("the method has no code")); }
+                m.instructions = s.instructions.clone();
+                match s.registers {
+                    Some(RegisterCount::Locals(n)) => m.set_locals(n),
+                    Some(r) => m.registers = r,
+                    None => { m.fix_locals()?; }
+                }
+                Ok(1)
+            }
+            PatchAction::InsertBefore(pattern, s) | PatchAction::InsertAfter(pattern, s) => {
+                let matching = |m: &SmaliMethod| -> Vec<usize> {
+                    m.instructions.iter().enumerate()
+                        .filter(|(_, i)| matches!(i, SmaliInstruction::Instruction(d) if glob(pattern, &d.to_string())))
+                        .map(|(k, _)| k)
+                        .collect()
+                };
+                if matching(m).is_empty() { return Ok(0); }
+
+                // The inserted code gets registers of its own, which also keeps the p registers as they were passed
+                let mut code = s.instructions.clone();
+                let base = m.add_registers(s.locals as u16)?.first().map_or(0, |r| r.number());
+                rename_registers(&mut code, |r| match r { Register::V(n) => Register::V(base + n), p => p });
+
+                let labels: HashSet<String> = m.instructions.iter().filter_map(|i| if let SmaliInstruction::Label(l) = i { Some(l.clone()) } else { None }).collect();
+                let relabels = code.iter().any(|i| matches!(i, SmaliInstruction::Label(_)));
+                let mut n = 0;
+                let sites = matching(m);
+                for &k in sites.iter().rev()
+                {
+                    let mut code = code.clone();
+                    if relabels
+                    {
+                        while labels.iter().any(|l| l.ends_with(&format!("_patch{}", n))) { n += 1; }
+                        relabel(&mut code, &format!("_patch{}", n));
+                        n += 1;
+                    }
+                    let at = match self {
+                        PatchAction::InsertBefore(..) => k,
+                        _ if is_move_result(m.instructions.get(k + 1)) => k + 2,
+                        _ => k + 1
+                    };
+                    m.instructions.splice(at..at, code);
+                }
+                Ok(sites.len())
+            }
+            PatchAction::Remove(pattern) => {
+                let mut removed = 0;
+                let mut k = 0;
+                while k < m.instructions.len()
+                {
+                    if matches!(&m.instructions[k], SmaliInstruction::Instruction(d) if glob(pattern, &d.to_string()))
+                    {
+                        let n = if is_move_result(m.instructions.get(k + 1)) { 2 } else { 1 };
+                        m.instructions.drain(k..k + n);
+                        removed += 1;
+                    }
+                    else { k += 1; }
+                }
+                Ok(removed)
+            }
+        }
+    }
+}
+
+impl Patch {
+    /* Applies every action to a method, leaving it unchanged if any fail */
+    fn apply_to(&self, m: &mut SmaliMethod) -> Result<Vec<usize>, SmaliError>
+    {
+        let (instructions, registers) = (m.instructions.clone(), m.registers);
+        let result = self.actions.iter().map(|a| a.apply(m)).collect::<Result<Vec<usize>, SmaliError>>()
+            .and_then(|changes| m.check_register_widths(0, m.locals()).map(|_| changes));
+        if result.is_err()
+        {
+            m.instructions = instructions;
+            m.registers = registers;
+        }
+        result
+    }
+}
+
+fn keyword(line: &str) -> IResult<&str, &str>
+{
+    let (o, k) = take_while1(|c: char| !c.is_whitespace())(line)?;
+    let (o, _) = space0(o)?;
+    IResult::Ok((o, k))
+}
+
+fn quoted_arg(arg: &str) -> Option<String>
+{
+    match string_literal(arg) {
+        IResult::Ok(("", s)) => Some(unescape_string(s)),
+        _ => None
+    }
+}
+
+fn number_arg(arg: &str) -> Option<i64>
+{
+    match parse_literal(arg) {
+        IResult::Ok(("", n)) => Some(n),
+        _ => None
+    }
+}
+
+/* Parses the smali lines of an action by wrapping them in a method */
+fn parse_snippet(lines: &[(usize, &str)]) -> Result<Snippet, SmaliError>
+{
+    const HEADER: &str = ".class public LPatch;\n.super Ljava/lang/Object;\n\n.method public static patch()V\n";
+    let body: Vec<&str> = lines.iter().map(|l| l.1).collect();
+    let text = format!("{}{}\n.end method\n", HEADER, body.join("\n"));
+    let c = SmaliClass::from_smali(&text).map_err(|e| match e {
+        SmaliError::Parse { line, column, expected, snippet, .. } if line > 4 && line - 5 < lines.len() => {
+            SmaliError::Parse { file: None, line: lines[line - 5].0, column, expected, snippet }
+        }
+        e => e
+    })?;
+    let m = c.methods.into_iter().next().ok_or_else(|| SmaliError::new("Patch code isn't a method body"))?;
+    let given = body.iter().any(|l| l.trim_start().starts_with(".locals") || l.trim_start().starts_with(".registers"));
+    Ok(Snippet { locals: m.locals_needed(), registers: if given { Some(m.registers) } else { None }, instructions: m.instructions })
+}
+
+impl PatchSet {
+    /// Parses a patch file
+    pub fn parse(text: &str) -> Result<PatchSet, SmaliError>
+    {
+        let lines: Vec<(usize, &str)> = text.lines().enumerate().map(|(i, l)| (i + 1, l.trim_end_matches('\r'))).collect();
+        let mut patches = vec![];
+        let mut current: Option<(usize, Patch)> = None;
+        let mut k = 0;
+        while k < lines.len()
+        {
+            let (n, line) = lines[k];
+            k += 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') { continue; }
+            let (arg, word) = keyword(trimmed).map_err(|_| SmaliError::at_line(n, line, "a patch"))?;
+            let err = |expected: &str| SmaliError::at_line(n, line, expected);
+
+            let Some((_, p)) = current.as_mut() else {
+                if word != "patch" || arg.is_empty() { return Err(err("a patch and its name")); }
+                current = Some((n, Patch { name: arg.to_string(), classes: vec![], methods: vec![], actions: vec![] }));
+                continue;
+            };
+            let pattern = || if arg.is_empty() { Err(err("a pattern")) } else { Ok(arg.to_string()) };
+            let quoted = || quoted_arg(arg).ok_or_else(|| err("a quoted string"));
+            let number = || number_arg(arg).filter(|n| *n >= 0).map(|n| n as usize).ok_or_else(|| err("a number"));
+            match word {
+                "end" => patches.push(current.take().unwrap().1),
+                "class" | "super" | "implements" | "fields" | "methods" | "has-method" | "has-string" => {
+                    if !p.methods.is_empty() || !p.actions.is_empty() { return Err(err("a method matcher or action, class matchers come first")); }
+                    p.classes.push(match word {
+                        "class" => ClassMatcher::Name(pattern()?),
+                        "super" => ClassMatcher::Super(pattern()?),
+                        "implements" => ClassMatcher::Implements(pattern()?),
+                        "fields" => ClassMatcher::Fields(number()?),
+                        "methods" => ClassMatcher::Methods(number()?),
+                        "has-method" => ClassMatcher::HasMethod(pattern()?),
+                        _ => ClassMatcher::HasString(quoted()?)
+                    });
+                }
+                "method" | "calls" | "uses-string" | "contains" => {
+                    if !p.actions.is_empty() { return Err(err("an action, method matchers come before actions")); }
+                    p.methods.push(match word {
+                        "method" => MethodMatcher::Name(pattern()?),
+                        "calls" => MethodMatcher::Calls(pattern()?),
+                        "uses-string" => MethodMatcher::UsesString(quoted()?),
+                        _ => MethodMatcher::Contains(pattern()?)
+                    });
+                }
+                "return" | "remove" | "replace" | "insert-before" | "insert-after" => {
+                    if p.methods.is_empty() { return Err(err("a method matcher before the actions")); }
+                    let action = match word {
+                        "return" => PatchAction::Return(match arg {
+                            "void" => ReturnValue::Void,
+                            "true" => ReturnValue::Bool(true),
+                            "false" => ReturnValue::Bool(false),
+                            "null" => ReturnValue::Null,
+                            _ => match (quoted_arg(arg), number_arg(arg)) {
+                                (Some(s), _) => ReturnValue::String(s),
+                                (_, Some(i)) => ReturnValue::Int(i),
+                                _ => return Err(err("void, true, false, null, a number or a quoted string"))
+                            }
+                        }),
+                        "remove" => PatchAction::Remove(pattern()?),
+                        _ => {
+                            // The smali runs to a line with just `end`
+                            let start = k;
+                            while k < lines.len() && lines[k].1.trim() != "end" { k += 1; }
+                            if k == lines.len() { return Err(err("an end for the code")); }
+                            let snippet = parse_snippet(&lines[start..k])?;
+                            k += 1;
+                            match word {
+                                "replace" if arg.is_empty() => PatchAction::Replace(snippet),
+                                "replace" => return Err(err("replace on a line of its own")),
+                                "insert-before" => PatchAction::InsertBefore(pattern()?, snippet),
+                                _ => PatchAction::InsertAfter(pattern()?, snippet)
+                            }
+                        }
+                    };
+                    p.actions.push(action);
+                }
+                _ => return Err(err("a matcher, action or end"))
+            }
+        }
+        if let Some((n, _)) = current { return Err(SmaliError::at_line(n, lines[n - 1].1, "an end for the patch")); }
+        Ok(PatchSet { patches })
+    }
+
+    /// Reads and parses a patch file
+    pub fn read_from_file(path: &Path) -> Result<PatchSet, SmaliError>
+    {
+        let text = fs::read_to_string(path).map_err(|error| SmaliError::Io { path: Some(path.to_path_buf()), error })?;
+        PatchSet::parse(&text).map_err(|e| e.with_path(path))
+    }
+
+    /// Applies every patch to a set of classes, e.g. `&mut Vec<SmaliClass>` or [`SmaliProject::classes_mut`](crate::project::SmaliProject::classes_mut)
+    ///
+    /// Patches are applied in order, so a later patch sees the changes made by earlier ones to the same class.
+    pub fn apply<C: DerefMut<Target = SmaliClass>, I: IntoIterator<Item = C>>(&self, classes: I) -> PatchReport
+    {
+        let mut outcomes: Vec<PatchOutcome> = self.patches.iter().map(|p| PatchOutcome {
+            name: p.name.clone(),
+            changes: vec![0; p.actions.len()],
+            method_matchers: !p.methods.is_empty(),
+            ..Default::default()
+        }).collect();
+
+        for mut c in classes
+        {
+            for (p, outcome) in self.patches.iter().zip(outcomes.iter_mut())
+            {
+                if !p.classes.iter().all(|m| m.matches(&c)) { continue; }
+                outcome.classes.push(c.name.clone());
+                let class = c.name.as_jni_type();
+                // Only borrow the class mutably for the methods patched, a SmaliProject hashes classes borrowed mutably
+                let matched: Vec<usize> = (0..c.methods.len())
+                    .filter(|&i| !p.methods.is_empty() && p.methods.iter().all(|x| x.matches(&c.methods[i])))
+                    .collect();
+                for i in matched
+                {
+                    let m = &mut c.methods[i];
+                    let name = format!("{}->{}{}", class, m.name, m.signature.to_jni());
+                    match p.apply_to(m) {
+                        Ok(changes) => outcome.changes.iter_mut().zip(changes).for_each(|(t, c)| *t += c),
+                        Err(e) => outcome.errors.push(format!("{}: {}", name, e))
+                    }
+                    outcome.methods.push(name);
+                }
+            }
+        }
+        PatchReport { outcomes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::find_smali_files;
+    use crate::patch::{PatchAction, PatchSet};
+    use crate::types::SmaliError;
+    use crate::verify::verify_class;
+
+    #[test]
+    fn patch_files() {
+        let text = "# Test patches\n\
+                    patch probe\n    class Lokhttp3/Request;\n    has-string \"url\"\nend\n\n\
+                    patch log-checks\n    class Lokhttp3/*\n    calls Lkotlin/jvm/internal/Intrinsics;->checkNotNull*\n\
+                    \x20   insert-before invoke-static {*}, Lkotlin/jvm/internal/Intrinsics;->checkNotNullParameter*\n\
+                    \x20       const-string v0, \"checking\"\n        if-eqz v0, :skip\n        invoke-static {v0}, Lhook/Log;->d(Ljava/lang/String;)V\n        :skip\n    end\n\
+                    \x20   remove invoke-static {*}, Lkotlin/jvm/internal/Intrinsics;->checkNotNullExpressionValue*\n\
+                    end\n\n\
+                    patch constants\n    class Lokhttp3/Request;\n    method isHttps()Z\n    return true\nend\n\n\
+                    patch wrong-type\n    class Lokhttp3/Request;\n    method url()Lokhttp3/HttpUrl;\n    return \"x\"\nend\n\n\
+                    patch missing\n    class Lcom/nothing/*\n    method *\n    return void\nend\n";
+        let patches = PatchSet::parse(text).unwrap();
+        assert_eq!(patches.patches.len(), 5);
+        assert!(matches!(patches.patches[1].actions[0], PatchAction::InsertBefore(_, ref s) if s.locals == 1));
+
+        let mut classes = find_smali_files(Path::new("tests")).unwrap();
+        let report = patches.apply(&mut classes);
+        assert_eq!(report.unmatched(), ["missing"]);
+        assert_eq!(report.outcomes[0].classes.len(), 1);
+        assert!(report.outcomes[1].changes.iter().all(|c| *c > 0), "{}", report);
+        assert_eq!(report.outcomes[2].changes, [1]);
+        assert_eq!(report.outcomes[3].errors.len(), 1);
+        assert!(!report.is_ok());
+
+        // Patched classes still verify and the code inserted more than once has its own labels
+        for c in &classes
+        {
+            let errors: Vec<String> = verify_class(c, None).iter().filter(|d| d.is_error()).map(|d| d.to_string()).collect();
+            assert!(errors.is_empty(), "{}: {:?}", c.name.as_jni_type(), errors);
+        }
+        let request = classes.iter().find(|c| c.name.as_jni_type() == "Lokhttp3/Request;").unwrap().to_smali();
+        assert!(request.contains(":skip_patch0") && request.contains(":skip_patch1"));
+
+        // Errors point at the line in the patch file
+        let e = PatchSet::parse("patch bad\n    method *\n    insert-after return*\n        bogus v0\n    end\nend\n").unwrap_err();
+        assert!(matches!(e, SmaliError::Parse { line: 4, .. }), "{}", e);
+        let e = PatchSet::parse("patch bad\n    return true\nend\n").unwrap_err();
+        assert!(matches!(e, SmaliError::Parse { line: 2, .. }), "{}", e);
+    }
+}
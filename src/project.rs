@@ -0,0 +1,446 @@
+//! Working with a whole apktool output directory
+//!
+//! A [`SmaliProject`] loads every smali root of an apktool output directory (`smali`, `smali_classes2`, ...),
+//! keeps track of which folder each class came from, and only writes back the classes that have changed.
+//!
+//! # Examples
+//!
+//! ```no_run
+//!  use std::path::Path;
+//!  use smali::project::SmaliProject;
+//!  use smali::types::ObjectIdentifier;
+//!
+//!  let mut project = SmaliProject::open(Path::new("out"))?;
+//!  if let Some(c) = project.class_mut(&ObjectIdentifier::from_java_type("com.app.MainActivity"))
+//!  {
+//!      c.source = None;
+//!  }
+//!  println!("{:} classes written.", project.save()?);
+//! # Ok::<(), smali::types::SmaliError>(())
+//! ```
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::ErrorKind;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use crate::find_smali_files_parallel;
+use crate::rename::{Conflict, Renamer};
+use crate::types::{ObjectIdentifier, SmaliClass, SmaliError};
+
+/* Whether a class needs writing, classes are only hashed once they've been borrowed mutably */
+enum State {
+    Clean,
+    Touched(u64),
+    New
+}
+
+struct Entry {
+    class: SmaliClass,
+    folder: usize,
+    state: State
+}
+
+fn text_hash(c: &SmaliClass) -> u64
+{
+    let mut h = DefaultHasher::new();
+    c.to_smali().hash(&mut h);
+    h.finish()
+}
+
+/// A class from [`SmaliProject::classes_mut`]
+///
+/// It's only hashed, to find out later whether it changed, when it's first borrowed mutably, so classes that are just
+/// looked at cost nothing when saving.
+pub struct ClassMut<'a> {
+    entry: &'a mut Entry
+}
+
+impl Deref for ClassMut<'_> {
+    type Target = SmaliClass;
+
+    fn deref(&self) -> &SmaliClass
+    {
+        &self.entry.class
+    }
+}
+
+impl DerefMut for ClassMut<'_> {
+    fn deref_mut(&mut self) -> &mut SmaliClass
+    {
+        if let State::Clean = self.entry.state { self.entry.state = State::Touched(text_hash(&self.entry.class)); }
+        &mut self.entry.class
+    }
+}
+
+/* smali first, then smali_classes2, smali_classes3, ... then any others by name */
+fn folder_order(name: &str) -> (u32, u32, String)
+{
+    if name == "smali" { return (0, 0, String::new()); }
+    match name.strip_prefix("smali_classes").and_then(|n| n.parse::<u32>().ok()) {
+        Some(n) => (1, n, String::new()),
+        None => (2, 0, name.to_string())
+    }
+}
+
+/// The smali classes of an apktool output directory, indexed by name
+///
+pub struct SmaliProject {
+    root: PathBuf,
+    folders: Vec<String>,
+    classes: BTreeMap<String, Entry>,
+    deleted: Vec<PathBuf>
+}
+
+impl SmaliProject {
+    /// Opens an apktool output directory, loading the classes from every `smali` and `smali_*` folder in it
+    pub fn open(root: &Path) -> Result<SmaliProject, SmaliError>
+    {
+        let entries = root.read_dir().map_err(|e| SmaliError::Io { path: Some(root.to_path_buf()), error: e })?;
+        let mut folders: Vec<String> = entries.flatten()
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+            .filter_map(|e| e.file_name().to_str().map(|n| n.to_string()))
+            .filter(|n| n == "smali" || n.starts_with("smali_"))
+            .collect();
+        folders.sort_by_key(|f| folder_order(f));
+
+        let mut project = SmaliProject { root: root.to_path_buf(), folders: vec![], classes: BTreeMap::new(), deleted: vec![] };
+        for (i, f) in folders.iter().enumerate()
+        {
+            for class in find_smali_files_parallel(&root.join(f), 0)?
+            {
+                let name = class.name.as_jni_type();
+                if let Some(e) = project.classes.get(&name)
+                {
+                    return Err(SmaliError::Other(format!("{} is in both {} and {}", name, folders[e.folder], f)));
+                }
+                project.classes.insert(name, Entry { class, folder: i, state: State::Clean });
+            }
+        }
+        project.folders = folders;
+        Ok(project)
+    }
+
+    /// The apktool output directory
+    pub fn root(&self) -> &Path
+    {
+        &self.root
+    }
+
+    /// The smali folders in DEX order e.g. `["smali", "smali_classes2"]`
+    pub fn dex_folders(&self) -> &[String]
+    {
+        &self.folders
+    }
+
+    /// Number of classes in the project
+    pub fn len(&self) -> usize
+    {
+        self.classes.len()
+    }
+
+    /// Does the project have no classes
+    pub fn is_empty(&self) -> bool
+    {
+        self.classes.is_empty()
+    }
+
+    /// Looks up a class by name
+    pub fn class(&self, name: &ObjectIdentifier) -> Option<&SmaliClass>
+    {
+        self.classes.get(&name.as_jni_type()).map(|e| &e.class)
+    }
+
+    /// Looks up a class to modify, it will be written by [`SmaliProject::save`] if it's changed
+    pub fn class_mut(&mut self, name: &ObjectIdentifier) -> Option<&mut SmaliClass>
+    {
+        let e = self.classes.get_mut(&name.as_jni_type())?;
+        if let State::Clean = e.state { e.state = State::Touched(text_hash(&e.class)); }
+        Some(&mut e.class)
+    }
+
+    /// Every class, sorted by name
+    pub fn classes(&self) -> impl Iterator<Item = &SmaliClass>
+    {
+        self.classes.values().map(|e| &e.class)
+    }
+
+    /// Every class to modify, sorted by name
+    ///
+    /// Classes borrowed mutably are compared with how they were before when saving, so only the ones actually changed
+    /// are written.
+    pub fn classes_mut(&mut self) -> impl Iterator<Item = ClassMut<'_>>
+    {
+        self.classes.values_mut().map(|entry| ClassMut { entry })
+    }
+
+    /// The classes in one smali folder, sorted by name
+    pub fn classes_in<'a>(&'a self, folder: &str) -> impl Iterator<Item = &'a SmaliClass> + 'a
+    {
+        let i = self.folders.iter().position(|f| f == folder);
+        self.classes.values().filter(move |e| Some(e.folder) == i).map(|e| &e.class)
+    }
+
+    /// The smali folder holding a class
+    pub fn dex_folder_of(&self, name: &ObjectIdentifier) -> Option<&str>
+    {
+        self.classes.get(&name.as_jni_type()).map(|e| self.folders[e.folder].as_str())
+    }
+
+    /// Has the class been added, moved or changed since it was loaded or last saved
+    pub fn is_dirty(&self, name: &ObjectIdentifier) -> bool
+    {
+        match self.classes.get(&name.as_jni_type()) {
+            Some(Entry { state: State::New, .. }) => true,
+            Some(Entry { state: State::Touched(h), class, .. }) => text_hash(class) != *h,
+            _ => false
+        }
+    }
+
+    /// The names of every class that will be written by [`SmaliProject::save`]
+    pub fn dirty_classes(&self) -> Vec<ObjectIdentifier>
+    {
+        self.classes.values().filter(|e| self.is_dirty(&e.class.name)).map(|e| e.class.name.clone()).collect()
+    }
+
+    fn folder_index(&mut self, folder: &str) -> usize
+    {
+        match self.folders.iter().position(|f| f == folder) {
+            Some(i) => i,
+            None => { self.folders.push(folder.to_string()); self.folders.len() - 1 }
+        }
+    }
+
+    fn class_path(&self, folder: usize, name: &ObjectIdentifier) -> PathBuf
+    {
+        let jni = name.as_jni_type();
+        self.root.join(&self.folders[folder]).join(format!("{}.smali", &jni[1..jni.len() - 1]))
+    }
+
+    /// Adds a new class to a smali folder, which is created if needed
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    ///  use std::path::Path;
+    ///  use smali::project::SmaliProject;
+    ///  use smali::types::SmaliClass;
+    ///
+    ///  let mut project = SmaliProject::open(Path::new("out"))?;
+    ///  let c = SmaliClass::from_smali(".class public Lcom/cool/Hook;\n.super Ljava/lang/Object;\n")?;
+    ///  project.add_class(c, "smali_classes3")?;
+    ///  project.save()?;
+    /// # Ok::<(), smali::types::SmaliError>(())
+    /// ```
+    pub fn add_class(&mut self, mut class: SmaliClass, folder: &str) -> Result<(), SmaliError>
+    {
+        let name = class.name.as_jni_type();
+        if self.classes.contains_key(&name)
+        {
+            return Err(SmaliError::Other(format!("{} is already in the project", name)));
+        }
+        let i = self.folder_index(folder);
+        class.file_path = Some(self.class_path(i, &class.name));
+        self.classes.insert(name, Entry { class, folder: i, state: State::New });
+        Ok(())
+    }
+
+    /// Removes a class, its file is deleted by [`SmaliProject::save`]
+    pub fn remove_class(&mut self, name: &ObjectIdentifier) -> Option<SmaliClass>
+    {
+        let e = self.classes.remove(&name.as_jni_type())?;
+        if let Some(p) = &e.class.file_path { self.deleted.push(p.clone()); }
+        Some(e.class)
+    }
+
+    /// Moves a class to another smali folder, i.e. to another DEX file
+    pub fn move_class(&mut self, name: &ObjectIdentifier, folder: &str) -> Result<(), SmaliError>
+    {
+        if !self.classes.contains_key(&name.as_jni_type())
+        {
+            return Err(SmaliError::Other(format!("{} is not in the project", name.as_jni_type())));
+        }
+        let i = self.folder_index(folder);
+        let path = self.class_path(i, name);
+        let e = self.classes.get_mut(&name.as_jni_type()).unwrap();
+        if e.folder == i { return Ok(()); }
+
+        if let Some(p) = e.class.file_path.replace(path) { self.deleted.push(p); }
+        e.folder = i;
+        e.state = State::New;
+        Ok(())
+    }
+
+    /// Applies renames to every class, see [`Renamer`]
+    ///
+    /// Renamed classes stay in their smali folder and are written to the file for their new name by
+    /// [`SmaliProject::save`], which deletes the old one. Nothing is changed if two classes would end up with the same
+    /// name, e.g. one renamed to the name of a class that's kept.
+    pub fn rename(&mut self, renamer: &Renamer) -> Result<(), Vec<Conflict>>
+    {
+        let mut names = BTreeSet::new();
+        let mut conflicts = vec![];
+        for e in self.classes.values()
+        {
+            let name = renamer.class(&e.class.name);
+            if !names.insert(name.as_jni_type()) && !conflicts.contains(&Conflict::ClassExists(name.clone()))
+            {
+                conflicts.push(Conflict::ClassExists(name));
+            }
+        }
+        if !conflicts.is_empty() { return Err(conflicts); }
+
+        // The renamer moves file paths too, but apktool doesn't always name files after their class
+        let mut paths: BTreeMap<String, PathBuf> = self.classes.iter()
+            .filter_map(|(name, e)| Some((name.clone(), e.class.file_path.clone()?)))
+            .collect();
+        renamer.apply(self.classes_mut());
+
+        for (old, mut e) in std::mem::take(&mut self.classes)
+        {
+            let name = e.class.name.as_jni_type();
+            if name != old
+            {
+                if let Some(p) = paths.remove(&old) { self.deleted.push(p); }
+                e.class.file_path = Some(self.class_path(e.folder, &e.class.name));
+                e.state = State::New;
+            }
+            self.classes.insert(name, e);
+        }
+        Ok(())
+    }
+
+    /// Writes every added, moved or changed class and deletes the files of removed ones, returning the number of
+    /// classes written
+    pub fn save(&mut self) -> Result<usize, SmaliError>
+    {
+        // A class can be removed then added back, so deletes go first. Files are only forgotten once they're deleted,
+        // so a failed save can be retried
+        while let Some(p) = self.deleted.first()
+        {
+            match fs::remove_file(p) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(SmaliError::Io { path: Some(p.clone()), error: e }),
+                _ => { self.deleted.remove(0); }
+            }
+        }
+
+        let mut written = 0;
+        let dirty = self.dirty_classes();
+        for name in dirty
+        {
+            let folder = self.classes[&name.as_jni_type()].folder;
+            let path = self.class_path(folder, &name);
+            let e = self.classes.get_mut(&name.as_jni_type()).unwrap();
+            let path = e.class.file_path.get_or_insert(path).clone();
+            if let Some(dir) = path.parent()
+            {
+                fs::create_dir_all(dir).map_err(|err| SmaliError::Io { path: Some(dir.to_path_buf()), error: err })?;
+            }
+            e.class.write_to_file(&path)?;
+            e.state = State::Clean;
+            written += 1;
+        }
+
+        // Classes that were looked at but not changed don't need hashing again
+        for e in self.classes.values_mut() { e.state = State::Clean; }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::project::{SmaliProject, State};
+    use crate::rename::{Conflict, Renamer};
+    use crate::types::ObjectIdentifier;
+
+    #[test]
+    fn project_dirty_tracking() {
+        let root = std::env::temp_dir().join(format!("smali-project-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (folder, file) in [("smali", "Request"), ("smali", "Response"), ("smali_classes2", "OkHttpClient")]
+        {
+            let dir = root.join(folder).join("okhttp3");
+            fs::create_dir_all(&dir).unwrap();
+            fs::copy(format!("tests/{}.smali", file), dir.join(format!("{}.smali", file))).unwrap();
+        }
+        fs::create_dir_all(root.join("res")).unwrap();
+
+        let request = ObjectIdentifier::from_java_type("okhttp3.Request");
+        let response = ObjectIdentifier::from_java_type("okhttp3.Response");
+        let client = ObjectIdentifier::from_java_type("okhttp3.OkHttpClient");
+        let mut project = SmaliProject::open(&root).unwrap();
+        assert_eq!(project.dex_folders(), ["smali", "smali_classes2"]);
+        assert_eq!(project.len(), 3);
+        assert_eq!(project.dex_folder_of(&client), Some("smali_classes2"));
+
+        // Looking at a class mutably doesn't make it dirty, changing it does
+        project.class_mut(&response).unwrap();
+        project.class_mut(&request).unwrap().source = Some("Changed.java".to_string());
+        assert_eq!(project.dirty_classes(), vec![request.clone()]);
+        assert_eq!(project.save().unwrap(), 1);
+        assert!(project.dirty_classes().is_empty());
+
+        assert!(project.move_class(&ObjectIdentifier::from_java_type("com.cool.Missing"), "smali_classes9").is_err());
+        assert_eq!(project.dex_folders(), ["smali", "smali_classes2"]);
+        project.move_class(&client, "smali_classes3").unwrap();
+        let removed = project.remove_class(&response).unwrap();
+        project.add_class(removed, "smali_classes2").unwrap();
+        assert_eq!(project.save().unwrap(), 2);
+        assert!(!root.join("smali/okhttp3/Response.smali").exists());
+        assert!(!root.join("smali_classes2/okhttp3/OkHttpClient.smali").exists());
+
+        let mut project = SmaliProject::open(&root).unwrap();
+        assert_eq!(project.dex_folders(), ["smali", "smali_classes2", "smali_classes3"]);
+        assert_eq!(project.class(&request).unwrap().source.as_deref(), Some("Changed.java"));
+        assert_eq!(project.dex_folder_of(&response), Some("smali_classes2"));
+        assert_eq!(project.classes_in("smali_classes3").count(), 1);
+        assert!(project.root() == Path::new(&root));
+
+        // Classes are only hashed once they're borrowed mutably
+        assert_eq!(project.classes_mut().filter(|c| c.name == client).count(), 1);
+        assert!(project.classes.values().all(|e| matches!(e.state, State::Clean)));
+
+        // Renamed classes move to the file for their new name, classes using them are written too
+        let renamed = ObjectIdentifier::from_java_type("com.cool.Req");
+        let mut renamer = Renamer::new();
+        renamer.rename_class(&request, &renamed);
+        project.rename(&renamer).unwrap();
+        assert!(project.class(&request).is_none());
+        assert_eq!(project.dex_folder_of(&renamed), Some("smali"));
+        assert_eq!(project.dirty_classes(), vec![renamed.clone(), client.clone(), response.clone()]);
+        assert_eq!(project.save().unwrap(), 3);
+        assert!(root.join("smali/com/cool/Req.smali").exists());
+        assert!(!root.join("smali/okhttp3/Request.smali").exists());
+
+        // Only the classes a rename changes are hashed
+        let mut renamer = Renamer::new();
+        renamer.rename_class(&ObjectIdentifier::from_java_type("com.cool.Unused"), &request);
+        project.rename(&renamer).unwrap();
+        assert!(project.classes.values().all(|e| matches!(e.state, State::Clean)));
+
+        // Renaming a class to the name of one that's kept changes nothing
+        let mut renamer = Renamer::new();
+        renamer.rename_class(&client, &response);
+        assert_eq!(project.rename(&renamer), Err(vec![Conflict::ClassExists(response.clone())]));
+        assert_eq!(project.len(), 3);
+        assert!(project.class(&client).is_some());
+        assert!(project.classes.values().all(|e| matches!(e.state, State::Clean)));
+
+        // Deletes that weren't done are kept for the next save
+        let blocked = root.join("blocked");
+        fs::create_dir_all(blocked.join("inside")).unwrap();
+        project.class_mut(&client).unwrap().file_path = Some(blocked.clone());
+        project.remove_class(&client).unwrap();
+        project.remove_class(&response).unwrap();
+        assert!(project.save().is_err());
+        fs::remove_dir_all(&blocked).unwrap();
+        assert!(root.join("smali_classes2/okhttp3/Response.smali").exists());
+        project.save().unwrap();
+        assert!(!root.join("smali_classes2/okhttp3/Response.smali").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
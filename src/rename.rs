@@ -0,0 +1,745 @@
+//! Renaming classes, fields and methods along with every reference to them
+//!
+//! A [`Renamer`] collects renames and then rewrites a set of classes: declarations, superclasses and interfaces,
+//! every type, field and method referenced by instructions, try blocks, debug information, annotations
+//! (including generic `Signature` annotations) and field initial values. References through a subclass, such as
+//! `invoke-virtual {v0}, Lcom/cool/Sub;->inherited()V`, are renamed too when the declaring class is in the set.
+//!
+//! Renamed classes get a new `file_path` alongside the old one, so `save` writes them to the right package folder.
+//!
+//! On a [`SmaliProject`], [`rename_class`](SmaliProject::rename_class), [`rename_field`](SmaliProject::rename_field)
+//! and [`rename_method`](SmaliProject::rename_method) also check the new name doesn't clash with an existing one, and
+//! rename a method together with everything overriding it. Nothing is changed if there are any [`Conflict`]s.
+//!
+//! # Examples
+//!
+//! ```
+//!  use smali::rename::Renamer;
+//!  use smali::types::{MethodRef, ObjectIdentifier, SmaliClass};
+//!
+//!  let mut classes = vec![
+//!      SmaliClass::from_smali(".class public La/a;\n.super Ljava/lang/Object;\n\n.method public static b()V\n    .locals 0\n    return-void\n.end method\n")?,
+//!      SmaliClass::from_smali(".class public La/c;\n.super La/a;\n\n.method public d()V\n    .locals 0\n    invoke-static {}, La/c;->b()V\n    return-void\n.end method\n")?
+//!  ];
+//!  let mut renamer = Renamer::new();
+//!  renamer.rename_class(&ObjectIdentifier::from_jni_type("La/a;"), &ObjectIdentifier::from_java_type("com.cool.Base"));
+//!  renamer.rename_method(&MethodRef::from_jni("La/a;->b()V"), "setUp");
+//!  renamer.apply(&mut classes);
+//!
+//!  assert_eq!(classes[0].name.as_java_type(), "com.cool.Base");
+//!  assert_eq!(classes[0].methods[0].name, "setUp");
+//!  assert!(classes[1].to_smali().contains(".super Lcom/cool/Base;"));
+//!  assert!(classes[1].to_smali().contains("invoke-static {}, La/c;->setUp()V"));
+//! # Ok::<(), smali::types::SmaliError>(())
+//! ```
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::ops::DerefMut;
+use std::path::{Path, PathBuf};
+use crate::hierarchy::{ClassHierarchy, ClassStub};
+use crate::instructions::Operand;
+use crate::project::SmaliProject;
+use crate::types::{EncodedValue, FieldRef, MethodHandle, MethodRef, MethodSignature, ObjectIdentifier, SmaliAnnotation,
+                   SmaliClass, SmaliInstruction, SmaliMethod, TypeSignature};
+
+/// A set of class, field and method renames to apply to classes
+///
+/// Fields and methods are identified by their declaring class, name and type, all as they are before renaming.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Renamer {
+    classes: HashMap<String, ObjectIdentifier>,
+    fields: HashMap<(String, String, String), String>,
+    methods: HashMap<(String, String, String), String>,
+    sources: HashMap<String, Option<String>>
+}
+
+impl Renamer {
+    pub fn new() -> Renamer
+    {
+        Renamer::default()
+    }
+
+    /// Are there no renames
+    pub fn is_empty(&self) -> bool
+    {
+        self.classes.is_empty() && self.fields.is_empty() && self.methods.is_empty() && self.sources.is_empty()
+    }
+
+    /// Renames a class
+    pub fn rename_class(&mut self, from: &ObjectIdentifier, to: &ObjectIdentifier)
+    {
+        self.classes.insert(from.as_jni_type(), to.clone());
+    }
+
+    /// Renames a field, given with the class declaring it
+    pub fn rename_field(&mut self, field: &FieldRef, name: &str)
+    {
+        self.fields.insert((field.class.as_jni_type(), field.name.clone(), field.signature.to_jni()), name.to_string());
+    }
+
+    /// Renames a method, given with the class declaring it
+    ///
+    /// Only that declaration is renamed, overriding methods need renaming as well to keep overriding them.
+    pub fn rename_method(&mut self, method: &MethodRef, name: &str)
+    {
+        self.methods.insert((method.class.to_jni(), method.name.clone(), method.signature.to_jni()), name.to_string());
+    }
+
+    /// Sets the `.source` of a class, given by its name before renaming
+    pub fn set_source(&mut self, class: &ObjectIdentifier, source: Option<&str>)
+    {
+        self.sources.insert(class.as_jni_type(), source.map(|s| s.to_string()));
+    }
+
+    /// The new name of a class
+    pub fn class(&self, c: &ObjectIdentifier) -> ObjectIdentifier
+    {
+        self.classes.get(&c.as_jni_type()).cloned().unwrap_or_else(|| c.clone())
+    }
+
+    /// A type with its classes renamed
+    pub fn type_signature(&self, t: &TypeSignature) -> TypeSignature
+    {
+        match t {
+            TypeSignature::Array(a) => TypeSignature::Array(Box::new(self.type_signature(a))),
+            TypeSignature::Object(o) => TypeSignature::Object(self.class(o)),
+            t => t.clone()
+        }
+    }
+
+    /// A method signature with its classes renamed
+    pub fn method_signature(&self, s: &MethodSignature) -> MethodSignature
+    {
+        MethodSignature { args: s.args.iter().map(|t| self.type_signature(t)).collect(), return_type: self.type_signature(&s.return_type) }
+    }
+
+    /// A generic signature, as used by `Signature` annotations and `.local`, with its classes renamed
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///  use smali::rename::Renamer;
+    ///  use smali::types::ObjectIdentifier;
+    ///
+    ///  let mut renamer = Renamer::new();
+    ///  renamer.rename_class(&ObjectIdentifier::from_jni_type("La/a;"), &ObjectIdentifier::from_jni_type("Lcom/cool/Item;"));
+    ///  assert_eq!(renamer.generic_signature("Ljava/util/Map<La/a;La/a<TT;>;>;"), "Ljava/util/Map<Lcom/cool/Item;Lcom/cool/Item<TT;>;>;");
+    /// ```
+    pub fn generic_signature(&self, s: &str) -> String
+    {
+        let mut out = String::new();
+        let mut rest = s;
+        let mut previous = None;
+        while let Some(c) = rest.chars().next()
+        {
+            // A class name starts with an L at the start or after a delimiter, and runs up to ; < or .
+            if c == 'L' && previous.is_none_or(|p| "<>;([):^*+-".contains(p))
+            {
+                let end = rest.find([';', '<', '.']).unwrap_or(rest.len());
+                let name = &rest[..end];
+                match self.classes.get(&format!("{};", name)) {
+                    Some(to) => { let to = to.as_jni_type(); out.push_str(&to[..to.len() - 1]); }
+                    None => out.push_str(name)
+                }
+                previous = name.chars().last();
+                rest = &rest[end..];
+                continue;
+            }
+            out.push(c);
+            previous = Some(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        out
+    }
+
+    /// Applies the renames to a set of classes, e.g. `&mut Vec<SmaliClass>`
+    ///
+    /// Classes not renamed themselves are still updated to use the new names.
+    pub fn apply<C: DerefMut<Target = SmaliClass>, I: IntoIterator<Item = C>>(&self, classes: I)
+    {
+        let mut classes: Vec<C> = classes.into_iter().collect();
+        let parents = classes.iter().map(|c| {
+            let supers = std::iter::once(&c.super_class).chain(&c.implements).map(|s| s.as_jni_type()).collect();
+            (c.name.as_jni_type(), supers)
+        }).collect();
+        let declared = classes.iter().flat_map(|c| {
+            let jni = c.name.as_jni_type();
+            c.fields.iter().map(|f| (jni.clone(), f.name.clone(), f.signature.to_jni()))
+                .chain(c.methods.iter().map(|m| (jni.clone(), m.name.clone(), m.signature.to_jni())))
+                .collect::<Vec<_>>()
+        }).collect();
+        let rewrite = Rewrite { renamer: self, parents, declared };
+        for c in classes.iter_mut() { rewrite.class(c); }
+    }
+}
+
+/* The new path of a class file that was at the path for its old name, e.g. out/smali/a/b.smali */
+fn moved_path(path: &Path, from: &ObjectIdentifier, to: &ObjectIdentifier) -> Option<PathBuf>
+{
+    let relative = |o: &ObjectIdentifier| { let jni = o.as_jni_type(); format!("{}.smali", &jni[1..jni.len() - 1]) };
+    let old = PathBuf::from(relative(from));
+    if !path.ends_with(&old) { return None; }
+    let root = path.ancestors().nth(old.components().count())?;
+    Some(root.join(relative(to)))
+}
+
+/* Applies a renamer, resolving member references through the superclasses and interfaces of the classes renamed */
+struct Rewrite<'a> {
+    renamer: &'a Renamer,
+    parents: HashMap<String, Vec<String>>,
+    declared: HashSet<(String, String, String)>
+}
+
+impl Rewrite<'_> {
+    /* Finds the new name of a member looking in a class then its ancestors, up to the first one declaring it */
+    fn member(&self, members: &HashMap<(String, String, String), String>, class: &str, name: &str, signature: &str) -> Option<String>
+    {
+        let mut pending = vec![class.to_string()];
+        let mut seen = vec![];
+        while let Some(c) = pending.pop()
+        {
+            let key = (c.clone(), name.to_string(), signature.to_string());
+            if let Some(to) = members.get(&key) { return Some(to.clone()); }
+            // A member declared again hides or overrides the one in its ancestors
+            if !self.declared.contains(&key)
+            {
+                if let Some(p) = self.parents.get(&c) { pending.extend(p.iter().rev().filter(|p| !seen.contains(*p)).cloned()); }
+            }
+            seen.push(c);
+        }
+        None
+    }
+
+    fn field(&self, f: &FieldRef) -> FieldRef
+    {
+        let name = self.member(&self.renamer.fields, &f.class.as_jni_type(), &f.name, &f.signature.to_jni()).unwrap_or_else(|| f.name.clone());
+        FieldRef { class: self.renamer.class(&f.class), name, signature: self.renamer.type_signature(&f.signature) }
+    }
+
+    fn method(&self, m: &MethodRef) -> MethodRef
+    {
+        let name = self.member(&self.renamer.methods, &m.class.to_jni(), &m.name, &m.signature.to_jni()).unwrap_or_else(|| m.name.clone());
+        MethodRef { class: self.renamer.type_signature(&m.class), name, signature: self.renamer.method_signature(&m.signature) }
+    }
+
+    fn method_handle(&self, h: &MethodHandle) -> MethodHandle
+    {
+        match h {
+            MethodHandle::StaticPut(f) => MethodHandle::StaticPut(self.field(f)),
+            MethodHandle::StaticGet(f) => MethodHandle::StaticGet(self.field(f)),
+            MethodHandle::InstancePut(f) => MethodHandle::InstancePut(self.field(f)),
+            MethodHandle::InstanceGet(f) => MethodHandle::InstanceGet(self.field(f)),
+            MethodHandle::InvokeStatic(m) => MethodHandle::InvokeStatic(self.method(m)),
+            MethodHandle::InvokeInstance(m) => MethodHandle::InvokeInstance(self.method(m)),
+            MethodHandle::InvokeConstructor(m) => MethodHandle::InvokeConstructor(self.method(m)),
+            MethodHandle::InvokeDirect(m) => MethodHandle::InvokeDirect(self.method(m)),
+            MethodHandle::InvokeInterface(m) => MethodHandle::InvokeInterface(self.method(m))
+        }
+    }
+
+    /* Renames the references in a value, strings are left alone apart from generic signatures */
+    fn value(&self, v: &EncodedValue, signature: bool) -> EncodedValue
+    {
+        match v {
+            EncodedValue::String(s) if signature => EncodedValue::String(self.renamer.generic_signature(s)),
+            EncodedValue::Type(t) => EncodedValue::Type(self.renamer.type_signature(t)),
+            EncodedValue::Field(f) => EncodedValue::Field(self.field(f)),
+            EncodedValue::Enum(f) => EncodedValue::Enum(self.field(f)),
+            EncodedValue::Method(m) => EncodedValue::Method(self.method(m)),
+            EncodedValue::MethodType(p) => EncodedValue::MethodType(self.renamer.method_signature(p)),
+            EncodedValue::MethodHandle(h) => EncodedValue::MethodHandle(self.method_handle(h)),
+            EncodedValue::Array(items) => EncodedValue::Array(items.iter().map(|i| self.value(i, signature)).collect()),
+            EncodedValue::Annotation(a) => EncodedValue::Annotation(self.annotation(a)),
+            v => v.clone()
+        }
+    }
+
+    fn annotation(&self, a: &SmaliAnnotation) -> SmaliAnnotation
+    {
+        let signature = a.annotation_type.to_jni() == "Ldalvik/annotation/Signature;";
+        let mut renamed = a.clone();
+        renamed.annotation_type = self.renamer.type_signature(&a.annotation_type);
+        renamed.elements.iter_mut().for_each(|e| e.value = self.value(&e.value, signature));
+        renamed
+    }
+
+    /* The renamed annotations, if any of them change */
+    fn annotations(&self, annotations: &[SmaliAnnotation]) -> Option<Vec<SmaliAnnotation>>
+    {
+        let renamed: Vec<SmaliAnnotation> = annotations.iter().map(|a| self.annotation(a)).collect();
+        if renamed.as_slice() != annotations { Some(renamed) } else { None }
+    }
+
+    /* The renamed instruction, if it changes */
+    fn instruction(&self, i: &SmaliInstruction) -> Option<SmaliInstruction>
+    {
+        match i {
+            SmaliInstruction::Instruction(d) if d.opcode().reference_kind().is_some() => {
+                let operands = d.operands().into_iter().map(|o| match o {
+                    Operand::Type(t) => Operand::Type(self.renamer.type_signature(&t)),
+                    Operand::Field(f) => Operand::Field(self.field(&f)),
+                    Operand::Method(m) => Operand::Method(self.method(&m)),
+                    Operand::Proto(p) => Operand::Proto(self.renamer.method_signature(&p)),
+                    Operand::MethodHandle(h) => Operand::MethodHandle(self.method_handle(&h)),
+                    o => o
+                }).collect();
+                crate::instructions::DexInstruction::from_operands(d.opcode(), operands)
+                    .filter(|renamed| renamed != d)
+                    .map(SmaliInstruction::Instruction)
+            }
+            SmaliInstruction::TryCatch { exception: Some(e), .. } => {
+                let to = self.renamer.class(e);
+                if to == *e { return None; }
+                let mut renamed = i.clone();
+                if let SmaliInstruction::TryCatch { exception, .. } = &mut renamed { *exception = Some(to); }
+                Some(renamed)
+            }
+            SmaliInstruction::Local { signature, generic, .. } => {
+                let s = signature.as_ref().map(|s| self.renamer.type_signature(s));
+                let g = generic.as_ref().map(|g| self.renamer.generic_signature(g));
+                if s == *signature && g == *generic { return None; }
+                let mut renamed = i.clone();
+                if let SmaliInstruction::Local { signature, generic, .. } = &mut renamed { *signature = s; *generic = g; }
+                Some(renamed)
+            }
+            _ => None
+        }
+    }
+
+    /* Renames the references in a class, only borrowing it mutably for the parts that change so a
+       SmaliProject knows which classes to check when saving */
+    fn class<C: DerefMut<Target = SmaliClass>>(&self, c: &mut C)
+    {
+        let jni = c.name.as_jni_type();
+        for i in 0..c.fields.len()
+        {
+            let f = &c.fields[i];
+            let name = self.renamer.fields.get(&(jni.clone(), f.name.clone(), f.signature.to_jni())).cloned();
+            let signature = self.renamer.type_signature(&f.signature);
+            let value = f.initial_value.as_ref().map(|v| self.value(v, false));
+            let annotations = self.annotations(&f.annotations);
+            if name.is_none() && signature == f.signature && value == f.initial_value && annotations.is_none() { continue; }
+
+            let f = &mut c.fields[i];
+            if let Some(name) = name { f.name = name; }
+            f.signature = signature;
+            f.initial_value = value;
+            if let Some(a) = annotations { f.annotations = a; }
+        }
+        for i in 0..c.methods.len()
+        {
+            let m = &c.methods[i];
+            let name = self.renamer.methods.get(&(jni.clone(), m.name.clone(), m.signature.to_jni())).cloned();
+            let signature = self.renamer.method_signature(&m.signature);
+            if name.is_some() || signature != m.signature
+            {
+                let m = &mut c.methods[i];
+                if let Some(name) = name { m.name = name; }
+                m.signature = signature;
+            }
+            if let Some(a) = self.annotations(&c.methods[i].annotations) { c.methods[i].annotations = a; }
+            for p in 0..c.methods[i].params.len()
+            {
+                if let Some(a) = self.annotations(&c.methods[i].params[p].annotations) { c.methods[i].params[p].annotations = a; }
+            }
+            for n in 0..c.methods[i].instructions.len()
+            {
+                if let Some(renamed) = self.instruction(&c.methods[i].instructions[n]) { c.methods[i].instructions[n] = renamed; }
+            }
+        }
+        if let Some(a) = self.annotations(&c.annotations) { c.annotations = a; }
+        if let Some(source) = self.renamer.sources.get(&jni)
+        {
+            if c.source != *source { c.source = source.clone(); }
+        }
+
+        let to = self.renamer.class(&c.name);
+        if to != c.name
+        {
+            // Keep the simple name reflection sees in step with the class name
+            let simple = |o: &ObjectIdentifier| { let jni = o.as_jni_type(); jni[jni.rfind(['/', '$']).map_or(1, |i| i + 1)..jni.len() - 1].to_string() };
+            let old = EncodedValue::String(simple(&c.name));
+            for a in c.annotations.iter_mut().filter(|a| a.annotation_type.to_jni() == "Ldalvik/annotation/InnerClass;")
+            {
+                for e in a.elements.iter_mut().filter(|e| e.name == "name")
+                {
+                    if e.value == old { e.value = EncodedValue::String(simple(&to)); }
+                }
+            }
+            if let Some(p) = c.file_path.as_ref().and_then(|p| moved_path(p, &c.name, &to)) { c.file_path = Some(p); }
+            c.name = to;
+        }
+        let super_class = self.renamer.class(&c.super_class);
+        if super_class != c.super_class { c.super_class = super_class; }
+        let implements: Vec<ObjectIdentifier> = c.implements.iter().map(|i| self.renamer.class(i)).collect();
+        if implements != c.implements { c.implements = implements; }
+    }
+}
+
+/// Why a rename on a [`SmaliProject`] can't be done
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflict {
+    /// The class, field or method to rename isn't in the project
+    NotFound(String),
+    /// There is already a class with the new name
+    ClassExists(ObjectIdentifier),
+    /// A field with the new name is declared by the class, or would hide or be hidden by one in a related class
+    FieldExists(FieldRef),
+    /// A method with the new name and signature is declared by a class that would then override or clash with it
+    MethodExists(MethodRef),
+    /// The method overrides or implements one declared outside the project, e.g. by the Android framework, or might
+    /// because one of its supertypes isn't in the project
+    External(MethodRef),
+    /// Constructors and static initialisers can't be renamed
+    SpecialMethod(MethodRef)
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Conflict::NotFound(s) => write!(f, "{} not found", s),
+            Conflict::ClassExists(c) => write!(f, "class {} already exists", c.as_jni_type()),
+            Conflict::FieldExists(x) => write!(f, "field {} already exists", x.to_jni()),
+            Conflict::MethodExists(m) => write!(f, "method {} already exists", m.to_jni()),
+            Conflict::External(m) => write!(f, "method {} overrides a method outside the project", m.to_jni()),
+            Conflict::SpecialMethod(m) => write!(f, "method {} can't be renamed", m.to_jni())
+        }
+    }
+}
+
+/* The method a class declares with a name and signature */
+fn declared<'a>(c: &'a SmaliClass, name: &str, signature: &MethodSignature) -> Option<&'a SmaliMethod>
+{
+    c.methods.iter().find(|m| m.name == name && m.signature == *signature)
+}
+
+fn method_ref(class: &ObjectIdentifier, name: &str, signature: &MethodSignature) -> MethodRef
+{
+    MethodRef { class: TypeSignature::Object(class.clone()), name: name.to_string(), signature: signature.clone() }
+}
+
+/* Can the method be overridden, i.e. it's an instance method that isn't private or a constructor */
+fn is_virtual(m: &SmaliMethod) -> bool
+{
+    !m.is_static() && !m.constructor && !m.modifiers.contains(&crate::types::Modifier::Private)
+}
+
+impl SmaliProject {
+    /// Renames a class and the classes nested in it, e.g. `La/b$c;` along with `La/b;`, returning their new names with the class first
+    pub fn rename_class(&mut self, from: &ObjectIdentifier, to: &ObjectIdentifier) -> Result<Vec<ObjectIdentifier>, Vec<Conflict>>
+    {
+        if self.class(from).is_none() { return Err(vec![Conflict::NotFound(from.as_jni_type())]); }
+        if from == to { return Ok(vec![]); }
+        let (from_jni, to_jni) = (from.as_jni_type(), to.as_jni_type());
+        let nested = format!("{}$", &from_jni[..from_jni.len() - 1]);
+
+        let mut renamer = Renamer::new();
+        let mut renamed = vec![];
+        let mut conflicts = vec![];
+        for c in self.classes().filter(|c| c.name == *from || c.name.as_jni_type().starts_with(&nested))
+        {
+            let new = ObjectIdentifier::from_jni_type(&format!("{}{}", &to_jni[..to_jni.len() - 1], &c.name.as_jni_type()[from_jni.len() - 1..]));
+            if self.class(&new).is_some() { conflicts.push(Conflict::ClassExists(new.clone())); }
+            renamer.rename_class(&c.name, &new);
+            renamed.push(new);
+        }
+        if !conflicts.is_empty() { return Err(conflicts); }
+        self.rename(&renamer)?;
+        renamed.sort_by_key(|n| n != to);
+        Ok(renamed)
+    }
+
+    /// Renames a field, given with the class declaring it
+    ///
+    /// A field with the new name in the class, its superclasses or subclasses is a conflict as references through a
+    /// subclass would find a different field.
+    pub fn rename_field(&mut self, field: &FieldRef, name: &str) -> Result<(), Vec<Conflict>>
+    {
+        let found = self.class(&field.class).is_some_and(|c| c.fields.iter().any(|f| f.name == field.name && f.signature == field.signature));
+        if !found { return Err(vec![Conflict::NotFound(field.to_jni())]); }
+        if field.name == name { return Ok(()); }
+
+        let hierarchy = ClassHierarchy::new(self.classes());
+        let mut conflicts = vec![];
+        let related = std::iter::once(field.class.clone()).chain(hierarchy.supertypes(&field.class)).chain(hierarchy.subtypes(&field.class));
+        for c in related.filter_map(|c| hierarchy.class(&c))
+        {
+            for f in c.fields.iter().filter(|f| f.name == name && (c.name == field.class || f.signature == field.signature))
+            {
+                conflicts.push(Conflict::FieldExists(FieldRef { class: c.name.clone(), name: f.name.clone(), signature: f.signature.clone() }));
+            }
+        }
+        if !conflicts.is_empty() { return Err(conflicts); }
+
+        let mut renamer = Renamer::new();
+        renamer.rename_field(field, name);
+        self.rename(&renamer)?;
+        Ok(())
+    }
+
+    /// Renames a method, given with the class declaring it, along with every method it overrides or that overrides it
+    ///
+    /// Returns the methods renamed. Overriding is followed through superclasses and interfaces in both directions,
+    /// including an interface method implemented by an inherited method. Methods of classes outside the project
+    /// can't be renamed, so overriding one of the `java.lang` methods known to [`ClassHierarchy`] is a conflict, as is
+    /// a virtual method of a class with a supertype that isn't in the project, since it might override one there. Use
+    /// [`rename_method_with`](SmaliProject::rename_method_with) to supply stubs for framework and library classes.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    ///  use std::path::Path;
+    ///  use smali::project::SmaliProject;
+    ///  use smali::types::{MethodRef, ObjectIdentifier};
+    ///
+    ///  let mut project = SmaliProject::open(Path::new("out"))?;
+    ///  project.rename_class(&ObjectIdentifier::from_jni_type("La/b;"), &ObjectIdentifier::from_java_type("com.cool.RootCheck")).unwrap();
+    ///  match project.rename_method(&MethodRef::from_jni("Lcom/cool/RootCheck;->a()Z"), "isRooted") {
+    ///      Ok(renamed) => println!("Renamed {} methods", renamed.len()),
+    ///      Err(conflicts) => for c in conflicts { println!("{}", c); }
+    ///  }
+    ///  project.save()?;
+    /// # Ok::<(), smali::types::SmaliError>(())
+    /// ```
+    pub fn rename_method(&mut self, method: &MethodRef, name: &str) -> Result<Vec<MethodRef>, Vec<Conflict>>
+    {
+        self.rename_method_with(method, name, |_| None)
+    }
+
+    /// Renames a method like [`rename_method`](SmaliProject::rename_method), asking `stubs` for the supertypes that
+    /// aren't in the project, as [`ClassHierarchy::resolve_stubs`] does
+    ///
+    /// A stub's own supertypes are asked for too, so the stubs should reach `java.lang.Object` for methods of the
+    /// classes extending them to be renamed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    ///  use std::path::Path;
+    ///  use smali::hierarchy::ClassStub;
+    ///  use smali::project::SmaliProject;
+    ///  use smali::types::{MethodRef, MethodSignature, ObjectIdentifier};
+    ///
+    ///  let mut project = SmaliProject::open(Path::new("out"))?;
+    ///  let renamed = project.rename_method_with(&MethodRef::from_jni("Lcom/cool/MainActivity;->a()Z"), "isRooted", |c| {
+    ///      let mut stub = ClassStub::new(c.clone(), Some(ObjectIdentifier::from_java_type("java.lang.Object")));
+    ///      if c.as_java_type() == "android.app.Activity"
+    ///      {
+    ///          stub.methods.push(("onCreate".to_string(), MethodSignature::from_jni("(Landroid/os/Bundle;)V")));
+    ///      }
+    ///      Some(stub)
+    ///  });
+    ///  assert!(renamed.is_ok());
+    /// # Ok::<(), smali::types::SmaliError>(())
+    /// ```
+    pub fn rename_method_with<F: Fn(&ObjectIdentifier) -> Option<ClassStub>>(&mut self, method: &MethodRef, name: &str, stubs: F) -> Result<Vec<MethodRef>, Vec<Conflict>>
+    {
+        let TypeSignature::Object(class) = &method.class else { return Err(vec![Conflict::NotFound(method.to_jni())]) };
+        let Some(m) = self.class(class).and_then(|c| declared(c, &method.name, &method.signature)) else {
+            return Err(vec![Conflict::NotFound(method.to_jni())]);
+        };
+        if m.constructor || m.name == "<clinit>" { return Err(vec![Conflict::SpecialMethod(method.clone())]); }
+        if method.name == name { return Ok(vec![]); }
+        let signature = &method.signature;
+        let mut hierarchy = ClassHierarchy::new(self.classes());
+        hierarchy.resolve_stubs(stubs);
+
+        // Every class declaring the method in the same family of overrides
+        let mut group = BTreeSet::from([class.as_jni_type()]);
+        let mut conflicts = vec![];
+        if is_virtual(m)
+        {
+            let mut pending = vec![class.clone()];
+            while let Some(c) = pending.pop()
+            {
+                for s in std::iter::once(c.clone()).chain(hierarchy.subtypes(&c))
+                {
+                    for t in std::iter::once(s.clone()).chain(hierarchy.supertypes(&s))
+                    {
+                        match hierarchy.class(&t) {
+                            Some(x) if declared(x, &method.name, signature).is_some_and(is_virtual) && group.insert(t.as_jni_type()) => pending.push(t),
+                            // A supertype missing from the project and its stubs might declare the method too
+                            None if !hierarchy.contains(&t) || hierarchy.resolve_method(&t, &method.name, signature).is_some_and(|r| r.method.is_none()) => {
+                                let external = Conflict::External(method_ref(&t, &method.name, signature));
+                                if !conflicts.contains(&external) { conflicts.push(external); }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        // The new name mustn't be declared by any of them, or by a class they'd then override, hide or be confused with
+        let mut clashes = BTreeSet::new();
+        for c in group.iter().map(|c| ObjectIdentifier::from_jni_type(c))
+        {
+            let related = std::iter::once(c.clone()).chain(hierarchy.supertypes(&c)).chain(hierarchy.subtypes(&c));
+            for t in related
+            {
+                let exists = match hierarchy.class(&t) {
+                    Some(x) => declared(x, name, signature).is_some(),
+                    None => hierarchy.resolve_method(&t, name, signature).is_some_and(|r| r.class == t)
+                };
+                if exists && clashes.insert(t.as_jni_type()) { conflicts.push(Conflict::MethodExists(method_ref(&t, name, signature))); }
+            }
+        }
+        if !conflicts.is_empty() { return Err(conflicts); }
+
+        let mut renamer = Renamer::new();
+        let renamed: Vec<MethodRef> = group.iter().map(|c| method_ref(&ObjectIdentifier::from_jni_type(c), &method.name, signature)).collect();
+        for m in &renamed { renamer.rename_method(m, name); }
+        self.rename(&renamer)?;
+        Ok(renamed.into_iter().map(|m| MethodRef { name: name.to_string(), ..m }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use crate::find_smali_files;
+    use crate::hierarchy::ClassStub;
+    use crate::project::SmaliProject;
+    use crate::rename::{Conflict, Renamer};
+    use crate::types::{FieldRef, MethodRef, MethodSignature, ObjectIdentifier, SmaliClass};
+
+    #[test]
+    fn rename_references() {
+        let mut classes = find_smali_files(Path::new("tests")).unwrap();
+        let before: Vec<String> = classes.iter().map(|c| c.to_smali()).collect();
+        let request = ObjectIdentifier::from_jni_type("Lokhttp3/Request;");
+        let c = classes.iter_mut().find(|c| c.name == request).unwrap();
+        c.file_path = Some(PathBuf::from("out/smali/okhttp3/Request.smali"));
+
+        let mut renamer = Renamer::new();
+        renamer.rename_class(&request, &ObjectIdentifier::from_jni_type("Lcom/cool/Req;"));
+        renamer.rename_field(&FieldRef::from_jni("Lokhttp3/Request;->url:Lokhttp3/HttpUrl;"), "address");
+        renamer.rename_method(&MethodRef::from_jni("Lokhttp3/Request;->url()Lokhttp3/HttpUrl;"), "address");
+        renamer.apply(&mut classes);
+
+        let renamed = classes.iter().find(|c| c.name.as_jni_type() == "Lcom/cool/Req;").unwrap();
+        assert_eq!(renamed.file_path.as_deref(), Some(Path::new("out/smali/com/cool/Req.smali")));
+        let text = renamed.to_smali();
+        assert!(text.contains(".field private final address:Lokhttp3/HttpUrl;"));
+        assert!(text.contains(".method public final address()Lokhttp3/HttpUrl;"));
+        assert!(text.contains("iget-object v0, p0, Lcom/cool/Req;->address:Lokhttp3/HttpUrl;"));
+        // Only Kotlin metadata strings still hold the old name
+        for c in &classes
+        {
+            let text = c.to_smali();
+            assert!(text.lines().all(|l| !l.contains("Lokhttp3/Request;") || l.trim().starts_with('"')), "{}", c.name.as_jni_type());
+        }
+
+        // Renaming back gives the classes as they were
+        let mut back = Renamer::new();
+        back.rename_class(&ObjectIdentifier::from_jni_type("Lcom/cool/Req;"), &request);
+        back.rename_field(&FieldRef::from_jni("Lcom/cool/Req;->address:Lokhttp3/HttpUrl;"), "url");
+        back.rename_method(&MethodRef::from_jni("Lcom/cool/Req;->address()Lokhttp3/HttpUrl;"), "url");
+        back.apply(&mut classes);
+        assert_eq!(classes.iter().map(|c| c.to_smali()).collect::<Vec<String>>(), before);
+    }
+
+    #[test]
+    fn rename_hidden_members() {
+        let mut classes = vec![
+            SmaliClass::from_smali(".class public La/Base;\n.super Ljava/lang/Object;\n\n.field public count:I\n\n\
+                                    .method public static make()V\n    .locals 0\n    return-void\n.end method\n").unwrap(),
+            SmaliClass::from_smali(".class public La/Sub;\n.super La/Base;\n\n.field public count:I\n\n\
+                                    .method public static make()V\n    .locals 0\n    return-void\n.end method\n").unwrap(),
+            SmaliClass::from_smali(".class public La/User;\n.super Ljava/lang/Object;\n\n\
+                                    .method public static use(La/Sub;La/Base;)V\n    .locals 1\n\
+                                    \x20   iget v0, p0, La/Sub;->count:I\n    iget v0, p1, La/Base;->count:I\n\
+                                    \x20   invoke-static {}, La/Sub;->make()V\n    invoke-static {}, La/Base;->make()V\n\
+                                    \x20   return-void\n.end method\n").unwrap()
+        ];
+        let mut renamer = Renamer::new();
+        renamer.rename_field(&FieldRef::from_jni("La/Base;->count:I"), "total");
+        renamer.rename_method(&MethodRef::from_jni("La/Base;->make()V"), "create");
+        renamer.apply(&mut classes);
+
+        // Sub declares its own count and make, so references through it aren't Base's
+        let text = classes[2].to_smali();
+        assert!(text.contains("iget v0, p0, La/Sub;->count:I"));
+        assert!(text.contains("iget v0, p1, La/Base;->total:I"));
+        assert!(text.contains("invoke-static {}, La/Sub;->make()V"));
+        assert!(text.contains("invoke-static {}, La/Base;->create()V"));
+        assert!(classes[1].to_smali().contains(".field public count:I"));
+    }
+
+    #[test]
+    fn project_renames() {
+        let root = std::env::temp_dir().join(format!("smali-rename-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("smali/a")).unwrap();
+        let run = ".method public run()V\n    .locals 0\n    return-void\n.end method\n";
+        let files = [
+            ("Base", format!(".class public La/Base;\n.super Ljava/lang/Object;\n\n.field protected count:I\n\n{}\n\
+                              .method public toString()Ljava/lang/String;\n    .locals 1\n    const-string v0, \"base\"\n    return-object v0\n.end method\n\n\
+                              .method private static helper()V\n    .locals 0\n    return-void\n.end method\n", run)),
+            ("Base$Inner", ".class La/Base$Inner;\n.super Ljava/lang/Object;\n".to_string()),
+            ("Task", ".class public interface abstract La/Task;\n.super Ljava/lang/Object;\n\n.method public abstract run()V\n.end method\n".to_string()),
+            ("Impl", format!(".class public La/Impl;\n.super La/Base;\n.implements La/Task;\n\n.field private size:I\n\n{}\n\
+                              .method public stop()V\n    .locals 0\n    return-void\n.end method\n\n\
+                              .method private static tidy()V\n    .locals 0\n    return-void\n.end method\n", run)),
+            ("Main", ".class public La/Main;\n.super Landroid/app/Activity;\n\n\
+                      .method protected onCreate(Landroid/os/Bundle;)V\n    .locals 0\n    return-void\n.end method\n\n\
+                      .method public a()Z\n    .locals 1\n    const/4 v0, 0x0\n    return v0\n.end method\n".to_string()),
+            ("Other", format!(".class public La/Other;\n.super Ljava/lang/Object;\n.implements La/Task;\n\n{}", run)),
+            ("User", ".class public La/User;\n.super Ljava/lang/Object;\n\n.method public static use(La/Impl;La/Task;)I\n    .locals 1\n\
+                      \x20   invoke-virtual {p0}, La/Impl;->run()V\n    invoke-interface {p1}, La/Task;->run()V\n\
+                      \x20   iget v0, p0, La/Impl;->count:I\n    return v0\n.end method\n".to_string())
+        ];
+        for (name, text) in files { fs::write(root.join(format!("smali/a/{}.smali", name)), text).unwrap(); }
+        let mut project = SmaliProject::open(&root).unwrap();
+        let class = ObjectIdentifier::from_jni_type;
+
+        // Conflicts leave the project unchanged
+        assert_eq!(project.rename_method(&MethodRef::from_jni("La/Base;->run()V"), "stop"),
+                   Err(vec![Conflict::MethodExists(MethodRef::from_jni("La/Impl;->stop()V"))]));
+        assert_eq!(project.rename_method(&MethodRef::from_jni("La/Base;->toString()Ljava/lang/String;"), "describe"),
+                   Err(vec![Conflict::External(MethodRef::from_jni("Ljava/lang/Object;->toString()Ljava/lang/String;"))]));
+        assert_eq!(project.rename_method(&MethodRef::from_jni("La/Main;->onCreate(Landroid/os/Bundle;)V"), "setUp"),
+                   Err(vec![Conflict::External(MethodRef::from_jni("Landroid/app/Activity;->onCreate(Landroid/os/Bundle;)V"))]));
+        // With a stub for the missing superclass only the methods it declares are external
+        let activity = |c: &ObjectIdentifier| (c.as_jni_type() == "Landroid/app/Activity;").then(|| {
+            let mut stub = ClassStub::new(c.clone(), Some(ObjectIdentifier::from_java_type("java.lang.Object")));
+            stub.methods.push(("onCreate".to_string(), MethodSignature::from_jni("(Landroid/os/Bundle;)V")));
+            stub
+        });
+        assert_eq!(project.rename_method_with(&MethodRef::from_jni("La/Main;->onCreate(Landroid/os/Bundle;)V"), "setUp", activity),
+                   Err(vec![Conflict::External(MethodRef::from_jni("Landroid/app/Activity;->onCreate(Landroid/os/Bundle;)V"))]));
+        assert_eq!(project.rename_method(&MethodRef::from_jni("La/Main;->a()Z"), "isRooted"),
+                   Err(vec![Conflict::External(MethodRef::from_jni("Landroid/app/Activity;->a()Z"))]));
+        // Static and private methods in superclasses and subclasses clash too
+        assert_eq!(project.rename_method(&MethodRef::from_jni("La/Impl;->stop()V"), "helper"),
+                   Err(vec![Conflict::MethodExists(MethodRef::from_jni("La/Base;->helper()V"))]));
+        assert_eq!(project.rename_method(&MethodRef::from_jni("La/Base;->helper()V"), "tidy"),
+                   Err(vec![Conflict::MethodExists(MethodRef::from_jni("La/Impl;->tidy()V"))]));
+        assert_eq!(project.rename_field(&FieldRef::from_jni("La/Base;->count:I"), "size"),
+                   Err(vec![Conflict::FieldExists(FieldRef::from_jni("La/Impl;->size:I"))]));
+        assert_eq!(project.rename_class(&class("La/Impl;"), &class("La/Other;")), Err(vec![Conflict::ClassExists(class("La/Other;"))]));
+        assert!(matches!(project.rename_method(&MethodRef::from_jni("La/User;->missing()V"), "x").unwrap_err()[..], [Conflict::NotFound(_)]));
+        assert!(project.dirty_classes().is_empty());
+
+        // Overrides are renamed together, linked through the interface
+        let renamed = project.rename_method(&MethodRef::from_jni("La/Other;->run()V"), "go").unwrap();
+        assert_eq!(renamed.iter().map(|m| m.to_jni()).collect::<Vec<String>>(), ["La/Base;->go()V", "La/Impl;->go()V", "La/Other;->go()V", "La/Task;->go()V"]);
+        assert_eq!(project.rename_method_with(&MethodRef::from_jni("La/Main;->a()Z"), "isRooted", activity).unwrap(),
+                   [MethodRef::from_jni("La/Main;->isRooted()Z")]);
+        project.rename_field(&FieldRef::from_jni("La/Base;->count:I"), "total").unwrap();
+        let renamed = project.rename_class(&class("La/Base;"), &class("Lcom/cool/Base;")).unwrap();
+        assert_eq!(renamed, [class("Lcom/cool/Base;"), class("Lcom/cool/Base$Inner;")]);
+
+        let user = project.class(&class("La/User;")).unwrap().to_smali();
+        assert!(user.contains("invoke-virtual {p0}, La/Impl;->go()V") && user.contains("invoke-interface {p1}, La/Task;->go()V"));
+        assert!(user.contains("iget v0, p0, La/Impl;->total:I"));
+        assert!(project.class(&class("La/Impl;")).unwrap().to_smali().contains(".super Lcom/cool/Base;"));
+        assert_eq!(project.save().unwrap(), 7);
+        assert!(root.join("smali/com/cool/Base$Inner.smali").exists() && !root.join("smali/a/Base.smali").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
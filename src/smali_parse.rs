@@ -1,19 +1,21 @@
 
-use nom::bytes::complete::{escaped, is_not, tag, take_while};
+use nom::bytes::complete::{escaped, tag, take_while, take_while1};
 use nom::branch::{ alt };
-use nom::character::complete::{alphanumeric1, char, multispace0, multispace1, newline, none_of, not_line_ending, one_of};
-use nom::combinator::value;
+use nom::character::complete::{alphanumeric1, char, digit1, hex_digit1, line_ending, multispace0, multispace1, newline, none_of, not_line_ending, one_of, space0, space1};
+use nom::combinator::{eof, map, opt, value};
 use nom::Err::Failure;
-use nom::error::{Error, ErrorKind, ParseError};
+use nom::error::{Error, ErrorKind};
 use nom::{Err, IResult};
-use nom::multi::{many0};
-use nom::sequence::{delimited, pair};
+use nom::multi::{many0, separated_list0};
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
+use crate::instructions::*;
 use crate::types::*;
+use crate::smali_write::{class_members, hash_member};
 
 
-fn ws<'a, F: 'a, O>(inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, O>
+fn ws<'a, F, O>(inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, O>
     where
-        F: Fn(&'a str) -> IResult<&'a str, O>,
+        F: 'a + Fn(&'a str) -> IResult<&'a str, O>,
 {
     delimited(
         multispace0,
@@ -38,9 +40,9 @@ pub fn comment(i: &str) -> IResult<&str, ()>
 {
     let (v, _) = value(
         (), // Output is thrown away.
-        pair(char('#'), is_not("\n\r"))
+        pair(char('#'), take_while(|c| c != '\n' && c != '\r'))
     )(i)?;
-    let (o, _) = newline(v)?;
+    let (o, _) = alt((line_ending, eof))(v)?;
     IResult::Ok((o, ()))
 }
 
@@ -51,22 +53,19 @@ pub fn blank_line(i: &str) -> IResult<&str, &str>
 
 fn parse_modifiers(smali: &str) -> IResult<&str, Vec<Modifier>>
 {
-    let (input, mods) = many0(
-                                        alt(( ws(tag("public ")),
-                                              ws(tag("protected ")),
-                                              ws(tag("private ")),
-                                              ws(tag("static ")),
-                                              ws(tag("final ")),
-                                              ws(tag("abstract ")),
-                                              ws(tag("synthetic ")),
-                                              ws(tag("transient ")),
-                                              ws(tag("volatile ")),
-                                              ws(tag("synchronized ")),
-                                              ws(tag("native ")),
-                                              ws(tag("varargs "))
-                                        )))(smali)?;
+    let mut input = smali;
     let mut v = vec![];
-    for m in mods { v.push(Modifier::from_str(m.trim())); }
+
+    // Modifiers are lower case words followed by a space, anything else is the start of the name
+    while let IResult::Ok((o, m)) = preceded(space0::<&str, Error<&str>>, terminated(take_while1(|c: char| c.is_ascii_lowercase() || c == '-'), space1))(input)
+    {
+        match m.parse::<Modifier>() {
+            Ok(m) => v.push(m),
+            Err(_) => return IResult::Err(Failure(Error { input: input.trim_start(), code: ErrorKind::Tag }))
+        }
+        input = o;
+    }
+    let (input, _) = space0(input)?;
     IResult::Ok((input, v))
 }
 
@@ -74,7 +73,8 @@ fn parse_visibility(smali: &str) -> IResult<&str, AnnotationVisibility>
 {
     let (input, visibility) =
         alt(( ws(tag("system")),
-              ws(tag("runtime"))
+              ws(tag("runtime")),
+              ws(tag("build"))
         ))(smali)?;
     IResult::Ok((input, AnnotationVisibility::from_str(visibility)))
 }
@@ -86,7 +86,7 @@ fn take_until_eol(s: &str) -> IResult<&str, &str> {
     IResult::Ok((input, r))
 }
 
-fn parse_typesignature(smali: &str) -> IResult<&str, TypeSignature>
+pub(crate) fn parse_typesignature(smali: &str) -> IResult<&str, TypeSignature>
 {
 
     // Object
@@ -100,7 +100,7 @@ fn parse_typesignature(smali: &str) -> IResult<&str, TypeSignature>
     }
     // Array
     let b:IResult<&str, &str> = tag("[")(smali);
-    if let IResult::Ok((o, t)) = b
+    if let IResult::Ok((o, _)) = b
     {
         let (o, t) = parse_typesignature(o)?;
         return IResult::Ok((o, TypeSignature::Array(Box::new(t))))
@@ -109,7 +109,7 @@ fn parse_typesignature(smali: &str) -> IResult<&str, TypeSignature>
     let p:IResult<&str, &str> = alt((tag("Z"), tag("B"), tag("C"), tag("S"), tag("I"), tag("J"), tag("F"), tag("D"), tag("L"), tag("V")))(smali);
     if let IResult::Ok((o, t)) = p
     {
-        return IResult::Ok((o, TypeSignature::from_jni(&t.to_string())))
+        return IResult::Ok((o, TypeSignature::from_jni(t)))
     }
 
     IResult::Err(Err::Error(Error { input: smali, code: ErrorKind::Complete }))
@@ -124,6 +124,215 @@ pub(crate) fn parse_methodsignature(smali: &str) -> IResult<&str, MethodSignatur
     IResult::Ok((o, MethodSignature { args: a, return_type: r }))
 }
 
+pub(crate) fn parse_methodref(smali: &str) -> IResult<&str, MethodRef>
+{
+    let (o, class) = parse_typesignature(smali)?;
+    let (o, _) = tag("->")(o)?;
+    let (o, name) = take_while1(|c| c != '(' && c != '\n')(o)?;
+    let (o, signature) = parse_methodsignature(o)?;
+    IResult::Ok((o, MethodRef { class, name: name.to_string(), signature }))
+}
+
+pub(crate) fn parse_fieldref(smali: &str) -> IResult<&str, FieldRef>
+{
+    let (o, class) = parse_typesignature(smali)?;
+    let class = match class {
+        TypeSignature::Object(c) => c,
+        _ => return IResult::Err(Err::Error(Error { input: smali, code: ErrorKind::Verify }))
+    };
+    let (o, _) = tag("->")(o)?;
+    let (o, name) = take_while1(|c| c != ':' && c != '\n')(o)?;
+    let (o, _) = char(':')(o)?;
+    let (o, signature) = parse_typesignature(o)?;
+    IResult::Ok((o, FieldRef { class, name: name.to_string(), signature }))
+}
+
+pub(crate) fn parse_method_handle(smali: &str) -> IResult<&str, MethodHandle>
+{
+    let (o, kind) = take_while1(|c: char| c.is_ascii_lowercase() || c == '-')(smali)?;
+    let (o, _) = char('@')(o)?;
+    match kind {
+        "static-put" => map(parse_fieldref, MethodHandle::StaticPut)(o),
+        "static-get" => map(parse_fieldref, MethodHandle::StaticGet)(o),
+        "instance-put" => map(parse_fieldref, MethodHandle::InstancePut)(o),
+        "instance-get" => map(parse_fieldref, MethodHandle::InstanceGet)(o),
+        "invoke-static" => map(parse_methodref, MethodHandle::InvokeStatic)(o),
+        "invoke-instance" => map(parse_methodref, MethodHandle::InvokeInstance)(o),
+        "invoke-constructor" => map(parse_methodref, MethodHandle::InvokeConstructor)(o),
+        "invoke-direct" => map(parse_methodref, MethodHandle::InvokeDirect)(o),
+        "invoke-interface" => map(parse_methodref, MethodHandle::InvokeInterface)(o),
+        _ => IResult::Err(Err::Error(Error { input: smali, code: ErrorKind::Tag }))
+    }
+}
+
+// Call sites are kept as text: name("method", (proto), args...)@bootstrap-method
+fn parse_call_site(smali: &str) -> IResult<&str, String>
+{
+    let (o, _) = take_while1(|c: char| c != '(' && !c.is_whitespace())(smali)?;
+    let (mut o, _) = char('(')(o)?;
+    let mut depth = 1;
+    while depth > 0
+    {
+        if let IResult::Ok((i, _)) = string_literal(o) { o = i; continue; }
+        let (i, c) = none_of("\n")(o)?;
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        o = i;
+    }
+    let (o, _) = char('@')(o)?;
+    let (o, _) = parse_methodref(o)?;
+    IResult::Ok((o, smali[..smali.len() - o.len()].to_string()))
+}
+
+fn parse_register(smali: &str) -> IResult<&str, Register>
+{
+    let (o, prefix) = one_of("vp")(smali)?;
+    let (o, n) = digit1(o)?;
+    let n = match n.parse::<u16>() {
+        Ok(n) => n,
+        Err(_) => return IResult::Err(Err::Error(Error { input: smali, code: ErrorKind::Digit }))
+    };
+    IResult::Ok((o, if prefix == 'v' { Register::V(n) } else { Register::P(n) }))
+}
+
+fn parse_register_list(smali: &str) -> IResult<&str, Vec<Register>>
+{
+    delimited(pair(char('{'), space0),
+              separated_list0(tuple((space0, char(','), space0)), parse_register),
+              pair(space0, char('}')))(smali)
+}
+
+fn parse_register_range(smali: &str) -> IResult<&str, RegisterRange>
+{
+    let (o, _) = pair(char('{'), space0)(smali)?;
+    let (o, start) = parse_register(o)?;
+    let (o, _) = tuple((space0, tag(".."), space0))(o)?;
+    let (o, end) = parse_register(o)?;
+    let (o, _) = pair(space0, char('}'))(o)?;
+    IResult::Ok((o, RegisterRange { start, end }))
+}
+
+// Integer literals: decimal or hex, optionally negative, with an optional L/S/T type suffix
+pub(crate) fn parse_literal(smali: &str) -> IResult<&str, i64>
+{
+    let (o, neg) = opt(char('-'))(smali)?;
+    let (o, hex) = opt(alt((tag("0x"), tag("0X"))))(o)?;
+    let (o, digits) = if hex.is_some() { hex_digit1(o)? } else { digit1(o)? };
+    let (o, _) = opt(one_of("LlSsTt"))(o)?;
+    let value = match u64::from_str_radix(digits, if hex.is_some() { 16 } else { 10 }) {
+        Ok(v) => v as i64,
+        Err(_) => return IResult::Err(Err::Error(Error { input: smali, code: ErrorKind::Digit }))
+    };
+    IResult::Ok((o, if neg.is_some() { value.wrapping_neg() } else { value }))
+}
+
+/* A decimal number such as a line number or register count, that has to fit in a u32 */
+fn decimal_u32(smali: &str) -> IResult<&str, u32>
+{
+    let (o, digits) = digit1(smali)?;
+    match digits.parse::<u32>() {
+        Ok(n) => IResult::Ok((o, n)),
+        Err(_) => IResult::Err(Failure(Error { input: smali, code: ErrorKind::TooLarge }))
+    }
+}
+
+fn parse_label_ref(smali: &str) -> IResult<&str, String>
+{
+    let (o, _) = char(':')(smali)?;
+    let (o, l) = take_while1(|c: char| !c.is_whitespace() && c != ',' && c != '}')(o)?;
+    IResult::Ok((o, l.to_string()))
+}
+
+pub(crate) fn string_literal(smali: &str) -> IResult<&str, &str>
+{
+    let esc = escaped(none_of("\\\""), '\\', one_of("'\"tbnrfu\\"));
+    delimited(char('"'), alt((esc, tag(""))), char('"'))(smali)
+}
+
+/* Converts the contents of a smali string literal to a Rust string */
+pub(crate) fn unescape_string(s: &str) -> String
+{
+    let mut units: Vec<u16> = vec![];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next()
+    {
+        if c != '\\'
+        {
+            let mut buf = [0u16; 2];
+            units.extend_from_slice(c.encode_utf16(&mut buf));
+            continue;
+        }
+        match chars.next() {
+            Some('n') => units.push('\n' as u16),
+            Some('t') => units.push('\t' as u16),
+            Some('r') => units.push('\r' as u16),
+            Some('b') => units.push(0x08),
+            Some('f') => units.push(0x0c),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                units.push(u16::from_str_radix(&hex, 16).unwrap_or(0xfffd));
+            }
+            Some(c) => units.push(c as u16),
+            None => {}
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+fn parse_operand(kind: OperandKind) -> impl FnMut(&str) -> IResult<&str, Operand>
+{
+    move |smali: &str| match kind {
+        OperandKind::Register => map(parse_register, Operand::Register)(smali),
+        OperandKind::RegisterList => map(parse_register_list, Operand::RegisterList)(smali),
+        OperandKind::RegisterRange => map(parse_register_range, Operand::RegisterRange)(smali),
+        OperandKind::Literal => map(parse_literal, Operand::Literal)(smali),
+        OperandKind::Label => map(parse_label_ref, Operand::Label)(smali),
+        OperandKind::String => map(string_literal, |s| Operand::String(unescape_string(s)))(smali),
+        OperandKind::Type => map(parse_typesignature, Operand::Type)(smali),
+        OperandKind::Field => map(parse_fieldref, Operand::Field)(smali),
+        OperandKind::Method => map(parse_methodref, Operand::Method)(smali),
+        OperandKind::Proto => map(parse_methodsignature, Operand::Proto)(smali),
+        OperandKind::CallSite => map(parse_call_site, Operand::CallSite)(smali),
+        OperandKind::MethodHandle => map(parse_method_handle, Operand::MethodHandle)(smali),
+    }
+}
+
+/* Any trailing comment is dropped */
+fn end_of_line(smali: &str) -> IResult<&str, ()>
+{
+    value((), tuple((space0, opt(pair(char('#'), not_line_ending)), alt((line_ending, eof)))))(smali)
+}
+
+/* Parses a single Dalvik instruction line */
+pub(crate) fn parse_dex_instruction(smali: &str) -> IResult<&str, DexInstruction>
+{
+    let (o, _) = space0(smali)?;
+    let (o, mnemonic) = take_while1(|c: char| c.is_ascii_alphanumeric() || c == '-' || c == '/')(o)?;
+    let opcode = match Opcode::from_mnemonic(mnemonic) {
+        Some(op) => op,
+        None => return IResult::Err(Err::Error(Error { input: smali, code: ErrorKind::Tag }))
+    };
+
+    let mut input = o;
+    let mut operands = vec![];
+    for (n, kind) in opcode.operand_kinds().into_iter().enumerate()
+    {
+        let (o, _) = if n == 0 { value((), space1)(input)? } else { value((), tuple((space0, char(','), space0)))(input)? };
+        let (o, operand) = parse_operand(kind)(o)?;
+        operands.push(operand);
+        input = o;
+    }
+
+    let (o, _) = end_of_line(input)?;
+    match DexInstruction::from_operands(opcode, operands) {
+        Some(i) => IResult::Ok((o, i)),
+        None => IResult::Err(Err::Error(Error { input: smali, code: ErrorKind::Verify }))
+    }
+}
+
 fn parse_class_line(smali: &str) -> IResult<&str, (Vec<Modifier>, String)>
 {
     let (input, _) = tag(".class")(smali)?;
@@ -153,83 +362,123 @@ fn parse_source_line(smali: &str) -> IResult<&str, String>
     Ok((input, class_type.trim().to_string()))
 }
 
-fn parse_java_array(smali: &str) -> IResult<&str, Vec<String>>
+fn parse_java_array(smali: &str) -> IResult<&str, Vec<EncodedValue>>
 {
-    let (o, _) = ws(tag("{"))(smali)?;
-    // Array of strings
-    let mut v = vec![];
+    delimited(ws(tag("{")), separated_list0(ws(tag(",")), parse_encoded_value), ws(tag("}")))(smali)
+}
 
-    let mut input= o;
-    loop
+fn char_literal(smali: &str) -> IResult<&str, &str>
+{
+    let esc = escaped(none_of("\\'"), '\\', one_of("'\"tbnrfu\\"));
+    delimited(char('\''), esc, char('\''))(smali)
+}
+
+/* A value of an annotation element or field initialiser, anything that isn't a block, string or char runs to the next `,` or `}` */
+pub(crate) fn parse_encoded_value(smali: &str) -> IResult<&str, EncodedValue>
+{
+    let (input, _) = multispace0(smali)?;
+    if input.starts_with(".subannotation")
     {
-        if let IResult::Ok((o, s)) = quoted()(input)
-        {
-            v.push(format!("\"{}\"", s));
-            input = o;
-        }
-        if let IResult::Ok((o, s)) = ws(is_not(",}}"))(input)
-        {
-            v.push(s.to_string());
-            input = o;
-        }
+        return map(|i| parse_annotation(i, true), EncodedValue::Annotation)(input);
+    }
+    if input.starts_with('{')
+    {
+        return map(parse_java_array, EncodedValue::Array)(input);
+    }
+    if let IResult::Ok((o, f)) = preceded(pair(tag(".enum"), space1), parse_fieldref)(input)
+    {
+        return Ok((o, EncodedValue::Enum(f)));
+    }
+    if let IResult::Ok((o, s)) = string_literal(input)
+    {
+        return Ok((o, EncodedValue::String(unescape_string(s))));
+    }
+    if let IResult::Ok((o, c)) = char_literal(input)
+    {
+        let units: Vec<u16> = unescape_string(c).encode_utf16().collect();
+        if units.len() == 1 { return Ok((o, EncodedValue::Char(units[0]))); }
+        return IResult::Err(Err::Error(Error { input, code: ErrorKind::Verify }));
+    }
+
+    let (o, token) = take_while1(|c| !",}\r\n".contains(c))(input)?;
+    match parse_scalar(token.trim()) {
+        Some(v) => Ok((o, v)),
+        None => IResult::Err(Err::Error(Error { input, code: ErrorKind::Verify }))
+    }
+}
 
-        if let IResult::Ok((o, _)) = ws(tag("}"))(input)
+/* Literals, references and types, as a whole token */
+fn parse_scalar(t: &str) -> Option<EncodedValue>
+{
+    let whole = |r: Option<(&str, EncodedValue)>| r.filter(|(rest, _)| rest.is_empty()).map(|(_, v)| v);
+    match t {
+        "null" => return Some(EncodedValue::Null),
+        "true" => return Some(EncodedValue::Boolean(true)),
+        "false" => return Some(EncodedValue::Boolean(false)),
+        _ => {}
+    }
+    if t.starts_with('(')
+    {
+        return whole(parse_methodsignature(t).ok().map(|(o, m)| (o, EncodedValue::MethodType(m))));
+    }
+    if t.starts_with(|c: char| c.is_ascii_lowercase()) && t.contains('@')
+    {
+        return whole(parse_method_handle(t).ok().map(|(o, m)| (o, EncodedValue::MethodHandle(m))));
+    }
+    if t.starts_with(['L', '[']) || (t.len() == 1 && "ZBCSIJFDV".contains(t))
+    {
+        if t.contains("->")
         {
-            return Ok((o, v))
+            if let Ok((o, m)) = parse_methodref(t) { return whole(Some((o, EncodedValue::Method(m)))); }
+            return whole(parse_fieldref(t).ok().map(|(o, f)| (o, EncodedValue::Field(f))));
         }
-
-        let (o, _) = tag(",")(input)?;
-        input = o;
+        return whole(parse_typesignature(t).ok().map(|(o, s)| (o, EncodedValue::Type(s))));
     }
-
+    parse_number(t)
 }
 
-fn parse_annotation_element(smali: &str) -> IResult<&str, AnnotationElement>
+/* Integers take their width from the suffix, `t` byte, `s` short and `L` long; floats end in `f` and doubles optionally in `d` */
+fn parse_number(t: &str) -> Option<EncodedValue>
 {
-    let (input, name) = ws(alphanumeric1)(smali)?;
-    let (i, _) = tag("=")(input)?;
-
-    let mut input= i;
-
-    let ae = AnnotationElement {
-        name: name.to_string(),
-        value:
+    let (neg, digits) = match t.strip_prefix('-') { Some(d) => (true, d), None => (false, t) };
+    if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X"))
+    {
+        let (hex, suffix) = match hex.char_indices().last() {
+            Some((i, c)) if "tTsSlL".contains(c) => (&hex[..i], Some(c.to_ascii_lowercase())),
+            _ => (hex, None)
+        };
+        return integer(u64::from_str_radix(hex, 16).ok()?, neg, suffix);
+    }
 
-        // Try subannotation
-        if let IResult::Ok((i, e)) = parse_annotation(input, true)
-        {
-            input = i;
-            AnnotationValue::SubAnnotation(e)
-        }
-        // Try enum
-        else if let IResult::Ok((i, e)) = parse_enum(input)
-        {
-            input = i;
-            e
-        }
-        // Try the array
-        else if let IResult::Ok((i, elements)) = parse_java_array(input)
-        {
-            input = i;
-            AnnotationValue::Array(elements)
-        } else {
-            let (i, v) = ws(not_line_ending)(input)?;
-            input = i;
-            AnnotationValue::Single(v.to_string())
-        }
-    };
+    let last = t.chars().last()?;
+    match last.to_ascii_lowercase() {
+        'f' => t[..t.len() - 1].parse::<f32>().ok().map(EncodedValue::Float),
+        'd' => t[..t.len() - 1].parse::<f64>().ok().map(EncodedValue::Double),
+        't' | 's' | 'l' => integer(digits[..digits.len() - 1].parse::<u64>().ok()?, neg, Some(last.to_ascii_lowercase())),
+        _ if digits.bytes().all(|b| b.is_ascii_digit()) => integer(digits.parse::<u64>().ok()?, neg, None),
+        _ => t.parse::<f64>().ok().map(EncodedValue::Double)
+    }
+}
 
-    Ok((input, ae))
+fn integer(magnitude: u64, neg: bool, suffix: Option<char>) -> Option<EncodedValue>
+{
+    let v = if neg { (magnitude as i64).wrapping_neg() } else { magnitude as i64 };
+    let fits = |min: i64, max: i64| (min..=max).contains(&v);
+    match suffix {
+        Some('t') if fits(i8::MIN as i64, u8::MAX as i64) => Some(EncodedValue::Byte(v as i8)),
+        Some('s') if fits(i16::MIN as i64, u16::MAX as i64) => Some(EncodedValue::Short(v as i16)),
+        Some('l') => Some(EncodedValue::Long(v)),
+        None if fits(i32::MIN as i64, u32::MAX as i64) => Some(EncodedValue::Int(v as i32)),
+        _ => None
+    }
 }
 
-fn parse_enum(smali: &str) -> IResult<&str, AnnotationValue>
+fn parse_annotation_element(smali: &str) -> IResult<&str, AnnotationElement>
 {
-    let (input, _) = ws(tag(".enum"))(smali)?;
-    let (input, object) = ws(take_while(|c| c != '-'))(input)?;
-    let (input, _) = tag("->")(input)?;
-    let (input, field) = take_while(|x| { x!= ':' && x != '\n'})(input)?;
-    let (input, _) = take_until_eol(input)?;
-    Ok((input, AnnotationValue::Enum(ObjectIdentifier::from_jni_type(object), field.to_string())))
+    let (input, name) = ws(alphanumeric1)(smali)?;
+    let (input, _) = tag("=")(input)?;
+    let (input, value) = parse_encoded_value(input)?;
+    Ok((input, AnnotationElement { name: name.to_string(), value }))
 }
 
 fn parse_annotation(smali: &str, subannotation: bool) -> IResult<&str, SmaliAnnotation>
@@ -284,7 +533,7 @@ fn parse_annotation(smali: &str, subannotation: bool) -> IResult<&str, SmaliAnno
         // Can't parse this - error
         if !found
         {
-            return IResult::Err(Failure(Error { input: input, code: ErrorKind::Fail }));
+            return IResult::Err(Failure(Error { input, code: ErrorKind::Fail }));
         }
     }
 }
@@ -311,9 +560,10 @@ fn parse_field(smali: &str) -> IResult<&str, SmaliField>
     let eq = ws(tag("="))(input);
     if let IResult::Ok((o, _)) = eq
     {
-        let (o, iv) = take_until_eol(o)?;
+        let (o, iv) = parse_encoded_value(o)?;
+        let (o, _) = pair(space0, opt(line_ending))(o)?;
         input = o;
-        field.initial_value = Some(iv.to_string());
+        field.initial_value = Some(iv);
     }
 
     // Check for any annotations
@@ -344,20 +594,118 @@ fn parse_field(smali: &str) -> IResult<&str, SmaliField>
             // Can't parse this - error
             if !found
             {
-                return IResult::Err(Failure(Error { input: input, code: ErrorKind::Fail }));
+                return IResult::Err(Failure(Error { input, code: ErrorKind::Fail }));
             }
         }
     }
     IResult::Ok((input, field))
 }
 
+fn parse_catch(smali: &str) -> IResult<&str, SmaliInstruction>
+{
+    let (o, _) = multispace0(smali)?;
+    let (o, exception) = alt((
+        map(tag(".catchall"), |_| None),
+        map(preceded(pair(tag(".catch"), space1), parse_typesignature), Some)
+    ))(o)?;
+    let exception = match exception {
+        Some(TypeSignature::Object(e)) => Some(e),
+        None => None,
+        _ => return IResult::Err(Err::Error(Error { input: smali, code: ErrorKind::Verify }))
+    };
+    let (o, _) = space1(o)?;
+    let (o, (start, end)) = delimited(
+        pair(char('{'), space0),
+        pair(parse_label_ref, preceded(tuple((space0, tag(".."), space0)), parse_label_ref)),
+        pair(space0, char('}'))
+    )(o)?;
+    let (o, _) = space1(o)?;
+    let (o, handler) = parse_label_ref(o)?;
+    let (o, _) = take_until_eol(o)?;
+    IResult::Ok((o, SmaliInstruction::TryCatch { exception, start, end, handler }))
+}
+
+fn parse_payload(smali: &str) -> IResult<&str, Payload>
+{
+    alt((
+        map(parse_packed_switch, Payload::PackedSwitch),
+        map(parse_sparse_switch, Payload::SparseSwitch),
+        map(parse_array_data, Payload::ArrayData)
+    ))(smali)
+}
+
+fn parse_packed_switch(smali: &str) -> IResult<&str, PackedSwitch>
+{
+    let (o, _) = ws(tag(".packed-switch"))(smali)?;
+    let (o, first_key) = terminated(parse_literal, end_of_line)(o)?;
+    let first_key = match i32::try_from(first_key) {
+        Ok(k) => k,
+        Err(_) => return IResult::Err(Failure(Error { input: smali, code: ErrorKind::TooLarge }))
+    };
+    let (o, targets) = many0(preceded(multispace0, terminated(parse_label_ref, end_of_line)))(o)?;
+    let (o, _) = ws(tag(".end packed-switch"))(o)?;
+    IResult::Ok((o, PackedSwitch { first_key, targets }))
+}
+
+fn parse_sparse_switch(smali: &str) -> IResult<&str, SparseSwitch>
+{
+    let (o, _) = ws(tag(".sparse-switch"))(smali)?;
+    let (o, entries) = many0(preceded(multispace0, terminated(
+        pair(parse_literal, preceded(tuple((space0, tag("->"), space0)), parse_label_ref)),
+        end_of_line
+    )))(o)?;
+    let (o, _) = ws(tag(".end sparse-switch"))(o)?;
+    let entries = match entries.into_iter().map(|(k, t)| i32::try_from(k).map(|k| (k, t))).collect() {
+        Ok(e) => e,
+        Err(_) => return IResult::Err(Failure(Error { input: smali, code: ErrorKind::TooLarge }))
+    };
+    IResult::Ok((o, SparseSwitch { entries }))
+}
+
+/* An array-data element, an integer or the bits of a float such as 1.5f or a double such as 2.0 */
+fn parse_array_element(smali: &str) -> IResult<&str, i64>
+{
+    let (o, t) = take_while1(|c: char| !c.is_whitespace() && c != '#')(smali)?;
+    if let IResult::Ok(("", v)) = parse_literal(t) { return IResult::Ok((o, v)); }
+    match parse_number(t) {
+        Some(EncodedValue::Float(f)) => IResult::Ok((o, f.to_bits() as i64)),
+        Some(EncodedValue::Double(d)) => IResult::Ok((o, d.to_bits() as i64)),
+        _ => IResult::Err(Err::Error(Error { input: smali, code: ErrorKind::Float }))
+    }
+}
+
+fn parse_array_data(smali: &str) -> IResult<&str, ArrayData>
+{
+    let (o, _) = ws(tag(".array-data"))(smali)?;
+    let (o, width) = terminated(digit1, end_of_line)(o)?;
+    let element_width = match width {
+        "1" => 1,
+        "2" => 2,
+        "4" => 4,
+        "8" => 8,
+        _ => return IResult::Err(Err::Error(Error { input: smali, code: ErrorKind::Verify }))
+    };
+    let (o, values) = many0(preceded(multispace0, terminated(parse_array_element, end_of_line)))(o)?;
+
+    // Sign extend from the element width, values may have been written unsigned but must fit either way
+    let shift = 64 - element_width * 8;
+    if shift > 0 && values.iter().any(|v| *v < -(1 << (63 - shift)) || *v >= 1 << (64 - shift))
+    {
+        return IResult::Err(Failure(Error { input: smali, code: ErrorKind::TooLarge }));
+    }
+    let values = values.into_iter().map(|v| (v << shift) >> shift).collect();
+    let (o, _) = ws(tag(".end array-data"))(o)?;
+    IResult::Ok((o, ArrayData { element_width, values }))
+}
+
 pub fn parse_instruction(smali: &str) -> IResult<&str, SmaliInstruction>
 {
     // Line
     if let IResult::Ok((o, _)) = ws(tag(".line"))(smali)
     {
-        let (o, n) = take_until_eol(o)?;
-        IResult::Ok((o, SmaliInstruction::Line(n.parse::<u32>().unwrap())))
+        let (o, n) = decimal_u32(o)?;
+        let (o, _) = take_until_eol(o)?;
+        IResult::Ok((o, SmaliInstruction::Line(n)))
     }
 
     // Label
@@ -367,10 +715,53 @@ pub fn parse_instruction(smali: &str) -> IResult<&str, SmaliInstruction>
         IResult::Ok((o, SmaliInstruction::Label(n.to_string())))
     }
 
+    // Switch tables and array data, with keys that don't fit in an int being an error
+    else if let r @ (IResult::Ok(_) | IResult::Err(Failure(_))) = parse_payload(smali)
+    {
+        r.map(|(o, p)| (o, SmaliInstruction::Payload(p)))
+    }
+
+    // Try / catch
+    else if let IResult::Ok((o, c)) = parse_catch(smali)
+    {
+        IResult::Ok((o, c))
+    }
+
+    // Debug information
+    else if let IResult::Ok((o, l)) = parse_local(smali)
+    {
+        IResult::Ok((o, l))
+    }
+    else if let IResult::Ok((o, r)) = preceded(pair(multispace0, tag(".end local")), preceded(space1, parse_register))(smali)
+    {
+        let (o, _) = end_of_line(o)?;
+        IResult::Ok((o, SmaliInstruction::EndLocal(r)))
+    }
+    else if let IResult::Ok((o, r)) = preceded(pair(multispace0, tag(".restart local")), preceded(space1, parse_register))(smali)
+    {
+        let (o, _) = end_of_line(o)?;
+        IResult::Ok((o, SmaliInstruction::RestartLocal(r)))
+    }
+    else if let IResult::Ok((o, _)) = terminated(preceded(multispace0, tag(".prologue")), end_of_line)(smali)
+    {
+        IResult::Ok((o, SmaliInstruction::Prologue))
+    }
+    else if let IResult::Ok((o, _)) = terminated(preceded(multispace0, tag(".epilogue")), end_of_line)(smali)
+    {
+        IResult::Ok((o, SmaliInstruction::Epilogue))
+    }
+
+    // Any other directive
+    else if let IResult::Ok((o, _)) = ws(tag("."))(smali)
+    {
+        let (o, n) = take_until_eol(o)?;
+        IResult::Ok((o, SmaliInstruction::Directive(format!(".{}", n.trim_end()))))
+    }
+
     // Actual instruction
-    else if let IResult::Ok((o, n)) = take_until_eol(smali)
+    else if let IResult::Ok((o, i)) = parse_dex_instruction(smali.trim_start())
     {
-        IResult::Ok((o, SmaliInstruction::Instruction(n.to_string())))
+        IResult::Ok((o, SmaliInstruction::Instruction(i)))
     }
 
     // Anything else - failure
@@ -380,76 +771,101 @@ pub fn parse_instruction(smali: &str) -> IResult<&str, SmaliInstruction>
     }
 }
 
+fn parse_param(smali: &str) -> IResult<&str, SmaliParameter>
+{
+    let (o, _) = preceded(multispace0, tag(".param"))(smali)?;
+    let (o, register) = preceded(space1, parse_register)(o)?;
+    let (o, name) = opt(preceded(tuple((space0, char(','), space0)), string_literal))(o)?;
+    let (o, _) = end_of_line(o)?;
+
+    let mut param = SmaliParameter {
+        register,
+        name: name.map(unescape_string),
+        annotations: vec![]
+    };
+
+    // Any parameter annotations, followed by the end directive. Without the end directive the annotations belong to
+    // the method, baksmali writes an unannotated .param straight before them
+    let mut input = o;
+    let mut annotations = vec![];
+    while let IResult::Ok((o, a)) = parse_annotation(input, false)
+    {
+        annotations.push(a);
+        input = o;
+    }
+    if let IResult::Ok((input, _)) = ws(tag(".end param"))(input)
+    {
+        param.annotations = annotations;
+        return IResult::Ok((input, param));
+    }
+
+    IResult::Ok((o, param))
+}
+
+/* .local v0, "name":Ljava/lang/String;, "generic signature" */
+fn parse_local(smali: &str) -> IResult<&str, SmaliInstruction>
+{
+    let (o, _) = preceded(multispace0, tag(".local"))(smali)?;
+    let (o, register) = preceded(space1, parse_register)(o)?;
+    let (o, details) = opt(preceded(tuple((space0, char(','), space0)), tuple((
+        alt((map(string_literal, |n| Some(unescape_string(n))), map(tag("null"), |_| None))),
+        preceded(char(':'), parse_typesignature),
+        opt(preceded(tuple((space0, char(','), space0)), string_literal))
+    ))))(o)?;
+    let (o, _) = end_of_line(o)?;
+
+    let (name, signature, generic) = match details {
+        Some((name, signature, generic)) => (name, Some(signature), generic.map(unescape_string)),
+        None => (None, None, None)
+    };
+    IResult::Ok((o, SmaliInstruction::Local { register, name, signature, generic }))
+}
+
 fn parse_method(smali: &str) -> IResult<&str, SmaliMethod>
 {
     let (input, _) = tag(".method")(smali)?;
-    let (o, modifiers) = parse_modifiers(input)?;
+    let (o, mut modifiers) = parse_modifiers(input)?;
 
     let mut input = o;
 
     // Is it a class initialiser or constructor
-    let constructor = if let IResult::Ok((o, _)) = ws(tag("constructor "))(input)
-    {
-        input = o;
-        true
-    }
-    else { false };
+    let constructor = modifiers.contains(&Modifier::Constructor);
+    modifiers.retain(|m| *m != Modifier::Constructor);
 
     let (o, name) = take_while(|c| c != '(')(input)?;
     let (o, ms) = parse_methodsignature(o)?;
 
-    let (o, type_sig) = take_until_eol(o)?;
+    let (o, _) = take_until_eol(o)?;
 
-    // locals
-    let l = ws(tag(".locals"))(o);
-    let locals = if let IResult::Ok((o, _)) = l
+    // locals or registers
+    let registers = if let IResult::Ok((o, _)) = ws(tag(".locals"))(o)
     {
-        let (o, local) = take_until_eol(o)?;
+        let (o, locals) = decimal_u32(o)?;
+        let (o, _) = take_until_eol(o)?;
         input = o;
-        local
+        RegisterCount::Locals(locals)
     }
-    else { input = o; "0" };
+    else if let IResult::Ok((o, _)) = ws(tag(".registers"))(o)
+    {
+        let (o, registers) = decimal_u32(o)?;
+        let (o, _) = take_until_eol(o)?;
+        input = o;
+        RegisterCount::Registers(registers)
+    }
+    else { input = o; RegisterCount::Locals(0) };
 
     let mut method = SmaliMethod {
         name: name.to_string(),
         constructor,
         modifiers,
         signature: ms,
-        locals: locals.parse::<u32>().unwrap(),
+        registers,
+        params: vec![],
         annotations: vec![],
         instructions: vec![]
     };
 
-    // Check for any annotations
-    if let IResult::Ok((o, a)) = parse_annotation(input, false )
-    {
-        method.annotations.push(a);
-        input = o;
-
-        loop
-        {
-            let mut found = false;
-
-            // Is it the end
-            let end: IResult<&str, &str> = ws(tag(".end method"))(input);
-            if let IResult::Ok((o, _)) = end
-            {
-                return Ok((o, method))
-            }
-
-            if let IResult::Ok((o, a)) = parse_annotation(input, false)
-            {
-                method.annotations.push(a);
-                input = o;
-                found = true;
-            }
-
-            // Can't parse this - move on to instructions
-            if !found {  break; }
-        }
-    }
-
-    // Now parse the instructions
+    // Now parse the parameters, annotations and instructions
     loop
     {
         let mut found = false;
@@ -457,6 +873,9 @@ fn parse_method(smali: &str) -> IResult<&str, SmaliMethod>
         // Try a blank line
         if let IResult::Ok((o, _)) = blank_line(input) { input = o; found = true; }
 
+        // Try a comment
+        if let IResult::Ok((o, _)) = comment(input) { input = o; found = true; }
+
         // Is it the end
         let end: IResult<&str, &str> = ws(tag(".end method"))(input);
         if let IResult::Ok((o, _)) = end
@@ -464,7 +883,19 @@ fn parse_method(smali: &str) -> IResult<&str, SmaliMethod>
             return Ok((o, method))
         }
 
-        if let IResult::Ok((o, i)) = parse_instruction(input)
+        if let IResult::Ok((o, p)) = parse_param(input)
+        {
+            method.params.push(p);
+            input = o;
+            found = true;
+        }
+        else if let IResult::Ok((o, a)) = parse_annotation(input, false)
+        {
+            method.annotations.push(a);
+            input = o;
+            found = true;
+        }
+        else if let IResult::Ok((o, i)) = parse_instruction(input)
         {
             method.instructions.push(i);
             input = o;
@@ -474,7 +905,7 @@ fn parse_method(smali: &str) -> IResult<&str, SmaliMethod>
         // Can't parse this - error
         if !found
         {
-            return IResult::Err(Failure(Error { input: input, code: ErrorKind::Fail }));
+            return IResult::Err(Failure(Error { input, code: ErrorKind::Fail }));
         }
     }
 }
@@ -482,6 +913,91 @@ fn parse_method(smali: &str) -> IResult<&str, SmaliMethod>
 
 
 pub(crate) fn parse_class(smali: &str) -> IResult<&str, SmaliClass>
+{
+    parse_class_with(smali, None)
+}
+
+/* Parses a class, recovering from broken members and recording them in diagnostics when given */
+pub(crate) fn parse_class_lenient(smali: &str) -> IResult<&str, (SmaliClass, Vec<SmaliError>)>
+{
+    let mut diagnostics = vec![];
+    let (o, dex) = parse_class_with(smali, Some(&mut diagnostics))?;
+    IResult::Ok((o, (dex, diagnostics)))
+}
+
+/* The kind and length of the member starting at input, used to skip it when it doesn't parse */
+fn member_extent(input: &str) -> (UnparsedKind, usize)
+{
+    let line_len = |s: &str| s.find('\n').map_or(s.len(), |i| i + 1);
+
+    // Everything up to and including the first line starting with the end directive
+    let block = |end: &str| {
+        let mut pos = 0;
+        while pos < input.len()
+        {
+            let n = line_len(&input[pos..]);
+            let line = &input[pos..pos + n];
+            pos += n;
+            if line.trim_start().starts_with(end) { return pos; }
+        }
+        input.len()
+    };
+
+    let trimmed = input.trim_start();
+    if trimmed.starts_with(".method") { (UnparsedKind::Method, block(".end method")) }
+    else if trimmed.starts_with(".annotation") { (UnparsedKind::Annotation, block(".end annotation")) }
+    else if trimmed.starts_with(".field")
+    {
+        // Fields only have an end directive when they have annotations
+        let first = line_len(input);
+        let next = input[first..].trim_start();
+        if next.starts_with(".annotation") || next.starts_with(".end field") { (UnparsedKind::Field, block(".end field")) }
+        else { (UnparsedKind::Field, first) }
+    }
+    else { (UnparsedKind::Other, line_len(input)) }
+}
+
+/* In lenient mode records the error and skips over the broken member, keeping its raw text */
+fn recover<'a>(smali: &str, input: &'a str, failed_at: &str, diagnostics: &mut Option<&mut Vec<SmaliError>>, dex: &mut SmaliClass) -> Option<&'a str>
+{
+    let d = diagnostics.as_deref_mut()?;
+    let (kind, len) = member_extent(input);
+
+    // A broken class header isn't recoverable
+    if kind == UnparsedKind::Other && input.trim_start().starts_with(".class") { return None; }
+
+    d.push(SmaliError::parse(smali, failed_at));
+    dex.unparsed.push(Unparsed { kind, text: input[..len].to_string() });
+    Some(&input[len..])
+}
+
+/* The text consumed going from input to rest */
+fn consumed<'a>(input: &'a str, rest: &str) -> &'a str
+{
+    &input[..input.len() - rest.len()]
+}
+
+/* Fills in the key and standard rendering hash of every member in the layout, members are recorded in
+   the same order they are added to the class */
+fn finish_layout(dex: &SmaliClass, mut segments: Vec<LayoutSegment>) -> SourceLayout
+{
+    let mut members = class_members(dex);
+    for seg in segments.iter_mut()
+    {
+        if let LayoutSegment::Member { kind, key, hash, .. } = seg
+        {
+            if let Some(i) = members.iter().position(|m| m.0 == *kind)
+            {
+                let (_, k, text) = members.remove(i);
+                *key = k;
+                *hash = hash_member(&text);
+            }
+        }
+    }
+    SourceLayout { segments }
+}
+
+fn parse_class_with<'a>(smali: &'a str, mut diagnostics: Option<&mut Vec<SmaliError>>) -> IResult<&'a str, SmaliClass>
 {
     let mut input = smali;
 
@@ -493,19 +1009,31 @@ pub(crate) fn parse_class(smali: &str) -> IResult<&str, SmaliClass>
         annotations: vec![],
         fields: vec![],
         methods: vec![],
+        unparsed: vec![],
+        layout: None,
         modifiers: vec![],
         file_path: None
     };
 
+    // The original text split into members and trivia, keys and hashes are filled in at the end
+    let mut segments = vec![];
+    let mut record = |kind: Option<MemberKind>, text: &str| match kind {
+        Some(kind) => segments.push(LayoutSegment::Member { kind, key: String::new(), text: text.to_string(), hash: 0 }),
+        None => match segments.last_mut() {
+            Some(LayoutSegment::Trivia(t)) => t.push_str(text),
+            _ => segments.push(LayoutSegment::Trivia(text.to_string()))
+        }
+    };
+
     loop
     {
         let mut found = false;
 
         // Try a blank line
-        if let IResult::Ok((o, _)) = blank_line(input) { /* println!("blank line"); */ input = o; found = true; }
+        if let IResult::Ok((o, _)) = blank_line(input) { /* println!("blank line"); */ record(None, consumed(input, o)); input = o; found = true; }
 
         // Try a comment
-        if let IResult::Ok((o, _)) = comment(input) { /* println!("comment"); */ input = o; found = true; }
+        if let IResult::Ok((o, _)) = comment(input) { /* println!("comment"); */ record(None, consumed(input, o)); input = o; found = true; }
 
         // Is it a class line
         if let IResult::Ok((o, (m, c))) = parse_class_line(input )
@@ -513,6 +1041,7 @@ pub(crate) fn parse_class(smali: &str) -> IResult<&str, SmaliClass>
             // println!("class line: {:?} {}", m, c);
             dex.modifiers = m;
             dex.name = ObjectIdentifier::from_jni_type(&c);
+            record(Some(MemberKind::Class), consumed(input, o));
             input = o; found = true;
         }
 
@@ -521,6 +1050,7 @@ pub(crate) fn parse_class(smali: &str) -> IResult<&str, SmaliClass>
         {
             // println!("super line: {}", c);
             dex.super_class = ObjectIdentifier::from_jni_type(&c);
+            record(Some(MemberKind::Super), consumed(input, o));
             input = o; found = true;
         }
 
@@ -529,6 +1059,7 @@ pub(crate) fn parse_class(smali: &str) -> IResult<&str, SmaliClass>
         {
             // println!("source line: {}", c);
             dex.source = Some(c);
+            record(Some(MemberKind::Source), consumed(input, o));
             input = o; found = true;
         }
 
@@ -537,51 +1068,125 @@ pub(crate) fn parse_class(smali: &str) -> IResult<&str, SmaliClass>
         {
             // println!("implements line: {}", c);
             dex.implements.push(ObjectIdentifier::from_jni_type(&c));
+            record(Some(MemberKind::Implements), consumed(input, o));
             input = o; found = true;
         }
 
         // Is it an annotation line
         //let ann: IResult<&str, SmaliAnnotation>  = parse_annotation(input );
-        if let IResult::Ok((o, a)) = parse_annotation(input, false )
+        match parse_annotation(input, false )
         {
-            // println!("annotation: {:?}", a);
-            dex.annotations.push(a);
-            input = o; found = true;
+            IResult::Ok((o, a)) => {
+                // println!("annotation: {:?}", a);
+                dex.annotations.push(a);
+                record(Some(MemberKind::Annotation), consumed(input, o));
+                input = o; found = true;
+            }
+            // Members report failures from inside their body, keep the position
+            IResult::Err(Failure(e)) => match recover(smali, input, e.input, &mut diagnostics, &mut dex) {
+                Some(o) => { record(Some(MemberKind::Unparsed), consumed(input, o)); input = o; found = true; }
+                None => return IResult::Err(Failure(e))
+            },
+            _ => {}
         }
 
         // Is it a field line
         //let ann: IResult<&str, SmaliAnnotation>  = parse_annotation(input );
-        if let IResult::Ok((o, f)) = parse_field(input )
+        match parse_field(input )
         {
-            // println!("field: {:?}", f);
-            dex.fields.push(f);
-            input = o; found = true;
+            IResult::Ok((o, f)) => {
+                // println!("field: {:?}", f);
+                dex.fields.push(f);
+                record(Some(MemberKind::Field), consumed(input, o));
+                input = o; found = true;
+            }
+            IResult::Err(Failure(e)) => match recover(smali, input, e.input, &mut diagnostics, &mut dex) {
+                Some(o) => { record(Some(MemberKind::Unparsed), consumed(input, o)); input = o; found = true; }
+                None => return IResult::Err(Failure(e))
+            },
+            _ => {}
         }
 
         // Is it a field line
         //let ann: IResult<&str, SmaliAnnotation>  = parse_annotation(input );
-        if let IResult::Ok((o, m)) = parse_method(input )
+        match parse_method(input )
         {
-            // println!("method: {:?}", m);
-            dex.methods.push(m);
-            input = o; found = true;
+            IResult::Ok((o, m)) => {
+                // println!("method: {:?}", m);
+                dex.methods.push(m);
+                record(Some(MemberKind::Method), consumed(input, o));
+                input = o; found = true;
+            }
+            IResult::Err(Failure(e)) => match recover(smali, input, e.input, &mut diagnostics, &mut dex) {
+                Some(o) => { record(Some(MemberKind::Unparsed), consumed(input, o)); input = o; found = true; }
+                None => return IResult::Err(Failure(e))
+            },
+            _ => {}
         }
 
-        if input.len() == 0 { return IResult::Ok((input, dex)) }
+        if input.is_empty()
+        {
+            dex.layout = Some(finish_layout(&dex, segments));
+            return IResult::Ok((input, dex))
+        }
 
         // Can't parse this - error
         if !found
         {
-            return IResult::Err(Failure(Error { input: input, code: ErrorKind::Fail }));
+            match recover(smali, input, input, &mut diagnostics, &mut dex) {
+                Some(o) => { record(Some(MemberKind::Unparsed), consumed(input, o)); input = o; }
+                None => return IResult::Err(Failure(Error { input, code: ErrorKind::Fail }))
+            }
         }
     }
 }
 
+/* Parses only the class header, stopping at the first annotation, field or method */
+pub(crate) fn parse_class_header(smali: &str) -> IResult<&str, SmaliClass>
+{
+    let mut input = smali;
+    let mut dex = SmaliClass {
+        name: ObjectIdentifier::from_java_type("java.lang.Object"),
+        super_class: ObjectIdentifier::from_java_type("java.lang.Object"),
+        source: None,
+        implements: vec![],
+        annotations: vec![],
+        fields: vec![],
+        methods: vec![],
+        unparsed: vec![],
+        layout: None,
+        modifiers: vec![],
+        file_path: None
+    };
+    let mut class_line = false;
+
+    loop
+    {
+        if let IResult::Ok((o, _)) = blank_line(input) { input = o; }
+        else if let IResult::Ok((o, _)) = comment(input) { input = o; }
+        else if let IResult::Ok((o, (m, c))) = parse_class_line(input)
+        {
+            dex.modifiers = m;
+            dex.name = ObjectIdentifier::from_jni_type(&c);
+            class_line = true;
+            input = o;
+        }
+        else if let IResult::Ok((o, c)) = parse_super_line(input) { dex.super_class = ObjectIdentifier::from_jni_type(&c); input = o; }
+        else if let IResult::Ok((o, c)) = parse_source_line(input) { dex.source = Some(c); input = o; }
+        else if let IResult::Ok((o, c)) = parse_implements_line(input) { dex.implements.push(ObjectIdentifier::from_jni_type(&c)); input = o; }
+        else if class_line { return IResult::Ok((input, dex)); }
+        else { return IResult::Err(Failure(Error { input, code: ErrorKind::Fail })); }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
-    use crate::smali_parse::{quoted, parse_annotation_element, parse_class, parse_class_line, parse_enum, parse_field, parse_implements_line, parse_java_array, parse_super_line, take_until_eol, parse_typesignature, parse_methodsignature};
-    use crate::types::{AnnotationValue};
+    use crate::smali_parse::{quoted, parse_annotation_element, parse_class, parse_class_line, parse_encoded_value, parse_field, parse_implements_line, parse_java_array, parse_super_line, take_until_eol, parse_typesignature, parse_methodsignature};
+    use crate::smali_parse::{parse_catch, parse_method, parse_dex_instruction, parse_payload};
+    use crate::smali_write::write_instruction;
+    use crate::instructions::{DexInstruction, Payload, Register};
+    use crate::types::{AnnotationVisibility, EncodedValue, Modifier, ModifierTarget, SmaliInstruction, TypeSignature};
 
     #[test]
     fn test_take_until_eol() {
@@ -643,20 +1248,16 @@ mod tests {
     fn test_parse_annotation_element_single() {
         let (_, a) = parse_annotation_element(" k = 0x1\n").unwrap();
         assert_eq!(a.name, "k");
-        match a.value
-        {
-            AnnotationValue::Single(s) => { assert_eq!( s, "0x1".to_string()); }
-            _ => { println!("{:?}", a); }
-        }
+        assert_eq!(a.value, EncodedValue::Int(1));
     }
 
     #[test]
     fn test_parse_enum() {
-        let (_, a) = parse_enum(" .enum Lkotlin/DeprecationLevel;->ERROR:Lkotlin/DeprecationLevel;\n" ).unwrap();
+        let (_, a) = parse_encoded_value(" .enum Lkotlin/DeprecationLevel;->ERROR:Lkotlin/DeprecationLevel;\n" ).unwrap();
         match a {
-            AnnotationValue::Enum(o, f) => {
-                assert_eq!(o.as_java_type(), "kotlin.DeprecationLevel");
-                assert_eq!(f, "ERROR");
+            EncodedValue::Enum(f) => {
+                assert_eq!(f.class.as_java_type(), "kotlin.DeprecationLevel");
+                assert_eq!(f.name, "ERROR");
             }
             _ => { println!("{:?}", a); }
         }
@@ -666,7 +1267,7 @@ mod tests {
     fn test_parse_java_array() {
         let (_, a) = parse_java_array("{ 1, 2,\n 3, 4\n } " ).unwrap();
         assert_eq!(a.len(), 4);
-        assert_eq!(a[1], "2".to_string());
+        assert_eq!(a[1], EncodedValue::Int(2));
         let (_, a) = parse_java_array("{ \"bo,o\", \"hoo\\\"\\u0000,boo\" } " ).unwrap();
         assert_eq!(a.len(), 2);
 
@@ -682,19 +1283,30 @@ mod tests {
         assert_eq!(a.name, "key");
         match a.value
         {
-            AnnotationValue::Array(a) => { assert_eq!( a[0], "\"a,\"".to_string()); }
+            EncodedValue::Array(a) => { assert_eq!( a[0], EncodedValue::String("a,".to_string())); }
             _ => { println!("{:?}", a); }
         }
 
         let (_, a) = parse_annotation_element(" value = .enum Ljava/lang/annotation/RetentionPolicy;->SOURCE:Ljava/lang/annotation/RetentionPolicy;\n").unwrap();
         match a.value
         {
-            AnnotationValue::Enum(obj, name) => {
-                assert_eq!(name, "SOURCE");
-                assert_eq!(obj.as_jni_type(), "Ljava/lang/annotation/RetentionPolicy;");
+            EncodedValue::Enum(f) => {
+                assert_eq!(f.name, "SOURCE");
+                assert_eq!(f.class.as_jni_type(), "Ljava/lang/annotation/RetentionPolicy;");
             }
             _ => { println!("{:?}", a); }
         }
+
+        let (_, a) = parse_annotation_element(" value = {\n .subannotation La/B;\n  c = '\\''\n .end subannotation,\n {}\n }\n").unwrap();
+        match &a.value
+        {
+            EncodedValue::Array(v) => {
+                assert!(matches!(&v[0], EncodedValue::Annotation(s) if s.elements[0].value == EncodedValue::Char(0x27)));
+                assert_eq!(v[1], EncodedValue::Array(vec![]));
+            }
+            _ => panic!("{:?}", a)
+        }
+        assert_eq!(a.value.to_string(), "{\n    .subannotation La/B;\n        c = '\\''\n    .end subannotation,\n    {}\n}");
     }
 
     #[test]
@@ -703,6 +1315,32 @@ mod tests {
         assert_eq!(f.name, "callTimeoutMillis".to_string());
         assert_eq!(f.modifiers.len(), 2);
         assert_eq!(f.signature.to_jni(), "I");
+
+        let (_, f) = parse_field(".field public static final enum RED:Lcom/Color;\n").unwrap();
+        assert_eq!(f.modifiers, vec![Modifier::Public, Modifier::Static, Modifier::Final, Modifier::Enum]);
+        assert_eq!(f.access_flags(), 0x4019);
+        assert!(parse_field(".field public shiny a:I\n").is_err());
+
+        let (_, f) = parse_field(".field public static final MAX:J = -0x80L\n").unwrap();
+        assert_eq!(f.initial_value, Some(EncodedValue::Long(-128)));
+        let (_, f) = parse_field(".field static NAME:Ljava/lang/String; = \"a\\tb\"\n").unwrap();
+        assert_eq!(f.initial_value, Some(EncodedValue::String("a\tb".to_string())));
+    }
+
+    #[test]
+    fn test_parse_modifiers() {
+        let (_, (m, c)) = parse_class_line(".class public interface abstract Lcom/Iface;\n").unwrap();
+        assert_eq!(m, vec![Modifier::Public, Modifier::Interface, Modifier::Abstract]);
+        assert_eq!(c, "Lcom/Iface;");
+
+        let (_, m) = parse_method(".method public declared-synchronized constructor <init>()V\n.end method\n").unwrap();
+        assert!(m.constructor);
+        assert_eq!(m.modifiers, vec![Modifier::Public, Modifier::DeclaredSynchronized]);
+        assert_eq!(m.access_flags(), 0x30001);
+
+        let (_, m) = parse_method(".method public bridge synthetic compareTo(Ljava/lang/Object;)I\n.end method\n").unwrap();
+        assert_eq!(m.name, "compareTo");
+        assert_eq!(Modifier::from_access_flags(m.access_flags(), ModifierTarget::Method), m.modifiers);
     }
 
     #[test]
@@ -711,4 +1349,205 @@ mod tests {
         let (_, d) = parse_class(&smali).unwrap();
         assert_eq!(d.name.as_java_type(), "okhttp3.OkHttpClient");
     }
+
+    #[test]
+    fn test_parse_dex_instruction() {
+        let (_, i) = parse_dex_instruction("invoke-virtual {p0, v1}, Lfoo;->bar(I)V\n").unwrap();
+        match &i
+        {
+            DexInstruction::InvokeVirtual { registers, method } => {
+                assert_eq!(registers, &vec![Register::P(0), Register::V(1)]);
+                assert_eq!(method.class, TypeSignature::from_jni("Lfoo;"));
+                assert_eq!(method.name, "bar");
+                assert_eq!(method.signature.args, vec![TypeSignature::Int]);
+            }
+            _ => panic!("{:?}", i)
+        }
+
+        let (_, i) = parse_dex_instruction("const-string v0, \"a\\n\\\"b\\u00e9\"\n").unwrap();
+        assert_eq!(i, DexInstruction::ConstString { dest: Register::V(0), value: "a\n\"b\u{e9}".to_string() });
+
+        let (_, i) = parse_dex_instruction("const/high16 v0, 0x3f800000    # 1.0f\n").unwrap();
+        assert_eq!(i, DexInstruction::ConstHigh16 { dest: Register::V(0), value: 0x3f800000 });
+
+        assert!(parse_dex_instruction("const/4 v0, 0x10\n").is_err());
+        assert!(parse_dex_instruction("not-an-opcode v0\n").is_err());
+    }
+
+    #[test]
+    fn test_write_dex_instruction() {
+        let lines = [
+            "nop",
+            "move-object/from16 v0, p17",
+            "move-result-wide v2",
+            "return-void",
+            "const/4 v0, -0x1",
+            "const/16 v1, 0x3e8",
+            "const v0, 0x7f0b001c",
+            "const-wide v0, 0x7fffffffffffffffL",
+            "const-wide/16 v2, -0x1",
+            "const-wide/high16 v0, 0x3ff0000000000000L",
+            "const-string v1, \"it\\'s \\\"quoted\\\"\\n\"",
+            "const-class v0, [Ljava/lang/String;",
+            "check-cast p1, Lokhttp3/Request;",
+            "instance-of v0, p1, Lokhttp3/OkHttpClient;",
+            "new-array v1, v0, [I",
+            "filled-new-array {v0, v1, v2}, [I",
+            "filled-new-array/range {v0 .. v5}, [Ljava/lang/Object;",
+            "fill-array-data v0, :array_0",
+            "goto/16 :goto_3",
+            "packed-switch v0, :pswitch_data_0",
+            "cmp-long v0, v2, v4",
+            "if-ge v0, v1, :cond_1",
+            "if-nez p1, :cond_0",
+            "aget-object v0, p1, v2",
+            "iget-object v0, p0, Lokhttp3/OkHttpClient;->dispatcher:Lokhttp3/Dispatcher;",
+            "sput-boolean v0, Lcom/foo/Bar;->enabled:Z",
+            "invoke-direct {p0}, Ljava/lang/Object;-><init>()V",
+            "invoke-virtual {v0}, [Ljava/lang/Object;->clone()Ljava/lang/Object;",
+            "invoke-static/range {v0 .. v6}, Lkotlin/jvm/internal/Intrinsics;->checkParameterIsNotNull(Ljava/lang/Object;Ljava/lang/String;)V",
+            "int-to-long v0, v1",
+            "xor-int/2addr v0, v1",
+            "rsub-int v0, v1, 0x10",
+            "and-int/lit8 v0, v0, -0x80",
+            "invoke-polymorphic {p1, v0}, Ljava/lang/invoke/MethodHandle;->invoke([Ljava/lang/Object;)Ljava/lang/Object;, (I)V",
+            "invoke-custom {p0}, call_site_0(\"run\", ()Ljava/lang/Runnable;, \")\")@Ljava/lang/invoke/LambdaMetafactory;->metafactory()Ljava/lang/invoke/CallSite;",
+            "const-method-handle v0, invoke-static@Lcom/foo/Bar;->baz(I)V",
+            "const-method-type v0, (II)Ljava/lang/String;",
+        ];
+        for l in lines
+        {
+            let (_, i) = parse_dex_instruction(&format!("{}\n", l)).unwrap();
+            assert_eq!(write_instruction(&i), l);
+        }
+    }
+
+    #[test]
+    fn test_parse_catch() {
+        let (_, c) = parse_catch("    .catch Ljava/io/IOException; {:try_start_0 .. :try_end_0} :catch_0\n").unwrap();
+        match c
+        {
+            SmaliInstruction::TryCatch { exception, start, end, handler } => {
+                assert_eq!(exception.unwrap().as_jni_type(), "Ljava/io/IOException;");
+                assert_eq!((start.as_str(), end.as_str(), handler.as_str()), ("try_start_0", "try_end_0", "catch_0"));
+            }
+            _ => panic!("{:?}", c)
+        }
+
+        let (_, c) = parse_catch("    .catchall {:try_start_1 .. :try_end_1} :catchall_0\n").unwrap();
+        assert!(matches!(c, SmaliInstruction::TryCatch { exception: None, .. }));
+    }
+
+    #[test]
+    fn test_parse_payload() {
+        let (_, p) = parse_payload("    .sparse-switch\n        -0x1 -> :sswitch_0\n        0x64 -> :sswitch_1\n    .end sparse-switch\n").unwrap();
+        match p
+        {
+            Payload::SparseSwitch(s) => {
+                assert_eq!(s.entries, vec![(-1, "sswitch_0".to_string()), (100, "sswitch_1".to_string())]);
+            }
+            _ => panic!("{:?}", p)
+        }
+
+        let (_, p) = parse_payload("    .array-data 1\n        0x1t\n        0xfft\n        -0x2t\n    .end array-data\n").unwrap();
+        match p
+        {
+            Payload::ArrayData(a) => {
+                assert_eq!(a.element_width, 1);
+                assert_eq!(a.values, vec![1, -1, -2]);
+            }
+            _ => panic!("{:?}", p)
+        }
+
+        // Switch keys have to fit in an int
+        assert!(parse_payload("    .packed-switch 0x80000000\n        :pswitch_0\n    .end packed-switch\n").is_err());
+        assert!(parse_payload("    .sparse-switch\n        0x100000000 -> :sswitch_0\n    .end sparse-switch\n").is_err());
+        assert!(parse_payload("    .sparse-switch\n        -0x80000000 -> :sswitch_0\n    .end sparse-switch\n").is_ok());
+        let method = ".method f()V\n    .registers 1\n    .packed-switch -0x80000000\n        :pswitch_0\n    .end packed-switch\n.end method\n";
+        assert!(parse_method(method).is_ok());
+        assert!(parse_method(&method.replace("-0x80000000", "-0x80000001")).is_err());
+
+        let (_, p) = parse_payload("    .array-data 4\n        0x3f800000    # 1.0f\n    .end array-data\n").unwrap();
+        assert!(matches!(p, Payload::ArrayData(a) if a.values == vec![0x3f800000]));
+
+        // Values must fit the element width signed or unsigned, floats and doubles are stored as their bits
+        let (_, p) = parse_payload("    .array-data 1\n        0xff\n        -0x80\n    .end array-data\n").unwrap();
+        assert!(matches!(p, Payload::ArrayData(a) if a.values == vec![-1, -0x80]));
+        assert!(parse_payload("    .array-data 1\n        0x1ff\n    .end array-data\n").is_err());
+        assert!(parse_payload("    .array-data 2\n        -0x8001\n    .end array-data\n").is_err());
+        assert!(parse_method(".method f()V\n    .registers 1\n    .array-data 1\n        0x1ff\n    .end array-data\n.end method\n").is_err());
+        let (_, p) = parse_payload("    .array-data 4\n        1.5f\n        -Infinityf\n    .end array-data\n").unwrap();
+        assert!(matches!(p, Payload::ArrayData(a) if a.values == vec![0x3fc00000, 0xff800000u32 as i32 as i64]));
+        let (_, p) = parse_payload("    .array-data 8\n        2.0    # comment\n    .end array-data\n").unwrap();
+        assert!(matches!(p, Payload::ArrayData(a) if a.values == vec![0x4000000000000000]));
+    }
+
+    #[test]
+    fn test_parse_param_before_method_annotation() {
+        // baksmali writes an unannotated .param straight before the method annotations
+        let smali = r#".method public greet(Ljava/lang/String;)V
+    .locals 0
+    .param p1, "name"    # Ljava/lang/String;
+    .annotation build Landroidx/annotation/RequiresApi;
+        api = 0x1a
+    .end annotation
+
+    return-void
+.end method
+"#;
+        let (_, m) = parse_method(smali).unwrap();
+        assert_eq!(m.params.len(), 1);
+        assert_eq!(m.params[0].name.as_deref(), Some("name"));
+        assert!(m.params[0].annotations.is_empty());
+        assert_eq!(m.annotations.len(), 1);
+        assert_eq!(m.annotations[0].annotation_type.to_jni(), "Landroidx/annotation/RequiresApi;");
+        assert!(m.instructions.iter().all(|i| !matches!(i, SmaliInstruction::Directive(_))));
+    }
+
+    #[test]
+    fn test_parse_debug_info() {
+        let smali = r#".method public static greet(Ljava/lang/String;J)V
+    .locals 1
+    .param p0, "name"    # Ljava/lang/String;
+        .annotation build Landroid/annotation/NonNull;
+        .end annotation
+    .end param
+    .param p1    # J
+
+    .prologue
+    .line 10
+    .local v0, "list":Ljava/util/List;, "Ljava/util/List<Ljava/lang/String;>;"
+    const/4 v0, 0x0
+    .end local v0    # "list":Ljava/util/List;, "Ljava/util/List<Ljava/lang/String;>;"
+    .restart local v0    # "list":Ljava/util/List;, "Ljava/util/List<Ljava/lang/String;>;"
+    .local p0, null:V
+    return-void
+.end method
+"#;
+        let (_, m) = parse_method(smali).unwrap();
+        assert_eq!(m.params.len(), 2);
+        assert_eq!(m.params[0].name.as_deref(), Some("name"));
+        assert!(matches!(m.params[0].annotations[0].visibility, AnnotationVisibility::Build));
+        assert!(m.params[1].name.is_none() && m.params[1].annotations.is_empty());
+        assert_eq!(m.parameter_type(Register::P(1)), Some(&TypeSignature::Long));
+        assert!(m.annotations.is_empty());
+
+        assert!(matches!(m.instructions[0], SmaliInstruction::Prologue));
+        match &m.instructions[2]
+        {
+            SmaliInstruction::Local { register, name, signature, generic } => {
+                assert_eq!(*register, Register::V(0));
+                assert_eq!(name.as_deref(), Some("list"));
+                assert_eq!(signature.as_ref().unwrap().to_jni(), "Ljava/util/List;");
+                assert_eq!(generic.as_deref(), Some("Ljava/util/List<Ljava/lang/String;>;"));
+            }
+            i => panic!("{:?}", i)
+        }
+        assert!(matches!(m.instructions[4], SmaliInstruction::EndLocal(Register::V(0))));
+        assert!(matches!(m.instructions[5], SmaliInstruction::RestartLocal(Register::V(0))));
+        assert!(matches!(&m.instructions[6], SmaliInstruction::Local { name: None, signature: Some(TypeSignature::Void), .. }));
+
+        // Numbers too big for a u32 are errors
+        assert!(parse_method(&smali.replace(".line 10", ".line 4294967296")).is_err());
+    }
 }
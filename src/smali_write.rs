@@ -1,158 +1,583 @@
-use crate::types::{AnnotationValue, Modifier, SmaliAnnotation, SmaliClass, SmaliInstruction, SmaliMethod};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use crate::instructions::{DexInstruction, Operand, Payload, Register};
+use crate::types::{EncodedValue, LayoutSegment, MemberKind, Modifier, RegisterCount, SmaliAnnotation, SmaliClass, SmaliField, SmaliInstruction, SmaliMethod, SourceLayout, UnparsedKind};
 
-fn write_modifiers(mods: &Vec<Modifier>) -> String
+fn write_modifiers(mods: &[Modifier]) -> String
 {
    let mut out = "".to_string();
 
    for m in mods
    {
-       out.push_str(&*format!("{} ", m.to_str()));
+       out.push_str(&format!("{} ", m.to_str()));
    }
 
    out
 }
 
-fn write_annotation(ann: &SmaliAnnotation, subannotation: bool, indented: bool) -> String
+pub(crate) fn write_annotation(ann: &SmaliAnnotation, subannotation: bool, base_indent: &str) -> String
 {
-    let end_tag;
-    let mut indent = "";
     let inset = "    ";
-    if indented && subannotation { indent = "        "; }
-    else if indented || subannotation { indent = "    "; }
-
     let mut out = if subannotation
     {
-        end_tag = ".end subannotation";
         ".subannotation ".to_string()
     }
     else
     {
-        end_tag = ".end annotation";
-        format!("{}.annotation {} ", indent, ann.visibility.to_str())
+        format!("{}.annotation {} ", base_indent, ann.visibility.to_str())
     };
     out.push_str(&ann.annotation_type.to_jni());
     out.push('\n');
 
     for i in &ann.elements
     {
-        out.push_str(&*format!("{}{}{} = ", indent, inset, i.name));
-        match &i.value
-        {
-            AnnotationValue::Array(a) => {
-                out.push_str("{\n");
-                let mut c = 0;
-                for v in a
-                {
-                    out.push_str(indent);
-                    out.push_str(inset);
-                    out.push_str(inset);
-                    out.push_str(v);
-                    c += 1;
-                    if c < a.len() { out.push(','); }
-                    out.push('\n');
-                }
-                out.push_str(&*format!("{}{}}}\n", indent, inset));
-            }
-            AnnotationValue::SubAnnotation(s) => { out.push_str( &*write_annotation(s, true, indented)); }
-            AnnotationValue::Enum(o, s) => { out.push_str(&*format!(".enum {}->{}:{}\n", o.as_jni_type(), s, o.as_jni_type())); }
-            AnnotationValue::Single(s) => { out.push_str(&*format!("{}\n", s)); }
-        }
+        out.push_str(&format!("{}{}{} = {}\n", base_indent, inset, i.name, write_value(&i.value, &format!("{}{}", base_indent, inset))));
     }
 
-    out.push_str(indent);
-    out.push_str(end_tag);
+    out.push_str(base_indent);
+    out.push_str(if subannotation { ".end subannotation" } else { ".end annotation" });
     out.push('\n');
 
     out
 }
 
+/* Literals are written as hex like baksmali, with the suffix for their type */
+fn hex(v: i64, suffix: &str) -> String
+{
+    if v < 0 { format!("-0x{:x}{}", v.unsigned_abs(), suffix) } else { format!("0x{:x}{}", v, suffix) }
+}
+
+/* Formats a floating point value the same way as Java's Float.toString and Double.toString */
+fn java_float(v: f64, digits: String) -> String
+{
+    if v.is_nan() { return "NaN".to_string(); }
+    if v.is_infinite() { return if v > 0.0 { "Infinity" } else { "-Infinity" }.to_string(); }
+
+    let a = v.abs();
+    if a == 0.0 || (1e-3..1e7).contains(&a)
+    {
+        if digits.contains('.') { digits } else { format!("{}.0", digits) }
+    }
+    else
+    {
+        // Scientific notation with a decimal point in the mantissa and an upper case E
+        let (mantissa, exponent) = digits.split_once('e').unwrap_or((&digits, "0"));
+        let mantissa = if mantissa.contains('.') { mantissa.to_string() } else { format!("{}.0", mantissa) };
+        format!("{}E{}", mantissa, exponent)
+    }
+}
+
+/* The text of a value, arrays and subannotations span several lines with their contents indented from `indent` */
+pub(crate) fn write_value(value: &EncodedValue, indent: &str) -> String
+{
+    match value {
+        EncodedValue::Byte(v) => hex(*v as i64, "t"),
+        EncodedValue::Short(v) => hex(*v as i64, "s"),
+        EncodedValue::Char(c) => format!("'{}'", escape_string(&String::from_utf16_lossy(&[*c]))),
+        EncodedValue::Int(v) => hex(*v as i64, ""),
+        EncodedValue::Long(v) => hex(*v, "L"),
+        EncodedValue::Float(f) => {
+            let digits = if f.abs() >= 1e7 || (*f != 0.0 && f.abs() < 1e-3) { format!("{:e}", f) } else { format!("{}", f) };
+            format!("{}f", java_float(*f as f64, digits))
+        }
+        EncodedValue::Double(d) => {
+            let digits = if d.abs() >= 1e7 || (*d != 0.0 && d.abs() < 1e-3) { format!("{:e}", d) } else { format!("{}", d) };
+            java_float(*d, digits)
+        }
+        EncodedValue::String(s) => format!("\"{}\"", escape_string(s)),
+        EncodedValue::Type(t) => t.to_jni(),
+        EncodedValue::Field(f) => f.to_jni(),
+        EncodedValue::Method(m) => m.to_jni(),
+        EncodedValue::MethodType(p) => p.to_jni(),
+        EncodedValue::MethodHandle(h) => h.to_jni(),
+        EncodedValue::Enum(f) => format!(".enum {}", f.to_jni()),
+        EncodedValue::Array(a) if a.is_empty() => "{}".to_string(),
+        EncodedValue::Array(a) => {
+            let inner = format!("{}    ", indent);
+            let items: Vec<String> = a.iter().map(|v| format!("{}{}", inner, write_value(v, &inner))).collect();
+            format!("{{\n{}\n{}}}", items.join(",\n"), indent)
+        }
+        EncodedValue::Annotation(a) => write_annotation(a, true, indent).trim_end().to_string(),
+        EncodedValue::Null => "null".to_string(),
+        EncodedValue::Boolean(b) => b.to_string()
+    }
+}
+
+/* Escapes a string the same way baksmali does, non printable characters as \uXXXX */
+pub(crate) fn escape_string(s: &str) -> String
+{
+    let mut out = String::new();
+    for u in s.encode_utf16()
+    {
+        match u {
+            0x27 | 0x22 | 0x5c => { out.push('\\'); out.push(u as u8 as char); }
+            0x20..=0x7e => out.push(u as u8 as char),
+            0x0a => out.push_str("\\n"),
+            0x0d => out.push_str("\\r"),
+            0x09 => out.push_str("\\t"),
+            _ => out.push_str(&format!("\\u{:04x}", u))
+        }
+    }
+    out
+}
+
+/* Literals are written as signed hex, with an L suffix only if they don't fit in an int */
+fn write_literal(v: i64) -> String
+{
+    let suffix = if v < i32::MIN as i64 || v > i32::MAX as i64 { "L" } else { "" };
+    if v < 0 { format!("-0x{:x}{}", v.unsigned_abs(), suffix) }
+    else { format!("0x{:x}{}", v, suffix) }
+}
+
+/* Whether a number is shorter written as a float than as an int in scientific notation, the way baksmali guesses
+   at literals. Any run of 000 or 999 in the float's mantissa is cut off first, as it's most likely imprecision */
+fn shorter_as_float(float: f64, int: i64) -> bool
+{
+    if float.is_infinite() { return true; }
+    let f = format!("{:e}", float);
+    let f = match (f.find('.'), f.find('e')) {
+        (Some(point), Some(exponent)) => match f[point..exponent].find("000").or_else(|| f[point..exponent].find("999")) {
+            Some(cut) => format!("{}{}", &f[..point + cut], &f[exponent..]),
+            None => f
+        },
+        _ => f
+    };
+    f.len() < format!("{:e}", int).len()
+}
+
+/* Same as baksmali's LiteralTools.isLikelyFloat */
+fn likely_float(v: i32) -> bool
+{
+    // NaN, Float.MAX_VALUE, pi and e
+    if [0x7fc00000, 0x7f7fffff, 0x40490fdb, 0x402df854].contains(&v) { return true; }
+    if v == i32::MAX || v == i32::MIN { return false; }
+
+    // Resource ids
+    let (package, kind, id) = (v >> 24, (v >> 16) & 0xff, v & 0xffff);
+    if (package == 0x7f || package == 1) && kind < 0x1f && id < 0xfff { return false; }
+
+    let f = f32::from_bits(v as u32);
+    !f.is_nan() && shorter_as_float(f as f64, v as i64)
+}
+
+/* Same as baksmali's LiteralTools.isLikelyDouble */
+fn likely_double(v: i64) -> bool
+{
+    // NaN, Double.MAX_VALUE, pi and e
+    if [0x7ff8000000000000, 0x7fefffffffffffff, 0x400921fb54442d18, 0x4005bf0a8b145769].contains(&v) { return true; }
+    if v == i64::MAX || v == i64::MIN { return false; }
+
+    let d = f64::from_bits(v as u64);
+    !d.is_nan() && shorter_as_float(d, v)
+}
+
+/* The comment baksmali writes after an int literal that is likely the bits of a float, such as `    # 1.0f` */
+fn float_comment(v: i32) -> String
+{
+    if !likely_float(v) { return String::new(); }
+    let f = f32::from_bits(v as u32);
+    let text = if f == f32::INFINITY { "Float.POSITIVE_INFINITY".to_string() }
+        else if f == f32::NEG_INFINITY { "Float.NEGATIVE_INFINITY".to_string() }
+        else if f.is_nan() { "Float.NaN".to_string() }
+        else if f == f32::MAX { "Float.MAX_VALUE".to_string() }
+        else if f == std::f32::consts::PI { "(float)Math.PI".to_string() }
+        else if f == std::f32::consts::E { "(float)Math.E".to_string() }
+        else { write_value(&EncodedValue::Float(f), "") };
+    format!("    # {}", text)
+}
+
+/* The comment baksmali writes after a long literal that is likely the bits of a double */
+fn double_comment(v: i64) -> String
+{
+    if !likely_double(v) { return String::new(); }
+    let d = f64::from_bits(v as u64);
+    let text = if d == f64::INFINITY { "Double.POSITIVE_INFINITY".to_string() }
+        else if d == f64::NEG_INFINITY { "Double.NEGATIVE_INFINITY".to_string() }
+        else if d.is_nan() { "Double.NaN".to_string() }
+        else if d == f64::MAX { "Double.MAX_VALUE".to_string() }
+        else if d == std::f64::consts::PI { "Math.PI".to_string() }
+        else if d == std::f64::consts::E { "Math.E".to_string() }
+        else { write_value(&EncodedValue::Double(d), "") };
+    format!("    # {}", text)
+}
+
+/* Constants get the same float or double comment as in baksmali's output */
+fn instruction_comment(instruction: &DexInstruction) -> String
+{
+    match instruction {
+        DexInstruction::Const4 { value, .. } => float_comment(i32::from(*value)),
+        DexInstruction::Const16 { value, .. } => float_comment(i32::from(*value)),
+        DexInstruction::Const { value, .. } | DexInstruction::ConstHigh16 { value, .. } => float_comment(*value),
+        DexInstruction::ConstWide16 { value, .. } => double_comment(i64::from(*value)),
+        DexInstruction::ConstWide32 { value, .. } => double_comment(i64::from(*value)),
+        DexInstruction::ConstWide { value, .. } | DexInstruction::ConstWideHigh16 { value, .. } => double_comment(*value),
+        _ => String::new()
+    }
+}
+
+fn write_operand(operand: &Operand) -> String
+{
+    match operand
+    {
+        Operand::Register(r) => r.to_string(),
+        Operand::RegisterList(l) => format!("{{{}}}", l.iter().map(|r| r.to_string()).collect::<Vec<String>>().join(", ")),
+        Operand::RegisterRange(r) => r.to_string(),
+        Operand::Literal(l) => write_literal(*l),
+        Operand::Label(l) => format!(":{}", l),
+        Operand::String(s) => format!("\"{}\"", escape_string(s)),
+        Operand::Type(t) => t.to_jni(),
+        Operand::Field(f) => f.to_jni(),
+        Operand::Method(m) => m.to_jni(),
+        Operand::Proto(p) => p.to_jni(),
+        Operand::CallSite(c) => c.to_string(),
+        Operand::MethodHandle(m) => m.to_jni()
+    }
+}
+
+fn write_payload(payload: &Payload) -> String
+{
+    match payload
+    {
+        Payload::PackedSwitch(p) => {
+            let mut out = format!("    .packed-switch {}\n", write_literal(i64::from(p.first_key)));
+            for t in &p.targets { out.push_str(&format!("        :{}\n", t)); }
+            out.push_str("    .end packed-switch\n");
+            out
+        }
+        Payload::SparseSwitch(s) => {
+            let mut out = "    .sparse-switch\n".to_string();
+            for (k, t) in &s.entries { out.push_str(&format!("        {} -> :{}\n", write_literal(i64::from(*k)), t)); }
+            out.push_str("    .end sparse-switch\n");
+            out
+        }
+        Payload::ArrayData(a) => {
+            let mut out = format!("    .array-data {}\n", a.element_width);
+            for v in &a.values
+            {
+                let v = *v;
+                out.push_str(&match a.element_width {
+                    1 => format!("        {}t\n", write_literal(v)),
+                    2 => format!("        {}s\n", write_literal(v)),
+                    4 => format!("        {}{}\n", write_literal(v), float_comment(v as i32)),
+                    _ => format!("        {}{}\n", hex(v, "L"), double_comment(v))
+                });
+            }
+            out.push_str("    .end array-data\n");
+            out
+        }
+    }
+}
+
+pub(crate) fn write_instruction(instruction: &DexInstruction) -> String
+{
+    let mnemonic = instruction.opcode().mnemonic();
+    let operands: Vec<String> = instruction.operands().iter().map(write_operand).collect();
+    if operands.is_empty() { mnemonic.to_string() }
+    else { format!("{} {}", mnemonic, operands.join(", ")) }
+}
+
+fn write_local_end(directive: &str, register: &Register, locals: &HashMap<Register, String>) -> String
+{
+    match locals.get(register)
+    {
+        Some(l) => format!("    .{} {}    # {}\n", directive, register, l),
+        None => format!("    .{} {}\n", directive, register)
+    }
+}
+
 fn write_method(method: &SmaliMethod) -> String
 {
     let mut out = format!(".method {}", write_modifiers(&method.modifiers));
     if method.constructor { out.push_str("constructor "); }
     out.push_str(&format!("{}{}\n", method.name, method.signature.to_jni()));
-    if method.instructions.len() > 0
+    if !method.instructions.is_empty()
     {
-        out.push_str(&format!("    .locals {:}\n", method.locals));
+        match method.registers
+        {
+            RegisterCount::Locals(l) => out.push_str(&format!("    .locals {:}\n", l)),
+            RegisterCount::Registers(r) => out.push_str(&format!("    .registers {:}\n", r))
+        }
+    }
+
+    for p in &method.params
+    {
+        out.push_str(&format!("    .param {}", p.register));
+        if let Some(n) = &p.name { out.push_str(&format!(", \"{}\"", escape_string(n))); }
+        if let Some(t) = method.parameter_type(p.register) { out.push_str(&format!("    # {}", t.to_jni())); }
+        out.push('\n');
+        if !p.annotations.is_empty()
+        {
+            for a in &p.annotations { out.push_str(&write_annotation(a, false, "        ")); }
+            out.push_str("    .end param\n");
+        }
     }
 
     for a in &method.annotations
     {
-        out.push_str(&write_annotation(a, false, true));
+        out.push_str(&write_annotation(a, false, "    "));
     }
 
+    // The last .local seen for each register, echoed as a comment on .end local and .restart local
+    let mut locals: HashMap<Register, String> = HashMap::new();
+
     for i in &method.instructions
     {
         match i
         {
             SmaliInstruction::Line(l) => { out.push_str(&format!("    .line {:}\n", l)); }
             SmaliInstruction::Label(l) => { out.push_str(&format!("    :{}\n", l)); }
-            SmaliInstruction::Instruction(s) => { out.push_str(&format!("    {}\n", s)); }
+            SmaliInstruction::Instruction(i) => { out.push_str(&format!("    {}{}\n", write_instruction(i), instruction_comment(i))); }
+            SmaliInstruction::TryCatch { exception, start, end, handler } => {
+                match exception {
+                    Some(e) => out.push_str(&format!("    .catch {} ", e.as_jni_type())),
+                    None => out.push_str("    .catchall ")
+                }
+                out.push_str(&format!("{{:{} .. :{}}} :{}\n", start, end, handler));
+            }
+            SmaliInstruction::Payload(p) => { out.push_str(&write_payload(p)); }
+            SmaliInstruction::Local { register, name, signature, generic } => {
+                let mut local = format!("{}:{}", name.as_ref().map_or("null".to_string(), |n| format!("\"{}\"", escape_string(n))),
+                                        signature.as_ref().map_or("V".to_string(), |s| s.to_jni()));
+                if let Some(g) = generic { local.push_str(&format!(", \"{}\"", escape_string(g))); }
+                if name.is_none() && signature.is_none() && generic.is_none() { out.push_str(&format!("    .local {}\n", register)); }
+                else { out.push_str(&format!("    .local {}, {}\n", register, local)); }
+                locals.insert(*register, local);
+            }
+            SmaliInstruction::EndLocal(r) => { out.push_str(&write_local_end("end local", r, &locals)); }
+            SmaliInstruction::RestartLocal(r) => { out.push_str(&write_local_end("restart local", r, &locals)); }
+            SmaliInstruction::Prologue => { out.push_str("    .prologue\n"); }
+            SmaliInstruction::Epilogue => { out.push_str("    .epilogue\n"); }
+            SmaliInstruction::Directive(d) => { out.push_str(&format!("    {}\n", d)); }
         }
     }
 
-    out.push_str(".end method\n\n");
+    out.push_str(".end method\n");
+    out
+}
+
+fn write_field(field: &SmaliField) -> String
+{
+    let mut out = format!(".field {}{}:{}", write_modifiers(&field.modifiers), field.name, field.signature.to_jni());
+    if let Some(iv) = &field.initial_value
+    {
+        out.push_str(&format!(" = {}", iv));
+    }
+    out.push('\n');
+    if !field.annotations.is_empty()
+    {
+        for a in &field.annotations {  out.push_str(&write_annotation(a, false, "    ")); }
+        out.push_str(".end field\n");
+    }
+    out
+}
+
+/* Every top level item of the class in the standard order, with the key used to find it in a SourceLayout */
+pub(crate) fn class_members(dex: &SmaliClass) -> Vec<(MemberKind, String, String)>
+{
+    let mut members = vec![
+        (MemberKind::Class, String::new(), format!(".class {}{}\n", write_modifiers(&dex.modifiers), dex.name.as_jni_type())),
+        (MemberKind::Super, String::new(), format!(".super {}\n", dex.super_class.as_jni_type()))
+    ];
+    if let Some(s) = &dex.source
+    {
+        members.push((MemberKind::Source, String::new(), format!(".source \"{}\"\n", s)));
+    }
+    for i in &dex.implements
+    {
+        members.push((MemberKind::Implements, i.as_jni_type(), format!(".implements {}\n", i.as_jni_type())));
+    }
+    for a in &dex.annotations
+    {
+        members.push((MemberKind::Annotation, a.annotation_type.to_jni(), write_annotation(a, false, "")));
+    }
+    for f in &dex.fields
+    {
+        members.push((MemberKind::Field, f.name.clone(), write_field(f)));
+    }
+    for m in &dex.methods
+    {
+        members.push((MemberKind::Method, format!("{}{}", m.name, m.signature.to_jni()), write_method(m)));
+    }
+    for u in &dex.unparsed
+    {
+        members.push((MemberKind::Unparsed, u.text.clone(), u.text.clone()));
+    }
+    members
+}
+
+pub(crate) fn hash_member(text: &str) -> u64
+{
+    let mut h = DefaultHasher::new();
+    text.hash(&mut h);
+    h.finish()
+}
+
+/* Writes a class keeping the original text of everything that hasn't changed since it was parsed */
+fn write_class_layout(dex: &SmaliClass, layout: &SourceLayout) -> String
+{
+    let members = class_members(dex);
+
+    // Match each member in the layout to the first current member of the same kind and key
+    let mut matched = vec![false; members.len()];
+    let matches: Vec<Option<usize>> = layout.segments.iter().map(|seg| match seg {
+        LayoutSegment::Member { kind, key, .. } => {
+            let i = (0..members.len()).find(|&i| !matched[i] && members[i].0 == *kind && members[i].1 == *key)?;
+            matched[i] = true;
+            Some(i)
+        }
+        LayoutSegment::Trivia(_) => None
+    }).collect();
+
+    // New members go after the last member of the same kind, or of the kind before it if there are none
+    let kinds = [MemberKind::Class, MemberKind::Super, MemberKind::Source, MemberKind::Implements,
+                 MemberKind::Annotation, MemberKind::Field, MemberKind::Method, MemberKind::Unparsed];
+    let mut anchors: Vec<(MemberKind, Option<usize>, bool)> = vec![];
+    let mut anchor = None;
+    for k in kinds
+    {
+        let last = layout.segments.iter().rposition(|seg| matches!(seg, LayoutSegment::Member { kind, .. } if *kind == k));
+        if last.is_some() { anchor = last; }
+        anchors.push((k, anchor, last.is_none()));
+    }
+
+    let write_new = |out: &mut String, at: Option<usize>| {
+        for (k, _, absent) in anchors.iter().filter(|(_, a, _)| *a == at)
+        {
+            let new: Vec<&String> = members.iter().enumerate().filter(|(i, m)| !matched[*i] && m.0 == *k).map(|(_, m)| &m.2).collect();
+            if new.is_empty() { continue; }
+
+            let section = match k {
+                MemberKind::Annotation => Some("annotations"),
+                MemberKind::Field => Some("fields"),
+                MemberKind::Method => Some("methods"),
+                MemberKind::Unparsed => Some(""),
+                _ => None
+            };
+            for (n, text) in new.iter().enumerate()
+            {
+                if let Some(name) = section
+                {
+                    if !out.ends_with("\n\n") { out.push('\n'); }
+                    if n == 0 && *absent && !name.is_empty() { out.push_str(&format!("# {}\n", name)); }
+                }
+                out.push_str(text);
+            }
+        }
+    };
+
+    let mut out = String::new();
+    let mut removed = false;
+    write_new(&mut out, None);
+    for (n, seg) in layout.segments.iter().enumerate()
+    {
+        match (seg, matches[n])
+        {
+            // Drop the blank lines that separated a removed member from the next one
+            (LayoutSegment::Trivia(t), _) if removed => {
+                let rest = t.trim_start();
+                out.push_str(&t[t[..t.len() - rest.len()].rfind('\n').map_or(0, |i| i + 1)..]);
+            }
+            (LayoutSegment::Trivia(t), _) => out.push_str(t),
+            (LayoutSegment::Member { text, hash, .. }, Some(i)) => {
+                let current = &members[i].2;
+                if hash_member(current) == *hash { out.push_str(text); }
+                else
+                {
+                    // Changed, keep the whitespace around the original
+                    let trimmed = text.trim_start();
+                    out.push_str(&text[..text.len() - trimmed.len()]);
+                    out.push_str(current.trim_end());
+                    out.push_str(&trimmed[trimmed.trim_end().len()..]);
+                }
+            }
+            // Removed since it was parsed
+            (LayoutSegment::Member { .. }, None) => {}
+        }
+        removed = matches!(seg, LayoutSegment::Member { .. }) && matches[n].is_none();
+        write_new(&mut out, Some(n));
+    }
+
     out
 }
 
 pub(crate) fn write_class(dex: &SmaliClass) -> String
 {
+    match &dex.layout
+    {
+        Some(l) => write_class_layout(dex, l),
+        None => write_class_standard(dex)
+    }
+}
+
+/* Writes a class in the same layout as baksmali */
+fn write_class_standard(dex: &SmaliClass) -> String
+{
+    // Members that failed to parse are written back as they were, at the end of their section
+    let has_unparsed = |kind| dex.unparsed.iter().any(|u| u.kind == kind);
+    let write_unparsed = |kind| {
+        let mut out = String::new();
+        for u in dex.unparsed.iter().filter(|u| u.kind == kind)
+        {
+            out.push_str(&u.text);
+            if !u.text.ends_with('\n') { out.push('\n'); }
+            out.push('\n');
+        }
+        out
+    };
+
     let mut out = format!(".class {}{}\n", write_modifiers(&dex.modifiers), dex.name.as_jni_type());
-    out.push_str(&*format!(".super {}\n", dex.super_class.as_jni_type()));
+    out.push_str(&format!(".super {}\n", dex.super_class.as_jni_type()));
     if let Some(s) = &dex.source
     {
-        out.push_str(&*format!(".source \"{}\"\n", s));
+        out.push_str(&format!(".source \"{}\"\n", s));
     }
 
-    if dex.implements.len() > 0
+    if !dex.implements.is_empty()
     {
         out.push_str("\n# interfaces\n");
         for i in &dex.implements
         {
             out.push_str(".implements ");
             out.push_str(&i.as_jni_type());
-            out.push_str("\n");
+            out.push('\n');
         }
     }
 
-    if dex.annotations.len() > 0
+    if !dex.annotations.is_empty() || has_unparsed(UnparsedKind::Annotation)
     {
         out.push_str("\n# annotations\n");
         for a in &dex.annotations
         {
-            out.push_str(&write_annotation(a, false, false));
-            out.push_str("\n");
+            out.push_str(&write_annotation(a, false, ""));
+            out.push('\n');
         }
+        out.push_str(&write_unparsed(UnparsedKind::Annotation));
     }
 
-    if dex.fields.len() > 0
+    if !dex.fields.is_empty() || has_unparsed(UnparsedKind::Field)
     {
         out.push_str("\n# fields\n");
         for f in &dex.fields
         {
-            out.push_str(&*format!(".field {}{}:{}", write_modifiers(&f.modifiers), f.name, f.signature.to_jni()));
-            if let Some(iv) = &f.initial_value
-            {
-                out.push_str(&*format!(" = {}", iv));
-            }
-            out.push_str("\n");
-            if f.annotations.len() > 0
-            {
-                for a in &f.annotations {  out.push_str(&write_annotation(a, false, true)); }
-                out.push_str(".end field\n");
-            }
-            out.push_str("\n");
+            out.push_str(&write_field(f));
+            out.push('\n');
         }
+        out.push_str(&write_unparsed(UnparsedKind::Field));
     }
 
-    if dex.methods.len() > 0
+    if !dex.methods.is_empty() || has_unparsed(UnparsedKind::Method)
     {
         out.push_str("\n# methods\n");
         for m in &dex.methods
         {
-            out.push_str(&*write_method(m));
+            out.push_str(&write_method(m));
+            out.push('\n');
         }
+        out.push_str(&write_unparsed(UnparsedKind::Method));
     }
 
+    out.push_str(&write_unparsed(UnparsedKind::Other));
+
     out
-}
\ No newline at end of file
+}
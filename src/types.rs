@@ -2,37 +2,155 @@
 /* Struct to represent a java object type identifer e.g. java.lang.Object */
 /* They are stored in the smali native (also JNI) format e.g. Ljava/lang/Object; */
 
-use std::error::Error;
 use std::{fmt, fs};
 use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
-use nom::Err::Failure;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use nom::Err::{Error, Failure, Incomplete};
 use nom::IResult;
-use crate::smali_parse::parse_class;
-use crate::smali_parse::parse_methodsignature;
-use crate::smali_write::write_class;
+use crate::instructions::{DexInstruction, Operand, Payload, Register, RegisterRange};
+use crate::smali_parse::{parse_class, parse_class_header, parse_class_lenient};
+use crate::smali_parse::{parse_encoded_value, parse_fieldref, parse_methodref, parse_methodsignature};
+use crate::smali_write::{write_class, write_value};
 
-/* Custom error for our command helper */
+/// Errors returned when reading, parsing or writing smali
+///
+/// Parse errors carry the position of the failure and render as a rustc style diagnostic
+/// pointing at the offending line.
+///
+/// # Examples
+///
+/// ```
+///  use smali::types::{SmaliClass, SmaliError};
+///
+///  let e = SmaliClass::from_smali(".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n.method public f()V\n    .locals 0\n    bogus v0\n.end method\n").unwrap_err();
+///  match &e {
+///      SmaliError::Parse { line, column, .. } => assert_eq!((*line, *column), (6, 5)),
+///      _ => panic!("{}", e)
+///  }
+///  println!("{}", e);
+/// ```
 #[derive(Debug)]
-pub struct SmaliError {
-    pub details: String
+pub enum SmaliError {
+    /// Reading or writing a file failed
+    Io {
+        path: Option<PathBuf>,
+        error: std::io::Error
+    },
+    /// The smali text could not be parsed, line and column are 1-based
+    Parse {
+        file: Option<PathBuf>,
+        line: usize,
+        column: usize,
+        expected: String,
+        /// The source line containing the error
+        snippet: String
+    },
+    /// The input is valid but uses something this crate doesn't handle
+    Unsupported(String),
+    /// A DEX file is malformed, offset is the position in the file where reading failed
+    InvalidDex {
+        offset: usize,
+        message: String
+    },
+    /// Any other error, e.g. an inconsistent class or method
+    Other(String)
 }
 
 impl SmaliError {
     pub fn new(msg: &str) -> SmaliError {
-        SmaliError{details: msg.to_string()}
+        SmaliError::Other(msg.to_string())
     }
+
+    /// Builds a parse error for the point in `source` where `remaining` begins
+    pub(crate) fn parse(source: &str, remaining: &str) -> SmaliError
+    {
+        // Point at the first token rather than the whitespace before it
+        let remaining = remaining.trim_start();
+        let offset = source.len() - remaining.len();
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[offset..].find('\n').map_or(source.len(), |i| offset + i);
+
+        SmaliError::Parse {
+            file: None,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            expected: expected_at(before).to_string(),
+            snippet: source[line_start..line_end].trim_end_matches('\r').to_string()
+        }
+    }
+
+    /// Builds a parse error pointing at the start of a line, for the line based formats such as patch files
+    pub(crate) fn at_line(line: usize, text: &str, expected: &str) -> SmaliError
+    {
+        SmaliError::Parse {
+            file: None,
+            line,
+            column: text.chars().take_while(|c| c.is_whitespace()).count() + 1,
+            expected: expected.to_string(),
+            snippet: text.to_string()
+        }
+    }
+
+    /// Attaches the path of the file being read or written
+    pub fn with_path(self, path: &Path) -> SmaliError
+    {
+        match self {
+            SmaliError::Io { error, .. } => SmaliError::Io { path: Some(path.to_path_buf()), error },
+            SmaliError::Parse { line, column, expected, snippet, .. } =>
+                SmaliError::Parse { file: Some(path.to_path_buf()), line, column, expected, snippet },
+            e => e
+        }
+    }
+}
+
+/* Works out what could have appeared at the end of `before` from the enclosing block */
+fn expected_at(before: &str) -> &'static str
+{
+    let mut blocks = vec![];
+    for l in before.lines().map(|l| l.trim())
+    {
+        if l.starts_with(".method") { blocks.push("an instruction or directive"); }
+        else if l.starts_with(".annotation") || l.starts_with(".subannotation") { blocks.push("an annotation element"); }
+        else if l.starts_with(".packed-switch") || l.starts_with(".sparse-switch") || l.starts_with(".array-data") { blocks.push("a payload entry"); }
+        else if l.starts_with(".end method") || l.starts_with(".end annotation") || l.starts_with(".end subannotation")
+            || l.starts_with(".end packed-switch") || l.starts_with(".end sparse-switch") || l.starts_with(".end array-data") { blocks.pop(); }
+    }
+    blocks.pop().unwrap_or("a class directive, field or method")
 }
 
 impl fmt::Display for SmaliError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,"{}",self.details)
+        match self {
+            SmaliError::Io { path: Some(p), error } => write!(f, "error: {}\n --> {}", error, p.display()),
+            SmaliError::Io { path: None, error } => write!(f, "error: {}", error),
+            SmaliError::Parse { file, line, column, expected, snippet } => {
+                let gutter = " ".repeat(line.to_string().len());
+                let token = snippet.chars().skip(column - 1).take_while(|c| !c.is_whitespace()).count().max(1);
+                writeln!(f, "error: expected {}", expected)?;
+                match file {
+                    Some(p) => writeln!(f, "{} --> {}:{}:{}", gutter, p.display(), line, column)?,
+                    None => writeln!(f, "{} --> {}:{}", gutter, line, column)?
+                }
+                writeln!(f, "{} |", gutter)?;
+                writeln!(f, "{} | {}", line, snippet)?;
+                write!(f, "{} | {}{}", gutter, " ".repeat(column - 1), "^".repeat(token))
+            }
+            SmaliError::Unsupported(s) => write!(f, "unsupported: {}", s),
+            SmaliError::InvalidDex { offset, message } => write!(f, "error: invalid DEX file at offset 0x{:x}: {}", offset, message),
+            SmaliError::Other(s) => write!(f, "{}", s)
+        }
     }
 }
 
-impl Error for SmaliError {
-    fn description(&self) -> &str {
-        &self.details
+impl std::error::Error for SmaliError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SmaliError::Io { error, .. } => Some(error),
+            _ => None
+        }
     }
 }
 
@@ -42,11 +160,13 @@ impl Error for SmaliError {
 /// # Examples
 ///
 /// ```
+///  use smali::types::ObjectIdentifier;
+///
 ///  let o = ObjectIdentifier::from_java_type("com.basic.Test");
 ///  assert_eq!(o.as_java_type(), "com.basic.Test");
 ///  assert_eq!(o.as_jni_type(), "Lcom/basic/Test;");
 /// ```
-#[derive(Debug, Eq)]
+#[derive(Debug, Clone, Eq)]
 pub struct ObjectIdentifier {
     jni_type: String
 }
@@ -72,7 +192,7 @@ impl ObjectIdentifier
 
     pub fn from_java_type(t: &str) -> ObjectIdentifier
     {
-        let jni_type = format!("L{};", t.replace(".", "/"));
+        let jni_type = format!("L{};", t.replace('.', "/"));
         ObjectIdentifier { jni_type }
     }
 
@@ -83,8 +203,7 @@ impl ObjectIdentifier
 
     pub fn as_java_type(&self) -> String
     {
-        let java_type = self.jni_type[1..self.jni_type.len() - 1].replace("/", ".");
-        java_type
+        self.jni_type[1..self.jni_type.len() - 1].replace('/', ".")
     }
 }
 
@@ -98,7 +217,7 @@ impl ObjectIdentifier
 ///  let t = TypeSignature::Bool;
 ///  assert_eq!(t.to_jni(), "Z");
 /// ```
-#[derive(Debug, Eq)]
+#[derive(Debug, Clone, Eq)]
 pub enum TypeSignature {
     Array(Box<TypeSignature>),
     Object(ObjectIdentifier),
@@ -119,12 +238,18 @@ impl PartialEq<Self> for TypeSignature {
     }
 }
 
+impl Hash for TypeSignature {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_jni().hash(state);
+    }
+}
+
 impl TypeSignature {
    pub fn from_jni(s: &str) -> TypeSignature
    {
        match s.chars().nth(0).unwrap()
        {
-           '[' => TypeSignature::Array(Box::new(TypeSignature::from_jni(&s[1..].to_string()))),
+           '[' => TypeSignature::Array(Box::new(TypeSignature::from_jni(&s[1..]))),
            'Z' => TypeSignature::Bool,
            'B' => TypeSignature::Byte,
            'C' => TypeSignature::Char,
@@ -138,6 +263,12 @@ impl TypeSignature {
        }
    }
 
+    /// Long and double values take two registers
+    pub fn is_wide(&self) -> bool
+    {
+        matches!(self, TypeSignature::Long | TypeSignature::Double)
+    }
+
     pub fn to_jni(&self) -> String
     {
         match self
@@ -159,60 +290,152 @@ impl TypeSignature {
 
 /// Simple enum to represent Java method, field and class modifiers
 ///
-
-#[derive(Debug)]
+/// Covers every Dalvik access flag. On methods the `constructor` flag is kept in [`SmaliMethod::constructor`].
+///
+/// # Examples
+///
+/// ```
+///  use smali::types::{Modifier, ModifierTarget};
+///
+///  let m: Modifier = "declared-synchronized".parse()?;
+///  assert_eq!(m, Modifier::DeclaredSynchronized);
+///  assert!("public-ish".parse::<Modifier>().is_err());
+///
+///  let flags = Modifier::to_access_flags(&[Modifier::Public, Modifier::Static, Modifier::Final]);
+///  assert_eq!(flags, 0x19);
+///  assert_eq!(Modifier::from_access_flags(0x41, ModifierTarget::Method), vec![Modifier::Public, Modifier::Bridge]);
+/// # Ok::<(), smali::types::SmaliError>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Modifier {
     Public,
-    Protected,
     Private,
+    Protected,
     Static,
     Final,
-    Abstract,
-    Synthetic,
-    Transient,
-    Volatile,
     Synchronized,
+    Volatile,
+    Bridge,
+    Transient,
+    Varargs,
     Native,
-    Varargs
+    Interface,
+    Abstract,
+    Strictfp,
+    Synthetic,
+    Annotation,
+    Enum,
+    Constructor,
+    DeclaredSynchronized
+}
+
+/// What a set of access flags belongs to, needed as some flag bits mean different things on fields and methods
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModifierTarget {
+    Class,
+    Field,
+    Method
 }
 
 impl Modifier {
-    pub fn from_str(s: &str) -> Modifier
-    {
-        match s {
-            "public" => Modifier::Public,
-            "protected" => Modifier::Protected,
-            "private" => Modifier::Private,
-            "static" => Modifier::Static,
-            "final" => Modifier::Final,
-            "abstract" => Modifier::Abstract,
-            "synthetic" => Modifier::Synthetic,
-            "transient" => Modifier::Transient,
-            "volatile" => Modifier::Volatile,
-            "synchronized" => Modifier::Synchronized,
-            "native" => Modifier::Native,
-            "varargs" => Modifier::Varargs,
-            _ => Modifier::Public
-        }
-    }
+    /// Every modifier in access flag order, the order baksmali writes them in
+    const ALL: [Modifier; 19] = [
+        Modifier::Public, Modifier::Private, Modifier::Protected, Modifier::Static, Modifier::Final,
+        Modifier::Synchronized, Modifier::Volatile, Modifier::Bridge, Modifier::Transient, Modifier::Varargs,
+        Modifier::Native, Modifier::Interface, Modifier::Abstract, Modifier::Strictfp, Modifier::Synthetic,
+        Modifier::Annotation, Modifier::Enum, Modifier::Constructor, Modifier::DeclaredSynchronized
+    ];
 
     pub fn to_str(&self) -> &str
     {
         match self {
             Modifier::Public => "public",
-            Modifier::Protected => "protected",
             Modifier::Private => "private",
+            Modifier::Protected => "protected",
             Modifier::Static => "static",
             Modifier::Final => "final",
-            Modifier::Abstract => "abstract",
-            Modifier::Synthetic => "synthetic",
-            Modifier::Transient => "transient",
-            Modifier::Volatile => "volatile",
             Modifier::Synchronized => "synchronized",
-            Modifier::Native => "native",
+            Modifier::Volatile => "volatile",
+            Modifier::Bridge => "bridge",
+            Modifier::Transient => "transient",
             Modifier::Varargs => "varargs",
+            Modifier::Native => "native",
+            Modifier::Interface => "interface",
+            Modifier::Abstract => "abstract",
+            Modifier::Strictfp => "strictfp",
+            Modifier::Synthetic => "synthetic",
+            Modifier::Annotation => "annotation",
+            Modifier::Enum => "enum",
+            Modifier::Constructor => "constructor",
+            Modifier::DeclaredSynchronized => "declared-synchronized"
+        }
+    }
+
+    /// The DEX access flag bit for this modifier
+    pub fn access_flag(&self) -> u32
+    {
+        match self {
+            Modifier::Public => 0x1,
+            Modifier::Private => 0x2,
+            Modifier::Protected => 0x4,
+            Modifier::Static => 0x8,
+            Modifier::Final => 0x10,
+            Modifier::Synchronized => 0x20,
+            Modifier::Volatile | Modifier::Bridge => 0x40,
+            Modifier::Transient | Modifier::Varargs => 0x80,
+            Modifier::Native => 0x100,
+            Modifier::Interface => 0x200,
+            Modifier::Abstract => 0x400,
+            Modifier::Strictfp => 0x800,
+            Modifier::Synthetic => 0x1000,
+            Modifier::Annotation => 0x2000,
+            Modifier::Enum => 0x4000,
+            Modifier::Constructor => 0x10000,
+            Modifier::DeclaredSynchronized => 0x20000
+        }
+    }
+
+    /// Whether the modifier can be used on a class, field or method
+    pub fn applies_to(&self, target: ModifierTarget) -> bool
+    {
+        match self {
+            Modifier::Public | Modifier::Private | Modifier::Protected | Modifier::Static | Modifier::Final | Modifier::Synthetic => true,
+            Modifier::Volatile | Modifier::Transient => target == ModifierTarget::Field,
+            Modifier::Synchronized | Modifier::Bridge | Modifier::Varargs | Modifier::Native | Modifier::Strictfp
+            | Modifier::Constructor | Modifier::DeclaredSynchronized => target == ModifierTarget::Method,
+            Modifier::Interface | Modifier::Annotation => target == ModifierTarget::Class,
+            Modifier::Abstract => target != ModifierTarget::Field,
+            Modifier::Enum => target != ModifierTarget::Method
         }
     }
+
+    /// Combines modifiers into a DEX `access_flags` bitmask
+    pub fn to_access_flags(modifiers: &[Modifier]) -> u32
+    {
+        modifiers.iter().fold(0, |flags, m| flags | m.access_flag())
+    }
+
+    /// Splits a DEX `access_flags` bitmask into modifiers, in the order baksmali writes them
+    pub fn from_access_flags(flags: u32, target: ModifierTarget) -> Vec<Modifier>
+    {
+        Modifier::ALL.iter()
+            .filter(|m| m.applies_to(target) && flags & m.access_flag() != 0)
+            .copied()
+            .collect()
+    }
+}
+
+impl FromStr for Modifier {
+    type Err = SmaliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        Modifier::ALL.iter()
+            .find(|m| m.to_str() == s)
+            .copied()
+            .ok_or_else(|| SmaliError::Unsupported(format!("unknown modifier '{}'", s)))
+    }
 }
 
 /// Represents a Java method signature consisting of arguments and a return type
@@ -225,7 +448,7 @@ impl Modifier {
 ///  let m = MethodSignature::from_jni("([I)V");
 ///  assert_eq!(m.return_type, TypeSignature::Void);
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MethodSignature {
     pub args: Vec<TypeSignature>,
     pub return_type: TypeSignature,
@@ -245,20 +468,137 @@ impl MethodSignature {
         for t in &self.args
         {
             let ts = t.to_jni();
-            s.push_str(&*ts);
+            s.push_str(&ts);
         }
         s.push(')');
-        s.push_str(&*self.return_type.to_jni());
+        s.push_str(&self.return_type.to_jni());
         s
     }
 }
 
+/// Represents a reference to a method as used by the invoke instructions
+///
+/// # Examples
+///
+/// ```
+///  use smali::types::{MethodRef, TypeSignature};
+///
+///  let m = MethodRef::from_jni("Ljava/lang/Object;->toString()Ljava/lang/String;");
+///  assert_eq!(m.name, "toString");
+///  assert_eq!(m.class, TypeSignature::from_jni("Ljava/lang/Object;"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MethodRef {
+    /// The class the method is referenced through, this is an array type for calls such as `[I->clone()`
+    pub class: TypeSignature,
+    /// Method name
+    pub name: String,
+    /// Method signature
+    pub signature: MethodSignature,
+}
+
+impl MethodRef {
+
+    pub fn from_jni(s: &str) -> MethodRef
+    {
+        let (_, m) = parse_methodref(s).expect("Can't parse MethodRef");
+        m
+    }
+
+    pub fn to_jni(&self) -> String
+    {
+        format!("{}->{}{}", self.class.to_jni(), self.name, self.signature.to_jni())
+    }
+}
+
+/// Represents a reference to a field as used by the iget/iput/sget/sput instructions
+///
+/// # Examples
+///
+/// ```
+///  use smali::types::{FieldRef, TypeSignature};
+///
+///  let f = FieldRef::from_jni("Lokhttp3/Response;->code:I");
+///  assert_eq!(f.name, "code");
+///  assert_eq!(f.signature, TypeSignature::Int);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FieldRef {
+    /// The class declaring the field
+    pub class: ObjectIdentifier,
+    /// Field name
+    pub name: String,
+    /// Type signature of the field
+    pub signature: TypeSignature,
+}
+
+impl FieldRef {
+
+    pub fn from_jni(s: &str) -> FieldRef
+    {
+        let (_, f) = parse_fieldref(s).expect("Can't parse FieldRef");
+        f
+    }
+
+    pub fn to_jni(&self) -> String
+    {
+        format!("{}->{}:{}", self.class.as_jni_type(), self.name, self.signature.to_jni())
+    }
+}
+
+/// Represents a method handle, a field accessor or method invocation
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MethodHandle {
+    StaticPut(FieldRef),
+    StaticGet(FieldRef),
+    InstancePut(FieldRef),
+    InstanceGet(FieldRef),
+    InvokeStatic(MethodRef),
+    InvokeInstance(MethodRef),
+    InvokeConstructor(MethodRef),
+    InvokeDirect(MethodRef),
+    InvokeInterface(MethodRef)
+}
+
+impl MethodHandle {
+    /// The smali name of the method handle type e.g. `invoke-static`
+    pub fn kind_str(&self) -> &str
+    {
+        match self {
+            MethodHandle::StaticPut(_) => "static-put",
+            MethodHandle::StaticGet(_) => "static-get",
+            MethodHandle::InstancePut(_) => "instance-put",
+            MethodHandle::InstanceGet(_) => "instance-get",
+            MethodHandle::InvokeStatic(_) => "invoke-static",
+            MethodHandle::InvokeInstance(_) => "invoke-instance",
+            MethodHandle::InvokeConstructor(_) => "invoke-constructor",
+            MethodHandle::InvokeDirect(_) => "invoke-direct",
+            MethodHandle::InvokeInterface(_) => "invoke-interface",
+        }
+    }
+
+    pub fn to_jni(&self) -> String
+    {
+        match self {
+            MethodHandle::StaticPut(f) | MethodHandle::StaticGet(f) | MethodHandle::InstancePut(f) | MethodHandle::InstanceGet(f) => {
+                format!("{}@{}", self.kind_str(), f.to_jni())
+            }
+            MethodHandle::InvokeStatic(m) | MethodHandle::InvokeInstance(m) | MethodHandle::InvokeConstructor(m)
+            | MethodHandle::InvokeDirect(m) | MethodHandle::InvokeInterface(m) => {
+                format!("{}@{}", self.kind_str(), m.to_jni())
+            }
+        }
+    }
+}
+
 /// Simple enum to represent annotation visibility: system or runtime.
 ///
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AnnotationVisibility {
     System,
-    Runtime
+    Runtime,
+    Build
 }
 
 impl AnnotationVisibility {
@@ -266,26 +606,81 @@ impl AnnotationVisibility {
     {
         match self {
             AnnotationVisibility::System => "system",
-            AnnotationVisibility::Runtime => "runtime"
+            AnnotationVisibility::Runtime => "runtime",
+            AnnotationVisibility::Build => "build"
         }
     }
 }
 
-/// Annotation values can be a Single value, Array, Enum or another Annotation
+/// A constant value, as used for annotation elements and the initial values of static fields
 ///
-#[derive(Debug)]
-pub enum AnnotationValue {
-    Single(String),
-    Array(Vec<String>),
-    SubAnnotation(SmaliAnnotation),
-    Enum(ObjectIdentifier, String)
+/// Literals keep the width given by their smali suffix, e.g. `0x1t` is a `Byte` and `0x1L` a `Long`, and strings are
+/// held unescaped.
+///
+/// # Examples
+///
+/// ```
+///  use smali::types::EncodedValue;
+///
+///  let v = EncodedValue::parse("{ \"a,b\", 0x7fL, .enum Ljava/lang/annotation/RetentionPolicy;->RUNTIME:Ljava/lang/annotation/RetentionPolicy; }").unwrap();
+///  match &v {
+///      EncodedValue::Array(a) => {
+///          assert_eq!(a[0], EncodedValue::String("a,b".to_string()));
+///          assert_eq!(a[1], EncodedValue::Long(127));
+///          assert!(matches!(&a[2], EncodedValue::Enum(f) if f.name == "RUNTIME"));
+///      }
+///      _ => panic!("not an array")
+///  }
+///  assert_eq!(EncodedValue::parse("-0x2s").unwrap().to_string(), "-0x2s");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum EncodedValue {
+    Byte(i8),
+    Short(i16),
+    Char(u16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Type(TypeSignature),
+    Field(FieldRef),
+    Method(MethodRef),
+    MethodType(MethodSignature),
+    MethodHandle(MethodHandle),
+    /// An enum constant, written as `.enum Lfoo/Bar;->VALUE:Lfoo/Bar;`
+    Enum(FieldRef),
+    Array(Vec<EncodedValue>),
+    /// A nested annotation, written as a `.subannotation` block
+    Annotation(SmaliAnnotation),
+    Null,
+    Boolean(bool)
+}
+
+impl EncodedValue {
+    /// Parses a value from its smali text, as written by baksmali
+    pub fn parse(text: &str) -> Result<EncodedValue, SmaliError>
+    {
+        match parse_encoded_value(text) {
+            Ok((rest, v)) if rest.trim().is_empty() => Ok(v),
+            _ => Err(SmaliError::Unsupported(format!("value `{}`", text.trim())))
+        }
+    }
+}
+
+impl fmt::Display for EncodedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", write_value(self, ""))
+    }
 }
 
 impl AnnotationVisibility {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> AnnotationVisibility
     {
         match s {
             "system" => AnnotationVisibility::System,
+            "build" => AnnotationVisibility::Build,
             _ => AnnotationVisibility::Runtime
         }
     }
@@ -294,15 +689,15 @@ impl AnnotationVisibility {
 
 /// Name, value pair for annotation elements. There can be several of these per annotation.
 ///
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AnnotationElement {
     pub name: String,
-    pub value: AnnotationValue,
+    pub value: EncodedValue,
 }
 
 /// Struct representing a Java annotation, these can occur at class level, method level, within a field or within another annotation.
 ///
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SmaliAnnotation {
     pub visibility: AnnotationVisibility,
     pub annotation_type: TypeSignature,
@@ -320,18 +715,133 @@ pub struct SmaliField {
     /// Type signature of the field
     pub signature: TypeSignature,
     /// If an initialiser is included
-    pub initial_value: Option<String>,
+    pub initial_value: Option<EncodedValue>,
     /// Field level annotations
     pub annotations: Vec<SmaliAnnotation>,
 }
 
-/// An enum representing instructions within a method, these can be a label, a line number or a dex instruction as a String.
+impl SmaliField {
+    /// The DEX `access_flags` of the field
+    pub fn access_flags(&self) -> u32
+    {
+        Modifier::to_access_flags(&self.modifiers)
+    }
+}
+
+/// An enum representing instructions within a method, these can be a label, a line number, a dex instruction
+/// or any other method level directive which is kept as a String.
 ///
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SmaliInstruction {
     Label(String),
     Line(u32),
-    Instruction(String)
+    Instruction(DexInstruction),
+    /// A `.catch` or `.catchall` directive, labels are stored without the leading `:`
+    TryCatch {
+        /// The exception caught, None for `.catchall`
+        exception: Option<ObjectIdentifier>,
+        /// Label at the start of the try range
+        start: String,
+        /// Label at the end of the try range
+        end: String,
+        /// Label of the exception handler
+        handler: String
+    },
+    /// Switch table or array data
+    Payload(Payload),
+    /// Start of a local variable's scope (`.local`), with its name, type and generic signature if known
+    Local {
+        register: Register,
+        name: Option<String>,
+        signature: Option<TypeSignature>,
+        generic: Option<String>
+    },
+    /// End of a local variable's scope (`.end local`)
+    EndLocal(Register),
+    /// Restarts the scope of a local variable previously ended (`.restart local`)
+    RestartLocal(Register),
+    /// End of the method prologue (`.prologue`)
+    Prologue,
+    /// Start of the method epilogue (`.epilogue`)
+    Epilogue,
+    Directive(String)
+}
+
+/// A try block of a method with its labels resolved to indexes into the method's instructions
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct TryBlock {
+    /// The exception caught, None for `.catchall`
+    pub exception: Option<ObjectIdentifier>,
+    /// Index of the label starting the try range, the range covers the instructions from here up to `end`
+    pub start: usize,
+    /// Index of the label ending the try range (exclusive)
+    pub end: usize,
+    /// Index of the handler label
+    pub handler: usize,
+}
+
+/// The register count of a method as declared in smali, either the number of local registers (`.locals`)
+/// or the total number of registers including the parameters (`.registers`).
+///
+/// # Examples
+///
+/// ```
+///  use smali::types::RegisterCount;
+///
+///  // A static method taking (JI) uses 3 parameter registers
+///  let r = RegisterCount::Registers(5);
+///  assert_eq!(r.locals(3), 2);
+///  assert_eq!(r.to_locals(3), RegisterCount::Locals(2));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterCount {
+    Locals(u32),
+    Registers(u32)
+}
+
+impl RegisterCount {
+    /// Number of local registers, given the number of parameter registers
+    pub fn locals(&self, parameter_registers: u32) -> u32
+    {
+        match self {
+            RegisterCount::Locals(l) => *l,
+            RegisterCount::Registers(r) => r.saturating_sub(parameter_registers)
+        }
+    }
+
+    /// Total number of registers, given the number of parameter registers
+    pub fn registers(&self, parameter_registers: u32) -> u32
+    {
+        match self {
+            RegisterCount::Locals(l) => l + parameter_registers,
+            RegisterCount::Registers(r) => *r
+        }
+    }
+
+    /// Converts to the `.locals` form
+    pub fn to_locals(self, parameter_registers: u32) -> RegisterCount
+    {
+        RegisterCount::Locals(self.locals(parameter_registers))
+    }
+
+    /// Converts to the `.registers` form
+    pub fn to_registers(self, parameter_registers: u32) -> RegisterCount
+    {
+        RegisterCount::Registers(self.registers(parameter_registers))
+    }
+}
+
+/// A method parameter declared with `.param`, giving its name and/or annotations
+///
+#[derive(Debug)]
+pub struct SmaliParameter {
+    /// The parameter register, usually a `p` register
+    pub register: Register,
+    /// Parameter name from the debug information
+    pub name: Option<String>,
+    /// Parameter annotations
+    pub annotations: Vec<SmaliAnnotation>,
 }
 
 /// Struct representing a Java method
@@ -346,24 +856,442 @@ pub struct SmaliMethod {
     pub constructor: bool,
     /// Method signature
     pub signature: MethodSignature,
-    /// Number of local variables required by the instructions
-    pub locals: u32,
+    /// Number of registers required by the instructions, as either `.locals` or `.registers`
+    pub registers: RegisterCount,
+    /// Parameter names and annotations
+    pub params: Vec<SmaliParameter>,
     /// Any method level annotations
     pub annotations: Vec<SmaliAnnotation>,
     /// Method instructions
     pub instructions: Vec<SmaliInstruction>,
 }
 
+impl SmaliMethod {
+
+    /// The DEX `access_flags` of the method, including the constructor flag
+    pub fn access_flags(&self) -> u32
+    {
+        let flags = Modifier::to_access_flags(&self.modifiers);
+        if self.constructor { flags | Modifier::Constructor.access_flag() } else { flags }
+    }
+
+    /// Is this a static method
+    pub fn is_static(&self) -> bool
+    {
+        self.modifiers.iter().any(|m| matches!(m, Modifier::Static))
+    }
+
+    /// Number of registers taken by the parameters, including `this` for non-static methods
+    pub fn parameter_registers(&self) -> u32
+    {
+        let args: u32 = self.signature.args.iter().map(|a| if a.is_wide() { 2 } else { 1 }).sum();
+        if self.is_static() { args } else { args + 1 }
+    }
+
+    /// The type of the parameter held in a register, None for `this` or a non-parameter register
+    pub fn parameter_type(&self, register: Register) -> Option<&TypeSignature>
+    {
+        let mut p = match register {
+            Register::P(p) => p as u32,
+            Register::V(v) => (v as u32).checked_sub(self.locals())?
+        };
+        if !self.is_static()
+        {
+            p = p.checked_sub(1)?;
+        }
+        let mut next = 0;
+        for a in &self.signature.args
+        {
+            if next == p { return Some(a); }
+            next += if a.is_wide() { 2 } else { 1 };
+        }
+        None
+    }
+
+    /// Number of local (non-parameter) registers
+    pub fn locals(&self) -> u32
+    {
+        self.registers.locals(self.parameter_registers())
+    }
+
+    /// Sets the number of local registers, keeping the `.locals` or `.registers` form of the method
+    pub fn set_locals(&mut self, locals: u32)
+    {
+        self.registers = match self.registers {
+            RegisterCount::Locals(_) => RegisterCount::Locals(locals),
+            RegisterCount::Registers(_) => RegisterCount::Registers(locals + self.parameter_registers())
+        };
+    }
+
+    /// The number of local registers the instructions need, one past the highest `v` register including the high
+    /// register of wide values
+    ///
+    /// `p` registers always follow the locals so don't need any.
+    pub fn locals_needed(&self) -> u32
+    {
+        self.registers_used().iter()
+            .filter_map(|(r, wide)| if let Register::V(n) = r { Some(*n as u32 + 1 + *wide as u32) } else { None })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Sets the number of local registers to what the instructions need, e.g. after patching
+    ///
+    /// Every `v` register is taken to be a local, so methods disassembled with `v` names for their parameters should be
+    /// converted with [`SmaliMethod::use_parameter_registers`] first. Fails without changing the method if moving the
+    /// parameter registers puts one out of reach of its instruction, e.g. `p0` past `v15` in an `iget`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///  use smali::instructions::DexInstruction::{ConstWide16, ReturnWide};
+    ///  use smali::instructions::Register::V;
+    ///  use smali::types::SmaliClass;
+    ///  use smali::types::SmaliInstruction::Instruction;
+    ///
+    ///  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n\
+    ///               .method public f(I)J\n    .locals 0\n    const-wide/16 p0, 0x0\n    return-wide p0\n.end method\n";
+    ///  let mut c = SmaliClass::from_smali(smali)?;
+    ///  let m = &mut c.methods[0];
+    ///  m.instructions = vec![Instruction(ConstWide16 { dest: V(0), value: 0 }), Instruction(ReturnWide { src: V(0) })];
+    ///  assert_eq!(m.fix_locals()?, 2);
+    /// # Ok::<(), smali::types::SmaliError>(())
+    /// ```
+    pub fn fix_locals(&mut self) -> Result<u32, SmaliError>
+    {
+        let locals = self.locals_needed();
+        self.check_register_widths(0, locals)?;
+        self.set_locals(locals);
+        Ok(locals)
+    }
+
+    /// Renames `v` registers that alias parameters to their `p` names, so they keep pointing at the parameters when the
+    /// number of locals changes
+    pub fn use_parameter_registers(&mut self)
+    {
+        let locals = self.locals() as u16;
+        self.rename_registers(|r| match r {
+            Register::V(n) if n >= locals => Register::P(n - locals),
+            r => r
+        });
+    }
+
+    /// Adds `count` scratch registers as `v0` upwards, moving the existing locals up to make room
+    ///
+    /// Low registers can be used by any instruction, unlike registers added after the existing locals which can be out
+    /// of reach of 4 bit register operands. Fails without changing the method if a moved register no longer fits its
+    /// instruction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///  use smali::instructions::Register::V;
+    ///  use smali::types::SmaliClass;
+    ///
+    ///  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n\
+    ///               .method public f()I\n    .locals 1\n    const/4 v0, 0x1\n    return v0\n.end method\n";
+    ///  let mut c = SmaliClass::from_smali(smali)?;
+    ///  assert_eq!(c.methods[0].reserve_registers(1)?, [V(0)]);
+    ///  assert!(c.to_smali().contains("    .locals 2\n    const/4 v1, 0x1\n    return v1\n"));
+    /// # Ok::<(), smali::types::SmaliError>(())
+    /// ```
+    pub fn reserve_registers(&mut self, count: u16) -> Result<Vec<Register>, SmaliError>
+    {
+        let locals = self.locals();
+        self.check_register_widths(count as u32, locals + count as u32)?;
+        self.rename_registers(|r| match r {
+            Register::V(n) => Register::V(n + count),
+            r => r
+        });
+        self.set_locals(locals + count as u32);
+        Ok((0..count).map(Register::V).collect())
+    }
+
+    /// Adds `count` registers after the locals without changing the number of any register already used, returning
+    /// the new registers
+    ///
+    /// Parameters are copied at the start of the method to the registers they were in before, where the instructions
+    /// now find them, so unlike [`SmaliMethod::reserve_registers`] no instruction can end up out of reach of its
+    /// registers. This costs a register for each parameter register.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///  use smali::instructions::Register::V;
+    ///  use smali::types::SmaliClass;
+    ///
+    ///  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n\
+    ///               .method public static f(I)I\n    .locals 1\n    add-int/lit8 v0, p0, 0x1\n    return v0\n.end method\n";
+    ///  let mut c = SmaliClass::from_smali(smali)?;
+    ///  assert_eq!(c.methods[0].add_registers(1)?, [V(2)]);
+    ///  assert!(c.to_smali().contains("    .locals 3\n    move/16 v1, p0\n    add-int/lit8 v0, v1, 0x1\n"));
+    /// # Ok::<(), smali::types::SmaliError>(())
+    /// ```
+    pub fn add_registers(&mut self, count: u16) -> Result<Vec<Register>, SmaliError>
+    {
+        let locals = self.locals();
+        let parameters = self.parameter_registers();
+        let total = locals + 2 * parameters + count as u32;
+        if total > 0xffff { return Err(SmaliError::new(&format!("{} registers is too many for method {}", total, self.name))); }
+
+        // Parameters are used by their p names or as v registers past the locals
+        let used: Vec<u32> = self.registers_used().iter()
+            .flat_map(|(r, wide)| {
+                let n = match r { Register::V(n) => (*n as u32).checked_sub(locals), Register::P(n) => Some(*n as u32) };
+                n.into_iter().flat_map(move |n| [Some(n), if *wide { Some(n + 1) } else { None }]).flatten()
+            })
+            .collect();
+        let mut types = vec![];
+        if !self.is_static() { types.push(TypeSignature::Object(ObjectIdentifier::from_java_type("java.lang.Object"))); }
+        types.extend(self.signature.args.iter().cloned());
+        let mut copies = vec![];
+        let mut p = 0;
+        for t in types
+        {
+            let (dest, src) = (Register::V((locals + p) as u16), Register::P(p as u16));
+            if used.contains(&p) || (t.is_wide() && used.contains(&(p + 1)))
+            {
+                copies.push(SmaliInstruction::Instruction(match t {
+                    _ if t.is_wide() => DexInstruction::MoveWide16 { dest, src },
+                    TypeSignature::Object(_) | TypeSignature::Array(_) => DexInstruction::MoveObject16 { dest, src },
+                    _ => DexInstruction::Move16 { dest, src }
+                }));
+            }
+            p += if t.is_wide() { 2 } else { 1 };
+        }
+
+        let l = locals as u16;
+        self.rename_registers(|r| match r { Register::P(n) => Register::V(l + n), r => r });
+        self.set_locals(locals + parameters + count as u32);
+        let at = self.instructions.iter().position(|i| matches!(i, SmaliInstruction::Instruction(_) | SmaliInstruction::Label(_))).unwrap_or(0);
+        self.instructions.splice(at..at, copies);
+        let first = (locals + parameters) as u16;
+        Ok((first..first + count).map(Register::V).collect())
+    }
+
+    /* Every register named by the instructions and debug directives, with whether it's the low register of a wide pair */
+    fn registers_used(&self) -> Vec<(Register, bool)>
+    {
+        let mut used = vec![];
+        for i in &self.instructions
+        {
+            match i {
+                SmaliInstruction::Instruction(d) => {
+                    let mut wide = d.opcode().wide_registers().into_iter();
+                    for o in d.operands()
+                    {
+                        match o {
+                            Operand::Register(r) => used.push((r, wide.next().unwrap_or(false))),
+                            Operand::RegisterList(l) => used.extend(l.into_iter().map(|r| (r, false))),
+                            Operand::RegisterRange(r) => used.extend(r.registers().into_iter().map(|r| (r, false))),
+                            _ => {}
+                        }
+                    }
+                }
+                SmaliInstruction::Local { register, signature, .. } => used.push((*register, signature.as_ref().is_some_and(|s| s.is_wide()))),
+                SmaliInstruction::EndLocal(r) | SmaliInstruction::RestartLocal(r) => used.push((*r, false)),
+                _ => {}
+            }
+        }
+        used
+    }
+
+    /* Checks every register would fit its instruction with v registers moved up by shift and the given number of locals */
+    pub(crate) fn check_register_widths(&self, shift: u32, locals: u32) -> Result<(), SmaliError>
+    {
+        let total = locals + self.parameter_registers();
+        if total > 0xffff { return Err(SmaliError::new(&format!("{} registers is too many for method {}", total, self.name))); }
+        let absolute = |r: &Register| match r {
+            Register::V(n) => *n as u32 + shift,
+            Register::P(n) => locals + *n as u32
+        };
+        for i in &self.instructions
+        {
+            let SmaliInstruction::Instruction(d) = i else { continue };
+            let mut bits = d.opcode().format().register_bits().into_iter();
+            for o in d.operands()
+            {
+                let (regs, b) = match o {
+                    Operand::Register(r) => (vec![r], bits.next()),
+                    Operand::RegisterList(l) => (l, bits.next()),
+                    Operand::RegisterRange(r) => (r.registers(), bits.next()),
+                    _ => continue
+                };
+                let b = b.unwrap_or(16);
+                if let Some(r) = regs.iter().find(|r| absolute(r) >= 1 << b)
+                {
+                    return Err(SmaliError::new(&format!("Register {} would be v{} which is out of range for {} in method {}", r, absolute(r), d.opcode().mnemonic(), self.name)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* Renames every register of the instructions and debug directives */
+    fn rename_registers<F: Fn(Register) -> Register>(&mut self, f: F)
+    {
+        rename_registers(&mut self.instructions, f);
+    }
+
+    /// Finds the index of a label within the method's instructions
+    pub fn label_index(&self, label: &str) -> Option<usize>
+    {
+        self.instructions.iter().position(|i| matches!(i, SmaliInstruction::Label(l) if l == label))
+    }
+
+    /// Finds the payload (switch table or array data) defined at a label
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///  use smali::instructions::Payload;
+    ///  use smali::types::SmaliClass;
+    ///
+    ///  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n\
+    ///               .method public static pick(I)I\n    .locals 1\n    packed-switch p0, :pswitch_data_0\n    const/4 v0, 0x0\n    return v0\n\
+    ///               :pswitch_0\n    const/4 v0, 0x1\n    return v0\n    :pswitch_data_0\n    .packed-switch 0x1\n        :pswitch_0\n    .end packed-switch\n.end method\n";
+    ///  let mut c = SmaliClass::from_smali(smali).unwrap();
+    ///  if let Some(Payload::PackedSwitch(p)) = c.methods[0].payload_mut("pswitch_data_0")
+    ///  {
+    ///      p.targets.push("pswitch_0".to_string());
+    ///  }
+    ///  assert!(c.to_smali().contains("    .packed-switch 0x1\n        :pswitch_0\n        :pswitch_0\n    .end packed-switch\n"));
+    /// ```
+    pub fn payload(&self, label: &str) -> Option<&Payload>
+    {
+        let start = self.label_index(label)?;
+        self.instructions[start..].iter()
+            .find(|i| !matches!(i, SmaliInstruction::Label(_)))
+            .and_then(|i| if let SmaliInstruction::Payload(p) = i { Some(p) } else { None })
+    }
+
+    /// Mutable version of [`SmaliMethod::payload`]
+    pub fn payload_mut(&mut self, label: &str) -> Option<&mut Payload>
+    {
+        let start = self.label_index(label)?;
+        self.instructions[start..].iter_mut()
+            .find(|i| !matches!(i, SmaliInstruction::Label(_)))
+            .and_then(|i| if let SmaliInstruction::Payload(p) = i { Some(p) } else { None })
+    }
+
+    /// Returns all the try blocks of this method with their labels resolved to instruction indexes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    ///  use smali::types::SmaliClass;
+    ///
+    ///  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n\
+    ///               .method public run()V\n    .locals 0\n    :try_start_0\n    invoke-static {}, Lcom/cool/Class;->go()V\n    :try_end_0\n\
+    ///               .catch Ljava/io/IOException; {:try_start_0 .. :try_end_0} :catch_0\n    return-void\n    :catch_0\n    return-void\n.end method\n";
+    ///  let c = SmaliClass::from_smali(smali).unwrap();
+    ///  let blocks = c.methods[0].try_blocks().unwrap();
+    ///  assert_eq!((blocks[0].start, blocks[0].end, blocks[0].handler), (0, 2, 5));
+    /// ```
+    pub fn try_blocks(&self) -> Result<Vec<TryBlock>, SmaliError>
+    {
+        let mut blocks = vec![];
+        for i in &self.instructions
+        {
+            if let SmaliInstruction::TryCatch { exception, start, end, handler } = i
+            {
+                let resolve = |l: &str| self.label_index(l).ok_or_else(|| SmaliError::new(&format!("Unknown label :{} in method {}", l, self.name)));
+                blocks.push(TryBlock {
+                    exception: exception.clone(),
+                    start: resolve(start)?,
+                    end: resolve(end)?,
+                    handler: resolve(handler)?
+                });
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+/* Renames every register of a list of instructions and debug directives */
+pub(crate) fn rename_registers<F: Fn(Register) -> Register>(instructions: &mut [SmaliInstruction], f: F)
+{
+    for i in instructions.iter_mut()
+    {
+        match i {
+            SmaliInstruction::Instruction(d) => {
+                let operands = d.operands().into_iter().map(|o| match o {
+                    Operand::Register(r) => Operand::Register(f(r)),
+                    Operand::RegisterList(l) => Operand::RegisterList(l.into_iter().map(&f).collect()),
+                    // A range has to stay all v or all p registers
+                    Operand::RegisterRange(r) => match (f(r.start), f(r.end)) {
+                        (start @ Register::V(_), end @ Register::V(_)) | (start @ Register::P(_), end @ Register::P(_)) => Operand::RegisterRange(RegisterRange { start, end }),
+                        _ => Operand::RegisterRange(r)
+                    },
+                    o => o
+                }).collect();
+                if let Some(renamed) = DexInstruction::from_operands(d.opcode(), operands) { *d = renamed; }
+            }
+            SmaliInstruction::Local { register, .. } | SmaliInstruction::EndLocal(register) | SmaliInstruction::RestartLocal(register) => *register = f(*register),
+            _ => {}
+        }
+    }
+}
+
+/// The kind of class member an [`Unparsed`] block stands in for
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnparsedKind {
+    Annotation,
+    Field,
+    Method,
+    /// A line outside any member that couldn't be parsed
+    Other
+}
+
+/// Raw text of a member that failed to parse in lenient mode, written back unchanged by `to_smali`
+///
+#[derive(Debug, Clone)]
+pub struct Unparsed {
+    pub kind: UnparsedKind,
+    pub text: String
+}
+
+/// The original text of a parsed class, split into its members and the comments and blank lines between them
+///
+/// Kept on [`SmaliClass`] so that `to_smali` writes unmodified members exactly as they were read,
+/// changed members in place and new members at the end of their section.
+///
+#[derive(Debug, Clone, Default)]
+pub struct SourceLayout {
+    pub(crate) segments: Vec<LayoutSegment>
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum LayoutSegment {
+    /// Comments and blank lines
+    Trivia(String),
+    /// A member's original text with the key and hash of its standard rendering when parsed
+    Member { kind: MemberKind, key: String, text: String, hash: u64 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MemberKind {
+    Class,
+    Super,
+    Source,
+    Implements,
+    Annotation,
+    Field,
+    Method,
+    Unparsed
+}
+
 /// Represents a smali class i.e. the whole .smali file
 ///
 /// # Examples
 ///
-/// ```
+/// ```no_run
 ///  use std::path::Path;
 ///  use smali::types::SmaliClass;
 ///
 ///  let c = SmaliClass::read_from_file(Path::new("smali/com/cool/Class.smali")).expect("Uh oh, does the file exist?");
-///  println!("Java class: {}" c.name.as_java_type());
+///  println!("Java class: {}", c.name.as_java_type());
 /// ```
 #[derive(Debug)]
 pub struct SmaliClass {
@@ -383,6 +1311,10 @@ pub struct SmaliClass {
     pub fields: Vec<SmaliField>,
     /// All the methods defined by the class
     pub methods: Vec<SmaliMethod>,
+    /// Members that couldn't be parsed when loaded in lenient mode
+    pub unparsed: Vec<Unparsed>,
+    /// The original text of the class if it was parsed, set to None to write the class in the standard baksmali layout
+    pub layout: Option<SourceLayout>,
 
     // Internal
     /// The file path where this class was loaded from (.smali file)
@@ -405,6 +1337,12 @@ impl Hash for SmaliClass {
 
 impl SmaliClass {
 
+    /// The DEX `access_flags` of the class
+    pub fn access_flags(&self) -> u32
+    {
+        Modifier::to_access_flags(&self.modifiers)
+    }
+
     /// Creates a SmaliClass from a String containing a valid smali document
     ///
     /// # Examples
@@ -412,24 +1350,50 @@ impl SmaliClass {
     /// ```
     ///  use smali::types::SmaliClass;
     ///
+    ///  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n";
     ///  let c = SmaliClass::from_smali(smali).expect("Parse error");
-    ///
+    ///  assert_eq!(c.name.as_java_type(), "com.cool.Class");
     /// ```
     pub fn from_smali(s: &str) -> Result<SmaliClass, SmaliError>
     {
         let d = parse_class(s);
         match d {
             IResult::Ok((_, cl)) => Ok(cl),
-            IResult::Err(Failure(e)) => Err(SmaliError { details: format!("Class parse error at: {:?}", e.to_string()) }),
-            _ => Err(SmaliError { details: "Unknown class parse error".to_string() })
+            IResult::Err(Failure(e)) | IResult::Err(Error(e)) => Err(SmaliError::parse(s, e.input)),
+            IResult::Err(Incomplete(_)) => Err(SmaliError::parse(s, ""))
         }
     }
 
-    /// Creates a SmaliClass from a file containing a valid smali document
+    /// Creates a SmaliClass from a smali document, skipping over any annotation, field or method that doesn't parse
+    ///
+    /// Broken members are kept as [`Unparsed`] raw text and written back unchanged, one diagnostic is
+    /// returned for each of them. Only an error in the class header fails the whole document.
     ///
     /// # Examples
     ///
     /// ```
+    ///  use smali::types::SmaliClass;
+    ///
+    ///  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n.method public f()V\n    bogus v0\n.end method\n";
+    ///  let (c, diagnostics) = SmaliClass::from_smali_lenient(smali)?;
+    ///  assert_eq!(diagnostics.len(), 1);
+    ///  assert_eq!(c.unparsed[0].text, ".method public f()V\n    bogus v0\n.end method\n");
+    /// # Ok::<(), smali::types::SmaliError>(())
+    /// ```
+    pub fn from_smali_lenient(s: &str) -> Result<(SmaliClass, Vec<SmaliError>), SmaliError>
+    {
+        match parse_class_lenient(s) {
+            IResult::Ok((_, (cl, diagnostics))) => Ok((cl, diagnostics)),
+            IResult::Err(Failure(e)) | IResult::Err(Error(e)) => Err(SmaliError::parse(s, e.input)),
+            IResult::Err(Incomplete(_)) => Err(SmaliError::parse(s, ""))
+        }
+    }
+
+    /// Creates a SmaliClass from a file containing a valid smali document
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
     ///  use std::path::Path;
     ///  use smali::types::SmaliClass;
     ///
@@ -441,19 +1405,46 @@ impl SmaliClass {
         match fs::read_to_string(path)
         {
             Ok(s) => {
-               let mut c = SmaliClass::from_smali(&s)?;
+               let mut c = SmaliClass::from_smali(&s).map_err(|e| e.with_path(path))?;
                 c.file_path = Some(PathBuf::from(path));
                Ok(c)
             }
-            Err(e) => { Err(SmaliError { details: format!("Error loading file {}: {}", path.to_str().unwrap(), e.to_string()) }) }
+            Err(e) => { Err(SmaliError::Io { path: Some(path.to_path_buf()), error: e }) }
         }
     }
 
-    /// Creates a smali document string from the current class
+    /// Creates a SmaliClass from a file in lenient mode, see [`SmaliClass::from_smali_lenient`]
     ///
     /// # Examples
     ///
+    /// ```no_run
+    ///  use std::path::Path;
+    ///  use smali::types::SmaliClass;
+    ///
+    ///  let (c, diagnostics) = SmaliClass::read_from_file_lenient(Path::new("smali/com/cool/Class.smali"))?;
+    ///  for d in diagnostics { eprintln!("{}", d); }
+    /// # Ok::<(), smali::types::SmaliError>(())
     /// ```
+    pub fn read_from_file_lenient(path: &Path) -> Result<(SmaliClass, Vec<SmaliError>), SmaliError>
+    {
+        match fs::read_to_string(path)
+        {
+            Ok(s) => {
+                let (mut c, diagnostics) = SmaliClass::from_smali_lenient(&s).map_err(|e| e.with_path(path))?;
+                c.file_path = Some(PathBuf::from(path));
+                Ok((c, diagnostics.into_iter().map(|d| d.with_path(path)).collect()))
+            }
+            Err(e) => { Err(SmaliError::Io { path: Some(path.to_path_buf()), error: e }) }
+        }
+    }
+
+    /// Creates a smali document string from the current class
+    ///
+    /// Classes that were parsed keep their original comments, blank lines and member order, see [`SourceLayout`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
     ///  use std::path::Path;
     ///  use smali::types::SmaliClass;
     ///
@@ -470,20 +1461,20 @@ impl SmaliClass {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```no_run
     ///  use std::path::Path;
     ///  use smali::types::SmaliClass;
     ///
     ///  let c = SmaliClass::read_from_file(Path::new("smali/com/cool/Class.smali")).expect("Uh oh, does the file exist?");
     ///  c.write_to_file(Path::new("smali_classes2/com/cool/Class.smali"))?;
-    ///
+    /// # Ok::<(), smali::types::SmaliError>(())
     /// ```
     pub fn write_to_file(&self, path: &Path) -> Result<(), SmaliError>
     {
         let smali = self.to_smali();
         if let Err(e) = fs::write(path, smali)
         {
-            Err(SmaliError { details: e.to_string() })
+            Err(SmaliError::Io { path: Some(path.to_path_buf()), error: e })
         }
         else { Ok(()) }
     }
@@ -492,24 +1483,24 @@ impl SmaliClass {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```no_run
     ///  use std::path::Path;
     ///  use smali::types::SmaliClass;
     ///
     ///  let c = SmaliClass::read_from_file(Path::new("smali/com/cool/Class.smali")).expect("Uh oh, does the file exist?");
     ///  c.write_to_directory(Path::new("smali_classes2"))?;
-    ///
+    /// # Ok::<(), smali::types::SmaliError>(())
     /// ```
     pub fn write_to_directory(&self, path: &Path) -> Result<(), SmaliError>
     {
 
-        if !path.exists() { let _ = fs::create_dir(&path); }
+        if !path.exists() { let _ = fs::create_dir(path); }
 
         // Create package dir structure
         let class_name = self.name.as_java_type();
-        let mut package_dirs: Vec<&str> = class_name.split('.').collect();
+        let package_dirs: Vec<&str> = class_name.split('.').collect();
         let mut dir = PathBuf::from(path);
-        for p in package_dirs[0..package_dirs.len()-1].to_vec()
+        for p in &package_dirs[0..package_dirs.len()-1]
         {
             dir.push(p);
             if !dir.exists() { let _ = fs::create_dir(&dir); }
@@ -525,13 +1516,14 @@ impl SmaliClass {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```no_run
+    ///  use std::path::Path;
     ///  use smali::types::SmaliClass;
     ///
     ///  let mut c = SmaliClass::read_from_file(Path::new("smali/com/cool/Class.smali")).expect("Uh oh, does the file exist?");
     ///  c.source = None;
     ///  c.save()?;
-    ///
+    /// # Ok::<(), smali::types::SmaliError>(())
     /// ```
     pub fn save(&self) -> Result<(), SmaliError>
     {
@@ -539,6 +1531,144 @@ impl SmaliClass {
         {
             self.write_to_file(p)
         }
-        else { Err(SmaliError { details: format!("Unable to save, no file_path set for class: {}", self.name.as_java_type()) }) }
+        else { Err(SmaliError::Other(format!("Unable to save, no file_path set for class: {}", self.name.as_java_type()))) }
+    }
+}
+
+/// A smali class where only the header (`.class`, `.super`, `.source` and `.implements`) is parsed up front,
+/// the rest of the class is parsed the first time it's needed
+///
+/// # Examples
+///
+/// ```
+///  use smali::types::LazySmaliClass;
+///
+///  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n.method public f()V\n    .locals 0\n    return-void\n.end method\n";
+///  let c = LazySmaliClass::from_smali(smali)?;
+///  assert_eq!(c.name().as_java_type(), "com.cool.Class");
+///  assert!(!c.is_loaded());
+///  assert_eq!(c.class()?.methods.len(), 1);
+/// # Ok::<(), smali::types::SmaliError>(())
+/// ```
+#[derive(Debug)]
+pub struct LazySmaliClass {
+    header: SmaliClass,
+    text: String,
+    class: OnceLock<SmaliClass>
+}
+
+impl LazySmaliClass {
+    fn from_string(text: String, path: Option<&Path>) -> Result<LazySmaliClass, SmaliError>
+    {
+        let mut header = match parse_class_header(&text) {
+            IResult::Ok((_, cl)) => cl,
+            IResult::Err(Failure(e)) | IResult::Err(Error(e)) => return Err(SmaliError::parse(&text, e.input)),
+            IResult::Err(Incomplete(_)) => return Err(SmaliError::parse(&text, ""))
+        };
+        header.file_path = path.map(PathBuf::from);
+        Ok(LazySmaliClass { header, text, class: OnceLock::new() })
+    }
+
+    /// Parses the header of a smali document, the rest is kept to be parsed later
+    pub fn from_smali(s: &str) -> Result<LazySmaliClass, SmaliError>
+    {
+        LazySmaliClass::from_string(s.to_string(), None)
+    }
+
+    /// Reads a smali file, only parsing its header
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    ///  use std::path::Path;
+    ///  use smali::types::LazySmaliClass;
+    ///
+    ///  let c = LazySmaliClass::read_from_file(Path::new("smali/com/cool/Class.smali"))?;
+    ///  println!("{} extends {}", c.name().as_java_type(), c.super_class().as_java_type());
+    /// # Ok::<(), smali::types::SmaliError>(())
+    /// ```
+    pub fn read_from_file(path: &Path) -> Result<LazySmaliClass, SmaliError>
+    {
+        match fs::read_to_string(path)
+        {
+            Ok(s) => LazySmaliClass::from_string(s, Some(path)).map_err(|e| e.with_path(path)),
+            Err(e) => Err(SmaliError::Io { path: Some(path.to_path_buf()), error: e })
+        }
+    }
+
+    /* The header until the class is loaded, then the full class as it may have been changed */
+    fn current(&self) -> &SmaliClass
+    {
+        self.class.get().unwrap_or(&self.header)
     }
-}
\ No newline at end of file
+
+    /// The name of the class
+    pub fn name(&self) -> &ObjectIdentifier
+    {
+        &self.current().name
+    }
+
+    /// The class modifiers
+    pub fn modifiers(&self) -> &[Modifier]
+    {
+        &self.current().modifiers
+    }
+
+    /// The class' superclass
+    pub fn super_class(&self) -> &ObjectIdentifier
+    {
+        &self.current().super_class
+    }
+
+    /// List of all the interfaces the class implements
+    pub fn implements(&self) -> &[ObjectIdentifier]
+    {
+        &self.current().implements
+    }
+
+    /// The source filename if included in the smali doc
+    pub fn source(&self) -> Option<&str>
+    {
+        self.current().source.as_deref()
+    }
+
+    /// The file path where this class was loaded from
+    pub fn file_path(&self) -> Option<&Path>
+    {
+        self.current().file_path.as_deref()
+    }
+
+    /// Has the rest of the class been parsed yet
+    pub fn is_loaded(&self) -> bool
+    {
+        self.class.get().is_some()
+    }
+
+    /// The full class, parsing it on first access
+    pub fn class(&self) -> Result<&SmaliClass, SmaliError>
+    {
+        if let Some(c) = self.class.get() { return Ok(c); }
+
+        let path = &self.header.file_path;
+        let mut c = SmaliClass::from_smali(&self.text).map_err(|e| match path { Some(p) => e.with_path(p), None => e })?;
+        c.file_path = path.clone();
+
+        // Another thread may have got there first, either result is the same
+        let _ = self.class.set(c);
+        Ok(self.class.get().unwrap())
+    }
+
+    /// The full class to modify, parsing it on first access
+    pub fn class_mut(&mut self) -> Result<&mut SmaliClass, SmaliError>
+    {
+        self.class()?;
+        Ok(self.class.get_mut().unwrap())
+    }
+
+    /// Takes the full class, parsing it if it hasn't been already
+    pub fn into_class(mut self) -> Result<SmaliClass, SmaliError>
+    {
+        self.class()?;
+        Ok(self.class.take().unwrap())
+    }
+}
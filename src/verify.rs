@@ -0,0 +1,885 @@
+//! Register type inference and bytecode verification
+//!
+//! [`verify_method`] works out the type held by every register before each instruction, much like the ART verifier,
+//! and reports the problems that would get a class rejected on a device: registers out of range, values of the
+//! wrong type, bad register counts and control running off the end of a method.
+//!
+//! # Examples
+//!
+//! ```
+//!  use smali::types::SmaliClass;
+//!  use smali::verify::{verify_class, DiagnosticKind};
+//!
+//!  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n\
+//!               .method public static isRooted()Z\n    .locals 0\n    const/4 v0, 0x0\n    return v0\n.end method\n";
+//!  let c = SmaliClass::from_smali(smali)?;
+//!  let diagnostics = verify_class(&c, None);
+//!  assert!(matches!(diagnostics[0].kind, DiagnosticKind::RegisterOutOfRange { .. }));
+//!  println!("{}", diagnostics[0]);
+//! # Ok::<(), smali::types::SmaliError>(())
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use crate::hierarchy::ClassHierarchy;
+use crate::instructions::{Opcode, Operand, Payload, Register};
+use crate::types::{Modifier, ObjectIdentifier, RegisterCount, SmaliClass, SmaliInstruction, SmaliMethod, TypeSignature};
+
+/// The type of value held in a register
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterType {
+    /// Not set on every path to the instruction
+    Undefined,
+    /// Set to values of incompatible types on different paths
+    Conflict,
+    /// A zero constant, which can be used as an int, float or null
+    Zero,
+    /// A non-zero 32 bit constant, which can be used as an int or float
+    Constant,
+    /// An int, boolean, byte, short or char
+    Integer,
+    Float,
+    /// The low register of a 64 bit constant, which can be used as a long or double
+    WideConstant,
+    /// The low register of a long
+    Long,
+    /// The low register of a double
+    Double,
+    /// The high register of a long, double or 64 bit constant
+    WideHigh,
+    /// An object or array reference
+    Reference(TypeSignature),
+    /// An object from `new-instance` at an instruction index before its constructor is called, None for `this` in a constructor
+    Uninitialized(TypeSignature, Option<usize>)
+}
+
+impl RegisterType {
+    /// The register type of a value of a Java type, [`RegisterType::Undefined`] for void
+    pub fn of(t: &TypeSignature) -> RegisterType
+    {
+        match t {
+            TypeSignature::Int | TypeSignature::Bool | TypeSignature::Byte | TypeSignature::Char | TypeSignature::Short => RegisterType::Integer,
+            TypeSignature::Float => RegisterType::Float,
+            TypeSignature::Long => RegisterType::Long,
+            TypeSignature::Double => RegisterType::Double,
+            TypeSignature::Object(_) | TypeSignature::Array(_) => RegisterType::Reference(t.clone()),
+            TypeSignature::Void => RegisterType::Undefined
+        }
+    }
+
+    /// Is this the low register of a 64 bit value
+    pub fn is_wide(&self) -> bool
+    {
+        matches!(self, RegisterType::WideConstant | RegisterType::Long | RegisterType::Double)
+    }
+
+    fn merge(&self, other: &RegisterType, h: Option<&ClassHierarchy>) -> RegisterType
+    {
+        use RegisterType::*;
+        match (self, other) {
+            (a, b) if a == b => a.clone(),
+            (Undefined, _) | (_, Undefined) => Undefined,
+            (Zero, t @ (Constant | Integer | Float | Reference(_))) | (t @ (Constant | Integer | Float | Reference(_)), Zero) => t.clone(),
+            (Constant, t @ (Integer | Float)) | (t @ (Integer | Float), Constant) => t.clone(),
+            (WideConstant, t @ (Long | Double)) | (t @ (Long | Double), WideConstant) => t.clone(),
+            (Reference(a), Reference(b)) => Reference(common_supertype(a, b, h)),
+            _ => Conflict
+        }
+    }
+}
+
+impl fmt::Display for RegisterType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegisterType::Undefined => write!(f, "undefined"),
+            RegisterType::Conflict => write!(f, "conflict"),
+            RegisterType::Zero => write!(f, "zero"),
+            RegisterType::Constant => write!(f, "constant"),
+            RegisterType::Integer => write!(f, "int"),
+            RegisterType::Float => write!(f, "float"),
+            RegisterType::WideConstant => write!(f, "wide constant"),
+            RegisterType::Long => write!(f, "long"),
+            RegisterType::Double => write!(f, "double"),
+            RegisterType::WideHigh => write!(f, "high half of a wide value"),
+            RegisterType::Reference(t) => write!(f, "{}", t.to_jni()),
+            RegisterType::Uninitialized(t, _) => write!(f, "uninitialized {}", t.to_jni())
+        }
+    }
+}
+
+/// What went wrong, see [`Diagnostic`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    /// The `.registers` count is smaller than the parameters need, or more than 65535
+    BadRegisterCount { registers: u32, parameters: u32 },
+    /// A register past the method's register count
+    RegisterOutOfRange { register: Register, registers: u32 },
+    /// A register holding the wrong type of value
+    TypeMismatch { register: Register, expected: String, found: RegisterType },
+    /// A return instruction that doesn't match the method's return type
+    ReturnMismatch { expected: TypeSignature },
+    /// A constructor returning before calling the superclass constructor
+    UninitializedThis,
+    /// Instructions that can never run, not an error but usually a patching mistake
+    Unreachable,
+    /// Control continues past the last instruction or into a payload
+    FallsOffEnd,
+    /// A missing label or payload, or an instruction in the wrong place
+    BadStructure(String)
+}
+
+/// A problem found in a method
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The method name and signature e.g. `isRooted()Z`
+    pub method: String,
+    /// The index of the instruction in [`SmaliMethod::instructions`], None for problems with the whole method
+    pub index: Option<usize>,
+    pub kind: DiagnosticKind
+}
+
+impl Diagnostic {
+    /// Would the class be rejected, everything but [`DiagnosticKind::Unreachable`] is an error
+    pub fn is_error(&self) -> bool
+    {
+        self.kind != DiagnosticKind::Unreachable
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.index {
+            Some(i) => write!(f, "{} instruction {}: ", self.method, i)?,
+            None => write!(f, "{}: ", self.method)?
+        }
+        match &self.kind {
+            DiagnosticKind::BadRegisterCount { registers, parameters } => write!(f, "{} registers but the parameters need {}", registers, parameters),
+            DiagnosticKind::RegisterOutOfRange { register, registers } => write!(f, "register {} out of range, the method has {} registers", register, registers),
+            DiagnosticKind::TypeMismatch { register, expected, found } => write!(f, "register {} expected {} but holds {}", register, expected, found),
+            DiagnosticKind::ReturnMismatch { expected } => write!(f, "return doesn't match the return type {}", expected.to_jni()),
+            DiagnosticKind::UninitializedThis => write!(f, "returning without calling the superclass constructor"),
+            DiagnosticKind::Unreachable => write!(f, "unreachable code"),
+            DiagnosticKind::FallsOffEnd => write!(f, "control falls off the end of the method"),
+            DiagnosticKind::BadStructure(s) => write!(f, "{}", s)
+        }
+    }
+}
+
+/// The result of verifying a method
+///
+#[derive(Debug, Clone)]
+pub struct Verification {
+    /// The total number of registers of the method
+    pub registers: u32,
+    /// The register types before each instruction, None where it can't be reached
+    pub types: Vec<Option<Vec<RegisterType>>>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+impl Verification {
+    /// Are there no errors
+    pub fn is_ok(&self) -> bool
+    {
+        !self.diagnostics.iter().any(|d| d.is_error())
+    }
+
+    /// The register types before an instruction, None if it can't be reached
+    pub fn types_at(&self, index: usize) -> Option<&[RegisterType]>
+    {
+        self.types.get(index).and_then(|t| t.as_deref())
+    }
+}
+
+fn object() -> TypeSignature
+{
+    TypeSignature::Object(ObjectIdentifier::from_java_type("java.lang.Object"))
+}
+
+/* Lenient assignability, only failing when the types certainly don't match */
+fn assignable(from: &TypeSignature, to: &TypeSignature, h: Option<&ClassHierarchy>) -> bool
+{
+    match (from, to) {
+        (_, to) if *to == object() => true,
+        (TypeSignature::Object(a), TypeSignature::Object(b)) => match h {
+            Some(h) if h.contains(a) && h.contains(b) && !h.is_interface(b) => h.is_subtype(a, b),
+            _ => true
+        },
+        (TypeSignature::Array(_), TypeSignature::Object(b)) => ["Ljava/lang/Cloneable;", "Ljava/io/Serializable;"].contains(&b.as_jni_type().as_str()),
+        // Merged references may have lost their array type
+        (TypeSignature::Object(_), TypeSignature::Array(_)) => *from == object(),
+        (TypeSignature::Array(a), TypeSignature::Array(b)) => match (a.as_ref(), b.as_ref()) {
+            (TypeSignature::Object(_) | TypeSignature::Array(_), TypeSignature::Object(_) | TypeSignature::Array(_)) => assignable(a, b, h),
+            _ => a == b
+        },
+        _ => from == to
+    }
+}
+
+fn common_supertype(a: &TypeSignature, b: &TypeSignature, h: Option<&ClassHierarchy>) -> TypeSignature
+{
+    if let (TypeSignature::Object(x), TypeSignature::Object(y), Some(h)) = (a, b, h)
+    {
+        if h.is_subtype(x, y) { return b.clone(); }
+        if h.is_subtype(y, x) { return a.clone(); }
+        if let Some(s) = h.superclasses(x).into_iter().find(|s| h.is_subtype(y, s)) { return TypeSignature::Object(s); }
+    }
+    object()
+}
+
+/* What an instruction needs in a register */
+#[derive(Debug, Clone)]
+enum Expect {
+    Int,
+    Float,
+    Long,
+    Double,
+    /// Any 32 bit value, for move
+    Narrow,
+    /// Any 64 bit value, for move-wide
+    Wide,
+    /// Any reference including uninitialized ones, for move-object
+    Object,
+    /// Null or a reference assignable to the type
+    Ref(TypeSignature),
+    IntOrRef,
+    Array
+}
+
+impl Expect {
+    fn of(t: &TypeSignature) -> Expect
+    {
+        match t {
+            TypeSignature::Float => Expect::Float,
+            TypeSignature::Long => Expect::Long,
+            TypeSignature::Double => Expect::Double,
+            TypeSignature::Object(_) | TypeSignature::Array(_) => Expect::Ref(t.clone()),
+            _ => Expect::Int
+        }
+    }
+
+    fn is_wide(&self) -> bool
+    {
+        matches!(self, Expect::Long | Expect::Double | Expect::Wide)
+    }
+
+    fn accepts(&self, found: &RegisterType, h: Option<&ClassHierarchy>) -> bool
+    {
+        use RegisterType::*;
+        match self {
+            Expect::Int => matches!(found, Zero | Constant | Integer),
+            Expect::Float => matches!(found, Zero | Constant | Float),
+            Expect::Narrow => matches!(found, Zero | Constant | Integer | Float),
+            Expect::Long => matches!(found, WideConstant | Long),
+            Expect::Double => matches!(found, WideConstant | Double),
+            Expect::Wide => found.is_wide(),
+            Expect::Object => matches!(found, Zero | Reference(_) | Uninitialized(..)),
+            Expect::Ref(t) => match found { Zero => true, Reference(f) => assignable(f, t, h), _ => false },
+            Expect::IntOrRef => matches!(found, Zero | Constant | Integer | Reference(_)),
+            Expect::Array => match found { Zero => true, Reference(t) => matches!(t, TypeSignature::Array(_)) || *t == object(), _ => false }
+        }
+    }
+
+    fn describe(&self) -> String
+    {
+        match self {
+            Expect::Int => "int".to_string(),
+            Expect::Float => "float".to_string(),
+            Expect::Long => "long".to_string(),
+            Expect::Double => "double".to_string(),
+            Expect::Narrow => "a 32 bit value".to_string(),
+            Expect::Wide => "a 64 bit value".to_string(),
+            Expect::Object => "a reference".to_string(),
+            Expect::Ref(t) => t.to_jni(),
+            Expect::IntOrRef => "int or reference".to_string(),
+            Expect::Array => "an array".to_string()
+        }
+    }
+}
+
+/* The result of the last invoke or filled-new-array, for move-result */
+#[derive(Debug, Clone, PartialEq)]
+enum Pending {
+    Nothing,
+    Value(RegisterType),
+    /// An invoke-custom, whose result type isn't checked
+    Unknown
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    regs: Vec<RegisterType>,
+    result: Pending
+}
+
+/* The category of a unary, binary or conversion operand from its name in a mnemonic */
+fn category(name: &str) -> Expect
+{
+    match name {
+        "long" => Expect::Long,
+        "float" => Expect::Float,
+        "double" => Expect::Double,
+        _ => Expect::Int
+    }
+}
+
+/* The destination and source operands of arithmetic, comparisons and conversions */
+fn arithmetic(op: Opcode) -> Option<(Expect, Vec<Expect>)>
+{
+    let m = op.mnemonic();
+    let (base, suffix) = m.split_once('/').unwrap_or((m, ""));
+    let (name, t) = base.split_once('-')?;
+    if let Some((from, to)) = base.split_once("-to-") { return Some((category(to), vec![category(from)])); }
+    match name {
+        "neg" | "not" => Some((category(t), vec![category(t)])),
+        "cmp" | "cmpl" | "cmpg" => Some((Expect::Int, vec![category(t), category(t)])),
+        "add" | "sub" | "mul" | "div" | "rem" | "and" | "or" | "xor" | "shl" | "shr" | "ushr" | "rsub" => {
+            if suffix.starts_with("lit") || name == "rsub" { return Some((Expect::Int, vec![Expect::Int])); }
+            let shift = matches!(name, "shl" | "shr" | "ushr");
+            Some((category(t), vec![category(t), if shift { Expect::Int } else { category(t) }]))
+        }
+        _ => None
+    }
+}
+
+struct Verifier<'a> {
+    method: &'a SmaliMethod,
+    hierarchy: Option<&'a ClassHierarchy<'a>>,
+    name: String,
+    locals: u32,
+    registers: u32,
+    labels: HashMap<&'a str, usize>,
+    handlers: HashMap<usize, TypeSignature>,
+    tries: Vec<(usize, usize, usize)>,
+    diagnostics: Option<Vec<Diagnostic>>
+}
+
+impl<'a> Verifier<'a> {
+    fn report(&mut self, index: usize, kind: DiagnosticKind)
+    {
+        let method = self.name.clone();
+        if let Some(d) = self.diagnostics.as_mut() { d.push(Diagnostic { method, index: Some(index), kind }); }
+    }
+
+    fn label(&mut self, i: usize, l: &str) -> Option<usize>
+    {
+        let t = self.labels.get(l).copied();
+        if t.is_none() { self.report(i, DiagnosticKind::BadStructure(format!("unknown label :{}", l))); }
+        t
+    }
+
+    fn index(&mut self, i: usize, r: Register, wide: bool) -> Option<usize>
+    {
+        let n = match r { Register::V(n) => n as u32, Register::P(n) => self.locals + n as u32 };
+        if n + wide as u32 >= self.registers
+        {
+            let register = if wide && n < self.registers { Register::V(n as u16 + 1) } else { r };
+            self.report(i, DiagnosticKind::RegisterOutOfRange { register, registers: self.registers });
+            return None;
+        }
+        Some(n as usize)
+    }
+
+    fn read(&mut self, i: usize, s: &State, r: Register, e: Expect) -> RegisterType
+    {
+        let Some(n) = self.index(i, r, e.is_wide()) else { return RegisterType::Conflict };
+        let found = s.regs[n].clone();
+        let high_ok = !e.is_wide() || s.regs[n + 1] == RegisterType::WideHigh;
+        if !e.accepts(&found, self.hierarchy) || !high_ok
+        {
+            let found = if high_ok { found } else { s.regs[n + 1].clone() };
+            self.report(i, DiagnosticKind::TypeMismatch { register: r, expected: e.describe(), found });
+            return RegisterType::Conflict;
+        }
+        found
+    }
+
+    fn write(&mut self, i: usize, s: &mut State, r: Register, t: RegisterType)
+    {
+        let wide = t.is_wide();
+        let Some(n) = self.index(i, r, wide) else { return };
+
+        // Overwriting half of a wide pair breaks the other half
+        if n > 0 && s.regs[n - 1].is_wide() { s.regs[n - 1] = RegisterType::Conflict; }
+        let last = if wide { n + 1 } else { n };
+        if s.regs[last].is_wide() && last + 1 < s.regs.len() { s.regs[last + 1] = RegisterType::Conflict; }
+        s.regs[n] = t;
+        if wide { s.regs[n + 1] = RegisterType::WideHigh; }
+    }
+
+    /* Checks the arguments of an invoke, the registers are split into wide pairs as needed */
+    fn arguments(&mut self, i: usize, s: &State, regs: &[Register], args: &[Expect])
+    {
+        let needed: usize = args.iter().map(|a| if a.is_wide() { 2 } else { 1 }).sum();
+        if needed != regs.len()
+        {
+            self.report(i, DiagnosticKind::BadStructure(format!("{} argument registers given but {} needed", regs.len(), needed)));
+            return;
+        }
+        let mut k = 0;
+        for a in args
+        {
+            if a.is_wide() && regs[k + 1].number() != regs[k].number() + 1
+            {
+                self.report(i, DiagnosticKind::BadStructure(format!("wide argument in {} and {} which aren't a pair", regs[k], regs[k + 1])));
+            }
+            else { self.read(i, s, regs[k], a.clone()); }
+            k += if a.is_wide() { 2 } else { 1 };
+        }
+    }
+
+    fn next(&mut self, i: usize) -> Vec<usize>
+    {
+        if i + 1 < self.method.instructions.len() { vec![i + 1] }
+        else { self.report(i, DiagnosticKind::FallsOffEnd); vec![] }
+    }
+
+    /* The exception caught by the handler starting just before a move-exception */
+    fn caught(&self, i: usize) -> Option<TypeSignature>
+    {
+        let insns = &self.method.instructions;
+        let mut j = i;
+        loop
+        {
+            if let Some(t) = self.handlers.get(&j) { return Some(t.clone()); }
+            if j == 0 || matches!(insns[j - 1], SmaliInstruction::Instruction(_) | SmaliInstruction::Payload(_)) { return None; }
+            j -= 1;
+        }
+    }
+
+    /* Applies an instruction to the register types, returning the new types and where control goes next */
+    fn step(&mut self, i: usize, state: &State) -> (State, Vec<usize>)
+    {
+        use RegisterType::*;
+        let mut s = state.clone();
+        let d = match &self.method.instructions[i] {
+            SmaliInstruction::Instruction(d) => d,
+            SmaliInstruction::Payload(_) => { self.report(i, DiagnosticKind::FallsOffEnd); return (s, vec![]); }
+            _ => return (s, self.next(i))
+        };
+        let pending = std::mem::replace(&mut s.result, Pending::Nothing);
+        let operands = d.operands();
+        let regs: Vec<Register> = operands.iter().flat_map(|o| match o {
+            Operand::Register(r) => vec![*r],
+            Operand::RegisterList(l) => l.clone(),
+            Operand::RegisterRange(r) => r.registers(),
+            _ => vec![]
+        }).collect();
+        let literal = operands.iter().find_map(|o| if let Operand::Literal(l) = o { Some(*l) } else { None });
+        let label = operands.iter().find_map(|o| if let Operand::Label(l) = o { Some(l.as_str()) } else { None });
+        let ty = operands.iter().find_map(|o| if let Operand::Type(t) = o { Some(t.clone()) } else { None });
+        let field = operands.iter().find_map(|o| if let Operand::Field(f) = o { Some(f.clone()) } else { None });
+        let mut succ = None;
+        let op = d.opcode();
+        let m = op.mnemonic();
+
+        match op {
+            Opcode::Nop => {}
+            Opcode::Move | Opcode::MoveFrom16 | Opcode::Move16 => { let t = self.read(i, &s, regs[1], Expect::Narrow); self.write(i, &mut s, regs[0], t); }
+            Opcode::MoveWide | Opcode::MoveWideFrom16 | Opcode::MoveWide16 => {
+                let t = self.read(i, &s, regs[1], Expect::Wide);
+                self.write(i, &mut s, regs[0], if t.is_wide() { t } else { WideConstant });
+            }
+            Opcode::MoveObject | Opcode::MoveObjectFrom16 | Opcode::MoveObject16 => { let t = self.read(i, &s, regs[1], Expect::Object); self.write(i, &mut s, regs[0], t); }
+            Opcode::MoveResult | Opcode::MoveResultWide | Opcode::MoveResultObject => {
+                let (e, unknown) = match op {
+                    Opcode::MoveResult => (Expect::Narrow, Constant),
+                    Opcode::MoveResultWide => (Expect::Wide, WideConstant),
+                    _ => (Expect::Object, Reference(object()))
+                };
+                let t = match pending {
+                    Pending::Value(t) if e.accepts(&t, self.hierarchy) => t,
+                    Pending::Unknown => unknown,
+                    Pending::Nothing => { self.report(i, DiagnosticKind::BadStructure(format!("{} without a result to move", m))); unknown }
+                    Pending::Value(t) => {
+                        self.report(i, DiagnosticKind::TypeMismatch { register: regs[0], expected: e.describe(), found: t });
+                        unknown
+                    }
+                };
+                self.write(i, &mut s, regs[0], t);
+            }
+            Opcode::MoveException => {
+                let t = match self.caught(i) {
+                    Some(t) => t,
+                    None => {
+                        self.report(i, DiagnosticKind::BadStructure("move-exception outside an exception handler".to_string()));
+                        TypeSignature::from_jni("Ljava/lang/Throwable;")
+                    }
+                };
+                self.write(i, &mut s, regs[0], Reference(t));
+            }
+            Opcode::ReturnVoid | Opcode::Return | Opcode::ReturnWide | Opcode::ReturnObject => {
+                let ret = &self.method.signature.return_type;
+                let fits = match op {
+                    Opcode::ReturnVoid => *ret == TypeSignature::Void,
+                    Opcode::ReturnWide => ret.is_wide(),
+                    Opcode::ReturnObject => matches!(ret, TypeSignature::Object(_) | TypeSignature::Array(_)),
+                    _ => !ret.is_wide() && !matches!(ret, TypeSignature::Void | TypeSignature::Object(_) | TypeSignature::Array(_))
+                };
+                if !fits { self.report(i, DiagnosticKind::ReturnMismatch { expected: ret.clone() }); }
+                else if op != Opcode::ReturnVoid { self.read(i, &s, regs[0], Expect::of(ret)); }
+                if self.method.constructor && s.regs.iter().any(|r| matches!(r, Uninitialized(_, None)))
+                {
+                    self.report(i, DiagnosticKind::UninitializedThis);
+                }
+                succ = Some(vec![]);
+            }
+            Opcode::Const4 | Opcode::Const16 | Opcode::Const | Opcode::ConstHigh16 => {
+                self.write(i, &mut s, regs[0], if literal == Some(0) { Zero } else { Constant });
+            }
+            Opcode::ConstWide16 | Opcode::ConstWide32 | Opcode::ConstWide | Opcode::ConstWideHigh16 => self.write(i, &mut s, regs[0], WideConstant),
+            Opcode::ConstString | Opcode::ConstStringJumbo => self.write(i, &mut s, regs[0], Reference(TypeSignature::from_jni("Ljava/lang/String;"))),
+            Opcode::ConstClass => self.write(i, &mut s, regs[0], Reference(TypeSignature::from_jni("Ljava/lang/Class;"))),
+            Opcode::ConstMethodHandle => self.write(i, &mut s, regs[0], Reference(TypeSignature::from_jni("Ljava/lang/invoke/MethodHandle;"))),
+            Opcode::ConstMethodType => self.write(i, &mut s, regs[0], Reference(TypeSignature::from_jni("Ljava/lang/invoke/MethodType;"))),
+            Opcode::MonitorEnter | Opcode::MonitorExit => { self.read(i, &s, regs[0], Expect::Ref(object())); }
+            Opcode::CheckCast => { self.read(i, &s, regs[0], Expect::Ref(object())); self.write(i, &mut s, regs[0], Reference(ty.unwrap())); }
+            Opcode::InstanceOf => { self.read(i, &s, regs[1], Expect::Ref(object())); self.write(i, &mut s, regs[0], Integer); }
+            Opcode::ArrayLength => { self.read(i, &s, regs[1], Expect::Array); self.write(i, &mut s, regs[0], Integer); }
+            Opcode::NewInstance => self.write(i, &mut s, regs[0], Uninitialized(ty.unwrap(), Some(i))),
+            Opcode::NewArray => { self.read(i, &s, regs[1], Expect::Int); self.write(i, &mut s, regs[0], Reference(ty.unwrap())); }
+            Opcode::FilledNewArray | Opcode::FilledNewArrayRange => {
+                let t = ty.unwrap();
+                if let TypeSignature::Array(e) = &t
+                {
+                    let args = vec![Expect::of(e); regs.len()];
+                    self.arguments(i, &s, &regs, &args);
+                }
+                s.result = Pending::Value(Reference(t));
+            }
+            Opcode::FillArrayData => {
+                self.read(i, &s, regs[0], Expect::Array);
+                if let Some(l) = label
+                {
+                    if !matches!(self.method.payload(l), Some(Payload::ArrayData(_))) { self.report(i, DiagnosticKind::BadStructure(format!("no array data at :{}", l))); }
+                }
+            }
+            Opcode::Throw => { self.read(i, &s, regs[0], Expect::Ref(TypeSignature::from_jni("Ljava/lang/Throwable;"))); succ = Some(vec![]); }
+            Opcode::Goto | Opcode::Goto16 | Opcode::Goto32 => succ = Some(label.and_then(|l| self.label(i, l)).into_iter().collect()),
+            Opcode::PackedSwitch | Opcode::SparseSwitch => {
+                self.read(i, &s, regs[0], Expect::Int);
+                let targets: Vec<String> = match label.and_then(|l| self.method.payload(l)) {
+                    Some(Payload::PackedSwitch(p)) if op == Opcode::PackedSwitch => p.targets.clone(),
+                    Some(Payload::SparseSwitch(p)) if op == Opcode::SparseSwitch => p.entries.iter().map(|e| e.1.clone()).collect(),
+                    _ => { self.report(i, DiagnosticKind::BadStructure(format!("no switch table at :{}", label.unwrap_or_default()))); vec![] }
+                };
+                let mut v: Vec<usize> = targets.iter().filter_map(|t| self.label(i, t)).collect();
+                v.extend(self.next(i));
+                succ = Some(v);
+            }
+            Opcode::IfEq | Opcode::IfNe | Opcode::IfLt | Opcode::IfGe | Opcode::IfGt | Opcode::IfLe => {
+                let refs = (op == Opcode::IfEq || op == Opcode::IfNe) && regs.iter().any(|r| {
+                    self.index(i, *r, false).is_some_and(|n| matches!(s.regs[n], Reference(_) | Uninitialized(..)))
+                });
+                let e = if refs { Expect::Object } else { Expect::Int };
+                self.read(i, &s, regs[0], e.clone());
+                self.read(i, &s, regs[1], e);
+                let mut v: Vec<usize> = label.and_then(|l| self.label(i, l)).into_iter().collect();
+                v.extend(self.next(i));
+                succ = Some(v);
+            }
+            Opcode::IfEqz | Opcode::IfNez | Opcode::IfLtz | Opcode::IfGez | Opcode::IfGtz | Opcode::IfLez => {
+                self.read(i, &s, regs[0], if op == Opcode::IfEqz || op == Opcode::IfNez { Expect::IntOrRef } else { Expect::Int });
+                let mut v: Vec<usize> = label.and_then(|l| self.label(i, l)).into_iter().collect();
+                v.extend(self.next(i));
+                succ = Some(v);
+            }
+            _ if m.starts_with("aget") || m.starts_with("aput") => {
+                let array = self.read(i, &s, regs[1], Expect::Array);
+                self.read(i, &s, regs[2], Expect::Int);
+                let element = match &array { Reference(TypeSignature::Array(e)) => Some(e.as_ref().clone()), _ => None };
+                let (e, unknown) = match m.split_once('-').map_or("", |x| x.1) {
+                    "wide" => (Expect::Wide, WideConstant),
+                    "object" => (Expect::Object, Reference(object())),
+                    "" => (Expect::Narrow, Constant),
+                    _ => (Expect::Int, Integer)
+                };
+                // The array's element type has to match the kind of access
+                let element = element.filter(|t| {
+                    let fits = e.accepts(&RegisterType::of(t), None);
+                    if !fits { self.report(i, DiagnosticKind::TypeMismatch { register: regs[1], expected: format!("an array for {}", m), found: array.clone() }); }
+                    fits
+                });
+                if m.starts_with("aget") { self.write(i, &mut s, regs[0], element.map_or(unknown, |t| RegisterType::of(&t))); }
+                else
+                {
+                    let e = match element { Some(t) if !matches!(e, Expect::Object) => Expect::of(&t), _ => e };
+                    self.read(i, &s, regs[0], e);
+                }
+            }
+            _ if field.is_some() => {
+                let f = field.unwrap();
+                let object = TypeSignature::Object(f.class.clone());
+                if m.starts_with('i')
+                {
+                    // Constructors can set their own fields before calling the superclass constructor
+                    let n = self.index(i, regs[1], false);
+                    let own = n.is_some_and(|n| matches!(&s.regs[n], Uninitialized(t, None) if m.starts_with("iput") && *t == object));
+                    if !own { self.read(i, &s, regs[1], Expect::Ref(object)); }
+                }
+                if m.contains("get") { self.write(i, &mut s, regs[0], RegisterType::of(&f.signature)); }
+                else { self.read(i, &s, regs[0], Expect::of(&f.signature)); }
+            }
+            _ if m.starts_with("invoke") => {
+                let method = operands.iter().find_map(|o| if let Operand::Method(r) = o { Some(r.clone()) } else { None });
+                let proto = operands.iter().find_map(|o| if let Operand::Proto(p) = o { Some(p.clone()) } else { None });
+                match (method, op) {
+                    (_, Opcode::InvokeCustom | Opcode::InvokeCustomRange) => s.result = Pending::Unknown,
+                    (Some(r), Opcode::InvokePolymorphic | Opcode::InvokePolymorphicRange) => {
+                        let proto = proto.unwrap();
+                        let mut args = vec![Expect::Ref(r.class.clone())];
+                        args.extend(proto.args.iter().map(Expect::of));
+                        self.arguments(i, &s, &regs, &args);
+                        s.result = Pending::Value(RegisterType::of(&proto.return_type));
+                    }
+                    (Some(r), _) => {
+                        let is_static = op == Opcode::InvokeStatic || op == Opcode::InvokeStaticRange;
+                        let init = r.name == "<init>" && (op == Opcode::InvokeDirect || op == Opcode::InvokeDirectRange);
+                        let mut args: Vec<Expect> = r.signature.args.iter().map(Expect::of).collect();
+                        let mut uninitialized = None;
+                        if !is_static
+                        {
+                            let this = regs.first().and_then(|t| self.index(i, *t, false)).map(|n| s.regs[n].clone());
+                            match this {
+                                Some(Uninitialized(t, at)) if init => { uninitialized = Some((Uninitialized(t.clone(), at), Reference(t))); args.insert(0, Expect::Object); }
+                                _ => args.insert(0, Expect::Ref(r.class.clone()))
+                            }
+                        }
+                        self.arguments(i, &s, &regs, &args);
+
+                        // Every copy of the new object is initialised
+                        if let Some((u, t)) = uninitialized
+                        {
+                            for x in s.regs.iter_mut().filter(|x| **x == u) { *x = t.clone(); }
+                        }
+                        if r.signature.return_type != TypeSignature::Void { s.result = Pending::Value(RegisterType::of(&r.signature.return_type)); }
+                    }
+                    _ => {}
+                }
+            }
+            _ => match arithmetic(op) {
+                Some((dest, srcs)) => {
+                    // 2addr instructions use the destination as the first source
+                    let from = if m.ends_with("/2addr") { 0 } else { 1 };
+                    for (k, e) in srcs.into_iter().enumerate() { self.read(i, &s, regs[from + k], e); }
+                    let t = match dest { Expect::Long => Long, Expect::Double => Double, Expect::Float => Float, _ => Integer };
+                    self.write(i, &mut s, regs[0], t);
+                }
+                None => self.report(i, DiagnosticKind::BadStructure(format!("{} isn't supported by the verifier", m)))
+            }
+        }
+
+        let succ = match succ { Some(v) => v, None => self.next(i) };
+        (s, succ)
+    }
+
+    fn merge(&self, into: &mut Option<State>, s: &State) -> bool
+    {
+        match into {
+            None => { *into = Some(s.clone()); true }
+            Some(old) => {
+                let regs: Vec<RegisterType> = old.regs.iter().zip(s.regs.iter()).map(|(a, b)| a.merge(b, self.hierarchy)).collect();
+                let result = match (&old.result, &s.result) {
+                    (a, b) if a == b => a.clone(),
+                    (Pending::Value(a), Pending::Value(b)) => Pending::Value(a.merge(b, self.hierarchy)),
+                    _ => Pending::Nothing
+                };
+                let new = State { regs, result };
+                let changed = new != *old;
+                *old = new;
+                changed
+            }
+        }
+    }
+}
+
+/// Infers the register types of a method and checks it would pass verification
+///
+/// Class types are only checked against each other when a hierarchy is given, and only when both classes are in it.
+///
+/// # Examples
+///
+/// ```
+///  use smali::types::SmaliClass;
+///  use smali::verify::{verify_method, RegisterType};
+///
+///  let smali = ".class public Lcom/cool/Class;\n.super Ljava/lang/Object;\n\n\
+///               .method public static f(J)J\n    .locals 0\n    return-wide p0\n.end method\n";
+///  let c = SmaliClass::from_smali(smali)?;
+///  let v = verify_method(&c, &c.methods[0], None);
+///  assert!(v.is_ok());
+///  assert_eq!(v.types_at(0).unwrap(), [RegisterType::Long, RegisterType::WideHigh]);
+/// # Ok::<(), smali::types::SmaliError>(())
+/// ```
+pub fn verify_method(class: &SmaliClass, method: &SmaliMethod, hierarchy: Option<&ClassHierarchy>) -> Verification
+{
+    let name = format!("{}{}", method.name, method.signature.to_jni());
+    let parameters = method.parameter_registers();
+    let registers = method.registers.registers(parameters);
+    let mut result = Verification { registers, types: vec![], diagnostics: vec![] };
+
+    let has_code = method.instructions.iter().any(|i| matches!(i, SmaliInstruction::Instruction(_)));
+    if !has_code && method.modifiers.iter().any(|m| matches!(m, Modifier::Abstract | Modifier::Native)) { return result; }
+    if matches!(method.registers, RegisterCount::Registers(r) if r < parameters) || registers > 0xffff
+    {
+        result.diagnostics.push(Diagnostic { method: name, index: None, kind: DiagnosticKind::BadRegisterCount { registers, parameters } });
+        return result;
+    }
+    if !has_code
+    {
+        result.diagnostics.push(Diagnostic { method: name, index: None, kind: DiagnosticKind::FallsOffEnd });
+        return result;
+    }
+    let tries = match method.try_blocks() {
+        Ok(t) => t,
+        Err(e) => {
+            result.diagnostics.push(Diagnostic { method: name, index: None, kind: DiagnosticKind::BadStructure(e.to_string()) });
+            return result;
+        }
+    };
+
+    // The exceptions each handler catches, handlers shared by different types catch Throwable
+    let mut handlers: HashMap<usize, TypeSignature> = HashMap::new();
+    let throwable = TypeSignature::from_jni("Ljava/lang/Throwable;");
+    for t in &tries
+    {
+        let caught = t.exception.clone().map_or(throwable.clone(), TypeSignature::Object);
+        let e = handlers.entry(t.handler).or_insert_with(|| caught.clone());
+        if *e != caught { *e = common_supertype(e, &caught, hierarchy); }
+    }
+
+    let labels = method.instructions.iter().enumerate()
+        .filter_map(|(i, x)| if let SmaliInstruction::Label(l) = x { Some((l.as_str(), i)) } else { None })
+        .collect();
+    let mut v = Verifier {
+        method, hierarchy, name, labels, handlers, diagnostics: None,
+        locals: registers - parameters, registers,
+        tries: tries.iter().map(|t| (t.start, t.end, t.handler)).collect()
+    };
+
+    // Parameters are in the last registers, `this` is uninitialised in a constructor until the superclass constructor is called
+    let mut regs = vec![RegisterType::Undefined; registers as usize];
+    let mut p = v.locals as usize;
+    if !method.is_static()
+    {
+        let this = TypeSignature::Object(class.name.clone());
+        regs[p] = if method.constructor && class.name.as_jni_type() != "Ljava/lang/Object;" { RegisterType::Uninitialized(this, None) }
+                  else { RegisterType::Reference(this) };
+        p += 1;
+    }
+    for a in &method.signature.args
+    {
+        regs[p] = RegisterType::of(a);
+        if a.is_wide() { regs[p + 1] = RegisterType::WideHigh; p += 2; } else { p += 1; }
+    }
+
+    // Work through the instructions until the types stop changing
+    let n = method.instructions.len();
+    let mut states: Vec<Option<State>> = vec![None; n];
+    states[0] = Some(State { regs, result: Pending::Nothing });
+    let mut work = BTreeSet::from([0]);
+    while let Some(i) = work.pop_first()
+    {
+        let state = states[i].clone().unwrap();
+        let (out, succ) = v.step(i, &state);
+        let mut targets: Vec<(usize, State)> = succ.into_iter().map(|t| (t, out.clone())).collect();
+
+        // Any instruction in a try range may throw, before it has changed any registers
+        if matches!(method.instructions[i], SmaliInstruction::Instruction(_))
+        {
+            let caught = State { regs: state.regs.clone(), result: Pending::Nothing };
+            targets.extend(v.tries.iter().filter(|t| t.0 <= i && i < t.1).map(|t| (t.2, caught.clone())));
+        }
+        for (t, s) in targets
+        {
+            let mut into = states[t].take();
+            if v.merge(&mut into, &s) { work.insert(t); }
+            states[t] = into;
+        }
+    }
+
+    // Then check every reachable instruction with its final types
+    v.diagnostics = Some(vec![]);
+    let mut reachable = true;
+    for (i, state) in states.iter().enumerate()
+    {
+        match state {
+            Some(state) => { v.step(i, state); reachable = true; }
+            None if matches!(method.instructions[i], SmaliInstruction::Instruction(_)) => {
+                if reachable { v.report(i, DiagnosticKind::Unreachable); }
+                reachable = false;
+            }
+            None => {}
+        }
+    }
+    result.diagnostics = v.diagnostics.take().unwrap_or_default();
+    result.types = states.into_iter().map(|s| s.map(|s| s.regs)).collect();
+    result
+}
+
+/// Verifies every method of a class, returning all the problems found
+pub fn verify_class(class: &SmaliClass, hierarchy: Option<&ClassHierarchy>) -> Vec<Diagnostic>
+{
+    class.methods.iter().flat_map(|m| verify_method(class, m, hierarchy).diagnostics).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::find_smali_files;
+    use crate::hierarchy::ClassHierarchy;
+    use crate::instructions::Register;
+    use crate::types::SmaliClass;
+    use crate::verify::{verify_class, verify_method, DiagnosticKind, RegisterType};
+
+    fn class(methods: &str) -> SmaliClass
+    {
+        SmaliClass::from_smali(&format!(".class public Lcom/basic/Test;\n.super Ljava/lang/Object;\n\n{}", methods)).unwrap()
+    }
+
+    #[test]
+    fn verify_test_classes() {
+        let classes = find_smali_files(Path::new("tests")).unwrap();
+        let hierarchy = ClassHierarchy::new(&classes);
+        for c in &classes
+        {
+            let errors: Vec<String> = verify_class(c, Some(&hierarchy)).iter().filter(|d| d.is_error()).map(|d| d.to_string()).collect();
+            assert!(errors.is_empty(), "{}: {:?}", c.name.as_jni_type(), errors);
+        }
+    }
+
+    #[test]
+    fn verify_errors() {
+        // The RootBeer patch without raising the locals count
+        let mut c = class(".method public static isRooted()Z\n    .locals 0\n    const/4 v0, 0x0\n    return v0\n.end method\n");
+        let v = verify_method(&c, &c.methods[0], None);
+        assert_eq!(v.diagnostics[0].kind, DiagnosticKind::RegisterOutOfRange { register: Register::V(0), registers: 0 });
+        c.methods[0].set_locals(1);
+        assert!(verify_method(&c, &c.methods[0], None).is_ok());
+
+        let c = class(".method public static f(J)I\n    .locals 1\n    const-string v0, \"x\"\n    return v0\n.end method\n\n\
+                       .method public static g(J)V\n    .locals 1\n    move p1, p0\n    return-void\n.end method\n\n\
+                       .method public static h()Ljava/lang/Object;\n    .locals 1\n    new-instance v0, Ljava/lang/Object;\n    return-object v0\n.end method\n\n\
+                       .method public constructor <init>()V\n    .locals 0\n    return-void\n.end method\n\n\
+                       .method public static k()V\n    .registers 2\n    goto :goto_0\n    const/4 v0, 0x1\n    :goto_0\n    const/4 v1, 0x1\n.end method\n");
+        let kinds: Vec<DiagnosticKind> = verify_class(&c, None).into_iter().map(|d| d.kind).collect();
+        assert!(matches!(&kinds[0], DiagnosticKind::TypeMismatch { register: Register::V(0), found: RegisterType::Reference(_), .. }));
+        assert!(matches!(&kinds[1], DiagnosticKind::TypeMismatch { register: Register::P(0), found: RegisterType::Long, .. }));
+        assert!(matches!(&kinds[2], DiagnosticKind::TypeMismatch { found: RegisterType::Uninitialized(_, Some(0)), .. }));
+        assert_eq!(kinds[3..], [DiagnosticKind::UninitializedThis, DiagnosticKind::Unreachable, DiagnosticKind::FallsOffEnd]);
+
+        // Types are merged where control flow joins and handlers get the caught exception
+        let c = class(".method public static m(Z)Ljava/lang/Object;\n    .locals 1\n    if-eqz p0, :cond_0\n    const/4 v0, 0x0\n    goto :goto_0\n\
+                       :cond_0\n    const-string v0, \"x\"\n    :goto_0\n    :try_start_0\n    invoke-virtual {v0}, Ljava/lang/Object;->hashCode()I\n    :try_end_0\n\
+                       .catch Ljava/lang/RuntimeException; {:try_start_0 .. :try_end_0} :catch_0\n    return-object v0\n\
+                       :catch_0\n    move-exception v0\n    return-object v0\n.end method\n");
+        let v = verify_method(&c, &c.methods[0], None);
+        assert!(v.is_ok(), "{:?}", v.diagnostics);
+        let join = c.methods[0].label_index("goto_0").unwrap();
+        assert_eq!(v.types_at(join).unwrap()[0], RegisterType::Reference(crate::types::TypeSignature::from_jni("Ljava/lang/String;")));
+        let handler = c.methods[0].label_index("catch_0").unwrap();
+        assert_eq!(v.types_at(handler + 2).unwrap()[0], RegisterType::Reference(crate::types::TypeSignature::from_jni("Ljava/lang/RuntimeException;")));
+    }
+}
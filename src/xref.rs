@@ -0,0 +1,211 @@
+//! Cross references: who calls a method, reads or writes a field, or uses a type or string
+//!
+//! # Examples
+//!
+//! ```no_run
+//!  use std::path::Path;
+//!  use smali::find_smali_files;
+//!  use smali::types::MethodRef;
+//!  use smali::xref::XrefIndex;
+//!
+//!  let classes = find_smali_files(Path::new("smali"))?;
+//!  let xrefs = XrefIndex::new(&classes);
+//!  for x in xrefs.callers(&MethodRef::from_jni("Lcom/scottyab/rootbeer/RootBeer;->isRooted()Z"))
+//!  {
+//!      println!("Called from {}->{}{} at {}", x.class.as_jni_type(), x.method, x.signature.to_jni(), x.index);
+//!  }
+//! # Ok::<(), smali::types::SmaliError>(())
+//! ```
+
+use std::collections::HashMap;
+use crate::instructions::{Opcode, Operand};
+use crate::types::{FieldRef, MethodRef, MethodSignature, ObjectIdentifier, SmaliClass, SmaliInstruction, TypeSignature};
+
+/// Where a reference was made from
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct XrefLocation {
+    /// The class containing the instruction
+    pub class: ObjectIdentifier,
+    /// The name of the method containing the instruction
+    pub method: String,
+    /// The signature of the method containing the instruction
+    pub signature: MethodSignature,
+    /// The index of the instruction in [`SmaliMethod::instructions`](crate::types::SmaliMethod::instructions)
+    pub index: usize,
+    /// The opcode of the instruction, e.g. to tell field reads from writes
+    pub opcode: Opcode
+}
+
+impl XrefLocation {
+    /// Does the instruction read a field (`iget`/`sget`)
+    pub fn is_read(&self) -> bool
+    {
+        self.opcode.mnemonic().starts_with("iget") || self.opcode.mnemonic().starts_with("sget")
+    }
+
+    /// Does the instruction write a field (`iput`/`sput`)
+    pub fn is_write(&self) -> bool
+    {
+        self.opcode.mnemonic().starts_with("iput") || self.opcode.mnemonic().starts_with("sput")
+    }
+}
+
+/// An index of every method, field, type and string referenced by the instructions of a set of classes
+///
+/// Locations are kept in the order the classes, methods and instructions were indexed.
+///
+/// # Examples
+///
+/// ```
+///  use smali::types::{FieldRef, SmaliClass};
+///  use smali::xref::XrefIndex;
+///
+///  let smali = ".class public Lcom/cool/A;\n.super Ljava/lang/Object;\n\n.field private static s:Ljava/lang/String;\n\n\
+///               .method public static f()V\n    .locals 1\n    const-string v0, \"secret\"\n    sput-object v0, Lcom/cool/A;->s:Ljava/lang/String;\n    return-void\n.end method\n";
+///  let classes = vec![SmaliClass::from_smali(smali)?];
+///  let xrefs = XrefIndex::new(&classes);
+///  assert_eq!(xrefs.string_uses("secret")[0].method, "f");
+///  assert_eq!(xrefs.field_writes(&FieldRef::from_jni("Lcom/cool/A;->s:Ljava/lang/String;")).len(), 1);
+/// # Ok::<(), smali::types::SmaliError>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct XrefIndex {
+    methods: HashMap<MethodRef, Vec<XrefLocation>>,
+    fields: HashMap<FieldRef, Vec<XrefLocation>>,
+    types: HashMap<TypeSignature, Vec<XrefLocation>>,
+    strings: HashMap<String, Vec<XrefLocation>>
+}
+
+impl XrefIndex {
+    /// Indexes a set of classes, e.g. `&Vec<SmaliClass>` or [`SmaliProject::classes`](crate::project::SmaliProject::classes)
+    pub fn new<'a, I: IntoIterator<Item = &'a SmaliClass>>(classes: I) -> XrefIndex
+    {
+        let mut index = XrefIndex::default();
+        for c in classes { index.add_class(c); }
+        index
+    }
+
+    /// Adds the references made by a class
+    pub fn add_class(&mut self, c: &SmaliClass)
+    {
+        for m in &c.methods
+        {
+            for (index, i) in m.instructions.iter().enumerate()
+            {
+                let SmaliInstruction::Instruction(i) = i else { continue };
+                let opcode = i.opcode();
+                if opcode.reference_kind().is_none() { continue; }
+
+                let location = || XrefLocation { class: c.name.clone(), method: m.name.clone(), signature: m.signature.clone(), index, opcode };
+                for o in i.operands()
+                {
+                    match o {
+                        Operand::Method(r) => self.methods.entry(r).or_default().push(location()),
+                        Operand::Field(r) => self.fields.entry(r).or_default().push(location()),
+                        Operand::Type(t) => self.types.entry(t).or_default().push(location()),
+                        Operand::String(s) => self.strings.entry(s).or_default().push(location()),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drops the references made by a class, e.g. before adding it again once it's been patched
+    pub fn remove_class(&mut self, class: &ObjectIdentifier)
+    {
+        fn remove<K>(map: &mut HashMap<K, Vec<XrefLocation>>, class: &ObjectIdentifier)
+        {
+            map.retain(|_, v| { v.retain(|x| x.class != *class); !v.is_empty() });
+        }
+        remove(&mut self.methods, class);
+        remove(&mut self.fields, class);
+        remove(&mut self.types, class);
+        remove(&mut self.strings, class);
+    }
+
+    /// Every `invoke-*` of a method
+    pub fn callers(&self, method: &MethodRef) -> &[XrefLocation]
+    {
+        self.methods.get(method).map_or(&[], |v| v)
+    }
+
+    /// Every `iget`, `iput`, `sget` and `sput` of a field
+    pub fn field_accesses(&self, field: &FieldRef) -> &[XrefLocation]
+    {
+        self.fields.get(field).map_or(&[], |v| v)
+    }
+
+    /// Every `iget` and `sget` of a field
+    pub fn field_reads(&self, field: &FieldRef) -> Vec<&XrefLocation>
+    {
+        self.field_accesses(field).iter().filter(|x| x.is_read()).collect()
+    }
+
+    /// Every `iput` and `sput` of a field
+    pub fn field_writes(&self, field: &FieldRef) -> Vec<&XrefLocation>
+    {
+        self.field_accesses(field).iter().filter(|x| x.is_write()).collect()
+    }
+
+    /// Every `const-class`, `new-instance`, `check-cast`, `instance-of` and array creation using a type
+    pub fn type_uses(&self, t: &TypeSignature) -> &[XrefLocation]
+    {
+        self.types.get(t).map_or(&[], |v| v)
+    }
+
+    /// Every `const-string` of a string literal
+    pub fn string_uses(&self, s: &str) -> &[XrefLocation]
+    {
+        self.strings.get(s).map_or(&[], |v| v)
+    }
+
+    /// Every string literal used, with where it's used, e.g. to search them
+    pub fn strings(&self) -> impl Iterator<Item = (&str, &[XrefLocation])>
+    {
+        self.strings.iter().map(|(s, v)| (s.as_str(), v.as_slice()))
+    }
+
+    /// Every method called, with where it's called from
+    pub fn methods(&self) -> impl Iterator<Item = (&MethodRef, &[XrefLocation])>
+    {
+        self.methods.iter().map(|(m, v)| (m, v.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::find_smali_files_parallel;
+    use crate::instructions::Opcode;
+    use crate::types::{FieldRef, MethodRef, ObjectIdentifier, SmaliInstruction, TypeSignature};
+    use crate::xref::XrefIndex;
+
+    #[test]
+    fn xref_index() {
+        let classes = find_smali_files_parallel(Path::new("tests"), 1).unwrap();
+        let mut xrefs = XrefIndex::new(&classes);
+
+        let check = MethodRef::from_jni("Lkotlin/jvm/internal/Intrinsics;->checkNotNullParameter(Ljava/lang/Object;Ljava/lang/String;)V");
+        let callers = xrefs.callers(&check);
+        assert!(callers.iter().any(|x| x.class == ObjectIdentifier::from_java_type("okhttp3.Request") && x.method == "<init>"));
+        assert!(callers.iter().all(|x| x.opcode == Opcode::InvokeStatic));
+
+        // Every location points at an instruction using the reference
+        let x = &callers[0];
+        let c = classes.iter().find(|c| c.name == x.class).unwrap();
+        let m = c.methods.iter().find(|m| m.name == x.method && m.signature == x.signature).unwrap();
+        assert!(matches!(&m.instructions[x.index], SmaliInstruction::Instruction(i) if i.opcode() == Opcode::InvokeStatic));
+
+        let headers = FieldRef::from_jni("Lokhttp3/Request;->headers:Lokhttp3/Headers;");
+        assert!(!xrefs.field_reads(&headers).is_empty());
+        assert_eq!(xrefs.field_writes(&headers).len(), 1);
+        assert!(xrefs.string_uses("url").iter().any(|x| x.opcode == Opcode::ConstString));
+        assert!(!xrefs.type_uses(&TypeSignature::from_jni("Lokhttp3/Request$Builder;")).is_empty());
+
+        xrefs.remove_class(&ObjectIdentifier::from_java_type("okhttp3.Request"));
+        assert!(xrefs.field_accesses(&headers).is_empty());
+        assert!(xrefs.strings().all(|(_, v)| !v.is_empty()));
+    }
+}